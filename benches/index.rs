@@ -0,0 +1,122 @@
+//! Benchmarks of the construction and the queries of the index, over
+//! synthetic datasets.
+//!
+//! Run with `cargo bench --features datagen`.
+
+use criterion::black_box;
+use criterion::criterion_group;
+use criterion::criterion_main;
+use criterion::BenchmarkId;
+use criterion::Criterion;
+
+use ironsea_index::IndexedDestructured;
+use ironsea_index_sfc_dbc::datagen::Distribution;
+use ironsea_index_sfc_dbc::datagen::Generator;
+use ironsea_index_sfc_dbc::datagen::Point;
+use ironsea_index_sfc_dbc::IndexOwned;
+
+type Index = IndexOwned<usize, Vec<u32>, u32>;
+
+const COUNT: usize = 100_000;
+const DIMENSIONS: usize = 3;
+const CELL_BITS: usize = 6;
+
+// Coordinates in [0, max): dense data has many records per distinct
+// coordinate, sparse data nearly one.
+const DENSE: u32 = 1 << 6;
+const SPARSE: u32 = 1 << 20;
+
+fn datasets() -> Vec<(String, Vec<Point<usize>>, u32)> {
+    let distributions = vec![
+        ("uniform", Distribution::Uniform),
+        (
+            "clustered",
+            Distribution::Clustered {
+                clusters: 16,
+                deviation: 0.02,
+            },
+        ),
+        ("skewed", Distribution::Skewed { exponent: 3.0 }),
+    ];
+
+    let mut datasets = vec![];
+    for (name, distribution) in distributions {
+        for &(density, max) in &[("dense", DENSE), ("sparse", SPARSE)] {
+            // Scale the deviation of the clusters with the space.
+            let scaled = match distribution {
+                Distribution::Clustered {
+                    clusters,
+                    deviation,
+                } => Distribution::Clustered {
+                    clusters,
+                    deviation: deviation * f64::from(max),
+                },
+                ref d => d.clone(),
+            };
+            let points = Generator::new(DIMENSIONS, max, scaled)
+                .unwrap()
+                .seed(42)
+                .points(COUNT);
+            datasets.push((format!("{}/{}", name, density), points, max));
+        }
+    }
+
+    datasets
+}
+
+fn build(c: &mut Criterion) {
+    let mut group = c.benchmark_group("build");
+    group.sample_size(10);
+    for (name, points, _) in datasets() {
+        group.bench_with_input(BenchmarkId::from_parameter(name), &points, |b, points| {
+            b.iter(|| Index::new(points.iter().cloned(), DIMENSIONS, CELL_BITS))
+        });
+    }
+    group.finish();
+}
+
+fn find(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find");
+    for (name, points, _) in datasets() {
+        let index = Index::new(points.iter().cloned(), DIMENSIONS, CELL_BITS);
+        let mut keys = points.iter().step_by(97).map(|p| &p.position).cycle();
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| index.find(black_box(keys.next().unwrap())))
+        });
+    }
+    group.finish();
+}
+
+fn find_range(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_range");
+    for (name, points, max) in datasets() {
+        let index = Index::new(points.iter().cloned(), DIMENSIONS, CELL_BITS);
+        // Boxes covering 1/64th and 1/2 of each dimension, in the middle
+        // of the space.
+        for &(size, divisor) in &[("small", 64), ("large", 2)] {
+            let side = max / divisor;
+            let start = vec![max / 2 - side / 2; DIMENSIONS];
+            let end = vec![max / 2 + side / 2; DIMENSIONS];
+            let id = BenchmarkId::new(size, &name);
+            group.bench_function(id, |b| {
+                b.iter(|| index.find_range(black_box(&start), black_box(&end)))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn find_by_value(c: &mut Criterion) {
+    let mut group = c.benchmark_group("find_by_value");
+    group.sample_size(20);
+    for (name, points, _) in datasets() {
+        let index = Index::new(points.iter().cloned(), DIMENSIONS, CELL_BITS);
+        group.bench_function(BenchmarkId::from_parameter(name), |b| {
+            b.iter(|| index.find_by_value(black_box(&(COUNT / 2))))
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, build, find, find_range, find_by_value);
+criterion_main!(benches);
@@ -0,0 +1,66 @@
+use std::fmt;
+
+/// Suggested change to the configuration of an index, see
+/// [`advise`](struct.IndexOwned.html#method.advise).
+#[derive(Clone, Debug, PartialEq)]
+pub enum Advice {
+    /// Cells hold many records, so that queries scan a lot of records
+    /// they do not return. Rebuild the index with more bits per
+    /// dimension.
+    IncreaseCellBits {
+        /// Average number of bits per dimension.
+        current: usize,
+        /// Suggested number of bits per dimension.
+        suggested: usize,
+    },
+    /// Most cells hold a single record, the grid is finer than needed.
+    /// Rebuild the index with fewer bits per dimension.
+    DecreaseCellBits {
+        /// Average number of bits per dimension.
+        current: usize,
+        /// Suggested number of bits per dimension.
+        suggested: usize,
+    },
+    /// Many records of a cell have equal values, which could be shared
+    /// with `compress_values`.
+    CompressValues {
+        /// Fraction of the records whose values are equal to the values
+        /// of another record of their cell.
+        shared: f64,
+    },
+    /// The write buffer holds many records, which every query scans.
+    /// Call `flush`.
+    Flush {
+        /// Number of records in the write buffer.
+        buffered: usize,
+    },
+}
+
+impl fmt::Display for Advice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Advice::IncreaseCellBits { current, suggested } => write!(
+                f,
+                "Cells are crowded, increase cell_bits from {} to {}",
+                current, suggested
+            ),
+            Advice::DecreaseCellBits { current, suggested } => write!(
+                f,
+                "Cells are nearly empty, decrease cell_bits from {} to {}",
+                current, suggested
+            ),
+            Advice::CompressValues { shared } => write!(
+                f,
+                "{:.0}% of the values are repeated within their cell, compress them",
+                shared * 100.0
+            ),
+            Advice::Flush { buffered } => {
+                write!(
+                    f,
+                    "{} records are waiting in the write buffer, flush it",
+                    buffered
+                )
+            }
+        }
+    }
+}
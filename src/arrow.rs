@@ -0,0 +1,357 @@
+//! Arrow IPC streams of the records of an index, to exchange indexed data
+//! with tools which do not read bincode, for example `pyarrow` or Spark,
+//! and construction of an index from the columns of a record batch.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::Read;
+use std::io::Write;
+use std::sync::Arc;
+
+use arrow_array::cast::AsArray;
+use arrow_array::types::ArrowPrimitiveType;
+use arrow_array::Array;
+use arrow_array::RecordBatch;
+use arrow_ipc::reader::StreamReader;
+use arrow_ipc::writer::StreamWriter;
+use arrow_schema::FieldRef;
+use arrow_schema::Schema;
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+use serde_arrow::schema::SchemaLike;
+use serde_arrow::schema::TracingOptions;
+use serde_arrow::utils::Items;
+
+use crate::build::BuildPolicy;
+use crate::build::BuildReport;
+use crate::error::SfcError;
+use crate::position::Position;
+use crate::position::RecordSlice;
+use crate::sfc::SpaceFillingCurve;
+
+// A record, as a row of the stream.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct Row<K, F> {
+    position: K,
+    fields: F,
+}
+
+impl<K: Clone, F> Record<K> for Row<K, F> {
+    fn key(&self) -> K {
+        self.position.clone()
+    }
+}
+
+impl<K, F: Clone> RecordFields<F> for Row<K, F> {
+    fn fields(&self) -> F {
+        self.fields.clone()
+    }
+}
+
+// A row of a record batch, borrowing its coordinates and values.
+struct Columns<'a, V, F> {
+    position: &'a [V],
+    fields: &'a F,
+}
+
+impl<'a, V, F> Clone for Columns<'a, V, F> {
+    fn clone(&self) -> Self {
+        Columns { ..*self }
+    }
+}
+
+impl<'a, V, F> RecordSlice<V> for Columns<'a, V, F> {
+    fn key_slice(&self) -> &[V] {
+        self.position
+    }
+}
+
+impl<'a, V, F: Clone> RecordFields<F> for Columns<'a, V, F> {
+    fn fields(&self) -> F {
+        self.fields.clone()
+    }
+}
+
+fn io<E: ToString>(e: E) -> SfcError {
+    SfcError::Io(e.to_string())
+}
+
+fn column<'a>(batch: &'a RecordBatch, name: &str) -> Result<&'a dyn Array, SfcError> {
+    let column = batch
+        .column_by_name(name)
+        .ok_or_else(|| SfcError::InvalidFormat(format!("Missing column {}", name)))?;
+    if column.null_count() > 0 {
+        return Err(SfcError::InvalidFormat(format!(
+            "Column {} holds null values",
+            name
+        )));
+    }
+
+    Ok(column.as_ref())
+}
+
+// Columns of the rows, derived from the types so that an empty index has
+// a schema as well.
+fn schema<K, F>() -> Result<Vec<FieldRef>, SfcError>
+where
+    F: DeserializeOwned,
+    K: DeserializeOwned,
+{
+    Vec::<FieldRef>::from_type::<Row<K, F>>(TracingOptions::default()).map_err(io)
+}
+
+impl<F, K, V> SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    /// Writes the records of the index, including the write buffer, as an
+    /// Arrow IPC stream of record batches of at most `rows` rows.
+    ///
+    /// Each row has two columns: `position`, the key of the record, and
+    /// `fields`, its values, mapped to Arrow types by `serde_arrow`. The
+    /// stream can be read back with [`read_arrow`](#method.read_arrow).
+    pub fn write_arrow<W>(&self, writer: W, rows: usize) -> Result<(), SfcError>
+    where
+        F: Serialize + DeserializeOwned,
+        K: Serialize + DeserializeOwned,
+        W: Write,
+    {
+        let fields = schema::<K, F>()?;
+        let mut writer = StreamWriter::try_new(writer, &Schema::new(fields.clone())).map_err(io)?;
+
+        let mut batch = Vec::with_capacity(rows.max(1));
+        let mut records = self.iter().peekable();
+        while records.peek().is_some() {
+            batch.clear();
+            batch.extend(
+                records
+                    .by_ref()
+                    .take(rows.max(1))
+                    .map(|(position, fields)| Row { position, fields }),
+            );
+            let record_batch = serde_arrow::to_record_batch(&fields, &batch).map_err(io)?;
+            writer.write(&record_batch).map_err(io)?;
+        }
+
+        writer.finish().map_err(io)
+    }
+
+    /// Builds an index from the records of an Arrow IPC stream, with the
+    /// `position` and `fields` columns of
+    /// [`write_arrow`](#method.write_arrow).
+    ///
+    /// See [`new`](#method.new) for the other parameters. Records which
+    /// cannot be indexed make the construction fail.
+    pub fn read_arrow<R>(reader: R, dimensions: usize, cell_bits: usize) -> Result<Self, SfcError>
+    where
+        F: Clone + Debug + DeserializeOwned,
+        K: Clone + DeserializeOwned,
+        R: Read,
+    {
+        let mut rows: Vec<Row<K, F>> = vec![];
+        for batch in StreamReader::try_new(reader, None).map_err(io)? {
+            let batch = batch.map_err(io)?;
+            let batch: Vec<Row<K, F>> = serde_arrow::from_record_batch(&batch).map_err(io)?;
+            rows.extend(batch);
+        }
+
+        let (index, _) = Self::new_with_policy(
+            rows.into_iter(),
+            dimensions,
+            cell_bits,
+            BuildPolicy::FailFast,
+        )?;
+
+        Ok(index)
+    }
+
+    /// Creates a new Index from the columns of a record batch.
+    ///
+    /// The `coordinates` columns, one per dimension, are primitive arrays
+    /// of type `P`, whose values are the coordinates of the records, and
+    /// the `fields` column holds their values, decoded by `serde_arrow`.
+    /// Coordinates are read straight from the columns, without building
+    /// a record per row. Columns which are missing, of another type, or
+    /// hold null coordinates are refused.
+    ///
+    /// See [`new`](#method.new) for `cell_bits`. Returns the index, as
+    /// well as a report of the processed records.
+    pub fn new_from_record_batch<P>(
+        batch: &RecordBatch,
+        coordinates: &[&str],
+        fields: &str,
+        cell_bits: usize,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        F: Clone + DeserializeOwned,
+        P: ArrowPrimitiveType<Native = V>,
+    {
+        let dimensions = coordinates.len();
+        let rows = batch.num_rows();
+
+        // Row-major copy of the coordinates, so that each record borrows
+        // its position as a slice.
+        let mut positions = Vec::with_capacity(rows * dimensions);
+        let columns = coordinates
+            .iter()
+            .map(|name| {
+                column(batch, name)?.as_primitive_opt::<P>().ok_or_else(|| {
+                    SfcError::InvalidFormat(format!("Unexpected type for column {}", name))
+                })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        for row in 0..rows {
+            positions.extend(columns.iter().map(|column| column.value(row)));
+        }
+
+        let field = batch
+            .schema()
+            .field_with_name(fields)
+            .map_err(|e| SfcError::InvalidFormat(e.to_string()))?
+            .clone()
+            .with_name("item");
+        let Items(values): Items<Vec<F>> =
+            serde_arrow::from_arrow(&[Arc::new(field)], &[batch.column_by_name(fields).unwrap()])
+                .map_err(|e| SfcError::InvalidFormat(e.to_string()))?;
+
+        let records = positions
+            .chunks(dimensions.max(1))
+            .zip(values.iter())
+            .map(|(position, fields)| Columns { position, fields });
+
+        Self::new_from_slices(
+            records,
+            dimensions,
+            cell_bits,
+            BuildPolicy::CollectAndReport,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ironsea_index::IndexedDestructured;
+
+    use crate::fixtures::points;
+
+    #[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+    struct Fields {
+        id: u32,
+        name: String,
+    }
+
+    type Index = SpaceFillingCurve<Fields, Vec<u16>, u16>;
+
+    fn index() -> Index {
+        let rows = points(300)
+            .into_iter()
+            .map(|point| Row {
+                position: point.position,
+                fields: Fields {
+                    id: point.id,
+                    name: format!("point {}", point.id),
+                },
+            })
+            .collect::<Vec<_>>();
+
+        Index::new(rows.into_iter(), 2, 4)
+    }
+
+    #[test]
+    fn write_arrow() {
+        let mut index = index();
+        index.insert_buffered(Row {
+            position: vec![100, 100],
+            fields: Fields {
+                id: 1000,
+                name: "buffered".to_string(),
+            },
+        });
+
+        let mut bytes = vec![];
+        index.write_arrow(&mut bytes, 64).unwrap();
+
+        let batches = StreamReader::try_new(bytes.as_slice(), None)
+            .unwrap()
+            .map(|batch| batch.unwrap())
+            .collect::<Vec<_>>();
+        assert_eq!(batches.len(), 5);
+        assert_eq!(batches.iter().map(|b| b.num_rows()).sum::<usize>(), 301);
+        assert!(batches[0].schema().field_with_name("position").is_ok());
+
+        let loaded = Index::read_arrow(bytes.as_slice(), 2, 4).unwrap();
+        assert_eq!(loaded.stats().records, 301);
+        assert_eq!(loaded.find(&vec![100, 100])[0].name, "buffered");
+        let mut expected = index.find_range(&vec![0, 0], &vec![20, 20]);
+        let mut found = loaded.find_range(&vec![0, 0], &vec![20, 20]);
+        expected.sort_by_key(|(_, fields)| fields.id);
+        found.sort_by_key(|(_, fields)| fields.id);
+        assert_eq!(found, expected);
+    }
+
+    #[test]
+    fn empty() {
+        let index = Index::new(Vec::<Row<Vec<u16>, Fields>>::new().into_iter(), 2, 4);
+
+        let mut bytes = vec![];
+        index.write_arrow(&mut bytes, 64).unwrap();
+        let loaded = Index::read_arrow(bytes.as_slice(), 2, 4).unwrap();
+        assert_eq!(loaded.stats().records, 0);
+    }
+
+    #[test]
+    fn new_from_record_batch() {
+        use arrow_array::types::UInt16Type;
+        use arrow_array::ArrayRef;
+        use arrow_array::StringArray;
+        use arrow_array::UInt16Array;
+
+        let points = points(200);
+        let x = points.iter().map(|p| p.position[0]).collect::<Vec<_>>();
+        let y = points.iter().map(|p| p.position[1]).collect::<Vec<_>>();
+        let names = (0..200)
+            .map(|id| format!("point {}", id))
+            .collect::<Vec<_>>();
+        let batch = RecordBatch::try_from_iter(vec![
+            ("x", Arc::new(UInt16Array::from(x)) as ArrayRef),
+            ("y", Arc::new(UInt16Array::from(y)) as ArrayRef),
+            ("name", Arc::new(StringArray::from(names)) as ArrayRef),
+        ])
+        .unwrap();
+
+        let (index, report) = SpaceFillingCurve::<String, Vec<u16>, u16>::new_from_record_batch::<
+            UInt16Type,
+        >(&batch, &["x", "y"], "name", 4)
+        .unwrap();
+        assert_eq!(report.indexed, 200);
+        assert_eq!(index.find(&vec![7, 13]), vec!["point 1"]);
+
+        assert!(matches!(
+            SpaceFillingCurve::<String, Vec<u16>, u16>::new_from_record_batch::<UInt16Type>(
+                &batch,
+                &["x", "z"],
+                "name",
+                4
+            ),
+            Err(SfcError::InvalidFormat(_))
+        ));
+        assert!(matches!(
+            SpaceFillingCurve::<String, Vec<u16>, u16>::new_from_record_batch::<UInt16Type>(
+                &batch,
+                &["x", "name"],
+                "name",
+                4
+            ),
+            Err(SfcError::InvalidFormat(_))
+        ));
+    }
+}
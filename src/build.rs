@@ -0,0 +1,184 @@
+use std::fmt;
+
+use super::error::SfcError;
+
+/// Outcome of the normalization callback for a single record.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Normalized<K> {
+    /// Index the record at its original position.
+    Unchanged,
+    /// Index the record at the provided position instead.
+    Modified(K),
+    /// Do not index the record.
+    Dropped,
+}
+
+/// What to do with records which cannot be stored in the index, see
+/// [`new_with_policy`](struct.IndexOwned.html#method.new_with_policy).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuildPolicy {
+    /// Skip the record, without logging nor reporting it.
+    SkipSilently,
+    /// Skip the record, log it, and add it to the `rejected` records of
+    /// the [`BuildReport`](struct.BuildReport.html).
+    CollectAndReport,
+    /// Stop the construction, and return the reason of the rejection.
+    FailFast,
+}
+
+impl BuildPolicy {
+    // Apply the policy to the record `source` of the source iterator,
+    // rejected because of `reason`.
+    pub(crate) fn reject<P>(
+        self,
+        report: &mut BuildReport,
+        source: usize,
+        position: &P,
+        reason: Rejection,
+    ) -> Result<(), SfcError>
+    where
+        P: fmt::Debug,
+    {
+        match self {
+            BuildPolicy::SkipSilently => Ok(()),
+            BuildPolicy::CollectAndReport => {
+                error!("Rejected position {:?}: {}", position, reason);
+                report.rejected.push((source, reason));
+                Ok(())
+            }
+            BuildPolicy::FailFast => {
+                error!("Rejected record {} at {:?}: {}", source, position, reason);
+                Err(reason.into())
+            }
+        }
+    }
+}
+
+/// How the values of each dimension are split into cells, see
+/// [`new_with_sizing`](struct.IndexOwned.html#method.new_with_sizing).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CellSizing {
+    /// Every cell of a dimension holds the same number of distinct
+    /// values, however many records use them.
+    Distinct,
+    /// Every cell of a dimension holds about the same number of records:
+    /// values shared by many records get cells of their own, and sparse
+    /// ranges of values share wider cells.
+    Records,
+}
+
+/// Reason why a record could not be stored in the index.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Rejection {
+    /// The position of the record is not in the dictionaries.
+    InvalidPosition(SfcError),
+    /// The cells of the position could not be encoded.
+    Encoding(SfcError),
+    /// The number of coordinates, or of offsets, does not match the
+    /// number of dimensions.
+    DimensionMismatch {
+        /// Number of dimensions of the index.
+        expected: usize,
+        /// Number of coordinates, or of offsets, of the record.
+        found: usize,
+    },
+    /// An offset is too large to be stored in the index.
+    OffsetOverflow {
+        /// Dimension of the offset.
+        dimension: usize,
+        /// Value of the offset.
+        offset: usize,
+    },
+    /// The record could not be parsed from its source, for example a row
+    /// of a CSV file.
+    Parse(String),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Rejection::InvalidPosition(e) => write!(f, "Invalid position: {}", e),
+            Rejection::Encoding(e) => write!(f, "Unable to encode position: {}", e),
+            Rejection::DimensionMismatch { expected, found } => write!(
+                f,
+                "Incorrect number of dimensions, expected {}, got {}",
+                expected, found
+            ),
+            Rejection::OffsetOverflow { dimension, offset } => {
+                write!(f, "Offset {} too large on dim[{}]", offset, dimension)
+            }
+            Rejection::Parse(e) => write!(f, "Unable to parse record: {}", e),
+        }
+    }
+}
+
+/// Summary of the records processed while building an index.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BuildReport {
+    /// Number of records read from the source iterator.
+    pub records: usize,
+    /// Number of records stored in the index.
+    pub indexed: usize,
+    /// Number of records whose position was changed by the
+    /// normalization callback.
+    pub modified: usize,
+    /// Number of records dropped by the normalization callback.
+    pub dropped: usize,
+    /// Records which could not be indexed, identified by their position
+    /// in the source iterator, starting at 0.
+    pub rejected: Vec<(usize, Rejection)>,
+    /// Number of records with the same position and values as another
+    /// record.
+    pub duplicates: usize,
+    /// Number of records with the same position as another record, but
+    /// different values.
+    pub near_duplicates: usize,
+    /// A few of the duplicates, exact or not, as pairs of positions in
+    /// the source iterator: the first record, and its duplicate.
+    pub duplicate_samples: Vec<(usize, usize)>,
+}
+
+/// Estimated memory used by an index, before and after a compaction.
+///
+/// Sizes are in bytes, and do not include memory owned by the values or
+/// the coordinates themselves, for example the content of a `String`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CompactionReport {
+    /// Estimated size before the compaction.
+    pub before: usize,
+    /// Estimated size after the compaction.
+    pub after: usize,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reject() {
+        let reason = Rejection::DimensionMismatch {
+            expected: 3,
+            found: 2,
+        };
+
+        let mut report = BuildReport::default();
+        BuildPolicy::SkipSilently
+            .reject(&mut report, 4, &[1, 2], reason.clone())
+            .unwrap();
+        assert!(report.rejected.is_empty());
+
+        BuildPolicy::CollectAndReport
+            .reject(&mut report, 4, &[1, 2], reason.clone())
+            .unwrap();
+        assert_eq!(report.rejected, vec![(4, reason.clone())]);
+
+        assert_eq!(
+            BuildPolicy::FailFast.reject(&mut report, 5, &[1, 2], reason),
+            Err(SfcError::DimensionMismatch {
+                expected: 3,
+                found: 2
+            })
+        );
+        assert_eq!(report.rejected.len(), 1);
+    }
+}
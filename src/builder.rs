@@ -0,0 +1,324 @@
+//! Construction of an index, one parameter at a time.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::marker::PhantomData;
+use std::sync::Arc;
+
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+
+use super::build::BuildPolicy;
+use super::build::BuildReport;
+use super::build::CellSizing;
+use super::build::Normalized;
+use super::cell_space::CellLayout;
+use super::error::SfcError;
+use super::position::Position;
+use super::quantizer::Quantizer;
+use super::sfc::SpaceFillingCurve;
+
+/// Parameters of a new [`IndexOwned`](struct.IndexOwned.html), see
+/// [`builder`](struct.IndexOwned.html#method.builder).
+///
+/// The number of dimensions and the bits of the grid have to be set,
+/// the other parameters default to what
+/// [`new`](struct.IndexOwned.html#method.new) uses.
+///
+/// There is deliberately no setting for the curve: the index is always
+/// built on a Morton curve, the only one implemented.
+pub struct SpaceFillingCurveBuilder<F, K, V> {
+    dimensions: Option<usize>,
+    cell_bits: Option<usize>,
+    policy: BuildPolicy,
+    layout: CellLayout<V>,
+    threads: usize,
+    _marker: PhantomData<fn() -> (F, K)>,
+}
+
+impl<F, K, V> Debug for SpaceFillingCurveBuilder<F, K, V> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("SpaceFillingCurveBuilder")
+            .field("dimensions", &self.dimensions)
+            .field("cell_bits", &self.cell_bits)
+            .field("policy", &self.policy)
+            .field("sizing", &self.layout.sizing)
+            .field("threads", &self.threads)
+            .finish()
+    }
+}
+
+impl<F, K, V> Default for SpaceFillingCurveBuilder<F, K, V> {
+    fn default() -> Self {
+        SpaceFillingCurveBuilder {
+            dimensions: None,
+            cell_bits: None,
+            policy: BuildPolicy::CollectAndReport,
+            layout: CellLayout::default(),
+            threads: 0,
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<F, K, V> SpaceFillingCurveBuilder<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    /// Returns a builder without any parameter set.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the number of dimensions of the space, a.k.a the length of
+    /// the vector representing a single position.
+    pub fn dimensions(mut self, dimensions: usize) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    /// Sets the number of bits reserved for the grid built on top of the
+    /// coordinate dictionaries, see [`new`](struct.IndexOwned.html#method.new).
+    pub fn cell_bits(mut self, cell_bits: usize) -> Self {
+        self.cell_bits = Some(cell_bits);
+        self
+    }
+
+    /// Sets what to do with records which cannot be indexed, see
+    /// [`new_with_policy`](struct.IndexOwned.html#method.new_with_policy).
+    pub fn policy(mut self, policy: BuildPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Sets how the values of every dimension are split into cells, see
+    /// [`new_with_sizing`](struct.IndexOwned.html#method.new_with_sizing).
+    pub fn sizing(mut self, sizing: CellSizing) -> Self {
+        self.layout.sizing = sizing;
+        self
+    }
+
+    /// Sets the quantizers of the dimensions, `None` keeping the
+    /// dictionary of the dimension, see
+    /// [`new_quantized`](struct.IndexOwned.html#method.new_quantized).
+    pub fn quantizers(mut self, quantizers: Vec<Option<Box<dyn Quantizer<V>>>>) -> Self {
+        self.layout.quantizers = quantizers
+            .into_iter()
+            .map(|quantizer| quantizer.map(Arc::from))
+            .collect();
+        self
+    }
+
+    /// Sets the number of threads used by
+    /// [`build_parallel`](#method.build_parallel), 0, the default, using
+    /// all the available cores.
+    #[cfg(feature = "parallel")]
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.threads = threads;
+        self
+    }
+
+    // Checks the parameters, and returns the dimensions and cell bits.
+    fn settings(&self) -> Result<(usize, usize), SfcError> {
+        let dimensions = self
+            .dimensions
+            .ok_or(SfcError::MissingSetting("dimensions"))?;
+        let cell_bits = self
+            .cell_bits
+            .ok_or(SfcError::MissingSetting("cell_bits"))?;
+
+        if !self.layout.quantizers.is_empty() && self.layout.quantizers.len() != dimensions {
+            return Err(SfcError::DimensionMismatch {
+                expected: dimensions,
+                found: self.layout.quantizers.len(),
+            });
+        }
+        self.layout.check()?;
+
+        Ok((dimensions, cell_bits))
+    }
+
+    /// Creates a new Index from the provided iterator.
+    ///
+    /// Returns the index, as well as a report of the processed records,
+    /// or an error if a parameter is missing or invalid, or if a record
+    /// is rejected with [`BuildPolicy::FailFast`](enum.BuildPolicy.html).
+    pub fn build<I, R>(self, iter: I) -> Result<(SpaceFillingCurve<F, K, V>, BuildReport), SfcError>
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        let (dimensions, cell_bits) = self.settings()?;
+
+        SpaceFillingCurve::build_normalized(
+            iter,
+            dimensions,
+            cell_bits,
+            self.layout,
+            |_| Normalized::Unchanged,
+            self.policy,
+        )
+    }
+}
+
+#[cfg(feature = "parallel")]
+impl<F, K, V> SpaceFillingCurveBuilder<F, K, V>
+where
+    F: PartialEq + Send + Sync,
+    K: Position<V> + Sync,
+    V: Clone + Debug + Hash + Ord + Send + Sync,
+{
+    /// Creates a new Index from the provided records, as
+    /// [`new_parallel`](struct.IndexOwned.html#method.new_parallel) does,
+    /// on the number of threads set with [`threads`](#method.threads).
+    ///
+    /// See [`build`](#method.build) for the returned values.
+    pub fn build_parallel<R>(
+        self,
+        records: &[R],
+    ) -> Result<(SpaceFillingCurve<F, K, V>, BuildReport), SfcError>
+    where
+        R: Record<K> + RecordFields<F> + Sync,
+    {
+        let (dimensions, cell_bits) = self.settings()?;
+        let threads = self.threads;
+        let build = move || {
+            SpaceFillingCurve::build_parallel(
+                records,
+                dimensions,
+                cell_bits,
+                self.layout,
+                self.policy,
+            )
+        };
+
+        if threads == 0 {
+            return build();
+        }
+
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build()
+            .map_err(|e| SfcError::Io(e.to_string()))?
+            .install(build)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ironsea_index::IndexedDestructured;
+
+    use crate::build::Rejection;
+    use crate::fixtures::Point;
+    use crate::quantizer::Uniform;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    fn points() -> Vec<Point> {
+        (0..1000)
+            .map(|id| Point {
+                position: vec![(id % 40) as u16, (id / 40) as u16],
+                id,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn build() {
+        let points = points();
+        let (index, report) = Index::builder()
+            .dimensions(2)
+            .cell_bits(3)
+            .build(points.iter().cloned())
+            .unwrap();
+
+        assert_eq!(report.indexed, 1000);
+        let expected = Index::new(points.iter().cloned(), 2, 3);
+        assert_eq!(index.cell_bits(), expected.cell_bits());
+        assert_eq!(index.stats(), expected.stats());
+        assert_eq!(index.find(&vec![3, 7]), vec![&283]);
+    }
+
+    #[test]
+    fn missing() {
+        let points = points();
+
+        let built = Index::builder().cell_bits(3).build(points.iter().cloned());
+        assert_eq!(built.err(), Some(SfcError::MissingSetting("dimensions")));
+
+        let built = Index::builder().dimensions(2).build(points.iter().cloned());
+        assert_eq!(built.err(), Some(SfcError::MissingSetting("cell_bits")));
+    }
+
+    #[test]
+    fn settings() {
+        let mut points = points();
+        points[5].position.pop();
+
+        let built = Index::builder()
+            .dimensions(2)
+            .cell_bits(3)
+            .policy(BuildPolicy::FailFast)
+            .build(points.iter().cloned());
+        assert_eq!(
+            built.err(),
+            Some(SfcError::DimensionMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+
+        let (index, report) = Index::builder()
+            .dimensions(2)
+            .cell_bits(3)
+            .sizing(CellSizing::Records)
+            .quantizers(vec![Some(Box::new(Uniform::new(0, 39, 8).unwrap())), None])
+            .build(points.iter().cloned())
+            .unwrap();
+        assert!(matches!(
+            report.rejected[..],
+            [(5, Rejection::DimensionMismatch { .. })]
+        ));
+        assert_eq!(index.space().dictionary_size(0), 0);
+        assert_eq!(index.space().cells(0), 8);
+
+        let built = Index::builder()
+            .dimensions(2)
+            .cell_bits(3)
+            .quantizers(vec![None])
+            .build(points.iter().cloned());
+        assert_eq!(
+            built.err(),
+            Some(SfcError::DimensionMismatch {
+                expected: 2,
+                found: 1
+            })
+        );
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn build_parallel() {
+        let points = points();
+        let (index, report) = Index::builder()
+            .dimensions(2)
+            .cell_bits(3)
+            .threads(2)
+            .build_parallel(&points)
+            .unwrap();
+
+        assert_eq!(report.indexed, 1000);
+        let expected = Index::new(points.iter().cloned(), 2, 3);
+        assert_eq!(index.stats(), expected.stats());
+        assert_eq!(
+            index.find_range(&vec![0, 0], &vec![39, 24]),
+            expected.find_range(&vec![0, 0], &vec![39, 24])
+        );
+    }
+}
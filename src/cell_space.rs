@@ -1,38 +1,133 @@
+use std::borrow::Borrow;
+use std::borrow::Cow;
+use std::collections::HashMap;
 use std::collections::HashSet;
 use std::fmt::Debug;
 use std::hash::Hash;
 use std::marker;
-use std::ops::Index;
+use std::sync::Arc;
 
-use ironsea_index::Record;
+#[cfg(feature = "serde")]
+use serde::ser::Error;
+#[cfg(feature = "serde")]
+use serde::ser::SerializeStruct;
+#[cfg(feature = "serde")]
 use serde::Deserialize;
+#[cfg(feature = "serde")]
 use serde::Serialize;
+#[cfg(feature = "serde")]
+use serde::Serializer;
+
+use super::build::CellSizing;
+use super::error::SfcError;
+use super::morton::MORTON_CODE_BITS;
+use super::morton::MORTON_VALUE_BITS;
+use super::position::Position;
+use super::quantizer;
+use super::quantizer::Quantizer;
 
 type Cell<T> = Vec<T>;
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+// Dimension, cell and offset of a value added to a dictionary.
+pub type Inserted = (usize, usize, usize);
+
+// New offsets of the values kept in the cells of a dictionary, indexed by
+// cell and previous offset.
+pub type Remap = Vec<Vec<usize>>;
+
+// Quantizer of every dimension, `None` for the dimensions using a
+// dictionary.
+pub type Quantizers<V> = Vec<Option<Arc<dyn Quantizer<V>>>>;
+
+// How the values of the dimensions are mapped to cells: with the
+// quantizers, missing entries being dimensions using a dictionary, and
+// otherwise by splitting their dictionary according to `sizing`.
+#[derive(Clone, Debug)]
+pub struct CellLayout<V> {
+    pub quantizers: Quantizers<V>,
+    pub sizing: CellSizing,
+}
+
+impl<V> CellLayout<V> {
+    // Checks the quantizers have cells, and not more than can be encoded,
+    // alone or together in a Morton code.
+    pub fn check(&self) -> Result<(), SfcError> {
+        let mut total = 0;
+        for (dimension, quantizer) in self.quantizers.iter().enumerate() {
+            match quantizer.as_ref().map(|quantizer| quantizer.cells()) {
+                Some(cells) if cells == 0 || cells > 1 << MORTON_VALUE_BITS => {
+                    return Err(SfcError::EncodeOverflow {
+                        dimension,
+                        value: cells,
+                    })
+                }
+                Some(cells) => {
+                    total += bits_for(cells);
+                    if total > MORTON_CODE_BITS {
+                        return Err(SfcError::EncodeOverflow {
+                            dimension,
+                            value: cells,
+                        });
+                    }
+                }
+                None => (),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl<V> Default for CellLayout<V> {
+    fn default() -> Self {
+        CellLayout {
+            quantizers: vec![],
+            sizing: CellSizing::Distinct,
+        }
+    }
+}
+
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 struct CellDictionary<K, V> {
     table: Vec<Cell<V>>,
     max_offset: usize,
-    _marker: marker::PhantomData<K>,
+    // No key is stored, so the dictionary is `Send` and `Sync` whatever
+    // `K`, which lets indices share it through an `Arc`.
+    _marker: marker::PhantomData<fn() -> K>,
+}
+
+// Not derived, as that would require `K: Clone`.
+impl<K, V: Clone> Clone for CellDictionary<K, V> {
+    fn clone(&self) -> Self {
+        CellDictionary {
+            table: self.table.clone(),
+            max_offset: self.max_offset,
+            _marker: marker::PhantomData,
+        }
+    }
 }
 
 impl<K, V> CellDictionary<K, V>
 where
-    K: Debug + Index<usize, Output = V>,
+    K: Position<V>,
     V: Clone + Debug + Hash + Ord,
 {
-    pub fn new<I, R>(iter: I, dimension: usize, cell_bits: usize) -> Self
+    // Sorted distinct values of the coordinate `dimension`, with their
+    // number of records.
+    fn distinct<I>(iter: I, dimension: usize) -> Vec<(V, usize)>
     where
-        I: Iterator<Item = R>,
-        R: Debug + Record<K>,
+        I: Iterator<Item = V>,
     {
         // 1. Retrieve a list of distinct values for the coordinate `dimension`
-        let mut distinct: HashSet<V> = iter.map(|record| record.key()[dimension].clone()).collect();
+        let mut distinct: HashMap<V, usize> = HashMap::new();
+        for value in iter {
+            *distinct.entry(value).or_default() += 1;
+        }
 
         // 2. Build a sorted list, of distinct elements
         let mut distinct = distinct.drain().collect::<Vec<_>>();
-        distinct.sort_unstable();
+        distinct.sort_unstable_by(|a, b| a.0.cmp(&b.0));
 
         info!(
             "Number of distinct coordinates on dim[{}]: {}",
@@ -40,7 +135,20 @@ where
             distinct.len()
         );
 
-        trace!("min {:?}, max {:?}", distinct[0], distinct.last());
+        trace!("min {:?}, max {:?}", distinct.first(), distinct.last());
+
+        distinct
+    }
+
+    pub fn new(
+        distinct: Vec<(V, usize)>,
+        dimension: usize,
+        cell_bits: usize,
+        sizing: CellSizing,
+    ) -> Self {
+        if sizing == CellSizing::Records {
+            return Self::by_records(distinct, dimension, cell_bits);
+        }
 
         // 3. Build the dictionary space
         // 3.1. Build dictionnary per dimension, Add cell and offset
@@ -57,7 +165,7 @@ where
         // Do not forget to initialise cells[0]!
         cells.push(Vec::with_capacity(max_offset));
 
-        for coordinate in distinct {
+        for (coordinate, _) in distinct {
             //trace!("{:?} {:?} {:?} {:?}", dimension, coordinate, cell, count);
 
             // Check first, otherwise we might add a cell which will stay empty.
@@ -85,6 +193,44 @@ where
         }
     }
 
+    // Same as `new`, splitting the values so that every cell holds about
+    // as many records: each value goes to the cell of the quantile of its
+    // first record, empty quantiles being skipped.
+    fn by_records(distinct: Vec<(V, usize)>, dimension: usize, cell_bits: usize) -> Self {
+        let total = distinct.iter().map(|(_, records)| records).sum::<usize>();
+        let quantiles = 1u128 << cell_bits;
+
+        let mut cells: Vec<Cell<V>> = vec![vec![]];
+        let mut quantile = 0;
+        let mut seen = 0;
+        for (coordinate, records) in distinct {
+            let q = seen as u128 * quantiles / total as u128;
+            if q != quantile {
+                quantile = q;
+                cells.push(vec![]);
+            }
+
+            let last = cells.len() - 1;
+            cells[last].push(coordinate);
+            seen += records;
+        }
+        let max_offset = cells.iter().map(Vec::len).max().unwrap_or(0).max(1);
+
+        info!(
+            "dim[{}]: {} cells, {} max per cell, {} records per cell",
+            dimension,
+            cells.len(),
+            max_offset,
+            total / cells.len(),
+        );
+
+        CellDictionary {
+            table: cells,
+            max_offset,
+            _marker: marker::PhantomData,
+        }
+    }
+
     fn max_offset(&self) -> usize {
         self.max_offset
     }
@@ -130,6 +276,8 @@ where
         result
     }
 
+    // Cell and offset of `position`, or else of the largest value below
+    // it, or of the first value if there is none.
     fn key_down(&self, position: &V) -> (usize, usize) {
         match self.cell_id(position) {
             Some(id) => match self.table[id].binary_search(position) {
@@ -149,6 +297,10 @@ where
         }
     }
 
+    fn first(&self) -> (usize, usize) {
+        (0, 0)
+    }
+
     fn last(&self) -> (usize, usize) {
         assert!(!self.table.is_empty());
         let last_id = self.table.len() - 1;
@@ -159,6 +311,8 @@ where
         (last_id, last_offset)
     }
 
+    // Cell and offset of `position`, or else of the smallest value above
+    // it, or of the last value if there is none.
     fn key_up(&self, position: &V) -> (usize, usize) {
         match self.cell_id(position) {
             Some(id) => match self.table[id].binary_search(position) {
@@ -177,37 +331,276 @@ where
         }
     }
 
-    fn value(&self, cell_id: usize, offset: usize) -> &V {
-        &self.table[cell_id][offset]
+    fn value(&self, cell_id: usize, offset: usize) -> Option<&V> {
+        self.table.get(cell_id)?.get(offset)
+    }
+
+    // Add `position` to the dictionary if needed. Returns its cell and
+    // offset, as well as whether it was added.
+    fn insert(&mut self, position: &V) -> (usize, usize, bool) {
+        // Values past the end of the dictionary go in the last cell.
+        let id = match self.cell_id(position) {
+            Some(id) => id,
+            None => self.table.len() - 1,
+        };
+
+        match self.table[id].binary_search(position) {
+            Ok(offset) => (id, offset, false),
+            Err(offset) => {
+                self.table[id].insert(offset, position.clone());
+                if self.table[id].len() > self.max_offset {
+                    self.max_offset = self.table[id].len();
+                }
+
+                (id, offset, true)
+            }
+        }
+    }
+
+    // First value for which `predicate` is false, `predicate` being true
+    // for all the values before it and false for all the ones after.
+    fn partition_point<P>(&self, predicate: P) -> Option<&V>
+    where
+        P: Fn(&V) -> bool,
+    {
+        let id = self
+            .table
+            .partition_point(|cell| matches!(cell.last(), Some(v) if predicate(v)));
+        let cell = self.table.get(id)?;
+
+        cell.get(cell.partition_point(|v| predicate(v)))
+    }
+
+    // Remove the values whose (cell, offset) are not in `used`. The last
+    // value of a cell is kept if the whole cell is unused, so that values
+    // stay in their cells.
+    fn retain(&mut self, used: &HashSet<(usize, usize)>) -> Remap {
+        let mut remap = Vec::with_capacity(self.table.len());
+        for (id, cell) in self.table.iter_mut().enumerate() {
+            let last = cell.len() - 1;
+            let keep = |offset: usize| used.contains(&(id, offset));
+            let keep_last = !(0..cell.len()).any(keep);
+
+            let mut offsets = Vec::with_capacity(cell.len());
+            let mut values = Vec::with_capacity(cell.len());
+            for (offset, value) in cell.drain(..).enumerate() {
+                offsets.push(values.len());
+                if keep(offset) || (keep_last && offset == last) {
+                    values.push(value);
+                }
+            }
+            values.shrink_to_fit();
+            *cell = values;
+            remap.push(offsets);
+        }
+
+        self.max_offset = self.table.iter().map(Vec::len).max().unwrap_or(0);
+
+        remap
+    }
+
+    // Memory used by the dictionary, without what the values own.
+    fn size(&self) -> usize {
+        self.table.capacity() * std::mem::size_of::<Cell<V>>()
+            + self
+                .table
+                .iter()
+                .map(|cell| cell.capacity() * std::mem::size_of::<V>())
+                .sum::<usize>()
     }
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+// Bits needed to number `cells` cells.
+fn bits_for(cells: usize) -> usize {
+    (usize::BITS - cells.saturating_sub(1).leading_zeros()) as usize
+}
+
+// Distribute `budget` bits among the dimensions, one at a time, to the
+// dimension with the most distinct values left per cell. A dimension with
+// `n` distinct values never gets more than ceil(log2(n)) bits, as extra
+// cells would stay empty.
+fn allocate_bits(distinct: &[usize], budget: usize) -> Vec<usize> {
+    let mut bits = vec![0; distinct.len()];
+
+    for _ in 0..budget {
+        let mut best: Option<(usize, f64)> = None;
+        for (k, n) in distinct.iter().enumerate() {
+            if bits[k] >= MORTON_VALUE_BITS || (1 << bits[k]) >= *n {
+                continue;
+            }
+
+            let remaining = (*n as f64).log2() - bits[k] as f64;
+            match best {
+                Some((_, r)) if r >= remaining => (),
+                _ => best = Some((k, remaining)),
+            }
+        }
+
+        match best {
+            Some((k, _)) => bits[k] += 1,
+            None => break,
+        }
+    }
+
+    bits
+}
+
+/// Grid of the index: the values of every dimension, sorted and split
+/// into cells, whose ids are combined into the Morton codes of the index.
+///
+/// Each dimension holds a dictionary of its distinct values, unless it
+/// uses a [`Quantizer`](trait.Quantizer.html). Returned by
+/// [`space`](struct.IndexOwned.html#method.space), to inspect the grid.
+#[derive(Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize))]
+#[cfg_attr(
+    feature = "serde",
+    serde(bound(deserialize = "K: Deserialize<'de>, V: Deserialize<'de>"))
+)]
 pub struct CellSpace<K, V> {
     dimensions: usize,
+    cell_bits: Vec<usize>,
     coordinates: Vec<CellDictionary<K, V>>,
     coordinates_max_offsets: Vec<usize>,
+    // Dimensions quantized instead of using their dictionary, which is
+    // then left empty. Not serialized.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    quantizers: Quantizers<V>,
+}
+
+// Keys are not stored, so they do not have to be cloneable.
+impl<K, V: Clone> Clone for CellSpace<K, V> {
+    fn clone(&self) -> Self {
+        CellSpace {
+            dimensions: self.dimensions,
+            cell_bits: self.cell_bits.clone(),
+            coordinates: self.coordinates.clone(),
+            coordinates_max_offsets: self.coordinates_max_offsets.clone(),
+            quantizers: self.quantizers.clone(),
+        }
+    }
+}
+
+// Same as the derived implementation, failing on quantizers, which cannot
+// be serialized.
+#[cfg(feature = "serde")]
+impl<K, V> Serialize for CellSpace<K, V>
+where
+    CellDictionary<K, V>: Serialize,
+{
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        if self.quantizers.iter().any(Option::is_some) {
+            return Err(S::Error::custom(
+                "Indices using quantizers cannot be serialized",
+            ));
+        }
+
+        let mut state = serializer.serialize_struct("CellSpace", 4)?;
+        state.serialize_field("dimensions", &self.dimensions)?;
+        state.serialize_field("cell_bits", &self.cell_bits)?;
+        state.serialize_field("coordinates", &self.coordinates)?;
+        state.serialize_field("coordinates_max_offsets", &self.coordinates_max_offsets)?;
+        state.end()
+    }
 }
 
 impl<K, V> CellSpace<K, V>
 where
-    K: Debug + Index<usize, Output = V>,
+    K: Position<V>,
     V: Clone + Debug + Hash + Ord,
 {
-    pub fn new<I, R>(iter: I, dimensions: usize, cell_bits: usize) -> Self
+    // Build the dictionaries, sharing `dimensions * cell_bits` bits among
+    // the dimensions according to their number of distinct values.
+    //
+    // The dimensions with a quantizer in `layout` use it instead of a
+    // dictionary.
+    pub(crate) fn new<I>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        layout: CellLayout<V>,
+    ) -> Self
+    where
+        I: Clone + Iterator<Item = K>,
+    {
+        // Keys with the wrong number of coordinates are rejected later on.
+        Self::new_with(
+            iter,
+            dimensions,
+            cell_bits,
+            layout,
+            |position, k| match position.dimensions() {
+                Some(found) if found != dimensions => None,
+                _ => Some(position.coordinate(k)),
+            },
+        )
+    }
+
+    // Same as `new`, reading the coordinates of the items of `iter` with
+    // `coordinate`, so that they do not have to be keys. Items for which
+    // `coordinate` returns `None` are skipped.
+    pub(crate) fn new_with<I, R, C>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        layout: CellLayout<V>,
+        coordinate: C,
+    ) -> Self
     where
         I: Clone + Iterator<Item = R>,
-        R: Debug + Record<K>,
+        C: Fn(&R, usize) -> Option<V>,
     {
+        let CellLayout {
+            mut quantizers,
+            sizing,
+        } = layout;
+        quantizers.resize(dimensions, None);
+
+        // Quantized dimensions take the bits their cells need, the others
+        // share the remaining ones, within the width of a Morton code.
+        let mut budget = dimensions.saturating_mul(cell_bits).min(MORTON_CODE_BITS);
+        let mut quantized_bits = vec![None; dimensions];
+        let mut distinct = Vec::with_capacity(dimensions);
+        for (k, quantizer) in quantizers.iter().enumerate() {
+            match quantizer {
+                Some(quantizer) => {
+                    let bits = bits_for(quantizer.cells());
+                    budget = budget.saturating_sub(bits);
+                    quantized_bits[k] = Some(bits);
+                    distinct.push(vec![]);
+                }
+                None => {
+                    let coordinates = iter.clone().filter_map(|item| coordinate(&item, k));
+                    distinct.push(CellDictionary::<K, V>::distinct(coordinates, k));
+                }
+            }
+        }
+
+        let mut bits = allocate_bits(&distinct.iter().map(Vec::len).collect::<Vec<_>>(), budget);
+        for (k, quantized) in quantized_bits.into_iter().enumerate() {
+            if let Some(quantized) = quantized {
+                bits[k] = quantized;
+            }
+        }
+        info!("Bits allocated per dimension: {:?}", bits);
+
         let mut space = CellSpace {
             dimensions,
+            cell_bits: bits,
             coordinates: vec![],
             coordinates_max_offsets: vec![],
+            quantizers,
         };
 
-        // FIXME: Add check to ensure all positions have the required number of dimensions.
-        for k in 0..dimensions {
-            let dic = CellDictionary::new(iter.clone(), k, cell_bits);
+        for (k, distinct) in distinct.into_iter().enumerate() {
+            let bits = match space.quantizer(k) {
+                Some(_) => 0,
+                None => space.cell_bits[k],
+            };
+            let dic = CellDictionary::new(distinct, k, bits, sizing);
             let max = dic.max_offset();
             space.coordinates.push(dic);
             space.coordinates_max_offsets.push(max);
@@ -216,39 +609,139 @@ where
         space
     }
 
-    pub fn last(&self) -> (Vec<usize>, Vec<usize>) {
-        let mut cells = Vec::with_capacity(self.dimensions);
-        let mut offsets = Vec::with_capacity(self.dimensions);
+    /// Returns the number of dimensions of the space.
+    pub fn dimensions(&self) -> usize {
+        self.dimensions
+    }
+
+    fn quantizer(&self, k: usize) -> Option<&dyn Quantizer<V>> {
+        self.quantizers.get(k)?.as_deref()
+    }
+
+    // Quantizers of the dimensions, possibly fewer than the dimensions.
+    pub(crate) fn quantizers(&self) -> &Quantizers<V> {
+        &self.quantizers
+    }
+
+    /// Returns the number of bits of the Morton code allocated to each
+    /// dimension.
+    pub fn cell_bits(&self) -> &[usize] {
+        &self.cell_bits
+    }
+
+    /// Returns the number of distinct values in the dictionary of the
+    /// dimension `k`. Quantized dimensions have no dictionary, and
+    /// return 0.
+    pub fn dictionary_size(&self, k: usize) -> usize {
+        match self.quantizer(k) {
+            Some(_) => 0,
+            None => self.coordinates[k].cells().iter().map(Vec::len).sum(),
+        }
+    }
+
+    /// Returns the number of cells of the dimension `k`.
+    pub fn cells(&self, k: usize) -> usize {
+        match self.quantizer(k) {
+            Some(quantizer) => quantizer.cells(),
+            None => self.coordinates[k].cells().len(),
+        }
+    }
+
+    /// Returns the smallest and largest values of `cell` in the dimension
+    /// `k`, or `None` if there is no such cell.
+    ///
+    /// Values of the dimension between the largest value of a cell and
+    /// the smallest one of the next cell are inserted in the latter.
+    pub fn cell_range(&self, k: usize, cell: usize) -> Option<(V, V)> {
+        match self.quantizer(k) {
+            Some(quantizer) => {
+                let last = quantizer.offsets(cell).checked_sub(1)?;
+                Some((quantizer.value(cell, 0)?, quantizer.value(cell, last)?))
+            }
+            None => {
+                let cell = self.coordinates[k].cells().get(cell)?;
+                Some((cell.first()?.clone(), cell.last()?.clone()))
+            }
+        }
+    }
+
+    /// Returns the cell of the dimension `k` in which `value` is, or
+    /// would be, stored, or `None` if `value` is larger than every value
+    /// of the dimension, or cannot be represented by its quantizer.
+    pub fn cell_of(&self, k: usize, value: &V) -> Option<usize> {
+        match self.quantizer(k) {
+            Some(quantizer) => quantizer.key(value).map(|(cell, _)| cell),
+            None => self.coordinates[k].cell_id(value),
+        }
+    }
+
+    pub(crate) fn key(&self, position: &K) -> Result<(Vec<usize>, Vec<usize>), SfcError> {
+        self.check(position)?;
+
+        self.key_with(|k| position.coordinate(k))
+    }
+
+    // Check that `position` has one coordinate per dimension, when its
+    // number of coordinates is known, and that the quantizers can
+    // represent them.
+    pub(crate) fn check(&self, position: &K) -> Result<(), SfcError> {
+        match position.dimensions() {
+            Some(found) if found != self.dimensions => {
+                return Err(SfcError::DimensionMismatch {
+                    expected: self.dimensions,
+                    found,
+                })
+            }
+            _ => (),
+        }
+
         for k in 0..self.dimensions {
-            let (cell_id, offset) = self.coordinates[k].last();
-            cells.push(cell_id);
-            offsets.push(offset);
+            if let Some(quantizer) = self.quantizer(k) {
+                let coordinate = position.coordinate(k);
+                if quantizer.key(&coordinate).is_none() {
+                    return Err(SfcError::OutOfBounds {
+                        dimension: k,
+                        value: format!("{:?}", coordinate),
+                    });
+                }
+            }
         }
 
-        (cells, offsets)
+        Ok(())
     }
 
-    pub fn key(&self, position: &K) -> Result<(Vec<usize>, Vec<usize>), String> {
-        //TODO: Should we check inside each objects, or just assume it is correct and/or rely on the bound checks?
-        /* This impose to require ExactSizeIterator, which is not implemented on Vec, and can't be in any easy way.
+    // Same as `key`, for a position given as a slice of coordinates.
+    pub(crate) fn key_slice(&self, position: &[V]) -> Result<(Vec<usize>, Vec<usize>), SfcError> {
         if self.dimensions != position.len() {
-            return Err(format!(
-                "Incorrect number of dimensions, expected {}, got {} for {:?}",
-                self.dimensions,
-                position.len(),
-                position
-            ));
-        }*/
+            return Err(SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: position.len(),
+            });
+        }
+
+        self.key_with(|k| &position[k])
+    }
 
+    fn key_with<C, B>(&self, coordinate: C) -> Result<(Vec<usize>, Vec<usize>), SfcError>
+    where
+        C: Fn(usize) -> B,
+        B: Borrow<V>,
+    {
         let mut cells = Vec::with_capacity(self.dimensions);
         let mut offsets = Vec::with_capacity(self.dimensions);
         for k in 0..self.dimensions {
-            match self.coordinates[k].key(&position[k]) {
+            let coordinate = coordinate(k);
+            let coordinate = coordinate.borrow();
+            let key = match self.quantizer(k) {
+                Some(quantizer) => quantizer.key(coordinate),
+                None => self.coordinates[k].key(coordinate),
+            };
+            match key {
                 None => {
-                    return Err(format!(
-                        "Incorrect value for position[{:?}]: {:?}",
-                        k, &position[k]
-                    ))
+                    return Err(SfcError::OutOfBounds {
+                        dimension: k,
+                        value: format!("{:?}", coordinate),
+                    })
                 }
                 Some((id, offset)) => {
                     cells.push(id);
@@ -260,23 +753,27 @@ where
         Ok((cells, offsets))
     }
 
-    // Round down to the preceding element or self if in the space
-    pub fn key_down(&self, position: &K) -> Result<(Vec<usize>, Vec<usize>), String> {
-        //TODO: Should we check inside each objects, or just assume it is correct and/or rely on the bound checks?
-        /* This impose to require ExactSizeIterator, which is not implemented on Vec, and can't be in any easy way.
+    // Round down to the preceding element or self if in the space.
+    // Unbounded dimensions, `None`, are set to the first element.
+    pub(crate) fn key_down(
+        &self,
+        position: &[Option<V>],
+    ) -> Result<(Vec<usize>, Vec<usize>), SfcError> {
         if self.dimensions != position.len() {
-            return Err(format!(
-                "Incorrect number of dimensions, expected {}, got {} for {:?}",
-                self.dimensions,
-                position.len(),
-                position
-            ));
-        }*/
+            return Err(SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: position.len(),
+            });
+        }
 
         let mut cells = Vec::with_capacity(self.dimensions);
         let mut offsets = Vec::with_capacity(self.dimensions);
-        for k in 0..self.dimensions {
-            let (id, offset) = self.coordinates[k].key_down(&position[k]);
+        for (k, coordinate) in position.iter().enumerate() {
+            let (id, offset) = match (coordinate, self.quantizer(k)) {
+                (Some(coordinate), Some(quantizer)) => quantizer::key_down(quantizer, coordinate),
+                (Some(coordinate), None) => self.coordinates[k].key_down(coordinate),
+                (None, _) => self.coordinates[k].first(),
+            };
             cells.push(id);
             offsets.push(offset);
         }
@@ -284,23 +781,28 @@ where
         Ok((cells, offsets))
     }
 
-    // Round up to the next element or self if in the space
-    pub fn key_up(&self, position: &K) -> Result<(Vec<usize>, Vec<usize>), String> {
-        //TODO: Should we check inside each objects, or just assume it is correct and/or rely on the bound checks?
-        /* This impose to require ExactSizeIterator, which is not implemented on Vec, and can't be in any easy way.
+    // Round up to the next element or self if in the space.
+    // Unbounded dimensions, `None`, are set to the last element.
+    pub(crate) fn key_up(
+        &self,
+        position: &[Option<V>],
+    ) -> Result<(Vec<usize>, Vec<usize>), SfcError> {
         if self.dimensions != position.len() {
-            return Err(format!(
-                "Incorrect number of dimensions, expected {}, got {} for {:?}",
-                self.dimensions,
-                position.len(),
-                position
-            ));
-        }*/
+            return Err(SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: position.len(),
+            });
+        }
 
         let mut cells = Vec::with_capacity(self.dimensions);
         let mut offsets = Vec::with_capacity(self.dimensions);
-        for k in 0..self.dimensions {
-            let (id, offset) = self.coordinates[k].key_up(&position[k]);
+        for (k, coordinate) in position.iter().enumerate() {
+            let (id, offset) = match (coordinate, self.quantizer(k)) {
+                (Some(coordinate), Some(quantizer)) => quantizer::key_up(quantizer, coordinate),
+                (Some(coordinate), None) => self.coordinates[k].key_up(coordinate),
+                (None, Some(quantizer)) => quantizer::last(quantizer),
+                (None, None) => self.coordinates[k].last(),
+            };
             cells.push(id);
             offsets.push(offset);
         }
@@ -308,32 +810,151 @@ where
         Ok((cells, offsets))
     }
 
-    pub fn value(&self, cells_id: Vec<usize>, offsets: Vec<usize>) -> Result<Vec<&V>, String> {
+    // Add the coordinates of `position` missing from the dictionaries,
+    // which must have been validated with `check`.
+    //
+    // Returns the cells and offsets of `position`, as well as the
+    // (dimension, cell, offset) of every added coordinate: the offsets of
+    // the values which follow it in its cell have been incremented.
+    pub(crate) fn insert(&mut self, position: &K) -> (Vec<usize>, Vec<usize>, Vec<Inserted>) {
+        let mut cells = Vec::with_capacity(self.dimensions);
+        let mut offsets = Vec::with_capacity(self.dimensions);
+        let mut inserted = vec![];
+        for k in 0..self.dimensions {
+            let coordinate = position.coordinate(k);
+            let (id, offset, added) = match self.quantizer(k) {
+                Some(quantizer) => match quantizer.key(&coordinate) {
+                    Some((id, offset)) => (id, offset, false),
+                    None => unreachable!("Positions are checked first: {:?}", coordinate),
+                },
+                None => self.coordinates[k].insert(&coordinate),
+            };
+            if added {
+                self.coordinates_max_offsets[k] = self.coordinates[k].max_offset();
+                inserted.push((k, id, offset));
+            }
+            cells.push(id);
+            offsets.push(offset);
+        }
+
+        (cells, offsets, inserted)
+    }
+
+    // Remove from the dictionaries the values not listed in `used`, which
+    // holds the (cell, offset) pairs still in use for every dimension.
+    //
+    // Returns, for every dimension, the new offsets of the values kept, or
+    // `None` for the quantized dimensions, whose offsets do not change.
+    pub(crate) fn retain(&mut self, used: &[HashSet<(usize, usize)>]) -> Vec<Option<Remap>> {
+        let mut remaps = Vec::with_capacity(self.dimensions);
+        for (k, used) in used.iter().enumerate().take(self.dimensions) {
+            if self.quantizer(k).is_some() {
+                remaps.push(None);
+                continue;
+            }
+            remaps.push(Some(self.coordinates[k].retain(used)));
+            self.coordinates_max_offsets[k] = self.coordinates[k].max_offset();
+        }
+
+        remaps
+    }
+
+    // First value of the dimension `k` for which `predicate` is false, see
+    // `slice::partition_point`. Returns `None` if it is true for all of
+    // them.
+    pub(crate) fn partition_point<P>(&self, k: usize, predicate: P) -> Option<Cow<'_, V>>
+    where
+        P: Fn(&V) -> bool,
+    {
+        match self.quantizer(k) {
+            Some(quantizer) => {
+                let (id, offset) = quantizer::search(quantizer, predicate)?;
+                quantizer.value(id, offset).map(Cow::Owned)
+            }
+            None => self.coordinates[k]
+                .partition_point(predicate)
+                .map(Cow::Borrowed),
+        }
+    }
+
+    // Smallest and largest values of the cell `cells_id`, on every
+    // dimension.
+    pub(crate) fn cell_bounds(&self, cells_id: &[usize]) -> (Vec<Cow<'_, V>>, Vec<Cow<'_, V>>) {
+        let mut min = Vec::with_capacity(self.dimensions);
+        let mut max = Vec::with_capacity(self.dimensions);
+        for (k, id) in cells_id.iter().enumerate().take(self.dimensions) {
+            match self.quantizer(k) {
+                Some(quantizer) => {
+                    let last = quantizer.offsets(*id).saturating_sub(1);
+                    // Cells past the last one are never decoded from codes
+                    // of the index.
+                    match (quantizer.value(*id, 0), quantizer.value(*id, last)) {
+                        (Some(first), Some(last)) => {
+                            min.push(Cow::Owned(first));
+                            max.push(Cow::Owned(last));
+                        }
+                        _ => unreachable!("Cell {} out of dim[{}]", id, k),
+                    }
+                }
+                None => {
+                    let cell = &self.coordinates[k].cells()[*id];
+                    min.push(Cow::Borrowed(&cell[0]));
+                    max.push(Cow::Borrowed(&cell[cell.len() - 1]));
+                }
+            }
+        }
+
+        (min, max)
+    }
+
+    // Memory used by the dictionaries, without what the values own.
+    pub(crate) fn size(&self) -> usize {
+        self.coordinates.iter().map(CellDictionary::size).sum()
+    }
+
+    pub(crate) fn value(
+        &self,
+        cells_id: Vec<usize>,
+        offsets: Vec<usize>,
+    ) -> Result<Vec<Cow<'_, V>>, SfcError> {
         //TODO: Should we check inside each objects, or just assume it is correct and/or rely on the bound checks?
         if self.dimensions != cells_id.len() {
-            return Err(format!(
-                "Incorrect number of dimensions, expected {}, got {} for {:?}",
-                self.dimensions,
-                cells_id.len(),
-                cells_id
-            ));
+            return Err(SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: cells_id.len(),
+            });
         }
 
         //TODO: Should we check inside each objects, or just assume it is correct and/or rely on the bound checks?
         if self.dimensions != offsets.len() {
-            return Err(format!(
-                "Incorrect number of dimensions, expected {}, got {} for {:?}",
-                self.dimensions,
-                offsets.len(),
-                offsets
-            ));
+            return Err(SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: offsets.len(),
+            });
         }
 
-        let mut values = Vec::with_capacity(self.dimensions);
-        for k in 0..self.dimensions {
-            values.push(self.coordinates[k].value(cells_id[k], offsets[k]));
-        }
+        (0..self.dimensions)
+            .map(|k| self.value_of(k, cells_id[k], offsets[k]))
+            .collect()
+    }
+
+    // Value of the dimension `k` stored at `offset` in the cell `cell_id`.
+    pub(crate) fn value_of(
+        &self,
+        k: usize,
+        cell_id: usize,
+        offset: usize,
+    ) -> Result<Cow<'_, V>, SfcError> {
+        let value = match self.quantizer(k) {
+            Some(quantizer) => quantizer.value(cell_id, offset).map(Cow::Owned),
+            None => self.coordinates[k]
+                .value(cell_id, offset)
+                .map(Cow::Borrowed),
+        };
 
-        Ok(values)
+        value.ok_or_else(|| SfcError::OutOfBounds {
+            dimension: k,
+            value: format!("cell {}, offset {}", cell_id, offset),
+        })
     }
 }
@@ -0,0 +1,267 @@
+//! Chunked layout of an index, written and read a group of cells at a
+//! time, so that neither side holds the whole serialized index in memory.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::Read;
+use std::io::Write;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::cell_space::CellSpace;
+use crate::dimension::Dimension;
+use crate::error::SfcError;
+use crate::persist::corrupted;
+use crate::persist::read_error;
+use crate::persist::Header;
+use crate::persist::Layout;
+use crate::position::Position;
+use crate::sfc::SpaceFillingCurve;
+
+// The layout starts with the versioned header of `persist`, followed by,
+// all integers being little-endian:
+//
+//  * dictionaries length: u64, then the dictionaries, the description of
+//    the dimensions, whether values are compressed, and the identifier of
+//    the next record inserted, serialized with bincode,
+//  * chunks, each as its length: u64, then consecutive cells, serialized
+//    with bincode,
+//  * a length of 0, marking the end of the index,
+//
+// every section, including the last one, being followed by the CRC-32 of
+// its bytes: u32.
+
+fn io<E: ToString>(e: E) -> SfcError {
+    SfcError::Io(e.to_string())
+}
+
+// Write `bytes`, prefixed with their length, and followed by their
+// checksum.
+fn write_section<W: Write>(writer: &mut W, bytes: &[u8]) -> Result<(), SfcError> {
+    writer
+        .write_all(&(bytes.len() as u64).to_le_bytes())
+        .map_err(io)?;
+    writer.write_all(bytes).map_err(io)?;
+    writer
+        .write_all(&crc32fast::hash(bytes).to_le_bytes())
+        .map_err(io)
+}
+
+// Read a section written by `write_section`, named `section` in errors.
+// The bytes are read as they come, so that a truncated input does not
+// allocate its announced length.
+fn read_section<R: Read>(reader: &mut R, section: &str) -> Result<Vec<u8>, SfcError> {
+    let mut length = [0; 8];
+    reader
+        .read_exact(&mut length)
+        .map_err(|e| read_error(section, e))?;
+    let length = u64::from_le_bytes(length);
+
+    let mut bytes = vec![];
+    reader
+        .by_ref()
+        .take(length)
+        .read_to_end(&mut bytes)
+        .map_err(io)?;
+
+    let mut sum = [0; 4];
+    reader
+        .read_exact(&mut sum)
+        .map_err(|e| read_error(section, e))?;
+    if bytes.len() as u64 != length || u32::from_le_bytes(sum) != crc32fast::hash(&bytes) {
+        return Err(corrupted(section));
+    }
+
+    Ok(bytes)
+}
+
+impl<F, K, V> SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    /// Writes the index in chunks of at most `cells` cells, each written
+    /// and flushed before the next one is serialized.
+    ///
+    /// The write buffer is flushed first. Memory used while writing is
+    /// bounded by the size of a chunk, so large indexes can be streamed,
+    /// for example as the parts of an upload to an object store. Read it
+    /// back with [`load`](#method.load).
+    pub fn write_chunked<W>(&mut self, mut writer: W, cells: usize) -> Result<(), SfcError>
+    where
+        F: Clone + Serialize,
+        K: Serialize,
+        V: Serialize,
+        W: Write,
+    {
+        self.flush()?;
+        Header::new(Layout::Chunked, self.cell_bits()).write(&mut writer)?;
+
+        let header = (
+            self.space(),
+            self.dimensions(),
+            self.is_compressed(),
+            self.next_record_id(),
+        );
+        write_section(&mut writer, &bincode::serialize(&header).map_err(io)?)?;
+
+        let cells = cells.max(1);
+        let mut first = 0;
+        while first < self.stats().cells {
+            write_section(&mut writer, &self.serialize_cells(first, cells)?)?;
+            writer.flush().map_err(io)?;
+            first += cells;
+        }
+
+        write_section(&mut writer, &[])?;
+        writer.flush().map_err(io)
+    }
+
+    // Read the index following a header with the `Chunked` layout, one
+    // chunk at a time.
+    pub(crate) fn read_chunked<R>(mut reader: R) -> Result<Self, SfcError>
+    where
+        F: DeserializeOwned,
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+        R: Read,
+    {
+        let (space, metadata, compressed, next_id): (CellSpace<K, V>, Vec<Dimension>, bool, u64) =
+            bincode::deserialize(&read_section(&mut reader, "dictionaries")?).map_err(io)?;
+
+        let dimensions = space.dimensions();
+        let mut index = SpaceFillingCurve::empty(space, dimensions);
+        if !metadata.is_empty() {
+            index.set_dimensions(metadata)?;
+        }
+        index.set_compressed(compressed);
+        index.set_next_record_id(next_id);
+
+        for chunk in 0.. {
+            let bytes = read_section(&mut reader, &format!("chunk {}", chunk))?;
+            if bytes.is_empty() {
+                break;
+            }
+            index.deserialize_cells(&bytes)?;
+        }
+
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ironsea_index::IndexedDestructured;
+
+    use crate::fixtures::points;
+    use crate::fixtures::Point;
+    use crate::record_id::RecordId;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    fn index() -> Index {
+        Index::new(points(300).into_iter(), 2, 4)
+    }
+
+    #[test]
+    fn write_chunked() {
+        let mut index = index();
+        index.insert_buffered(Point {
+            position: vec![100, 100],
+            id: 1000,
+        });
+
+        for cells in &[0, 1, 7, 1000] {
+            let mut bytes = vec![];
+            index.write_chunked(&mut bytes, *cells).unwrap();
+
+            let loaded = Index::load(bytes.as_slice()).unwrap();
+            assert_eq!(loaded.stats(), index.stats());
+            assert_eq!(loaded.find(&vec![100, 100]), vec![&1000]);
+            assert_eq!(
+                loaded.find_range(&vec![0, 0], &vec![20, 20]),
+                index.find_range(&vec![0, 0], &vec![20, 20])
+            );
+        }
+
+        let mut empty = Index::new(Vec::<Point>::new().into_iter(), 2, 4);
+        let mut bytes = vec![];
+        empty.write_chunked(&mut bytes, 10).unwrap();
+        assert_eq!(Index::load(bytes.as_slice()).unwrap().stats().records, 0);
+    }
+
+    #[test]
+    fn record_ids() {
+        let mut index = index();
+        let last = index
+            .insert(Point {
+                position: vec![100, 100],
+                id: 1000,
+            })
+            .unwrap();
+        assert_eq!(index.remove_id(last), Some(1000));
+
+        let mut bytes = vec![];
+        index.write_chunked(&mut bytes, 7).unwrap();
+        let mut loaded = Index::load(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.get(RecordId(10)), index.get(RecordId(10)));
+
+        // Identifiers of removed records are not handed out again either.
+        let id = loaded
+            .insert(Point {
+                position: vec![3, 3],
+                id: 1001,
+            })
+            .unwrap();
+        assert!(id > last);
+        assert_eq!(loaded.get(id), Some((vec![3, 3], &1001)));
+    }
+
+    #[test]
+    fn truncated() {
+        let mut index = index();
+        let mut bytes = vec![];
+        index.write_chunked(&mut bytes, 5).unwrap();
+
+        for len in &[bytes.len() - 1, bytes.len() - 8, bytes.len() / 2] {
+            assert!(matches!(
+                Index::load(&bytes[..*len]),
+                Err(SfcError::Corrupted { .. })
+            ));
+        }
+
+        // Chunks written in the wrong order are refused.
+        let (_, start) = Header::parse(&bytes).unwrap();
+        let mut reader = &bytes[start..];
+        let dictionaries = read_section(&mut reader, "dictionaries").unwrap();
+        let first = read_section(&mut reader, "chunk 0").unwrap();
+        let second = read_section(&mut reader, "chunk 1").unwrap();
+
+        let mut other = bytes[..start].to_vec();
+        for section in &[&dictionaries, &second, &first] {
+            write_section(&mut other, section).unwrap();
+        }
+        write_section(&mut other, &[]).unwrap();
+        assert!(matches!(
+            Index::load(other.as_slice()),
+            Err(SfcError::InvalidFormat(_))
+        ));
+
+        // As well as damaged ones.
+        let dictionaries = start + 8 + dictionaries.len() + 4;
+        let mut other = bytes.clone();
+        other[dictionaries + 8 + first.len() + 4 + 10] ^= 1;
+        assert_eq!(
+            Index::load(other.as_slice()).err(),
+            Some(SfcError::Corrupted {
+                section: "chunk 1".to_string()
+            })
+        );
+    }
+}
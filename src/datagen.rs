@@ -0,0 +1,263 @@
+//! Synthetic data generators, to size `cell_bits` and compare encoders
+//! on workloads resembling real ones.
+//!
+//! Generation is deterministic for a given seed, so that measurements
+//! can be reproduced.
+
+use std::f64::consts::PI;
+
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+
+/// Synthetic record, made of a position and a payload.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Point<F> {
+    /// Coordinates of the point.
+    pub position: Vec<u32>,
+    /// Payload of the point.
+    pub fields: F,
+}
+
+impl<F> Record<Vec<u32>> for Point<F> {
+    fn key(&self) -> Vec<u32> {
+        self.position.clone()
+    }
+}
+
+impl<F> RecordFields<F> for Point<F>
+where
+    F: Clone,
+{
+    fn fields(&self) -> F {
+        self.fields.clone()
+    }
+}
+
+/// Spatial distribution of the generated points.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Distribution {
+    /// Coordinates uniformly distributed over the space.
+    Uniform,
+    /// Gaussian mixture: points are spread around `clusters` centers,
+    /// themselves uniformly distributed, with a standard deviation of
+    /// `deviation` on every dimension.
+    Clustered {
+        /// Number of clusters.
+        clusters: usize,
+        /// Standard deviation of the points around their center.
+        deviation: f64,
+    },
+    /// Coordinates concentrated towards 0, following `max * u^exponent`
+    /// where `u` is uniform over [0, 1). The larger the exponent, the
+    /// more skewed the points are.
+    Skewed {
+        /// Skew exponent, 1.0 being uniform.
+        exponent: f64,
+    },
+}
+
+/// Generator of synthetic points.
+#[derive(Clone, Debug)]
+pub struct Generator {
+    dimensions: usize,
+    max: u32,
+    distribution: Distribution,
+    seed: u64,
+}
+
+impl Generator {
+    /// Creates a new generator of `dimensions`-dimensional points, with
+    /// coordinates in [0, `max`).
+    ///
+    /// Returns `None` if `max` is 0, or if the distribution is clustered
+    /// without any cluster.
+    pub fn new(dimensions: usize, max: u32, distribution: Distribution) -> Option<Self> {
+        match distribution {
+            _ if max == 0 => return None,
+            Distribution::Clustered { clusters: 0, .. } => return None,
+            _ => (),
+        }
+
+        Some(Generator {
+            dimensions,
+            max,
+            distribution,
+            seed: 0,
+        })
+    }
+
+    /// Sets the seed of the generator, 0 by default.
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Generates `count` points, whose payload is their sequence number.
+    pub fn points(&self, count: usize) -> Vec<Point<usize>> {
+        self.points_with(count, |id, _| id)
+    }
+
+    /// Generates `count` points, whose payload is computed by `payload`
+    /// from the sequence number and the coordinates of each point.
+    pub fn points_with<F, P>(&self, count: usize, mut payload: P) -> Vec<Point<F>>
+    where
+        P: FnMut(usize, &[u32]) -> F,
+    {
+        let mut rng = SplitMix64::new(self.seed);
+
+        let centers = match self.distribution {
+            Distribution::Clustered { clusters, .. } => (0..clusters)
+                .map(|_| {
+                    (0..self.dimensions)
+                        .map(|_| rng.next_f64() * f64::from(self.max))
+                        .collect::<Vec<_>>()
+                })
+                .collect::<Vec<_>>(),
+            _ => vec![],
+        };
+
+        let mut points = Vec::with_capacity(count);
+        for id in 0..count {
+            let position = match self.distribution {
+                Distribution::Uniform => (0..self.dimensions)
+                    .map(|_| self.coordinate(rng.next_f64() * f64::from(self.max)))
+                    .collect::<Vec<_>>(),
+                Distribution::Clustered { deviation, .. } => {
+                    let center = &centers[rng.next_u64() as usize % centers.len()];
+                    center
+                        .iter()
+                        .map(|c| self.coordinate(c + rng.next_gaussian() * deviation))
+                        .collect::<Vec<_>>()
+                }
+                Distribution::Skewed { exponent } => (0..self.dimensions)
+                    .map(|_| self.coordinate(rng.next_f64().powf(exponent) * f64::from(self.max)))
+                    .collect::<Vec<_>>(),
+            };
+
+            let fields = payload(id, &position);
+            points.push(Point { position, fields });
+        }
+
+        points
+    }
+
+    // Clamp a generated value within [0, max).
+    fn coordinate(&self, value: f64) -> u32 {
+        if value <= 0.0 {
+            0
+        } else if value >= f64::from(self.max) {
+            self.max - 1
+        } else {
+            value as u32
+        }
+    }
+}
+
+// SplitMix64, small and good enough for synthetic data, and stable across
+// releases, unlike external generators.
+struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    fn new(seed: u64) -> Self {
+        SplitMix64 { state: seed }
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    // Uniform over [0, 1).
+    fn next_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    // Standard normal distribution, using the Box-Muller transform.
+    fn next_gaussian(&mut self) -> f64 {
+        let u = 1.0 - self.next_f64();
+        let v = self.next_f64();
+
+        (-2.0 * u.ln()).sqrt() * (2.0 * PI * v).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(distribution: Distribution) -> Vec<Point<usize>> {
+        let generator = Generator::new(3, 1000, distribution).unwrap().seed(42);
+        let points = generator.points(1000);
+
+        assert_eq!(points.len(), 1000);
+        for (id, point) in points.iter().enumerate() {
+            assert_eq!(point.fields, id);
+            assert_eq!(point.position.len(), 3);
+            assert!(point.position.iter().all(|v| *v < 1000));
+        }
+        assert_eq!(points, generator.points(1000));
+
+        points
+    }
+
+    #[test]
+    fn uniform() {
+        check(Distribution::Uniform);
+    }
+
+    #[test]
+    fn clustered() {
+        let points = check(Distribution::Clustered {
+            clusters: 4,
+            deviation: 5.0,
+        });
+
+        // Most of the points are close to one of the few centers.
+        let mut distinct = points
+            .iter()
+            .map(|p| p.position[0] / 50)
+            .collect::<Vec<_>>();
+        distinct.sort_unstable();
+        distinct.dedup();
+        assert!(distinct.len() <= 8);
+    }
+
+    #[test]
+    fn skewed() {
+        let points = check(Distribution::Skewed { exponent: 3.0 });
+
+        let low = points.iter().filter(|p| p.position[0] < 125).count();
+        assert!(low > 400);
+    }
+
+    #[test]
+    fn payload() {
+        let generator = Generator::new(2, 10, Distribution::Uniform).unwrap();
+        let points = generator.points_with(10, |_, position| position[0] + position[1]);
+
+        for point in points {
+            assert_eq!(point.fields, point.position[0] + point.position[1]);
+        }
+    }
+    #[test]
+    fn invalid() {
+        assert!(Generator::new(2, 0, Distribution::Uniform).is_none());
+        assert!(Generator::new(
+            2,
+            10,
+            Distribution::Clustered {
+                clusters: 0,
+                deviation: 1.0,
+            }
+        )
+        .is_none());
+
+        let generator = Generator::new(2, 1, Distribution::Skewed { exponent: 2.0 }).unwrap();
+        assert!(generator.points(10).iter().all(|p| p.position == [0, 0]));
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Description of a dimension of the space, stored with the index so
+/// that exports can annotate the coordinates.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Dimension {
+    /// Name of the dimension, for example `x` or `t`.
+    pub label: String,
+    /// Unit of the coordinates, for example `µm` or `ms since epoch`.
+    pub unit: String,
+}
+
+impl Dimension {
+    /// Creates the description of a dimension.
+    pub fn new<L, U>(label: L, unit: U) -> Self
+    where
+        L: Into<String>,
+        U: Into<String>,
+    {
+        Dimension {
+            label: label.into(),
+            unit: unit.into(),
+        }
+    }
+}
+
+impl fmt::Display for Dimension {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if self.unit.is_empty() {
+            write!(f, "{}", self.label)
+        } else {
+            write!(f, "{}: {}", self.label, self.unit)
+        }
+    }
+}
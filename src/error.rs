@@ -0,0 +1,105 @@
+use std::error::Error;
+use std::fmt;
+
+use super::build::Rejection;
+
+/// Errors returned by the index.
+#[derive(Clone, Debug, PartialEq)]
+pub enum SfcError {
+    /// A coordinate is not in the dictionary of its dimension, or cannot
+    /// be represented by its quantizer.
+    OutOfBounds {
+        /// Dimension of the coordinate.
+        dimension: usize,
+        /// Debug representation of the coordinate.
+        value: String,
+    },
+    /// The number of coordinates, or of bounds, does not match the
+    /// number of dimensions of the index.
+    DimensionMismatch {
+        /// Number of dimensions of the index.
+        expected: usize,
+        /// Number of dimensions provided.
+        found: usize,
+    },
+    /// A value is too large to be encoded, either a cell in the Morton
+    /// code, or an offset in a record.
+    EncodeOverflow {
+        /// Dimension of the value.
+        dimension: usize,
+        /// Value which could not be encoded.
+        value: usize,
+    },
+    /// The index holds no records.
+    EmptyIndex,
+    /// A key could not be rebuilt from its coordinates.
+    InvalidKey(String),
+    /// Reading or writing data outside of the index failed.
+    Io(String),
+    /// The data is not a persisted index, or does not match its header.
+    InvalidFormat(String),
+    /// The index was persisted with another version of the format.
+    VersionMismatch {
+        /// Version supported by this crate.
+        expected: u32,
+        /// Version of the persisted index.
+        found: u32,
+    },
+    /// A section of the persisted index is truncated, or does not match
+    /// its checksum.
+    Corrupted {
+        /// Name of the section.
+        section: String,
+    },
+    /// A required setting of a
+    /// [`SpaceFillingCurveBuilder`](struct.SpaceFillingCurveBuilder.html)
+    /// was not provided.
+    MissingSetting(&'static str),
+}
+
+impl fmt::Display for SfcError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SfcError::OutOfBounds { dimension, value } => {
+                write!(f, "Incorrect value for position[{}]: {}", dimension, value)
+            }
+            SfcError::DimensionMismatch { expected, found } => write!(
+                f,
+                "Incorrect number of dimensions, expected {}, got {}",
+                expected, found
+            ),
+            SfcError::EncodeOverflow { dimension, value } => {
+                write!(f, "Value {} too large on dim[{}]", value, dimension)
+            }
+            SfcError::EmptyIndex => write!(f, "The index is empty"),
+            SfcError::InvalidKey(e) => write!(f, "Invalid key: {}", e),
+            SfcError::Io(e) => write!(f, "I/O error: {}", e),
+            SfcError::InvalidFormat(e) => write!(f, "Invalid format: {}", e),
+            SfcError::VersionMismatch { expected, found } => write!(
+                f,
+                "Unsupported format version {}, expected {}",
+                found, expected
+            ),
+            SfcError::Corrupted { section } => write!(f, "Corrupted section: {}", section),
+            SfcError::MissingSetting(setting) => write!(f, "Missing setting: {}", setting),
+        }
+    }
+}
+
+impl Error for SfcError {}
+
+impl From<Rejection> for SfcError {
+    fn from(rejection: Rejection) -> Self {
+        match rejection {
+            Rejection::InvalidPosition(e) | Rejection::Encoding(e) => e,
+            Rejection::DimensionMismatch { expected, found } => {
+                SfcError::DimensionMismatch { expected, found }
+            }
+            Rejection::OffsetOverflow { dimension, offset } => SfcError::EncodeOverflow {
+                dimension,
+                value: offset,
+            },
+            Rejection::Parse(e) => SfcError::InvalidFormat(e),
+        }
+    }
+}
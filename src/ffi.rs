@@ -0,0 +1,398 @@
+//! C interface to an index of points, for use from C or C++.
+//!
+//! Positions are arrays of `double`, one per dimension, and the values
+//! are opaque `uint64_t` ids. The index is an opaque handle, created by
+//! `sfc_index_new` and released by `sfc_index_free`:
+//!
+//! ```c
+//! typedef struct SfcIndex SfcIndex;
+//!
+//! SfcIndex *sfc_index_new(const double *positions, const uint64_t *ids,
+//!                         size_t count, size_t dimensions, size_t cell_bits);
+//! int sfc_index_insert(SfcIndex *index, const double *position, uint64_t id);
+//! size_t sfc_index_find(const SfcIndex *index, const double *position,
+//!                       uint64_t *ids, size_t capacity);
+//! size_t sfc_index_find_range(const SfcIndex *index, const double *start,
+//!                             const double *end, uint64_t *ids,
+//!                             double *positions, size_t capacity);
+//! void sfc_index_free(SfcIndex *index);
+//! ```
+//!
+//! The queries return the number of records found, of which at most
+//! `capacity` are written to the output arrays, so that a query can be
+//! repeated with larger arrays. Null output arrays are not written to,
+//! to only count the records. Build the crate as a static or dynamic
+//! library to link against it, for example with
+//! `cargo rustc --release --features ffi --crate-type staticlib`.
+//!
+//! Coordinates are ordered as [`Float`](../struct.Float.html): `-0.0`
+//! and `0.0` are equal, and NaN is greater than any other value.
+
+#![allow(unsafe_code)]
+
+use std::panic::catch_unwind;
+use std::panic::UnwindSafe;
+use std::ptr;
+use std::slice;
+
+use ironsea_index::IndexedDestructured;
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+
+use crate::float::Float;
+use crate::sfc::SpaceFillingCurve;
+
+type Index = SpaceFillingCurve<u64, Vec<f64>, Float<f64>>;
+
+/// Opaque handle on an index.
+#[derive(Debug)]
+pub struct SfcIndex {
+    index: Index,
+    dimensions: usize,
+}
+
+#[derive(Clone, Debug)]
+struct Point {
+    position: Vec<f64>,
+    id: u64,
+}
+
+impl Record<Vec<f64>> for Point {
+    fn key(&self) -> Vec<f64> {
+        self.position.clone()
+    }
+}
+
+impl RecordFields<u64> for Point {
+    fn fields(&self) -> u64 {
+        self.id
+    }
+}
+
+// Read `dimensions` coordinates from `position`.
+unsafe fn position(position: *const f64, dimensions: usize) -> Option<Vec<f64>> {
+    if position.is_null() {
+        return None;
+    }
+
+    Some(slice::from_raw_parts(position, dimensions).to_vec())
+}
+
+// Run `f`, returning `default` if it panics, as unwinding into C is
+// undefined behaviour.
+fn guard<T, G: FnOnce() -> T + UnwindSafe>(default: T, f: G) -> T {
+    catch_unwind(f).unwrap_or_else(|_| {
+        error!("Panic in a call through the C interface");
+        default
+    })
+}
+
+// Write the records found, returning their number.
+unsafe fn output(
+    found: Vec<(Vec<f64>, &u64)>,
+    ids: *mut u64,
+    positions: *mut f64,
+    capacity: usize,
+) -> usize {
+    // Only the written part of the arrays is borrowed, so that its
+    // length cannot overflow.
+    let written = capacity.min(found.len());
+
+    if !ids.is_null() {
+        let ids = slice::from_raw_parts_mut(ids, written);
+        for (id, (_, value)) in ids.iter_mut().zip(&found) {
+            *id = **value;
+        }
+    }
+
+    if !positions.is_null() {
+        if let Some((first, _)) = found.first() {
+            let dimensions = first.len();
+            let positions = slice::from_raw_parts_mut(positions, written * dimensions);
+            for (slot, (position, _)) in positions.chunks_mut(dimensions).zip(&found) {
+                slot.copy_from_slice(position);
+            }
+        }
+    }
+
+    found.len()
+}
+
+/// Creates an index over `count` records, the position of record `i`
+/// being `positions[i * dimensions..(i + 1) * dimensions]` and its id
+/// `ids[i]`.
+///
+/// Returns a null pointer if any of the arrays is null while `count` is
+/// not 0, if `dimensions` is 0, or if `count * dimensions` overflows.
+///
+/// # Safety
+///
+/// `positions` must point to `count * dimensions` doubles, and `ids` to
+/// `count` ids.
+#[no_mangle]
+pub unsafe extern "C" fn sfc_index_new(
+    positions: *const f64,
+    ids: *const u64,
+    count: usize,
+    dimensions: usize,
+    cell_bits: usize,
+) -> *mut SfcIndex {
+    if dimensions == 0 || (count > 0 && (positions.is_null() || ids.is_null())) {
+        return ptr::null_mut();
+    }
+    let len = match count.checked_mul(dimensions) {
+        Some(len) => len,
+        None => return ptr::null_mut(),
+    };
+
+    let records = if count == 0 {
+        vec![]
+    } else {
+        let positions = slice::from_raw_parts(positions, len);
+        let ids = slice::from_raw_parts(ids, count);
+        positions
+            .chunks(dimensions)
+            .zip(ids)
+            .map(|(position, id)| Point {
+                position: position.to_vec(),
+                id: *id,
+            })
+            .collect::<Vec<_>>()
+    };
+
+    guard(ptr::null_mut(), || {
+        let index = Index::new(records.into_iter(), dimensions, cell_bits);
+        Box::into_raw(Box::new(SfcIndex { index, dimensions }))
+    })
+}
+
+/// Inserts a record into the index.
+///
+/// Returns 0 on success, and -1 if the record could not be inserted or
+/// a pointer is null.
+///
+/// # Safety
+///
+/// `index` must have been returned by `sfc_index_new`, and not freed,
+/// and `position` must point to one double per dimension.
+#[no_mangle]
+pub unsafe extern "C" fn sfc_index_insert(
+    index: *mut SfcIndex,
+    position: *const f64,
+    id: u64,
+) -> i32 {
+    let index = match index.as_mut() {
+        Some(index) => index,
+        None => return -1,
+    };
+    let position = match self::position(position, index.dimensions) {
+        Some(position) => position,
+        None => return -1,
+    };
+
+    let index = std::panic::AssertUnwindSafe(index);
+    guard(-1, move || {
+        match index.0.index.insert(Point { position, id }) {
+            Ok(_) => 0,
+            Err(e) => {
+                error!("sfc_index_insert: {}", e);
+                -1
+            }
+        }
+    })
+}
+
+/// Finds the records at `position`, writing at most `capacity` of their
+/// ids to `ids`, unless it is null.
+///
+/// Returns the number of records found, also when `ids` is null, and 0
+/// if `index` or `position` is null.
+///
+/// # Safety
+///
+/// `index` must have been returned by `sfc_index_new`, and not freed,
+/// `position` must point to one double per dimension, and `ids`, when
+/// not null, to `capacity` ids.
+#[no_mangle]
+pub unsafe extern "C" fn sfc_index_find(
+    index: *const SfcIndex,
+    position: *const f64,
+    ids: *mut u64,
+    capacity: usize,
+) -> usize {
+    let index = match index.as_ref() {
+        Some(index) => index,
+        None => return 0,
+    };
+    let position = match self::position(position, index.dimensions) {
+        Some(position) => position,
+        None => return 0,
+    };
+
+    let found = guard(vec![], || {
+        index
+            .index
+            .find(&position)
+            .into_iter()
+            .map(|id| (vec![], id))
+            .collect()
+    });
+
+    output(found, ids, ptr::null_mut(), capacity)
+}
+
+/// Finds the records within `[start, end]`, writing at most `capacity`
+/// of their ids to `ids`, and of their positions to `positions`, each
+/// unless it is null.
+///
+/// Returns the number of records found, also when the output arrays are
+/// null, and 0 if `index`, `start` or `end` is null.
+///
+/// # Safety
+///
+/// `index` must have been returned by `sfc_index_new`, and not freed,
+/// `start` and `end` must point to one double per dimension, and, when
+/// not null, `ids` to `capacity` ids and `positions` to
+/// `capacity * dimensions` doubles.
+#[no_mangle]
+pub unsafe extern "C" fn sfc_index_find_range(
+    index: *const SfcIndex,
+    start: *const f64,
+    end: *const f64,
+    ids: *mut u64,
+    positions: *mut f64,
+    capacity: usize,
+) -> usize {
+    let index = match index.as_ref() {
+        Some(index) => index,
+        None => return 0,
+    };
+    let (start, end) = match (
+        position(start, index.dimensions),
+        position(end, index.dimensions),
+    ) {
+        (Some(start), Some(end)) => (start, end),
+        _ => return 0,
+    };
+
+    let found = guard(vec![], || index.index.find_range(&start, &end));
+
+    output(found, ids, positions, capacity)
+}
+
+/// Releases an index.
+///
+/// # Safety
+///
+/// `index` must have been returned by `sfc_index_new`, or be null, and
+/// must not be used afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn sfc_index_free(index: *mut SfcIndex) {
+    if !index.is_null() {
+        drop(Box::from_raw(index));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn index() {
+        let positions = (0..200)
+            .flat_map(|i| vec![f64::from(i % 20) - 10.0, f64::from(i / 20) * 0.5])
+            .collect::<Vec<_>>();
+        let ids = (0..200).collect::<Vec<u64>>();
+
+        unsafe {
+            let index = sfc_index_new(positions.as_ptr(), ids.as_ptr(), 200, 2, 3);
+            assert!(!index.is_null());
+
+            assert_eq!(sfc_index_insert(index, [100.0, 100.0].as_ptr(), 1000), 0);
+            assert_eq!(sfc_index_insert(index, ptr::null(), 1001), -1);
+
+            let mut found = [0; 4];
+            assert_eq!(
+                sfc_index_find(index, [-9.0, 0.5].as_ptr(), found.as_mut_ptr(), 4),
+                1
+            );
+            assert_eq!(found[0], 21);
+            assert_eq!(
+                sfc_index_find(index, [100.0, 100.0].as_ptr(), found.as_mut_ptr(), 4),
+                1
+            );
+            assert_eq!(found[0], 1000);
+
+            // x in [-1, 1], y in [0, 1]: 3 x 3 records.
+            let (start, end) = ([-1.0, 0.0], [1.0, 1.0]);
+            let mut ids = [0; 16];
+            let mut found = [0.0; 32];
+            let count = sfc_index_find_range(
+                index,
+                start.as_ptr(),
+                end.as_ptr(),
+                ids.as_mut_ptr(),
+                found.as_mut_ptr(),
+                16,
+            );
+            assert_eq!(count, 9);
+            for (id, position) in ids[..count].iter().zip(found.chunks(2)) {
+                assert_eq!(position, &positions[*id as usize * 2..*id as usize * 2 + 2]);
+            }
+
+            // Only `capacity` records are written.
+            let mut ids = [u64::MAX; 4];
+            let count = sfc_index_find_range(
+                index,
+                start.as_ptr(),
+                end.as_ptr(),
+                ids.as_mut_ptr(),
+                ptr::null_mut(),
+                2,
+            );
+            assert_eq!(count, 9);
+            assert_eq!(ids[2..], [u64::MAX; 2]);
+
+            // Without output arrays, the records are only counted.
+            let count = sfc_index_find_range(
+                index,
+                start.as_ptr(),
+                end.as_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+                usize::MAX,
+            );
+            assert_eq!(count, 9);
+            assert_eq!(
+                sfc_index_find(index, [-9.0, 0.5].as_ptr(), ptr::null_mut(), 0),
+                1
+            );
+
+            sfc_index_free(index);
+            sfc_index_free(ptr::null_mut());
+        }
+    }
+
+    #[test]
+    fn invalid() {
+        unsafe {
+            assert!(sfc_index_new(ptr::null(), ptr::null(), 1, 2, 3).is_null());
+            assert!(sfc_index_new(ptr::null(), ptr::null(), 0, 0, 3).is_null());
+            let (positions, ids) = ([0.0; 2], [0; 1]);
+            assert!(sfc_index_new(positions.as_ptr(), ids.as_ptr(), usize::MAX, 2, 3).is_null());
+
+            let index = sfc_index_new(ptr::null(), ptr::null(), 0, 2, 3);
+            assert!(!index.is_null());
+            let mut ids = [0; 4];
+            assert_eq!(
+                sfc_index_find(index, [1.0, 1.0].as_ptr(), ids.as_mut_ptr(), 4),
+                0
+            );
+            assert_eq!(
+                sfc_index_find(ptr::null(), [1.0, 1.0].as_ptr(), ids.as_mut_ptr(), 4),
+                0
+            );
+            sfc_index_free(index);
+        }
+    }
+}
@@ -0,0 +1,53 @@
+//! Records shared by the tests of the modules.
+
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+
+#[cfg(feature = "geo")]
+use crate::geo::GeoRecord;
+#[cfg(feature = "geo")]
+use crate::sfc::SpaceFillingCurve;
+
+/// Record made of a position and a payload, an identifier by default.
+#[derive(Clone, Debug)]
+pub struct Point<F = u32> {
+    pub position: Vec<u16>,
+    pub id: F,
+}
+
+impl<F> Record<Vec<u16>> for Point<F> {
+    fn key(&self) -> Vec<u16> {
+        self.position.clone()
+    }
+}
+
+impl<F> RecordFields<F> for Point<F>
+where
+    F: Clone,
+{
+    fn fields(&self) -> F {
+        self.id.clone()
+    }
+}
+
+/// Returns `count` points spread over [0, 50) x [0, 40), whose payloads
+/// are their sequence numbers.
+pub fn points(count: u32) -> Vec<Point> {
+    (0..count)
+        .map(|id| Point {
+            position: vec![(id * 7 % 50) as u16, (id * 13 % 40) as u16],
+            id,
+        })
+        .collect()
+}
+
+/// Returns an index of the points of a 20 x 20 grid starting at
+/// (-10, -10), whose payloads are their sequence numbers.
+#[cfg(feature = "geo")]
+pub fn geo_index() -> SpaceFillingCurve<u32, geo_types::Point<i32>, i32> {
+    let records = (0..400)
+        .map(|id| GeoRecord::new((id as i32 % 20 - 10, id as i32 / 20 - 10), id))
+        .collect::<Vec<_>>();
+
+    SpaceFillingCurve::new(records.into_iter(), 2, 3)
+}
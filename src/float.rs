@@ -0,0 +1,367 @@
+//! Floating-point coordinates.
+
+use std::cmp::Ordering;
+use std::convert::TryInto;
+use std::fmt;
+use std::hash::Hash;
+use std::hash::Hasher;
+use std::str::FromStr;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Deserializer;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+#[cfg(feature = "serde")]
+use serde::Serializer;
+
+use crate::position::Position;
+
+/// Floating-point coordinate, totally ordered and hashable, created with
+/// `Float::from`.
+///
+/// `f32` and `f64` are neither `Ord` nor `Hash`, which the dictionaries
+/// of the index require. Keys made of floats, such as `Vec<f64>` or
+/// `[f32; 3]`, are instead indexed with `Float<f64>`, or `Float<f32>`, as
+/// the coordinate type.
+///
+/// `-0.0` and `0.0` are equal, and NaN, whatever its sign and payload,
+/// is equal to itself and greater than any other value, infinity
+/// included. Ranges whose bounds are not NaN thus never hold NaN.
+///
+/// ```rust
+/// # use ironsea_index::IndexedDestructured;
+/// # use ironsea_index_sfc_dbc::{Float, IndexOwned, Record, RecordFields};
+/// #[derive(Clone, Debug)]
+/// struct Sample {
+///     position: Vec<f64>,
+///     id: u32,
+/// }
+/// # impl Record<Vec<f64>> for Sample {
+/// #     fn key(&self) -> Vec<f64> { self.position.clone() }
+/// # }
+/// # impl RecordFields<u32> for Sample {
+/// #     fn fields(&self) -> u32 { self.id }
+/// # }
+///
+/// let samples = vec![
+///     Sample { position: vec![-0.5, 1.25], id: 0 },
+///     Sample { position: vec![2.0, 1e-3], id: 1 },
+/// ];
+/// let index = IndexOwned::<u32, Vec<f64>, Float<f64>>::new(samples.into_iter(), 2, 3);
+///
+/// assert_eq!(index.find_range(&vec![-1.0, 0.0], &vec![0.0, 2.0]).len(), 1);
+/// ```
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Float<T>(T);
+
+macro_rules! float {
+    ($t:ident) => {
+        impl Float<$t> {
+            /// Returns the wrapped value.
+            pub fn get(self) -> $t {
+                self.0
+            }
+        }
+
+        impl PartialEq for Float<$t> {
+            fn eq(&self, other: &Self) -> bool {
+                self.0.to_bits() == other.0.to_bits()
+            }
+        }
+
+        impl Eq for Float<$t> {}
+
+        impl PartialOrd for Float<$t> {
+            fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Float<$t> {
+            fn cmp(&self, other: &Self) -> Ordering {
+                self.0.total_cmp(&other.0)
+            }
+        }
+
+        impl Hash for Float<$t> {
+            fn hash<H: Hasher>(&self, state: &mut H) {
+                self.0.to_bits().hash(state);
+            }
+        }
+
+        impl From<$t> for Float<$t> {
+            fn from(value: $t) -> Self {
+                if value.is_nan() {
+                    Float($t::NAN)
+                } else if value == 0.0 {
+                    Float(0.0)
+                } else {
+                    Float(value)
+                }
+            }
+        }
+
+        impl From<Float<$t>> for $t {
+            fn from(value: Float<$t>) -> Self {
+                value.0
+            }
+        }
+
+        impl FromStr for Float<$t> {
+            type Err = std::num::ParseFloatError;
+
+            fn from_str(s: &str) -> Result<Self, Self::Err> {
+                s.parse::<$t>().map(Float::from)
+            }
+        }
+
+        impl fmt::Display for Float<$t> {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(f)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl Serialize for Float<$t> {
+            fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+            where
+                S: Serializer,
+            {
+                self.0.serialize(serializer)
+            }
+        }
+
+        #[cfg(feature = "serde")]
+        impl<'de> Deserialize<'de> for Float<$t> {
+            fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+            where
+                D: Deserializer<'de>,
+            {
+                $t::deserialize(deserializer).map(Float::from)
+            }
+        }
+
+        impl Position<Float<$t>> for Vec<$t> {
+            fn coordinate(&self, k: usize) -> Float<$t> {
+                Float::from(self[k])
+            }
+
+            fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+            where
+                I: IntoIterator<Item = Float<$t>>,
+            {
+                Ok(coordinates.into_iter().map($t::from).collect())
+            }
+
+            fn dimensions(&self) -> Option<usize> {
+                Some(self.len())
+            }
+        }
+
+        impl Position<Float<$t>> for Box<[$t]> {
+            fn coordinate(&self, k: usize) -> Float<$t> {
+                Float::from(self[k])
+            }
+
+            fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+            where
+                I: IntoIterator<Item = Float<$t>>,
+            {
+                Ok(coordinates.into_iter().map($t::from).collect())
+            }
+
+            fn dimensions(&self) -> Option<usize> {
+                Some(self.len())
+            }
+        }
+
+        impl<const N: usize> Position<Float<$t>> for [$t; N] {
+            fn coordinate(&self, k: usize) -> Float<$t> {
+                Float::from(self[k])
+            }
+
+            fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+            where
+                I: IntoIterator<Item = Float<$t>>,
+            {
+                let coordinates = coordinates.into_iter().map($t::from).collect::<Vec<_>>();
+                let found = coordinates.len();
+
+                coordinates
+                    .try_into()
+                    .map_err(|_| format!("Expected {} coordinates, got {}", N, found))
+            }
+
+            fn dimensions(&self) -> Option<usize> {
+                Some(N)
+            }
+        }
+
+        #[cfg(feature = "nalgebra")]
+        impl<const N: usize> Position<Float<$t>> for nalgebra::Point<$t, N> {
+            fn coordinate(&self, k: usize) -> Float<$t> {
+                Float::from(self[k])
+            }
+
+            fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+            where
+                I: IntoIterator<Item = Float<$t>>,
+            {
+                let coordinates = <[$t; N] as Position<Float<$t>>>::from_coordinates(coordinates)?;
+
+                Ok(coordinates.into())
+            }
+
+            fn dimensions(&self) -> Option<usize> {
+                Some(N)
+            }
+        }
+    };
+}
+
+float!(f32);
+float!(f64);
+
+impl From<Float<f32>> for f64 {
+    fn from(value: Float<f32>) -> Self {
+        value.0.into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::collections::HashSet;
+
+    use ironsea_index::IndexedDestructured;
+    use ironsea_index::Record;
+    use ironsea_index::RecordFields;
+
+    use crate::sfc::SpaceFillingCurve;
+
+    #[derive(Clone, Debug)]
+    struct Sample {
+        position: Vec<f64>,
+        id: u32,
+    }
+
+    impl Record<Vec<f64>> for Sample {
+        fn key(&self) -> Vec<f64> {
+            self.position.clone()
+        }
+    }
+
+    impl RecordFields<u32> for Sample {
+        fn fields(&self) -> u32 {
+            self.id
+        }
+    }
+
+    type Index = SpaceFillingCurve<u32, Vec<f64>, Float<f64>>;
+
+    #[test]
+    fn order() {
+        let values = [
+            f64::NEG_INFINITY,
+            -1.5,
+            -f64::MIN_POSITIVE,
+            0.0,
+            1e-300,
+            f64::INFINITY,
+            f64::NAN,
+        ];
+        for pair in values.windows(2) {
+            assert!(Float::from(pair[0]) < Float::from(pair[1]));
+        }
+
+        assert_eq!(Float::from(-0.0), Float::from(0.0));
+        assert_eq!(Float::from(f64::NAN), Float::from(-f64::NAN));
+        let set = [0.0, -0.0, f64::NAN, -f64::NAN]
+            .iter()
+            .map(|v| Float::from(*v))
+            .collect::<HashSet<_>>();
+        assert_eq!(set.len(), 2);
+
+        assert_eq!("-2.5".parse::<Float<f32>>(), Ok(Float::from(-2.5)));
+        assert_eq!(f64::from(Float::from(0.25f32)), 0.25);
+    }
+
+    #[test]
+    fn index() {
+        let samples = (0..400)
+            .map(|id| Sample {
+                position: vec![f64::from(id % 20) * 0.25 - 2.0, f64::from(id / 20) / 1024.0],
+                id,
+            })
+            .collect::<Vec<_>>();
+        let index = Index::new(samples.iter().cloned(), 2, 3);
+
+        assert_eq!(index.find(&vec![-1.75, 2.0 / 1024.0]), vec![&41]);
+        assert_eq!(index.find(&vec![-0.0, 0.0]), vec![&8]);
+
+        // x in [-0.5, 0.0], y in [1.5, 3] / 1024: 3 x 2 samples.
+        let mut found = index
+            .find_range(&vec![-0.5, 1.5 / 1024.0], &vec![0.0, 3.0 / 1024.0])
+            .into_iter()
+            .map(|(_, id)| *id)
+            .collect::<Vec<_>>();
+        found.sort_unstable();
+        assert_eq!(found, vec![46, 47, 48, 66, 67, 68]);
+
+        // The column at x = 0, and its two neighbours at y = 0.
+        assert_eq!(index.find_within_radius(&vec![0.0, 0.0], 0.25).len(), 22);
+
+        // NaN is stored past the largest values.
+        let mut index = index;
+        index
+            .insert(Sample {
+                position: vec![f64::NAN, 0.0],
+                id: 1000,
+            })
+            .unwrap();
+        assert_eq!(index.find(&vec![f64::NAN, 0.0]), vec![&1000]);
+        assert!(index
+            .find_range(&vec![f64::NEG_INFINITY; 2], &vec![f64::INFINITY; 2])
+            .iter()
+            .all(|(_, id)| **id != 1000));
+    }
+
+    #[test]
+    fn arrays() {
+        let keys = (0..50)
+            .map(|i| ([i as f32 / 8.0, -(i as f32)], i))
+            .collect::<Vec<_>>();
+        let index = SpaceFillingCurve::<u32, [f32; 2], Float<f32>>::new(
+            keys.iter().map(|(position, id)| Fixed {
+                position: *position,
+                id: *id,
+            }),
+            2,
+            3,
+        );
+
+        assert_eq!(index.find(&[1.0, -8.0]), vec![&8]);
+        assert_eq!(index.find_range(&[0.5, -10.0], &[1.0, 0.0]).len(), 5);
+    }
+
+    #[derive(Clone, Debug)]
+    struct Fixed {
+        position: [f32; 2],
+        id: u32,
+    }
+
+    impl Record<[f32; 2]> for Fixed {
+        fn key(&self) -> [f32; 2] {
+            self.position
+        }
+    }
+
+    impl RecordFields<u32> for Fixed {
+        fn fields(&self) -> u32 {
+            self.id
+        }
+    }
+}
@@ -0,0 +1,285 @@
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::collections::hash_map::DefaultHasher;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::hash::Hasher;
+
+use ironsea_index::IndexedDestructured;
+
+use super::dimension::Dimension;
+use super::error::SfcError;
+use super::position::Position;
+use super::query::QuerySpec;
+use super::region::Region;
+use super::sfc::SFCCode;
+use super::sfc::SFCOffset;
+use super::sfc::SpaceFillingCurve;
+
+// Bits of the bloom filter per record, and number of hash functions,
+// for a false positive rate around 1%.
+const BLOOM_BITS: usize = 10;
+const BLOOM_HASHES: u64 = 7;
+
+// Bloom filter over the code and offsets of the records.
+#[derive(Clone, Debug)]
+struct Bloom {
+    bits: Vec<u64>,
+}
+
+impl Bloom {
+    fn new(count: usize) -> Self {
+        Bloom {
+            bits: vec![0; (count * BLOOM_BITS) / 64 + 1],
+        }
+    }
+
+    // Double hashing, positions are h1 + i * h2.
+    fn positions(&self, code: SFCCode, offsets: &[SFCOffset]) -> impl Iterator<Item = usize> {
+        let mut hasher = DefaultHasher::new();
+        code.hash(&mut hasher);
+        offsets.hash(&mut hasher);
+        let h1 = hasher.finish();
+        h1.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+
+        let len = (self.bits.len() * 64) as u64;
+        (0..BLOOM_HASHES).map(move |i| (h1.wrapping_add(i.wrapping_mul(h2)) % len) as usize)
+    }
+
+    fn insert(&mut self, code: SFCCode, offsets: &[SFCOffset]) {
+        for bit in self.positions(code, offsets).collect::<Vec<_>>() {
+            self.bits[bit / 64] |= 1 << (bit % 64);
+        }
+    }
+
+    fn contains(&self, code: SFCCode, offsets: &[SFCOffset]) -> bool {
+        self.positions(code, offsets)
+            .all(|bit| self.bits[bit / 64] & (1 << (bit % 64)) != 0)
+    }
+}
+
+// Codes of the cells in Eytzinger order, the implicit layout of a
+// complete binary search tree, with the index of their cell. This keeps
+// the first levels of the search in a few cache lines.
+#[derive(Clone, Debug)]
+struct Eytzinger {
+    // Slot 0 is unused, the root is at 1 and the children of k are at
+    // 2k and 2k + 1.
+    tree: Vec<(SFCCode, usize)>,
+}
+
+impl Eytzinger {
+    fn new(codes: &[SFCCode]) -> Self {
+        let mut tree = vec![(0, 0); codes.len() + 1];
+        let mut next = 0;
+        Self::fill(codes, &mut tree, &mut next, 1);
+
+        Eytzinger { tree }
+    }
+
+    // In-order traversal of the tree, assigning the sorted codes.
+    fn fill(codes: &[SFCCode], tree: &mut [(SFCCode, usize)], next: &mut usize, k: usize) {
+        if k < tree.len() {
+            Self::fill(codes, tree, next, 2 * k);
+            tree[k] = (codes[*next], *next);
+            *next += 1;
+            Self::fill(codes, tree, next, 2 * k + 1);
+        }
+    }
+
+    // Index of the cell with the given code, if any.
+    fn search(&self, code: SFCCode) -> Option<usize> {
+        let mut k = 1;
+        while k < self.tree.len() {
+            k = 2 * k + (self.tree[k].0 < code) as usize;
+        }
+        // Go back up to the last node where the search went left, which
+        // holds the smallest code not lower than `code`.
+        k >>= k.trailing_ones() + 1;
+
+        match self.tree.get(k) {
+            Some((c, idx)) if k > 0 && *c == code => Some(*idx),
+            _ => None,
+        }
+    }
+}
+
+/// Read-only Space Filling Curve-based index.
+///
+/// Built by [`freeze`](struct.IndexOwned.html#method.freeze), it offers
+/// no way to modify the index, and keeps auxiliary structures to speed
+/// up lookups: a bloom filter, to reject most absent keys without
+/// searching for them, and a cache-friendly layout of the cell codes.
+///
+/// As it is never modified, it can be shared between threads as soon as
+/// the values and positions can.
+#[derive(Clone, Debug)]
+pub struct FrozenIndex<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Ord,
+{
+    index: SpaceFillingCurve<F, K, V>,
+    cells: Eytzinger,
+    bloom: Bloom,
+}
+
+impl<F, K, V> FrozenIndex<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    pub(crate) fn new(index: SpaceFillingCurve<F, K, V>) -> Self {
+        let cells = Eytzinger::new(&index.codes().collect::<Vec<_>>());
+
+        let entries = index.entries().collect::<Vec<_>>();
+        let mut bloom = Bloom::new(entries.len());
+        for (code, offsets) in entries {
+            bloom.insert(code, offsets);
+        }
+
+        FrozenIndex {
+            index,
+            cells,
+            bloom,
+        }
+    }
+
+    /// Returns the index, which can be modified again.
+    pub fn thaw(self) -> SpaceFillingCurve<F, K, V> {
+        self.index
+    }
+
+    /// See [`find_range_partial`](struct.IndexOwned.html#method.find_range_partial).
+    pub fn find_range_partial(&self, start: &[Option<V>], end: &[Option<V>]) -> Vec<(K, &F)> {
+        self.index.find_range_partial(start, end)
+    }
+
+    /// See [`find_within_radius`](struct.IndexOwned.html#method.find_within_radius).
+    pub fn find_within_radius(&self, center: &K, radius: f64) -> Vec<(K, &F)>
+    where
+        V: Into<f64>,
+    {
+        self.index.find_within_radius(center, radius)
+    }
+
+    /// See [`find_in_region`](struct.IndexOwned.html#method.find_in_region).
+    pub fn find_in_region<G>(&self, region: &G) -> Vec<(K, &F)>
+    where
+        G: Region<V>,
+    {
+        self.index.find_in_region(region)
+    }
+
+    /// See [`find_by_value`](struct.IndexOwned.html#method.find_by_value).
+    pub fn find_by_value(&self, value: &F) -> Vec<K> {
+        self.index.find_by_value(value)
+    }
+
+    /// See [`run_query`](struct.IndexOwned.html#method.run_query).
+    pub fn run_query(&self, spec: &QuerySpec<F, V>) -> Result<Vec<(K, &F)>, SfcError> {
+        self.index.run_query(spec)
+    }
+
+    /// See [`dimensions`](struct.IndexOwned.html#method.dimensions).
+    pub fn dimensions(&self) -> &[Dimension] {
+        self.index.dimensions()
+    }
+
+    /// See [`cell_bits`](struct.IndexOwned.html#method.cell_bits).
+    pub fn cell_bits(&self) -> &[usize] {
+        self.index.cell_bits()
+    }
+}
+
+impl<F, K, V> IndexedDestructured<F, K> for FrozenIndex<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    fn find(&self, key: &K) -> Vec<&F> {
+        let (code, offsets) = match self.index.entry(key) {
+            Err(_) => return vec![],
+            Ok(entry) => entry,
+        };
+
+        if !self.bloom.contains(code, &offsets) {
+            return vec![];
+        }
+
+        match self.cells.search(code) {
+            None => vec![],
+            Some(idx) => self.index.cell_fields(idx, &offsets),
+        }
+    }
+
+    fn find_range(&self, start: &K, end: &K) -> Vec<(K, &F)> {
+        self.index.find_range(start, end)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::points;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    fn is_sync<T: Sync>(_: &T) {}
+
+    #[test]
+    fn find() {
+        let points = points(500);
+        let mut index = Index::new(points[..400].iter().cloned(), 2, 4);
+        for point in &points[400..] {
+            index.insert_buffered(point.clone());
+        }
+
+        let frozen = index.freeze().unwrap();
+        is_sync(&frozen);
+
+        for point in &points {
+            assert!(frozen.find(&point.position).contains(&&point.id));
+        }
+        assert!(frozen.find(&vec![1, 1]).is_empty());
+        assert!(frozen.find(&vec![100, 1]).is_empty());
+
+        let index = frozen.thaw();
+        assert_eq!(index.buffered(), 0);
+    }
+
+    #[test]
+    fn eytzinger() {
+        for len in 0..40 {
+            let codes = (0..len).map(|c| 3 * c + 1).collect::<Vec<_>>();
+            let tree = Eytzinger::new(&codes);
+
+            for code in 0..(3 * len + 3) {
+                let expected = codes.binary_search(&code).ok();
+                assert_eq!(tree.search(code), expected, "{} in {:?}", code, codes);
+            }
+        }
+    }
+
+    #[test]
+    fn bloom() {
+        let mut bloom = Bloom::new(1000);
+        for code in 0..1000 {
+            bloom.insert(code, &[code as SFCOffset, 1]);
+        }
+
+        for code in 0..1000 {
+            assert!(bloom.contains(code, &[code as SFCOffset, 1]));
+        }
+
+        let false_positives = (1000..11000)
+            .filter(|code| bloom.contains(*code, &[*code as SFCOffset, 1]))
+            .count();
+        assert!(false_positives < 300);
+    }
+}
@@ -0,0 +1,300 @@
+//! Adapters for the `geo-types` crate: points as keys, and rectangles and
+//! polygons as query regions.
+//!
+//! Both dimensions share the coordinate type `T`, which has to be
+//! ordered and hashable to be indexed, for example `i32` or `i64`. Points
+//! and regions of `f64` are indexed with `Float<f64>` coordinates.
+
+use geo_types::Coord;
+use geo_types::CoordNum;
+use geo_types::LineString;
+use geo_types::MultiPolygon;
+use geo_types::Point;
+use geo_types::Polygon;
+use geo_types::Rect;
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+
+use crate::float::Float;
+use crate::position::Position;
+use crate::region::Region;
+
+/// Record made of a point and its values, to index geometries without
+/// a record type of their own.
+#[derive(Clone, Debug, PartialEq)]
+pub struct GeoRecord<T: CoordNum, F> {
+    /// Position of the record.
+    pub point: Point<T>,
+    /// Values of the record.
+    pub fields: F,
+}
+
+impl<T: CoordNum, F> GeoRecord<T, F> {
+    /// Creates a record at `point`, a `Point`, a `Coord` or an `(x, y)`
+    /// tuple.
+    pub fn new<P: Into<Point<T>>>(point: P, fields: F) -> Self {
+        GeoRecord {
+            point: point.into(),
+            fields,
+        }
+    }
+}
+
+impl<T: CoordNum, F> Record<Point<T>> for GeoRecord<T, F> {
+    fn key(&self) -> Point<T> {
+        self.point
+    }
+}
+
+impl<T: CoordNum, F: Clone> RecordFields<F> for GeoRecord<T, F> {
+    fn fields(&self) -> F {
+        self.fields.clone()
+    }
+}
+
+impl<T: CoordNum> Position<T> for Point<T> {
+    fn coordinate(&self, k: usize) -> T {
+        match k {
+            0 => self.x(),
+            1 => self.y(),
+            _ => panic!("Incorrect dimension {} for {:?}", k, self),
+        }
+    }
+
+    fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut coordinates = coordinates.into_iter();
+        match (coordinates.next(), coordinates.next(), coordinates.next()) {
+            (Some(x), Some(y), None) => Ok(Point::new(x, y)),
+            _ => Err("Expected two coordinates, x and y".to_string()),
+        }
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+impl Position<Float<f64>> for Point<f64> {
+    fn coordinate(&self, k: usize) -> Float<f64> {
+        Float::from(Position::<f64>::coordinate(self, k))
+    }
+
+    fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = Float<f64>>,
+    {
+        Point::from_coordinates(coordinates.into_iter().map(f64::from))
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        Some(2)
+    }
+}
+
+impl<T: CoordNum> Region<T> for Rect<T> {
+    fn contains(&self, point: &[&T]) -> bool {
+        let (min, max) = (self.min(), self.max());
+
+        min.x <= *point[0] && *point[0] <= max.x && min.y <= *point[1] && *point[1] <= max.y
+    }
+
+    fn intersects_box(&self, min: &[&T], max: &[&T]) -> bool {
+        let (start, end) = (self.min(), self.max());
+
+        start.x <= *max[0] && *min[0] <= end.x && start.y <= *max[1] && *min[1] <= end.y
+    }
+}
+
+// Whether (x, y) is on the segment [a, b].
+fn on_segment(a: (f64, f64), b: (f64, f64), (x, y): (f64, f64)) -> bool {
+    let cross = (b.0 - a.0) * (y - a.1) - (b.1 - a.1) * (x - a.0);
+
+    cross == 0.0 && a.0.min(b.0) <= x && x <= a.0.max(b.0) && a.1.min(b.1) <= y && y <= a.1.max(b.1)
+}
+
+// Whether (x, y) is within the closed ring, with its boundary, or on its
+// boundary only.
+fn ring_contains<T: CoordNum>(ring: &LineString<T>, point: (f64, f64)) -> (bool, bool) {
+    let vertices = ring
+        .0
+        .iter()
+        .map(|c| {
+            (
+                c.x.to_f64().unwrap_or(f64::NAN),
+                c.y.to_f64().unwrap_or(f64::NAN),
+            )
+        })
+        .collect::<Vec<_>>();
+
+    let (x, y) = point;
+    let mut inside = false;
+    for (a, b) in vertices.iter().zip(vertices.iter().skip(1)) {
+        if on_segment(*a, *b, point) {
+            return (true, true);
+        }
+        if (a.1 > y) != (b.1 > y) && x < a.0 + (y - a.1) * (b.0 - a.0) / (b.1 - a.1) {
+            inside = !inside;
+        }
+    }
+
+    (inside, false)
+}
+
+impl<T: CoordNum> Region<T> for Polygon<T> {
+    /// Points on the boundary of the polygon, holes included, are within
+    /// the region.
+    fn contains(&self, point: &[&T]) -> bool {
+        let point = match (point[0].to_f64(), point[1].to_f64()) {
+            (Some(x), Some(y)) => (x, y),
+            _ => return false,
+        };
+
+        ring_contains(self.exterior(), point).0
+            && self.interiors().iter().all(|hole| {
+                let (inside, boundary) = ring_contains(hole, point);
+                !inside || boundary
+            })
+    }
+
+    /// Compares the bounding box of the polygon with the box.
+    fn intersects_box(&self, min: &[&T], max: &[&T]) -> bool {
+        let mut coords = self.exterior().0.iter();
+        let first = match coords.next() {
+            Some(first) => *first,
+            None => return false,
+        };
+        let (start, end) = coords.fold((first, first), |(start, end), c| {
+            let lower = |a: T, b: T| if b < a { b } else { a };
+            let upper = |a: T, b: T| if b > a { b } else { a };
+            (
+                Coord {
+                    x: lower(start.x, c.x),
+                    y: lower(start.y, c.y),
+                },
+                Coord {
+                    x: upper(end.x, c.x),
+                    y: upper(end.y, c.y),
+                },
+            )
+        });
+
+        Rect::new(start, end).intersects_box(min, max)
+    }
+}
+
+impl<T: CoordNum> Region<T> for MultiPolygon<T> {
+    fn contains(&self, point: &[&T]) -> bool {
+        self.0.iter().any(|polygon| polygon.contains(point))
+    }
+
+    fn intersects_box(&self, min: &[&T], max: &[&T]) -> bool {
+        self.0
+            .iter()
+            .any(|polygon| polygon.intersects_box(min, max))
+    }
+}
+
+// Regions of floats, for `Float` coordinates.
+macro_rules! float_region {
+    ($($region:ident),+) => {
+        $(
+            impl Region<Float<f64>> for $region<f64> {
+                fn contains(&self, point: &[&Float<f64>]) -> bool {
+                    let point = [point[0].get(), point[1].get()];
+
+                    Region::<f64>::contains(self, &[&point[0], &point[1]])
+                }
+
+                fn intersects_box(&self, min: &[&Float<f64>], max: &[&Float<f64>]) -> bool {
+                    let (min, max) = ([min[0].get(), min[1].get()], [max[0].get(), max[1].get()]);
+
+                    Region::<f64>::intersects_box(self, &[&min[0], &min[1]], &[&max[0], &max[1]])
+                }
+            }
+        )+
+    };
+}
+
+float_region!(Rect, Polygon, MultiPolygon);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use geo_types::polygon;
+    use ironsea_index::IndexedDestructured;
+
+    use crate::fixtures::geo_index;
+    use crate::sfc::SpaceFillingCurve;
+
+    #[test]
+    fn find() {
+        let index = geo_index();
+        assert_eq!(index.find(&Point::new(-10, -10)), vec![&0]);
+        assert_eq!(index.find_range(&(0, 0).into(), &(2, 1).into()).len(), 6);
+
+        let record = GeoRecord::new(Coord { x: 3, y: 4 }, 0);
+        assert_eq!(record.key(), Point::new(3, 4));
+        assert_eq!(Point::from_coordinates(vec![3, 4]), Ok(Point::new(3, 4)));
+        assert!(Point::<i32>::from_coordinates(vec![3]).is_err());
+    }
+
+    #[test]
+    fn rect() {
+        let index = geo_index();
+        let rect = Rect::new(Coord { x: 3, y: -2 }, Coord { x: -1, y: 5 });
+
+        let found = index.find_in_region(&rect);
+        assert_eq!(found.len(), 5 * 8);
+        assert!(found
+            .iter()
+            .all(|(k, _)| (-1..=3).contains(&k.x()) && (-2..=5).contains(&k.y())));
+    }
+
+    #[test]
+    fn floats() {
+        let records = (0..400)
+            .map(|id| GeoRecord::new((f64::from(id % 20) / 4.0, f64::from(id / 20) - 10.0), id))
+            .collect::<Vec<_>>();
+        let index =
+            SpaceFillingCurve::<u32, Point<f64>, Float<f64>>::new(records.into_iter(), 2, 3);
+
+        assert_eq!(index.find(&Point::new(0.25, -10.0)), vec![&1]);
+        let rect = Rect::new(Coord { x: 0.6, y: -0.5 }, Coord { x: 1.0, y: 1.0 });
+        assert_eq!(index.find_in_region(&rect).len(), 2 * 2);
+        let triangle = polygon![(x: 0.0, y: 0.0), (x: 1.0, y: 0.0), (x: 0.0, y: 1.0)];
+        assert_eq!(index.find_in_region(&triangle).len(), 5 + 1);
+    }
+
+    #[test]
+    fn polygon() {
+        let index = geo_index();
+
+        // Triangle (0, 0), (6, 0), (0, 6), with a triangular hole holding
+        // (2, 2), the boundary of the hole being part of the polygon.
+        let triangle = polygon![
+            exterior: [(x: 0, y: 0), (x: 6, y: 0), (x: 0, y: 6), (x: 0, y: 0)],
+            interiors: [[(x: 1, y: 1), (x: 1, y: 4), (x: 4, y: 1), (x: 1, y: 1)]],
+        ];
+
+        let mut found = index
+            .find_in_region(&triangle)
+            .into_iter()
+            .map(|(k, _)| k.x_y())
+            .collect::<Vec<_>>();
+        found.sort_unstable();
+
+        let mut expected = vec![];
+        for x in 0..=6 {
+            for y in 0..=6 - x {
+                if (x, y) != (2, 2) {
+                    expected.push((x, y));
+                }
+            }
+        }
+        assert_eq!(found, expected);
+    }
+}
@@ -0,0 +1,258 @@
+//! Query regions from GeoJSON geometries, as received by web APIs.
+//!
+//! Polygons, multi-polygons and bounding boxes are supported. Their first
+//! coordinate, the longitude or x, is compared with the first dimension
+//! of the index, and their second one with the second dimension.
+
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+use geo_types::Coord;
+use geo_types::CoordNum;
+use geo_types::MultiPolygon;
+use geo_types::Polygon;
+use geo_types::Rect;
+use geojson::GeoJson;
+use geojson::Geometry;
+use geojson::Value;
+
+use crate::error::SfcError;
+use crate::float::Float;
+use crate::region::Region;
+
+/// Query region described by a GeoJSON geometry or bounding box.
+///
+/// Boundaries are part of the region, as for the regions of `geo-types`.
+#[derive(Clone, Debug, PartialEq)]
+pub enum GeoJsonRegion {
+    /// Bounding box.
+    BBox(Rect<f64>),
+    /// Polygon or multi-polygon.
+    Polygons(MultiPolygon<f64>),
+}
+
+fn invalid<E: ToString>(e: E) -> SfcError {
+    SfcError::InvalidFormat(e.to_string())
+}
+
+// Coordinates of a point, as floats.
+fn to_f64<T: CoordNum>(point: &[&T]) -> Option<[f64; 2]> {
+    Some([point[0].to_f64()?, point[1].to_f64()?])
+}
+
+impl GeoJsonRegion {
+    /// Creates a region from a GeoJSON bounding box: `[west, south, east,
+    /// north]`, that is minimum x and y, then maximum x and y.
+    pub fn from_bbox(bbox: &[f64]) -> Result<Self, SfcError> {
+        match bbox {
+            [min_x, min_y, max_x, max_y] => Ok(GeoJsonRegion::BBox(Rect::new(
+                Coord {
+                    x: *min_x,
+                    y: *min_y,
+                },
+                Coord {
+                    x: *max_x,
+                    y: *max_y,
+                },
+            ))),
+            _ => Err(invalid(format!(
+                "Expected a two-dimensional bounding box, got {:?}",
+                bbox
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&Geometry> for GeoJsonRegion {
+    type Error = SfcError;
+
+    fn try_from(geometry: &Geometry) -> Result<Self, SfcError> {
+        match &geometry.value {
+            value @ Value::Polygon(_) => Ok(GeoJsonRegion::Polygons(MultiPolygon(vec![
+                Polygon::try_from(value).map_err(invalid)?,
+            ]))),
+            value @ Value::MultiPolygon(_) => Ok(GeoJsonRegion::Polygons(
+                MultiPolygon::try_from(value).map_err(invalid)?,
+            )),
+            value => Err(invalid(format!(
+                "Unsupported geometry {}, expected a Polygon or a MultiPolygon",
+                value.type_name()
+            ))),
+        }
+    }
+}
+
+impl TryFrom<&GeoJson> for GeoJsonRegion {
+    type Error = SfcError;
+
+    /// Uses the geometry of a `Geometry` or a `Feature`, or the bounding
+    /// box of a `Feature` without geometry.
+    fn try_from(geojson: &GeoJson) -> Result<Self, SfcError> {
+        match geojson {
+            GeoJson::Geometry(geometry) => Self::try_from(geometry),
+            GeoJson::Feature(feature) => match (&feature.geometry, &feature.bbox) {
+                (Some(geometry), _) => Self::try_from(geometry),
+                (None, Some(bbox)) => Self::from_bbox(bbox),
+                (None, None) => Err(invalid("Feature without geometry nor bounding box")),
+            },
+            GeoJson::FeatureCollection(_) => Err(invalid(
+                "Expected a Geometry or a Feature, got a FeatureCollection",
+            )),
+        }
+    }
+}
+
+impl FromStr for GeoJsonRegion {
+    type Err = SfcError;
+
+    /// Parses a GeoJSON `Geometry` or `Feature`, see
+    /// [`try_from`](#method.try_from-1).
+    fn from_str(s: &str) -> Result<Self, SfcError> {
+        Self::try_from(&s.parse::<GeoJson>().map_err(invalid)?)
+    }
+}
+
+impl<T: CoordNum> Region<T> for GeoJsonRegion {
+    fn contains(&self, point: &[&T]) -> bool {
+        let [x, y] = match to_f64(point) {
+            Some(point) => point,
+            None => return false,
+        };
+
+        match self {
+            GeoJsonRegion::BBox(rect) => rect.contains(&[&x, &y]),
+            GeoJsonRegion::Polygons(polygons) => polygons.contains(&[&x, &y]),
+        }
+    }
+
+    fn intersects_box(&self, min: &[&T], max: &[&T]) -> bool {
+        let (min, max) = match (to_f64(min), to_f64(max)) {
+            (Some(min), Some(max)) => (min, max),
+            _ => return false,
+        };
+        let (min, max) = ([&min[0], &min[1]], [&max[0], &max[1]]);
+
+        match self {
+            GeoJsonRegion::BBox(rect) => rect.intersects_box(&min, &max),
+            GeoJsonRegion::Polygons(polygons) => polygons.intersects_box(&min, &max),
+        }
+    }
+}
+
+impl Region<Float<f64>> for GeoJsonRegion {
+    fn contains(&self, point: &[&Float<f64>]) -> bool {
+        let point = [point[0].get(), point[1].get()];
+
+        Region::<f64>::contains(self, &[&point[0], &point[1]])
+    }
+
+    fn intersects_box(&self, min: &[&Float<f64>], max: &[&Float<f64>]) -> bool {
+        let (min, max) = ([min[0].get(), min[1].get()], [max[0].get(), max[1].get()]);
+
+        Region::<f64>::intersects_box(self, &[&min[0], &min[1]], &[&max[0], &max[1]])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use geo_types::Point;
+
+    use crate::fixtures::geo_index;
+    use crate::geo::GeoRecord;
+    use crate::sfc::SpaceFillingCurve;
+
+    type Index = SpaceFillingCurve<u32, Point<i32>, i32>;
+
+    fn found(index: &Index, region: &GeoJsonRegion) -> Vec<(i32, i32)> {
+        let mut found = index
+            .find_in_region(region)
+            .into_iter()
+            .map(|(k, _)| k.x_y())
+            .collect::<Vec<_>>();
+        found.sort_unstable();
+        found
+    }
+
+    #[test]
+    fn polygons() {
+        let index = geo_index();
+
+        let triangle: GeoJsonRegion = r#"{
+            "type": "Polygon",
+            "coordinates": [[[0, 0], [2, 0], [0, 2], [0, 0]]]
+        }"#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            found(&index, &triangle),
+            vec![(0, 0), (0, 1), (0, 2), (1, 0), (1, 1), (2, 0)]
+        );
+
+        let squares: GeoJsonRegion = r#"{
+            "type": "Feature",
+            "properties": {},
+            "geometry": {
+                "type": "MultiPolygon",
+                "coordinates": [
+                    [[[-10, -10], [-9, -10], [-9, -9], [-10, -9], [-10, -10]]],
+                    [[[5.5, 5.5], [6.5, 5.5], [6.5, 6.5], [5.5, 6.5], [5.5, 5.5]]]
+                ]
+            }
+        }"#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            found(&index, &squares),
+            vec![(-10, -10), (-10, -9), (-9, -10), (-9, -9), (6, 6)]
+        );
+    }
+
+    #[test]
+    fn bbox() {
+        let index = geo_index();
+
+        let region: GeoJsonRegion = r#"{
+            "type": "Feature",
+            "properties": null,
+            "geometry": null,
+            "bbox": [-1.5, 2, 1, 3]
+        }"#
+        .parse()
+        .unwrap();
+        assert_eq!(
+            found(&index, &region),
+            vec![(-1, 2), (-1, 3), (0, 2), (0, 3), (1, 2), (1, 3)]
+        );
+
+        assert!(GeoJsonRegion::from_bbox(&[0.0, 0.0, 0.0, 1.0, 1.0, 1.0]).is_err());
+    }
+
+    #[test]
+    fn floats() {
+        let records = (0..100)
+            .map(|id| GeoRecord::new((f64::from(id % 10) / 2.0, f64::from(id / 10) / 2.0), id))
+            .collect::<Vec<_>>();
+        let index =
+            SpaceFillingCurve::<u32, Point<f64>, Float<f64>>::new(records.into_iter(), 2, 3);
+
+        let region = GeoJsonRegion::from_bbox(&[0.5, 0.25, 1.5, 1.0]).unwrap();
+        assert_eq!(index.find_in_region(&region).len(), 3 * 2);
+    }
+
+    #[test]
+    fn unsupported() {
+        for geojson in &[
+            r#"{ "type": "Point", "coordinates": [1, 2] }"#,
+            r#"{ "type": "FeatureCollection", "features": [] }"#,
+            r#"{ "type": "Feature", "properties": null, "geometry": null }"#,
+            "[0, 0, 1, 1]",
+        ] {
+            assert!(matches!(
+                geojson.parse::<GeoJsonRegion>(),
+                Err(SfcError::InvalidFormat(_))
+            ));
+        }
+    }
+}
@@ -1,4 +1,5 @@
-#![forbid(unsafe_code)]
+#![cfg_attr(not(feature = "ffi"), forbid(unsafe_code))]
+#![cfg_attr(feature = "ffi", deny(unsafe_code))]
 #![deny(missing_docs)]
 
 //! # Iron Sea - Index SFC DBC
@@ -28,13 +29,123 @@
 #[macro_use]
 extern crate log;
 
-#[macro_use]
-extern crate arrayref;
+// Timing the queries relies on `Instant`, which panics in the browser.
+#[cfg(all(feature = "recording", target_arch = "wasm32", target_os = "unknown"))]
+compile_error!("The `recording` feature is not supported on wasm32-unknown-unknown");
 
+mod advice;
+#[cfg(feature = "arrow")]
+mod arrow;
+mod build;
+mod builder;
 mod cell_space;
+#[cfg(feature = "serde")]
+mod chunked;
+#[cfg(feature = "datagen")]
+pub mod datagen;
+mod dimension;
+mod error;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+#[cfg(test)]
+mod fixtures;
+mod float;
+mod frozen;
+#[cfg(feature = "geo")]
+mod geo;
+#[cfg(feature = "geojson")]
+mod geojson;
+#[cfg(feature = "csv")]
+pub mod loaders;
+#[cfg(feature = "serde")]
+mod mapped;
+mod metric;
 mod morton;
+mod multi;
+mod overlay;
+#[cfg(feature = "parallel")]
+mod parallel;
+#[cfg(feature = "serde")]
+mod persist;
+mod position;
+mod quantizer;
+mod query;
+#[cfg(feature = "radix")]
+mod radix;
+mod record_id;
+#[cfg(feature = "recording")]
+pub mod recording;
+mod region;
 mod sfc;
+#[cfg(feature = "parallel")]
+mod sharded;
+#[cfg(feature = "serde")]
+mod spill;
+mod stats;
+#[cfg(feature = "async")]
+mod stream;
+mod violation;
+mod visit;
 
+pub use advice::Advice;
+pub use build::BuildPolicy;
+pub use build::BuildReport;
+pub use build::CellSizing;
+pub use build::CompactionReport;
+pub use build::Normalized;
+pub use build::Rejection;
+pub use builder::SpaceFillingCurveBuilder;
+pub use cell_space::CellSpace;
+pub use dimension::Dimension;
+pub use error::SfcError;
+pub use float::Float;
+pub use frozen::FrozenIndex;
+#[cfg(feature = "geo")]
+pub use geo::GeoRecord;
+#[cfg(feature = "geojson")]
+pub use geojson::GeoJsonRegion;
+#[cfg(feature = "serde")]
+pub use mapped::MappedIndex;
+pub use metric::Chebyshev;
+pub use metric::Euclidean;
+pub use metric::Manhattan;
+pub use metric::Metric;
+pub use morton::MortonCode;
+pub use morton::MortonEncoder;
+pub use morton::MortonValue;
+pub use multi::MultiKeyIndex;
+pub use multi::MultiRecord;
+pub use overlay::OverlayView;
+#[cfg(feature = "serde")]
+pub use persist::Compression;
+#[cfg(feature = "serde")]
+pub use persist::FORMAT_VERSION;
+pub use position::Coordinate2;
+pub use position::Coordinate3;
+pub use position::Coordinate4;
+pub use position::Position;
+pub use position::RecordSlice;
+pub use quantizer::Breakpoints;
+pub use quantizer::Quantizer;
+pub use quantizer::Uniform;
+pub use query::Cursor;
+pub use query::Order;
+pub use query::QuerySpec;
+pub use query::RangeExplain;
+pub use record_id::RecordId;
+pub use region::Region;
 pub use sfc::Record;
 pub use sfc::RecordFields;
 pub use sfc::SpaceFillingCurve as IndexOwned;
+#[cfg(feature = "parallel")]
+pub use sharded::ShardedSfc;
+pub use stats::CellSummary;
+pub use stats::IndexStats;
+pub use stats::MemoryBreakdown;
+#[cfg(feature = "async")]
+pub use stream::RangeStream;
+#[cfg(feature = "async")]
+pub use stream::STREAM_CAPACITY;
+pub use violation::Violation;
+pub use visit::CellRecords;
+pub use visit::CellVisit;
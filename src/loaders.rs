@@ -0,0 +1,4 @@
+//! Construction of an index from common file formats.
+
+#[cfg(feature = "csv")]
+pub mod csv;
@@ -0,0 +1,302 @@
+//! Construction of an index from CSV, or TSV, files.
+//!
+//! Each row is a record: some columns hold its coordinates, and the
+//! values are parsed from the other ones. Rows are numbered from 0,
+//! starting with the first row after the headers, in the
+//! [`BuildReport`](../../struct.BuildReport.html) of the construction.
+
+use std::fmt::Debug;
+use std::fmt::Display;
+use std::hash::Hash;
+use std::io::Read;
+use std::str::FromStr;
+
+use csv::ReaderBuilder;
+pub use csv::StringRecord;
+use csv::Trim;
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+
+use crate::build::BuildPolicy;
+use crate::build::BuildReport;
+use crate::build::Rejection;
+use crate::error::SfcError;
+use crate::position::Position;
+use crate::sfc::SpaceFillingCurve;
+
+#[derive(Clone, Debug)]
+struct Row<K, F> {
+    position: K,
+    fields: F,
+}
+
+impl<K: Clone, F> Record<K> for Row<K, F> {
+    fn key(&self) -> K {
+        self.position.clone()
+    }
+}
+
+impl<K, F: Clone> RecordFields<F> for Row<K, F> {
+    fn fields(&self) -> F {
+        self.fields.clone()
+    }
+}
+
+/// Parses the value of `column` in `record`.
+pub fn column<T>(record: &StringRecord, column: usize) -> Result<T, String>
+where
+    T: FromStr,
+    T::Err: Display,
+{
+    let value = record
+        .get(column)
+        .ok_or_else(|| format!("Missing column {}", column))?;
+
+    value
+        .parse()
+        .map_err(|e| format!("Column {}, {:?}: {}", column, value, e))
+}
+
+/// Loader of an index from a CSV file.
+///
+/// By default, fields are separated by commas, the first row holds the
+/// headers, and rows which cannot be parsed or indexed are reported,
+/// following [`BuildPolicy::CollectAndReport`](../../enum.BuildPolicy.html).
+#[derive(Clone, Debug)]
+pub struct CsvLoader {
+    coordinates: Vec<usize>,
+    delimiter: u8,
+    headers: bool,
+    trim: bool,
+    comment: Option<u8>,
+    policy: BuildPolicy,
+}
+
+impl CsvLoader {
+    /// Creates a loader reading the coordinates of the records from the
+    /// `coordinates` columns, in dimension order. Columns are numbered
+    /// from 0.
+    pub fn new(coordinates: &[usize]) -> Self {
+        CsvLoader {
+            coordinates: coordinates.to_vec(),
+            delimiter: b',',
+            headers: true,
+            trim: false,
+            comment: None,
+            policy: BuildPolicy::CollectAndReport,
+        }
+    }
+
+    /// Sets the separator of the fields, for example `b'\t'` for TSV
+    /// files.
+    pub fn delimiter(mut self, delimiter: u8) -> Self {
+        self.delimiter = delimiter;
+        self
+    }
+
+    /// Sets whether the first row holds headers, and is skipped.
+    pub fn has_headers(mut self, headers: bool) -> Self {
+        self.headers = headers;
+        self
+    }
+
+    /// Sets whether whitespace around the fields is removed.
+    pub fn trim(mut self, trim: bool) -> Self {
+        self.trim = trim;
+        self
+    }
+
+    /// Sets the character starting comment lines, which are skipped.
+    pub fn comment(mut self, comment: Option<u8>) -> Self {
+        self.comment = comment;
+        self
+    }
+
+    /// Sets what to do with the rows which cannot be parsed or indexed.
+    pub fn policy(mut self, policy: BuildPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    /// Builds an index from the rows of `reader`, parsing the values of
+    /// the records from the `fields` column.
+    ///
+    /// See [`new`](../../struct.IndexOwned.html#method.new) for
+    /// `cell_bits`. Returns the index, as well as a report of the
+    /// processed rows.
+    pub fn load<F, K, V, R>(
+        &self,
+        reader: R,
+        fields: usize,
+        cell_bits: usize,
+    ) -> Result<(SpaceFillingCurve<F, K, V>, BuildReport), SfcError>
+    where
+        F: Clone + Debug + PartialEq + FromStr,
+        F::Err: Display,
+        K: Clone + Position<V>,
+        V: Clone + Debug + Hash + Ord + FromStr,
+        V::Err: Display,
+        R: Read,
+    {
+        self.load_with(reader, |record| column(record, fields), cell_bits)
+    }
+
+    /// Builds an index from the rows of `reader`, the values of the
+    /// records being computed by `fields` from their rows.
+    ///
+    /// Rows for which `fields` returns an error are handled as the rows
+    /// which cannot be parsed. See [`load`](#method.load) for the other
+    /// parameters.
+    pub fn load_with<F, K, V, R, P>(
+        &self,
+        reader: R,
+        mut fields: P,
+        cell_bits: usize,
+    ) -> Result<(SpaceFillingCurve<F, K, V>, BuildReport), SfcError>
+    where
+        F: Clone + Debug + PartialEq,
+        K: Clone + Position<V>,
+        V: Clone + Debug + Hash + Ord + FromStr,
+        V::Err: Display,
+        R: Read,
+        P: FnMut(&StringRecord) -> Result<F, String>,
+    {
+        let mut reader = ReaderBuilder::new()
+            .delimiter(self.delimiter)
+            .has_headers(self.headers)
+            .trim(if self.trim { Trim::All } else { Trim::None })
+            .comment(self.comment)
+            // Rows of the wrong length are reported as missing columns.
+            .flexible(true)
+            .from_reader(reader);
+
+        let mut count = 0;
+        let mut rejected = BuildReport::default();
+        let mut rows = vec![];
+        for (row, record) in reader.records().enumerate() {
+            count += 1;
+            let parsed = match record {
+                Ok(record) => self.parse(&record, &mut fields),
+                Err(e) if e.is_io_error() => return Err(SfcError::Io(e.to_string())),
+                Err(e) => Err(e.to_string()),
+            };
+            match parsed {
+                Ok(parsed) => rows.push((row, parsed)),
+                Err(e) => self
+                    .policy
+                    .reject(&mut rejected, row, &row, Rejection::Parse(e))?,
+            }
+        }
+
+        let (index, mut report) = SpaceFillingCurve::new_with_policy(
+            rows.iter().map(|(_, parsed)| parsed.clone()),
+            self.coordinates.len(),
+            cell_bits,
+            self.policy,
+        )?;
+
+        // Number the records by their rows.
+        report.records = count;
+        for (source, _) in report.rejected.iter_mut() {
+            *source = rows[*source].0;
+        }
+        for (first, duplicate) in report.duplicate_samples.iter_mut() {
+            *first = rows[*first].0;
+            *duplicate = rows[*duplicate].0;
+        }
+        report.rejected.extend(rejected.rejected);
+        report.rejected.sort_by_key(|(row, _)| *row);
+
+        Ok((index, report))
+    }
+
+    // Build the record of a row.
+    fn parse<F, K, V, P>(&self, record: &StringRecord, fields: &mut P) -> Result<Row<K, F>, String>
+    where
+        K: Position<V>,
+        V: FromStr,
+        V::Err: Display,
+        P: FnMut(&StringRecord) -> Result<F, String>,
+    {
+        let coordinates = self
+            .coordinates
+            .iter()
+            .map(|c| column(record, *c))
+            .collect::<Result<Vec<V>, _>>()?;
+
+        Ok(Row {
+            position: K::from_coordinates(coordinates)?,
+            fields: fields(record)?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ironsea_index::IndexedDestructured;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    const POINTS: &str = "\
+id,x,y,name
+0,1,2,first
+1,3,4,second
+2,x,4,third
+3,5
+4,3,4,fifth
+5,70000,1,sixth
+";
+
+    #[test]
+    fn load() {
+        let (index, report): (Index, _) = CsvLoader::new(&[1, 2])
+            .load(POINTS.as_bytes(), 0, 3)
+            .unwrap();
+
+        assert_eq!(index.find(&vec![1, 2]), vec![&0]);
+        assert_eq!(index.find_range(&vec![0, 0], &vec![10, 10]).len(), 3);
+        assert_eq!(report.records, 6);
+        assert_eq!(report.indexed, 3);
+        assert_eq!(
+            report
+                .rejected
+                .iter()
+                .map(|(row, _)| *row)
+                .collect::<Vec<_>>(),
+            vec![2, 3, 5]
+        );
+        assert!(matches!(report.rejected[1].1, Rejection::Parse(_)));
+        assert_eq!(report.near_duplicates, 1);
+        assert_eq!(report.duplicate_samples, vec![(1, 4)]);
+
+        assert!(matches!(
+            CsvLoader::new(&[1, 2])
+                .policy(BuildPolicy::FailFast)
+                .load::<u32, Vec<u16>, u16, _>(POINTS.as_bytes(), 0, 3),
+            Err(SfcError::InvalidFormat(_))
+        ));
+    }
+
+    #[test]
+    fn load_with() {
+        let tsv = "# x y id name\n 1\t2\t0\tfirst\n3\t4 \t1\tsecond\n";
+
+        let (index, report) = CsvLoader::new(&[0, 1])
+            .delimiter(b'\t')
+            .has_headers(false)
+            .trim(true)
+            .comment(Some(b'#'))
+            .load_with(
+                tsv.as_bytes(),
+                |record| Ok(format!("{}: {}", column::<u32>(record, 2)?, &record[3])),
+                3,
+            )
+            .unwrap();
+        let index: SpaceFillingCurve<String, Vec<u16>, u16> = index;
+
+        assert_eq!(report.indexed, 2);
+        assert_eq!(index.find(&vec![3, 4]), vec!["1: second"]);
+    }
+}
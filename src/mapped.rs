@@ -0,0 +1,621 @@
+//! Flat on-disk layout of an index, which can be queried in place, for
+//! example from a memory-mapped file, without deserializing its cells.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::borrow::Cow;
+use std::convert::TryInto;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::Write;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::build::Rejection;
+use crate::cell_space::CellSpace;
+use crate::dimension::Dimension;
+use crate::error::SfcError;
+use crate::persist::corrupted;
+use crate::persist::Checksummed;
+use crate::persist::Header;
+use crate::persist::Layout;
+use crate::position::Position;
+use crate::sfc::is_after;
+use crate::sfc::is_before;
+use crate::sfc::SFCCode;
+use crate::sfc::SFCOffset;
+use crate::sfc::SpaceFillingCurve;
+
+// The layout starts with the versioned header of `persist`, then holds
+// the dictionaries, followed by fixed-size sections, all integers being
+// little-endian:
+//
+//  * dictionaries length: u64, then the dictionaries, serialized with
+//    bincode,
+//  * number of cells, records and values: 3 x u64,
+//  * codes of the cells: cells x u64,
+//  * first record of every cell, and the end of the last: (cells + 1) x u64,
+//  * first value of every cell, and the end of the last: (cells + 1) x u64,
+//  * offsets of the records: records x dimensions x u32,
+//  * value of each record, within its cell: records x u32,
+//  * end of each value in the value data: values x u64,
+//  * values, serialized with bincode,
+//  * CRC-32 of each of the previous sections, `SECTIONS` giving their
+//    names: 9 x u32.
+
+const SECTIONS: [&str; 9] = [
+    "dictionaries",
+    "counts",
+    "codes",
+    "record starts",
+    "value starts",
+    "offsets",
+    "record values",
+    "value ends",
+    "values",
+];
+
+fn io<E: ToString>(e: E) -> SfcError {
+    SfcError::Io(e.to_string())
+}
+
+// Compare the checksum of `section`, held by `bytes[start..end]`, with the
+// one stored at `checksums`.
+fn check_section(
+    bytes: &[u8],
+    (start, end): (usize, usize),
+    checksums: usize,
+    section: usize,
+) -> Result<(), SfcError> {
+    let pos = checksums + section * 4;
+    let expected = u32::from_le_bytes(bytes[pos..pos + 4].try_into().map_err(io)?);
+    if crc32fast::hash(&bytes[start..end]) != expected {
+        return Err(corrupted(SECTIONS[section]));
+    }
+
+    Ok(())
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> Result<(), SfcError> {
+    writer.write_all(&value.to_le_bytes()).map_err(io)
+}
+
+fn write_u32<W: Write>(writer: &mut W, value: u32) -> Result<(), SfcError> {
+    writer.write_all(&value.to_le_bytes()).map_err(io)
+}
+
+impl<F, K, V> SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    /// Writes the index in a flat layout, which can be queried in place
+    /// with a [`MappedIndex`](struct.MappedIndex.html).
+    ///
+    /// The write buffer is flushed first. Only the dictionaries have to
+    /// be deserialized to query the index, the cells and the values are
+    /// read as needed.
+    pub fn write_mapped<W>(&mut self, mut writer: W) -> Result<(), SfcError>
+    where
+        F: Clone + Serialize,
+        K: Serialize,
+        V: Serialize,
+        W: Write,
+    {
+        self.flush()?;
+        Header::new(Layout::Mapped, self.cell_bits()).write(&mut writer)?;
+
+        let mut writer = Checksummed::new(writer);
+        let mut sums = vec![];
+
+        let header = bincode::serialize(&(self.space(), self.dimensions())).map_err(io)?;
+        write_u64(&mut writer, header.len() as u64)?;
+        writer.write_all(&header).map_err(io)?;
+        sums.push(writer.take_sum());
+
+        let (mut cells, mut records, mut values) = (0, 0, 0);
+        for (_, cell_records, cell_values) in self.cells() {
+            cells += 1;
+            records += cell_records.len();
+            values += cell_values.len();
+        }
+        for count in &[cells, records, values] {
+            write_u64(&mut writer, *count as u64)?;
+        }
+        sums.push(writer.take_sum());
+
+        for code in self.codes() {
+            write_u64(&mut writer, code)?;
+        }
+        sums.push(writer.take_sum());
+
+        let mut first = 0;
+        write_u64(&mut writer, first)?;
+        for (_, cell_records, _) in self.cells() {
+            first += cell_records.len() as u64;
+            write_u64(&mut writer, first)?;
+        }
+        sums.push(writer.take_sum());
+
+        let mut first = 0;
+        write_u64(&mut writer, first)?;
+        for (_, _, cell_values) in self.cells() {
+            first += cell_values.len() as u64;
+            write_u64(&mut writer, first)?;
+        }
+        sums.push(writer.take_sum());
+
+        for (_, cell_records, _) in self.cells() {
+            for (offsets, _) in cell_records {
+                for offset in offsets {
+                    write_u32(&mut writer, *offset)?;
+                }
+            }
+        }
+        sums.push(writer.take_sum());
+
+        for (_, cell_records, _) in self.cells() {
+            for (_, value) in cell_records {
+                write_u32(&mut writer, value as u32)?;
+            }
+        }
+        sums.push(writer.take_sum());
+
+        // Sizes first, so that the values do not have to be kept in memory.
+        let mut end = 0;
+        for (_, _, cell_values) in self.cells() {
+            for value in cell_values {
+                end += bincode::serialized_size(value).map_err(io)?;
+                write_u64(&mut writer, end)?;
+            }
+        }
+        sums.push(writer.take_sum());
+
+        for (_, _, cell_values) in self.cells() {
+            for value in cell_values {
+                bincode::serialize_into(&mut writer, value).map_err(io)?;
+            }
+        }
+        sums.push(writer.take_sum());
+
+        let mut writer = writer.into_inner();
+        for sum in sums {
+            write_u32(&mut writer, sum)?;
+        }
+
+        writer.flush().map_err(io)
+    }
+}
+
+/// Read-only index, queried in place from its flat layout.
+///
+/// Built over the bytes written by
+/// [`write_mapped`](struct.IndexOwned.html#method.write_mapped), usually
+/// a memory-mapped file, for example with the `memmap2` crate. Only the
+/// dictionaries are deserialized when it is opened: the cells are
+/// searched within the bytes, and the values of the selected records
+/// decoded, so that queries return owned values.
+#[derive(Debug)]
+pub struct MappedIndex<'a, F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Ord,
+{
+    bytes: &'a [u8],
+    // Index without any record, holding the dictionaries.
+    index: SpaceFillingCurve<F, K, V>,
+    cells: usize,
+    records: usize,
+    values: usize,
+    // Position of each section in `bytes`.
+    codes: usize,
+    record_starts: usize,
+    value_starts: usize,
+    offsets: usize,
+    record_values: usize,
+    value_ends: usize,
+    data: usize,
+    checksums: usize,
+}
+
+impl<'a, F, K, V> MappedIndex<'a, F, K, V>
+where
+    F: PartialEq + DeserializeOwned,
+    K: Position<V> + DeserializeOwned,
+    V: Clone + Debug + Hash + Ord + DeserializeOwned,
+{
+    /// Opens the index stored in `bytes`.
+    ///
+    /// The format of the index is checked, the size of the sections
+    /// checked against the length of `bytes`, and the dictionaries
+    /// deserialized once their checksum is verified. The checksums of the
+    /// other sections are only verified by [`verify`](#method.verify).
+    pub fn new(bytes: &'a [u8]) -> Result<Self, SfcError> {
+        let (format, start) = Header::parse(bytes)?;
+        format.expect(Layout::Mapped)?;
+        let bytes = &bytes[start..];
+        let truncated = || corrupted("checksums");
+        let read = |pos: usize| -> Result<usize, SfcError> {
+            let value = bytes.get(pos..pos + 8).ok_or_else(truncated)?;
+            Ok(u64::from_le_bytes(value.try_into().map_err(io)?) as usize)
+        };
+
+        let header_len = read(0)?;
+        let counts = 8usize.checked_add(header_len).ok_or_else(truncated)?;
+        let (cells, records, values) = (read(counts)?, read(counts + 8)?, read(counts + 16)?);
+        let dimensions = format.cell_bits.len();
+
+        // Start of each section, from the end of the previous one.
+        let sizes = [
+            cells.checked_mul(8),
+            cells.checked_add(1).and_then(|n| n.checked_mul(8)),
+            cells.checked_add(1).and_then(|n| n.checked_mul(8)),
+            records.checked_mul(dimensions * 4),
+            records.checked_mul(4),
+            values.checked_mul(8),
+        ];
+        let mut sections = [counts + 24; 7];
+        for (k, size) in sizes.iter().enumerate() {
+            sections[k + 1] = size
+                .and_then(|size| sections[k].checked_add(size))
+                .ok_or_else(truncated)?;
+        }
+        let data_len = match values {
+            0 => 0,
+            _ => read(sections[5] + (values - 1) * 8)?,
+        };
+        let checksums = sections[6].checked_add(data_len).ok_or_else(truncated)?;
+        if checksums.saturating_add(SECTIONS.len() * 4) > bytes.len() {
+            return Err(truncated());
+        }
+
+        check_section(bytes, (0, counts), checksums, 0)?;
+        check_section(bytes, (counts, sections[0]), checksums, 1)?;
+        let (space, metadata): (CellSpace<K, V>, Vec<Dimension>) =
+            bincode::deserialize(&bytes[8..counts]).map_err(io)?;
+        format.check(space.cell_bits())?;
+
+        let mut index = SpaceFillingCurve::empty(space, dimensions);
+        if !metadata.is_empty() {
+            index.set_dimensions(metadata)?;
+        }
+
+        Ok(MappedIndex {
+            bytes,
+            index,
+            cells,
+            records,
+            values,
+            codes: sections[0],
+            record_starts: sections[1],
+            value_starts: sections[2],
+            offsets: sections[3],
+            record_values: sections[4],
+            value_ends: sections[5],
+            data: sections[6],
+            checksums,
+        })
+    }
+
+    /// Verifies the checksums of every section of the index.
+    ///
+    /// This reads the whole index, for example to check a file before
+    /// serving queries from it.
+    pub fn verify(&self) -> Result<(), SfcError> {
+        let starts = [
+            0,
+            self.codes - 24,
+            self.codes,
+            self.record_starts,
+            self.value_starts,
+            self.offsets,
+            self.record_values,
+            self.value_ends,
+            self.data,
+            self.checksums,
+        ];
+
+        (0..SECTIONS.len()).try_for_each(|section| {
+            let bounds = (starts[section], starts[section + 1]);
+            check_section(self.bytes, bounds, self.checksums, section)
+        })
+    }
+
+    /// Returns the number of records in the index.
+    pub fn len(&self) -> usize {
+        self.records
+    }
+
+    /// Returns whether the index holds no record.
+    pub fn is_empty(&self) -> bool {
+        self.records == 0
+    }
+
+    /// See [`dimensions`](struct.IndexOwned.html#method.dimensions).
+    pub fn dimensions(&self) -> &[Dimension] {
+        self.index.dimensions()
+    }
+
+    /// See [`cell_bits`](struct.IndexOwned.html#method.cell_bits).
+    pub fn cell_bits(&self) -> &[usize] {
+        self.index.cell_bits()
+    }
+
+    /// Returns the values stored at `key`.
+    pub fn find(&self, key: &K) -> Result<Vec<F>, SfcError> {
+        let (code, offsets) = match self.index.entry(key) {
+            Ok(entry) => entry,
+            // Not in the dictionaries, so not in the cells either.
+            Err(Rejection::InvalidPosition(SfcError::OutOfBounds { .. })) => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut values = vec![];
+        if let Ok(idx) = self.search(code) {
+            for record in self.records(idx) {
+                if self.record_offsets(record).eq(offsets.iter().copied()) {
+                    values.push(self.value(idx, record)?);
+                }
+            }
+        }
+
+        Ok(values)
+    }
+
+    /// Returns the records within the range `[start, end]`.
+    pub fn find_range(&self, start: &K, end: &K) -> Result<Vec<(K, F)>, SfcError> {
+        let start = self
+            .index
+            .try_coordinates(start)?
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .index
+            .try_coordinates(end)?
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        if self.cells == 0 {
+            return Ok(vec![]);
+        }
+
+        // Same rounding of the limits as `SpaceFillingCurve`.
+        let space = self.index.space();
+        let (cells, _) = space.key_down(&start)?;
+        let first = match self.search(self.index.encode(&cells)?) {
+            Ok(idx) => idx,
+            Err(idx) => idx.saturating_sub(1),
+        };
+        let (cells, _) = space.key_up(&end)?;
+        let last = match self.search(self.index.encode(&cells)?) {
+            Ok(idx) => idx + 1,
+            Err(idx) => idx.min(self.cells),
+        };
+
+        let mut values = vec![];
+        for idx in first..last {
+            let cell_ids = self
+                .index
+                .encoder()
+                .decode(self.code(idx))
+                .iter()
+                .map(|id| *id as usize)
+                .collect::<Vec<_>>();
+            let (min, max) = space.cell_bounds(&cell_ids);
+            let inside = is_after(&start, &min) && is_before(&end, &max);
+
+            for record in self.records(idx) {
+                let offsets = self
+                    .record_offsets(record)
+                    .map(|offset| offset as usize)
+                    .collect();
+                let position = space.value(cell_ids.clone(), offsets)?;
+                if inside || (is_after(&start, &position) && is_before(&end, &position)) {
+                    let key = K::from_coordinates(position.into_iter().map(Cow::into_owned))
+                        .map_err(SfcError::InvalidKey)?;
+                    values.push((key, self.value(idx, record)?));
+                }
+            }
+        }
+
+        Ok(values)
+    }
+
+    fn u64_at(&self, pos: usize) -> u64 {
+        u64::from_le_bytes(self.bytes[pos..pos + 8].try_into().unwrap())
+    }
+
+    fn u32_at(&self, pos: usize) -> u32 {
+        u32::from_le_bytes(self.bytes[pos..pos + 4].try_into().unwrap())
+    }
+
+    fn code(&self, idx: usize) -> SFCCode {
+        self.u64_at(self.codes + idx * 8)
+    }
+
+    // Binary search of the cell with the given code.
+    fn search(&self, code: SFCCode) -> Result<usize, usize> {
+        let (mut low, mut high) = (0, self.cells);
+        while low < high {
+            let mid = low + (high - low) / 2;
+            match self.code(mid) {
+                c if c < code => low = mid + 1,
+                c if c > code => high = mid,
+                _ => return Ok(mid),
+            }
+        }
+
+        Err(low)
+    }
+
+    // Indices of the records of the cell `idx`.
+    fn records(&self, idx: usize) -> std::ops::Range<usize> {
+        let start = self.u64_at(self.record_starts + idx * 8) as usize;
+        let end = self.u64_at(self.record_starts + (idx + 1) * 8) as usize;
+
+        start.min(self.records)..end.min(self.records)
+    }
+
+    fn record_offsets(&self, record: usize) -> impl Iterator<Item = SFCOffset> + '_ {
+        let dimensions = self.index.space().dimensions();
+        let start = self.offsets + record * dimensions * 4;
+
+        (0..dimensions).map(move |k| self.u32_at(start + k * 4))
+    }
+
+    // Decode the value of `record`, from the cell `idx`.
+    fn value(&self, idx: usize, record: usize) -> Result<F, SfcError> {
+        let value = self.u64_at(self.value_starts + idx * 8) as usize
+            + self.u32_at(self.record_values + record * 4) as usize;
+        if value >= self.values {
+            return Err(SfcError::Io(format!("Invalid value {}", value)));
+        }
+
+        let end = self.u64_at(self.value_ends + value * 8) as usize;
+        let start = match value {
+            0 => 0,
+            _ => self.u64_at(self.value_ends + (value - 1) * 8) as usize,
+        };
+        let bytes = self
+            .bytes
+            .get(self.data + start..self.data + end)
+            .ok_or_else(|| SfcError::Io(format!("Invalid value {}", value)))?;
+
+        bincode::deserialize(bytes).map_err(io)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ironsea_index::IndexedDestructured;
+
+    use crate::fixtures;
+    use crate::fixtures::Point;
+
+    type Index = SpaceFillingCurve<String, Vec<u16>, u16>;
+
+    fn points() -> Vec<Point<String>> {
+        fixtures::points(500)
+            .into_iter()
+            .map(|point| Point {
+                position: point.position,
+                id: format!("point {}", point.id % 70),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn find() {
+        let points = points();
+        let mut index = Index::new(points[..400].iter().cloned(), 2, 4);
+        index.compress_values();
+        index
+            .set_dimensions(vec![Dimension::new("x", "m"), Dimension::new("y", "m")])
+            .unwrap();
+        for point in &points[400..] {
+            index.insert_buffered(point.clone());
+        }
+
+        let mut bytes = vec![];
+        index.write_mapped(&mut bytes).unwrap();
+        let mapped = MappedIndex::<String, Vec<u16>, u16>::new(&bytes).unwrap();
+
+        assert_eq!(mapped.len(), 500);
+        assert_eq!(mapped.dimensions(), index.dimensions());
+        assert_eq!(mapped.cell_bits(), index.cell_bits());
+        for point in &points {
+            let mut found = mapped.find(&point.position).unwrap();
+            let mut expected = index.find(&point.position);
+            found.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(found.iter().collect::<Vec<_>>(), expected);
+        }
+        assert!(mapped.find(&vec![100, 1]).unwrap().is_empty());
+        assert!(mapped.find(&vec![1]).is_err());
+
+        for (start, end) in &[(vec![0, 0], vec![49, 39]), (vec![5, 12], vec![31, 20])] {
+            let mut found = mapped.find_range(start, end).unwrap();
+            let mut expected = index
+                .find_range(start, end)
+                .into_iter()
+                .map(|(k, f)| (k, f.clone()))
+                .collect::<Vec<_>>();
+            found.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(found, expected);
+        }
+    }
+
+    #[test]
+    fn empty() {
+        let mut index = Index::new(Vec::<Point<String>>::new().into_iter(), 2, 4);
+        let mut bytes = vec![];
+        index.write_mapped(&mut bytes).unwrap();
+
+        let mapped = MappedIndex::<String, Vec<u16>, u16>::new(&bytes).unwrap();
+        assert!(mapped.is_empty());
+        assert!(mapped.find(&vec![1, 1]).unwrap().is_empty());
+        assert!(mapped
+            .find_range(&vec![0, 0], &vec![10, 10])
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn truncated() {
+        let mut index = Index::new(points().into_iter(), 2, 4);
+        let mut bytes = vec![];
+        index.write_mapped(&mut bytes).unwrap();
+
+        for len in &[0, 20, bytes.len() / 2] {
+            assert!(MappedIndex::<String, Vec<u16>, u16>::new(&bytes[..*len]).is_err());
+        }
+
+        // Indexes saved whole cannot be mapped.
+        let mut saved = vec![];
+        index.save(&mut saved).unwrap();
+        assert!(matches!(
+            MappedIndex::<String, Vec<u16>, u16>::new(&saved),
+            Err(SfcError::InvalidFormat(_))
+        ));
+
+        assert_eq!(
+            MappedIndex::<String, Vec<u16>, u16>::new(&bytes[..bytes.len() - 1]).err(),
+            Some(corrupted("checksums"))
+        );
+    }
+
+    #[test]
+    fn verify() {
+        let mut index = Index::new(points().into_iter(), 2, 4);
+        let mut bytes = vec![];
+        index.write_mapped(&mut bytes).unwrap();
+        MappedIndex::<String, Vec<u16>, u16>::new(&bytes)
+            .unwrap()
+            .verify()
+            .unwrap();
+
+        // The dictionaries are verified when opening the index.
+        let (_, start) = Header::parse(&bytes).unwrap();
+        let mut other = bytes.clone();
+        other[start + 10] ^= 1;
+        assert_eq!(
+            MappedIndex::<String, Vec<u16>, u16>::new(&other).err(),
+            Some(corrupted("dictionaries"))
+        );
+
+        // Values are only verified on demand.
+        let mut other = bytes.clone();
+        let last = other.len() - SECTIONS.len() * 4 - 1;
+        other[last] ^= 1;
+        let mapped = MappedIndex::<String, Vec<u16>, u16>::new(&other).unwrap();
+        assert_eq!(mapped.verify(), Err(corrupted("values")));
+    }
+}
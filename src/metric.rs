@@ -0,0 +1,79 @@
+/// Distance between positions, used by the proximity queries, see
+/// [`find_within_distance`](struct.IndexOwned.html#method.find_within_distance)
+/// and [`find_nearest`](struct.IndexOwned.html#method.find_nearest).
+///
+/// Positions are given as one coordinate per dimension, converted to
+/// `f64`.
+pub trait Metric {
+    /// Returns the distance between `a` and `b`.
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64;
+
+    /// Returns how far from a center the coordinate of dimension `k` of
+    /// a position within `radius` of it can be.
+    ///
+    /// Only the records within these bounds are checked. The default,
+    /// `radius`, holds for the metrics never smaller than the difference
+    /// of any single coordinate, as the ones of this crate. Metrics
+    /// scaling the dimensions, for example, have to override it.
+    fn extent(&self, _k: usize, radius: f64) -> f64 {
+        radius
+    }
+}
+
+/// Straight-line distance, the square root of the sum of the squared
+/// differences of the coordinates.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Euclidean;
+
+impl Metric for Euclidean {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b) * (a - b))
+            .sum::<f64>()
+            .sqrt()
+    }
+}
+
+/// Distance along the axes, the sum of the absolute differences of the
+/// coordinates.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Manhattan;
+
+impl Metric for Manhattan {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        a.iter().zip(b).map(|(a, b)| (a - b).abs()).sum()
+    }
+}
+
+/// Largest absolute difference of the coordinates, which makes the
+/// points within a radius of a center a box.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Chebyshev;
+
+impl Metric for Chebyshev {
+    fn distance(&self, a: &[f64], b: &[f64]) -> f64 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f64::max)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn distance() {
+        let (a, b) = ([1.0, 2.0, 3.0], [4.0, 6.0, 3.0]);
+
+        assert_eq!(Euclidean.distance(&a, &b), 5.0);
+        assert_eq!(Manhattan.distance(&a, &b), 7.0);
+        assert_eq!(Chebyshev.distance(&a, &b), 4.0);
+        for metric in [&Euclidean as &dyn Metric, &Manhattan, &Chebyshev] {
+            assert_eq!(metric.distance(&a, &a), 0.0);
+            assert_eq!(metric.extent(0, 2.5), 2.5);
+        }
+    }
+}
@@ -1,28 +1,46 @@
 use std::fmt;
 use std::fmt::Debug;
 
+#[cfg(feature = "serde")]
 use serde::de;
+#[cfg(feature = "serde")]
 use serde::de::Deserialize;
+#[cfg(feature = "serde")]
 use serde::de::Deserializer;
+#[cfg(feature = "serde")]
 use serde::de::MapAccess;
+#[cfg(feature = "serde")]
 use serde::de::SeqAccess;
+#[cfg(feature = "serde")]
 use serde::de::Visitor;
+#[cfg(feature = "serde")]
 use serde::ser::Serialize;
+#[cfg(feature = "serde")]
 use serde::ser::SerializeStruct;
+#[cfg(feature = "serde")]
 use serde::ser::Serializer;
 
-pub type MortonCode = u32;
+use super::error::SfcError;
+
+/// Morton code of a cell, interleaving the bits of its coordinates.
+pub type MortonCode = u64;
+/// Coordinate of a cell along one dimension.
 pub type MortonValue = u16;
 
-const MORTON_CODE_BITS: usize = 32;
-const MORTON_VALUE_BITS: usize = 10;
+pub const MORTON_CODE_BITS: usize = 64;
+pub const MORTON_VALUE_BITS: usize = 10;
 const MORTON_MAX_VALUES: usize = 1024;
 
+/// Encoder projecting the coordinates of cells on a Z-order curve.
 #[derive(Clone)]
 pub struct MortonEncoder {
-    cell_bits: usize,
-    cell_mask: usize,
+    cell_bits: Vec<usize>,
+    cell_masks: Vec<usize>,
+    // Bits of the code used by each dimension.
+    masks: Vec<MortonCode>,
     dimensions: usize,
+    // Position in the code of each bit of the values, per dimension.
+    positions: Vec<Vec<usize>>,
     table: Vec<[MortonCode; MORTON_MAX_VALUES]>,
 }
 
@@ -30,8 +48,8 @@ impl Debug for MortonEncoder {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         write!(
             f,
-            "MortonEncoder {{ cell_bits: {}, cell_mask: {}, dimensions: {}, table: ",
-            self.cell_bits, self.cell_mask, self.dimensions
+            "MortonEncoder {{ cell_bits: {:?}, cell_masks: {:?}, dimensions: {}, table: ",
+            self.cell_bits, self.cell_masks, self.dimensions
         )?;
         write!(f, "[ ")?;
         for k in &self.table {
@@ -46,47 +64,66 @@ impl Debug for MortonEncoder {
 }
 
 impl MortonEncoder {
-    pub fn new(dimensions: usize, cell_bits: usize) -> Self {
+    // Build an encoder using `cell_bits` bits for every dimension.
+    #[cfg(test)]
+    fn new(dimensions: usize, cell_bits: usize) -> Self {
+        Self::with_cell_bits(vec![cell_bits; dimensions])
+    }
+
+    /// Builds an encoder using `cell_bits[k]` bits for the dimension k.
+    ///
+    /// Bits are interleaved starting with the least significant bit of
+    /// every dimension, skipping dimensions once all their bits have been
+    /// placed. When all the dimensions use the same number of bits, this
+    /// is the usual Morton encoding.
+    pub fn with_cell_bits(cell_bits: Vec<usize>) -> Self {
+        let dimensions = cell_bits.len();
+
         // Make sure we can store the encoding in a single T.
         // Don't know how to make that test generically
-        assert!(MORTON_VALUE_BITS >= cell_bits);
-        assert!(MORTON_CODE_BITS >= cell_bits * dimensions);
+        assert!(cell_bits.iter().all(|bits| MORTON_VALUE_BITS >= *bits));
+        assert!(MORTON_CODE_BITS >= cell_bits.iter().sum());
+
+        // Compute where each bit of each dimension lands in the code.
+        let mut positions = vec![vec![]; dimensions];
+        let mut position = 0;
+        for p in 0..cell_bits.iter().max().cloned().unwrap_or(0) {
+            for k in 0..dimensions {
+                if p < cell_bits[k] {
+                    positions[k].push(position);
+                    position += 1;
+                }
+            }
+        }
 
-        //let mut masks = vec![];
         let mut table = vec![];
-        let cell_max = 1 << cell_bits;
-        let cell_mask = cell_max - 1;
+        let mut cell_masks = vec![];
 
         // Build lookup table & masks
         for k in 0..dimensions {
+            let cell_max = 1 << cell_bits[k];
+            cell_masks.push(cell_max - 1);
+
             table.push([0; MORTON_MAX_VALUES]);
             for i in 0..cell_max {
                 let mut v = 0;
-                for p in 0..cell_bits {
-                    // Note: bit is at position p, so shift it only K-1 p position again below, instead
-                    // of K times
-                    let bit = i & (1 << p);
-                    let new_bit = bit << (p * (dimensions - 1) + k);
-                    v |= new_bit;
+                for (p, position) in positions[k].iter().enumerate() {
+                    let bit = (i as MortonCode >> p) & 1;
+                    v |= bit << position;
                 }
-                table[k][i] = v as MortonCode;
-            }
-            /*
-            let mut v = 0usize;
-            for p in 0..cell_bits {
-                let new_bit = 1 << p * (dimensions - 1) + k;
-                v = v | new_bit;
+                table[k][i] = v;
             }
-            masks.push(v as MortonCode);
-            */
         }
 
+        let masks = (0..dimensions).map(|k| table[k][cell_masks[k]]).collect();
+
         MortonEncoder {
             cell_bits,
-            cell_mask,
+            cell_masks,
+            masks,
             dimensions,
+            positions,
             table,
-            //masks,
         }
     }
 
@@ -97,7 +134,7 @@ impl MortonEncoder {
 
         // Ensure we only have valid values in inputs, even when less bits than
         // the maximum is used to define those values.
-        let v = v as usize & self.cell_mask;
+        let v = v as usize & self.cell_masks[k];
         self.table[k][v]
     }
 
@@ -107,36 +144,40 @@ impl MortonEncoder {
 
         let mut v = 0;
 
-        for i in 0..self.cell_bits {
-            let bit_pos = i * self.table.len() + k;
-            let bit = code as usize & (1 << bit_pos);
-            let bit_pos = bit_pos - i;
-            v |= (bit >> bit_pos) as MortonValue;
+        for (i, bit_pos) in self.positions[k].iter().enumerate() {
+            let bit = (code >> bit_pos) & 1;
+            v |= (bit << i) as MortonValue;
         }
 
-        v as MortonValue
+        v
     }
 
-    pub fn encode(&self, v: &[MortonValue]) -> Result<MortonCode, String> {
+    /// Encodes the coordinates of a cell, one value per dimension.
+    pub fn encode(&self, v: &[MortonValue]) -> Result<MortonCode, SfcError> {
         //TODO: Should we check inside each objects, or just assume it is correct and/or rely on the bound checks?
         if self.dimensions != v.len() {
-            return Err(format!(
-                "Incorrect number of dimensions, expected {}, got {} for {:?}",
-                self.dimensions,
-                v.len(),
-                v
-            ));
+            return Err(SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: v.len(),
+            });
         }
 
         let mut code = 0;
 
         for (k, i) in v.iter().enumerate().take(self.dimensions) {
+            if *i as usize > self.cell_masks[k] {
+                return Err(SfcError::EncodeOverflow {
+                    dimension: k,
+                    value: *i as usize,
+                });
+            }
             code |= self.encode_1(k, *i);
         }
 
         Ok(code)
     }
 
+    /// Decodes the coordinates of a cell.
     pub fn decode(&self, code: MortonCode) -> Vec<MortonValue> {
         let mut values = vec![];
 
@@ -146,8 +187,59 @@ impl MortonEncoder {
 
         values
     }
+
+    /// Returns the code of the cell `step` cells away from `code` along
+    /// `dimension`, or `None` if it falls outside of the grid.
+    ///
+    /// This works directly on the code, without decoding it.
+    pub fn neighbor(&self, code: MortonCode, dimension: usize, step: isize) -> Option<MortonCode> {
+        let magnitude = step.unsigned_abs();
+        if magnitude > self.cell_masks[dimension] {
+            return None;
+        }
+
+        let mask = self.masks[dimension];
+        let value = code & mask;
+        let delta = self.table[dimension][magnitude];
+
+        // The order of the values is preserved by the encoding, so a
+        // wrap-around shows up as a move in the wrong direction.
+        let moved = if step >= 0 {
+            // Set the bits of the other dimensions, so that the carries go
+            // through them.
+            let moved = (value | !mask).wrapping_add(delta) & mask;
+            if moved < value {
+                return None;
+            }
+            moved
+        } else {
+            let moved = value.wrapping_sub(delta) & mask;
+            if moved > value {
+                return None;
+            }
+            moved
+        };
+
+        Some(moved | (code & !mask))
+    }
+
+    /// Returns the codes of the cells sharing a face with the cell
+    /// `code`, that is the cells one step away along a single dimension.
+    pub fn neighbors(&self, code: MortonCode) -> Vec<MortonCode> {
+        let mut neighbors = Vec::with_capacity(2 * self.dimensions);
+        for k in 0..self.dimensions {
+            for step in &[-1, 1] {
+                if let Some(neighbor) = self.neighbor(code, k, *step) {
+                    neighbors.push(neighbor);
+                }
+            }
+        }
+
+        neighbors
+    }
 }
 
+#[cfg(feature = "serde")]
 impl Serialize for MortonEncoder {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
@@ -155,7 +247,8 @@ impl Serialize for MortonEncoder {
     {
         // We serialize the minimum amount of information necessary to
         // deserialize the table.
-        // This is the parameters to init(dimensions, cell_bits)
+        // This is the parameters to with_cell_bits(cell_bits), as well as
+        // the number of dimensions, as a consistency check.
         let mut state = serializer.serialize_struct("MortonEncoder", 2)?;
         state.serialize_field("cell_bits", &self.cell_bits)?;
         state.serialize_field("dimensions", &self.dimensions)?;
@@ -163,6 +256,26 @@ impl Serialize for MortonEncoder {
     }
 }
 
+#[cfg(feature = "serde")]
+impl MortonEncoder {
+    fn from_fields<E>(cell_bits: Vec<usize>, dimensions: usize) -> Result<Self, E>
+    where
+        E: de::Error,
+    {
+        if cell_bits.len() != dimensions {
+            return Err(de::Error::custom(format!(
+                "Incorrect number of dimensions, expected {}, got {} for {:?}",
+                dimensions,
+                cell_bits.len(),
+                cell_bits
+            )));
+        }
+
+        Ok(MortonEncoder::with_cell_bits(cell_bits))
+    }
+}
+
+#[cfg(feature = "serde")]
 impl<'de> Deserialize<'de> for MortonEncoder {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -222,7 +335,7 @@ impl<'de> Deserialize<'de> for MortonEncoder {
                 let dimensions = seq
                     .next_element()?
                     .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                Ok(MortonEncoder::new(dimensions, cell_bits))
+                MortonEncoder::from_fields(cell_bits, dimensions)
             }
 
             fn visit_map<V>(self, mut map: V) -> Result<MortonEncoder, V::Error>
@@ -250,7 +363,7 @@ impl<'de> Deserialize<'de> for MortonEncoder {
                 let cell_bits = cell_bits.ok_or_else(|| de::Error::missing_field("cell_bits"))?;
                 let dimensions =
                     dimensions.ok_or_else(|| de::Error::missing_field("dimensions"))?;
-                Ok(MortonEncoder::new(dimensions, cell_bits))
+                MortonEncoder::from_fields(cell_bits, dimensions)
             }
         }
 
@@ -282,14 +395,20 @@ mod tests {
 
         #[test]
         #[should_panic]
-        fn dim33_bit1() {
-            let _m = MortonEncoder::new(33, 1);
+        fn dim65_bit1() {
+            let _m = MortonEncoder::new(65, 1);
         }
 
         #[test]
         #[should_panic]
-        fn dim17_bit2() {
-            let _m = MortonEncoder::new(17, 2);
+        fn dim33_bit2() {
+            let _m = MortonEncoder::new(33, 2);
+        }
+
+        #[test]
+        #[should_panic]
+        fn dim7_bit10() {
+            let _m = MortonEncoder::new(7, 10);
         }
 
         #[test]
@@ -317,6 +436,21 @@ mod tests {
             let _m = MortonEncoder::new(32, 1);
         }
 
+        #[test]
+        fn dim6_bit10() {
+            let _m = MortonEncoder::new(6, 10);
+        }
+
+        #[test]
+        fn dim16_bit4() {
+            let _m = MortonEncoder::new(16, 4);
+        }
+
+        #[test]
+        fn dim64_bit1() {
+            let _m = MortonEncoder::new(64, 1);
+        }
+
         /*
             morton_init();
         // Morton table looks OK
@@ -442,5 +576,113 @@ mod tests {
             let m = MortonEncoder::new(4, 8);
             check(4, 256, 8, m);
         }
+
+        #[test]
+        fn table_dim8_bit8() {
+            let m = MortonEncoder::new(8, 8);
+            check(8, 256, 8, m);
+        }
+
+        #[test]
+        fn table_dim16_bit4() {
+            let m = MortonEncoder::new(16, 4);
+            check(16, 16, 4, m);
+        }
+
+        #[test]
+        fn table_uneven_bits() {
+            let m = MortonEncoder::with_cell_bits(vec![4, 1, 2]);
+
+            // Bits are interleaved as: d0 d1 d2 | d0 d2 | d0 | d0
+            assert_eq!(m.encode_1(0, 0b1111), 0b110_1001);
+            assert_eq!(m.encode_1(1, 0b1), 0b000_0010);
+            assert_eq!(m.encode_1(2, 0b11), 0b001_0100);
+
+            let values = vec![0b1010, 1, 0b10];
+            let code = m.encode(&values).unwrap();
+            assert_eq!(m.decode(code), values);
+
+            assert_eq!(
+                m.encode(&[0, 2, 0]),
+                Err(SfcError::EncodeOverflow {
+                    dimension: 1,
+                    value: 2
+                })
+            );
+            assert_eq!(
+                m.encode(&[0, 0]),
+                Err(SfcError::DimensionMismatch {
+                    expected: 3,
+                    found: 2
+                })
+            );
+        }
+
+        #[test]
+        fn decode_dim10_bit6() {
+            let m = MortonEncoder::new(10, 6);
+            let values = (0..10).map(|k| (k * 7) as MortonValue).collect::<Vec<_>>();
+            let code = m.encode(&values).unwrap();
+            assert_eq!(m.decode(code), values);
+        }
+    }
+
+    mod neighbor {
+        use super::*;
+
+        fn check(m: &MortonEncoder, values: &[MortonValue], dimension: usize, step: isize) {
+            let code = m.encode(values).unwrap();
+
+            let mut moved = values.to_vec();
+            let target = values[dimension] as isize + step;
+            let max = (1 << m.cell_bits[dimension]) as isize;
+            let expected = if target < 0 || target >= max {
+                None
+            } else {
+                moved[dimension] = target as MortonValue;
+                Some(m.encode(&moved).unwrap())
+            };
+
+            assert_eq!(m.neighbor(code, dimension, step), expected);
+        }
+
+        #[test]
+        fn dim3_bit4() {
+            let m = MortonEncoder::new(3, 4);
+            for x in 0..16 {
+                for k in 0..3 {
+                    for step in &[-16, -3, -1, 0, 1, 3, 16] {
+                        check(&m, &[x, (x * 7) % 16, 15 - x], k, *step);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn uneven_bits() {
+            let m = MortonEncoder::with_cell_bits(vec![4, 1, 2]);
+            for x in 0..16 {
+                for k in 0..3 {
+                    for step in &[-2, -1, 1, 2] {
+                        check(&m, &[x, x % 2, x % 4], k, *step);
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn neighbors() {
+            let m = MortonEncoder::new(2, 3);
+
+            let corner = m.encode(&[0, 0]).unwrap();
+            let mut neighbors = m.neighbors(corner);
+            neighbors.sort_unstable();
+            let mut expected = vec![m.encode(&[1, 0]).unwrap(), m.encode(&[0, 1]).unwrap()];
+            expected.sort_unstable();
+            assert_eq!(neighbors, expected);
+
+            let center = m.encode(&[3, 4]).unwrap();
+            assert_eq!(m.neighbors(center).len(), 4);
+        }
     }
 }
@@ -0,0 +1,238 @@
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use ironsea_index::IndexedDestructured;
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use super::position::Position;
+use super::sfc::SpaceFillingCurve;
+
+type PayloadId = usize;
+
+/// Records which occupy several positions at once.
+///
+/// This is the multi-position counterpart of `Record`, for example for
+/// the two endpoints of a segment.
+pub trait MultiRecord<K> {
+    /// Returns all the positions of the record.
+    fn keys(&self) -> Vec<K>;
+}
+
+#[derive(Clone, Debug)]
+struct Entry<K> {
+    key: K,
+    payload: PayloadId,
+}
+
+impl<K> Record<K> for Entry<K>
+where
+    K: Clone,
+{
+    fn key(&self) -> K {
+        self.key.clone()
+    }
+}
+
+impl<K> RecordFields<PayloadId> for Entry<K> {
+    fn fields(&self) -> PayloadId {
+        self.payload
+    }
+}
+
+/// Space Filling Curve-based index over records with multiple positions.
+///
+/// Every payload is stored once, and referenced from each of the
+/// positions of its record. Queries return each matching payload only
+/// once.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct MultiKeyIndex<F, K, V>
+where
+    K: Position<V>,
+    V: Clone + Debug + Ord,
+{
+    index: SpaceFillingCurve<PayloadId, K, V>,
+    payloads: Vec<F>,
+}
+
+impl<F, K, V> MultiKeyIndex<F, K, V>
+where
+    F: PartialEq,
+    K: Clone + Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    /// Creates a new Index from the provided iterator.
+    ///
+    /// See `SpaceFillingCurve::new` for the meaning of `dimensions` and
+    /// `cell_bits`.
+    pub fn new<I, R>(iter: I, dimensions: usize, cell_bits: usize) -> Self
+    where
+        I: Iterator<Item = R>,
+        R: Debug + MultiRecord<K> + RecordFields<F>,
+    {
+        let mut payloads = vec![];
+        let mut entries = vec![];
+        for record in iter {
+            let payload = payloads.len();
+            for key in record.keys() {
+                entries.push(Entry { key, payload });
+            }
+            payloads.push(record.fields());
+        }
+
+        debug!(
+            "Interned {} payloads at {} positions",
+            payloads.len(),
+            entries.len()
+        );
+
+        MultiKeyIndex {
+            index: SpaceFillingCurve::new(entries.into_iter(), dimensions, cell_bits),
+            payloads,
+        }
+    }
+
+    /// Returns a vector of all the keys of the records which have
+    /// stored values in the index equal to `value`.
+    pub fn find_by_value(&self, value: &F) -> Vec<K> {
+        let payloads = self
+            .payloads
+            .iter()
+            .enumerate()
+            .filter(|(_, fields)| *fields == value)
+            .map(|(payload, _)| payload)
+            .collect::<HashSet<_>>();
+
+        if payloads.is_empty() {
+            return vec![];
+        }
+        self.index
+            .find_by_fields(|payload| payloads.contains(payload))
+    }
+}
+
+impl<F, K, V> IndexedDestructured<F, K> for MultiKeyIndex<F, K, V>
+where
+    F: PartialEq,
+    K: Clone + Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    fn find(&self, key: &K) -> Vec<&F> {
+        let mut seen = HashSet::new();
+
+        self.index
+            .find(key)
+            .into_iter()
+            .filter(|payload| seen.insert(**payload))
+            .map(|payload| &self.payloads[*payload])
+            .collect()
+    }
+
+    /// Each matching payload is returned once, along with the first of
+    /// its positions within the range, in curve order.
+    fn find_range(&self, start: &K, end: &K) -> Vec<(K, &F)> {
+        let mut seen = HashSet::new();
+
+        self.index
+            .find_range(start, end)
+            .into_iter()
+            .filter(|(_, payload)| seen.insert(**payload))
+            .map(|(key, payload)| (key, &self.payloads[*payload]))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Clone, Debug)]
+    struct Segment {
+        start: Vec<u32>,
+        end: Vec<u32>,
+        name: String,
+    }
+
+    impl MultiRecord<Vec<u32>> for Segment {
+        fn keys(&self) -> Vec<Vec<u32>> {
+            vec![self.start.clone(), self.end.clone()]
+        }
+    }
+
+    impl RecordFields<String> for Segment {
+        fn fields(&self) -> String {
+            self.name.clone()
+        }
+    }
+
+    fn segments() -> Vec<Segment> {
+        (0..16)
+            .map(|i| Segment {
+                start: vec![i, i, 0],
+                end: vec![i + 1, i + 1, 0],
+                name: format!("s{}", i),
+            })
+            .collect()
+    }
+
+    type Index = MultiKeyIndex<String, Vec<u32>, u32>;
+
+    #[test]
+    fn find() {
+        let index = Index::new(segments().into_iter(), 3, 2);
+
+        assert_eq!(index.find(&vec![0, 0, 0]), vec!["s0"]);
+
+        let mut shared = index.find(&vec![3, 3, 0]);
+        shared.sort_unstable();
+        assert_eq!(shared, vec!["s2", "s3"]);
+    }
+
+    #[test]
+    fn find_range() {
+        let index = Index::new(segments().into_iter(), 3, 2);
+
+        let mut names = index
+            .find_range(&vec![2, 2, 0], &vec![4, 4, 0])
+            .into_iter()
+            .map(|(_, name)| name.as_str())
+            .collect::<Vec<_>>();
+        names.sort_unstable();
+
+        assert_eq!(names, vec!["s1", "s2", "s3", "s4"]);
+    }
+
+    #[test]
+    fn find_by_value() {
+        let index = Index::new(segments().into_iter(), 3, 2);
+
+        let mut keys = index.find_by_value(&"s5".to_string());
+        keys.sort_unstable();
+
+        assert_eq!(keys, vec![vec![5, 5, 0], vec![6, 6, 0]]);
+    }
+
+    #[test]
+    fn find_by_value_shared() {
+        let mut segments = segments();
+        segments[9].name = "s5".to_string();
+        let index = Index::new(segments.into_iter(), 3, 2);
+
+        let mut keys = index.find_by_value(&"s5".to_string());
+        keys.sort_unstable();
+
+        assert_eq!(
+            keys,
+            vec![vec![5, 5, 0], vec![6, 6, 0], vec![9, 9, 0], vec![10, 10, 0]]
+        );
+        assert!(index.find_by_value(&"s16".to_string()).is_empty());
+    }
+}
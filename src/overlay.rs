@@ -0,0 +1,136 @@
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use ironsea_index::IndexedDestructured;
+
+use super::position::Position;
+use super::sfc::SpaceFillingCurve;
+
+/// Read-only view of an index, with the records of a second index, the
+/// patch, shadowing or supplementing its records.
+///
+/// A key found in the patch returns the records of the patch only, other
+/// keys return the records of the base index. Neither index is modified
+/// or copied, so that corrections can be applied to a large index
+/// without rebuilding it.
+///
+/// See [`overlay`](struct.IndexOwned.html#method.overlay).
+#[derive(Debug)]
+pub struct OverlayView<'a, F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Ord,
+{
+    base: &'a SpaceFillingCurve<F, K, V>,
+    patch: &'a SpaceFillingCurve<F, K, V>,
+}
+
+impl<'a, F, K, V> OverlayView<'a, F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    pub(crate) fn new(
+        base: &'a SpaceFillingCurve<F, K, V>,
+        patch: &'a SpaceFillingCurve<F, K, V>,
+    ) -> Self {
+        OverlayView { base, patch }
+    }
+
+    /// Returns the base index.
+    pub fn base(&self) -> &'a SpaceFillingCurve<F, K, V> {
+        self.base
+    }
+
+    /// Returns the patch index.
+    pub fn patch(&self) -> &'a SpaceFillingCurve<F, K, V> {
+        self.patch
+    }
+
+    /// See [`find_range_partial`](struct.IndexOwned.html#method.find_range_partial).
+    pub fn find_range_partial(&self, start: &[Option<V>], end: &[Option<V>]) -> Vec<(K, &F)> {
+        self.merge(
+            self.base.find_range_partial(start, end),
+            self.patch.find_range_partial(start, end),
+        )
+    }
+
+    // Drop the records of the base shadowed by the patch, and add the
+    // records of the patch.
+    fn merge<'b>(&self, base: Vec<(K, &'b F)>, patch: Vec<(K, &'b F)>) -> Vec<(K, &'b F)> {
+        let mut values = base
+            .into_iter()
+            .filter(|(key, _)| self.patch.find(key).is_empty())
+            .collect::<Vec<_>>();
+        values.extend(patch);
+
+        values
+    }
+}
+
+impl<'a, F, K, V> IndexedDestructured<F, K> for OverlayView<'a, F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    fn find(&self, key: &K) -> Vec<&F> {
+        let values = self.patch.find(key);
+        if values.is_empty() {
+            self.base.find(key)
+        } else {
+            values
+        }
+    }
+
+    fn find_range(&self, start: &K, end: &K) -> Vec<(K, &F)> {
+        self.merge(
+            self.base.find_range(start, end),
+            self.patch.find_range(start, end),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::Point;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    fn point(x: u16, y: u16, id: u32) -> Point {
+        Point {
+            position: vec![x, y],
+            id,
+        }
+    }
+
+    #[test]
+    fn shadow() {
+        let base = (0..100)
+            .map(|id| point((id % 10) as u16, (id / 10) as u16, id))
+            .collect::<Vec<_>>();
+        let base = Index::new(base.into_iter(), 2, 2);
+        let patch = vec![point(1, 1, 1000), point(20, 20, 1001)];
+        let patch = Index::new(patch.into_iter(), 2, 2);
+        let view = base.overlay(&patch);
+
+        assert_eq!(view.find(&vec![1, 1]), vec![&1000]);
+        assert_eq!(view.find(&vec![20, 20]), vec![&1001]);
+        assert_eq!(view.find(&vec![2, 1]), vec![&12]);
+        assert!(view.find(&vec![30, 30]).is_empty());
+
+        let values = view.find_range(&vec![0, 0], &vec![20, 20]);
+        assert_eq!(values.len(), 101);
+        assert!(values.contains(&(vec![1, 1], &1000)));
+        assert!(!values.contains(&(vec![1, 1], &11)));
+
+        let values = view.find_range_partial(&[Some(1), None], &[Some(1), Some(1)]);
+        assert_eq!(values, vec![(vec![1, 0], &1), (vec![1, 1], &1000)]);
+    }
+}
@@ -0,0 +1,181 @@
+//! Construction and queries of an index using all the available cores.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+use rayon::prelude::*;
+
+use crate::build::BuildPolicy;
+use crate::build::BuildReport;
+use crate::cell_space::CellLayout;
+use crate::cell_space::CellSpace;
+use crate::error::SfcError;
+use crate::position::Position;
+use crate::sfc::any;
+use crate::sfc::flat_order;
+use crate::sfc::SpaceFillingCurve;
+
+impl<F, K, V> SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq + Send + Sync,
+    K: Position<V> + Sync,
+    V: Clone + Debug + Hash + Ord + Send + Sync,
+{
+    /// Creates a new Index from the provided records, as
+    /// [`new_normalized`](#method.new_normalized) does without a
+    /// normalization callback, using all the available cores.
+    ///
+    /// The codes of the records are computed and sorted in parallel, the
+    /// dictionaries and the cells are built sequentially.
+    pub fn new_parallel<R>(
+        records: &[R],
+        dimensions: usize,
+        cell_bits: usize,
+    ) -> (Self, BuildReport)
+    where
+        R: Record<K> + RecordFields<F> + Sync,
+    {
+        match Self::build_parallel(
+            records,
+            dimensions,
+            cell_bits,
+            CellLayout::default(),
+            BuildPolicy::CollectAndReport,
+        ) {
+            Ok(built) => built,
+            Err(e) => unreachable!("Rejections are collected, not raised: {}", e),
+        }
+    }
+
+    // Shared by `new_parallel` and the builder.
+    pub(crate) fn build_parallel<R>(
+        records: &[R],
+        dimensions: usize,
+        cell_bits: usize,
+        layout: CellLayout<V>,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        R: Record<K> + RecordFields<F> + Sync,
+    {
+        let space = CellSpace::new(
+            records.iter().map(Record::key),
+            dimensions,
+            cell_bits,
+            layout,
+        );
+        let mut index = Self::empty(space, dimensions);
+
+        let entries = records
+            .par_iter()
+            .enumerate()
+            .map(|(i, record)| {
+                index
+                    .entry(&record.key())
+                    .map(|(code, offsets)| (code, i, (offsets, record.fields())))
+                    .map_err(|e| (i, e))
+            })
+            .collect::<Vec<_>>();
+
+        let mut report = BuildReport {
+            records: records.len(),
+            ..BuildReport::default()
+        };
+        let mut flat_table = Vec::with_capacity(entries.len());
+        for entry in entries {
+            match entry {
+                Ok(entry) => flat_table.push(entry),
+                Err((i, e)) => policy.reject(&mut report, i, &records[i].key(), e)?,
+            }
+        }
+
+        flat_table.par_sort_unstable_by(flat_order);
+        index.build_sorted(flat_table, &mut report);
+
+        Ok((index, report))
+    }
+
+    /// Returns the records within the range `[start, end]`, as
+    /// `find_range` does, scanning the cells on all the available cores.
+    ///
+    /// The records are returned in the same order as `find_range`.
+    pub fn par_find_range(&self, start: &K, end: &K) -> Vec<(K, &F)>
+    where
+        K: Send,
+    {
+        let start = self
+            .coordinates(start)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .coordinates(end)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        let mut values = self
+            .range_cells(&start, &end)
+            .into_par_iter()
+            .flat_map_iter(|idx| self.range_cell(idx, &start, &end, &any))
+            .collect::<Vec<_>>();
+        self.range_buffered(&start, &end, &mut values);
+
+        values
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ironsea_index::IndexedDestructured;
+
+    use crate::fixtures;
+    use crate::fixtures::Point;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    // The shared points, with a third dimension.
+    fn points() -> Vec<Point> {
+        let mut points = fixtures::points(5000);
+        for point in &mut points {
+            point.position.push((point.id % 9) as u16);
+        }
+
+        points
+    }
+
+    #[test]
+    fn new_parallel() {
+        let points = points();
+        let (index, report) = Index::new_parallel(&points, 3, 3);
+        let expected = Index::new(points.iter().cloned(), 3, 3);
+
+        assert_eq!(report.records, 5000);
+        assert_eq!(report.indexed, 5000);
+        assert_eq!(index.stats(), expected.stats());
+        for point in &points {
+            assert_eq!(index.find(&point.position), expected.find(&point.position));
+        }
+    }
+
+    #[test]
+    fn par_find_range() {
+        let points = points();
+        let mut index = Index::new(points[..4000].iter().cloned(), 3, 3);
+        for point in &points[4000..] {
+            index.insert_buffered(point.clone());
+        }
+
+        let start = vec![5, 3, 1];
+        let end = vec![40, 30, 7];
+        let values = index.par_find_range(&start, &end);
+        assert!(!values.is_empty());
+        assert_eq!(values, index.find_range(&start, &end));
+    }
+}
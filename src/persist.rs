@@ -0,0 +1,531 @@
+//! Versioned on-disk formats of an index.
+//!
+//! Every persisted index starts with a fixed header: a magic number, the
+//! version of the format, its layout, and the shape of the index, so
+//! that files written by an incompatible version of the crate are
+//! refused instead of being decoded into garbage. The sections following
+//! the header are protected by CRC-32 checksums, so that truncated or
+//! damaged files are reported as such.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::Read;
+use std::io::Write;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use crate::error::SfcError;
+use crate::position::Position;
+use crate::sfc::SpaceFillingCurve;
+
+const MAGIC: [u8; 8] = *b"SFC-DBC\0";
+
+/// Version of the on-disk formats, incremented on every incompatible
+/// change of either layout.
+pub const FORMAT_VERSION: u32 = 5;
+
+// How the index follows the header.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub(crate) enum Layout {
+    // The whole index, serialized with bincode, see `save`.
+    Serialized = 0,
+    // The flat layout of `write_mapped`.
+    Mapped = 1,
+    // Same as `Serialized`, compressed with zstd.
+    SerializedZstd = 2,
+    // Same as `Serialized`, compressed with LZ4.
+    SerializedLz4 = 3,
+    // The chunks of `write_chunked`.
+    Chunked = 4,
+}
+
+impl Layout {
+    fn from_u32(layout: u32) -> Result<Self, SfcError> {
+        match layout {
+            0 => Ok(Layout::Serialized),
+            1 => Ok(Layout::Mapped),
+            2 => Ok(Layout::SerializedZstd),
+            3 => Ok(Layout::SerializedLz4),
+            4 => Ok(Layout::Chunked),
+            _ => Err(SfcError::InvalidFormat(format!(
+                "Unknown layout {}",
+                layout
+            ))),
+        }
+    }
+}
+
+/// Compression of a saved index, see
+/// [`save_compressed`](struct.IndexOwned.html#method.save_compressed).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Compression {
+    /// Store the index as is.
+    None,
+    /// Compress the index with zstd, at the given level, from 1 to 22.
+    #[cfg(feature = "zstd")]
+    Zstd(i32),
+    /// Compress the index with LZ4, favouring speed over ratio.
+    #[cfg(feature = "lz4")]
+    Lz4,
+}
+
+impl Compression {
+    fn layout(self) -> Layout {
+        match self {
+            Compression::None => Layout::Serialized,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(_) => Layout::SerializedZstd,
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => Layout::SerializedLz4,
+        }
+    }
+}
+
+fn io<E: ToString>(e: E) -> SfcError {
+    SfcError::Io(e.to_string())
+}
+
+pub(crate) fn corrupted(section: &str) -> SfcError {
+    SfcError::Corrupted {
+        section: section.to_string(),
+    }
+}
+
+// Error raised while reading `section`: a section ending early is
+// corrupted, other I/O errors are reported as is.
+pub(crate) fn read_error(section: &str, e: std::io::Error) -> SfcError {
+    match e.kind() {
+        std::io::ErrorKind::UnexpectedEof => corrupted(section),
+        _ => io(e),
+    }
+}
+
+// Error raised while decoding `section`, which cannot be told apart from
+// corrupted bytes, unless reading them failed.
+fn decode_error(section: &str, e: bincode::ErrorKind) -> SfcError {
+    match e {
+        bincode::ErrorKind::Io(e) => read_error(section, e),
+        _ => corrupted(section),
+    }
+}
+
+// Reader or writer computing the checksum of the bytes going through it.
+pub(crate) struct Checksummed<T> {
+    inner: T,
+    hasher: crc32fast::Hasher,
+}
+
+impl<T> Checksummed<T> {
+    pub fn new(inner: T) -> Self {
+        Checksummed {
+            inner,
+            hasher: crc32fast::Hasher::new(),
+        }
+    }
+
+    // Checksum of the bytes since the previous call, or since the start.
+    pub fn take_sum(&mut self) -> u32 {
+        std::mem::take(&mut self.hasher).finalize()
+    }
+
+    pub fn into_inner(self) -> T {
+        self.inner
+    }
+}
+
+impl<W: Write> Write for Checksummed<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let written = self.inner.write(buf)?;
+        self.hasher.update(&buf[..written]);
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<R: Read> Read for Checksummed<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.hasher.update(&buf[..read]);
+        Ok(read)
+    }
+}
+
+// Serialize `value`, followed by the checksum of its bytes.
+fn write_checked<W, T>(writer: W, value: &T) -> Result<(), SfcError>
+where
+    W: Write,
+    T: Serialize,
+{
+    let mut writer = Checksummed::new(writer);
+    bincode::serialize_into(&mut writer, value).map_err(io)?;
+    let sum = writer.take_sum();
+
+    writer
+        .into_inner()
+        .write_all(&sum.to_le_bytes())
+        .map_err(io)
+}
+
+// Deserialize a value written by `write_checked`, as the section `section`.
+fn read_checked<R, T>(reader: R, section: &str) -> Result<T, SfcError>
+where
+    R: Read,
+    T: DeserializeOwned,
+{
+    let mut reader = Checksummed::new(reader);
+    let value = bincode::deserialize_from(&mut reader).map_err(|e| decode_error(section, *e))?;
+    let sum = reader.take_sum();
+
+    let mut expected = [0; 4];
+    reader
+        .into_inner()
+        .read_exact(&mut expected)
+        .map_err(|e| read_error(section, e))?;
+    if u32::from_le_bytes(expected) != sum {
+        return Err(corrupted(section));
+    }
+
+    Ok(value)
+}
+
+// Header of a persisted index:
+//
+//  * magic number: 8 bytes,
+//  * format version: u32,
+//  * layout: u32,
+//  * number of dimensions: u32,
+//  * bits of the grid on every dimension: dimensions x u32,
+//
+// all integers being little-endian.
+#[derive(Clone, Debug, PartialEq)]
+pub(crate) struct Header {
+    pub layout: Layout,
+    pub cell_bits: Vec<usize>,
+}
+
+impl Header {
+    pub fn new(layout: Layout, cell_bits: &[usize]) -> Self {
+        Header {
+            layout,
+            cell_bits: cell_bits.to_vec(),
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), SfcError> {
+        let mut bytes = MAGIC.to_vec();
+        bytes.extend_from_slice(&FORMAT_VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.layout as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.cell_bits.len() as u32).to_le_bytes());
+        for bits in &self.cell_bits {
+            bytes.extend_from_slice(&(*bits as u32).to_le_bytes());
+        }
+
+        writer.write_all(&bytes).map_err(io)
+    }
+
+    // Read a header from `reader`.
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, SfcError> {
+        let mut read_u32 = || -> Result<u32, SfcError> {
+            let mut bytes = [0; 4];
+            reader.read_exact(&mut bytes).map_err(io)?;
+            Ok(u32::from_le_bytes(bytes))
+        };
+
+        let magic = [read_u32()?.to_le_bytes(), read_u32()?.to_le_bytes()].concat();
+        if magic != MAGIC {
+            return Err(SfcError::InvalidFormat(
+                "Not an index, the magic number does not match".to_string(),
+            ));
+        }
+
+        let version = read_u32()?;
+        if version != FORMAT_VERSION {
+            return Err(SfcError::VersionMismatch {
+                expected: FORMAT_VERSION,
+                found: version,
+            });
+        }
+
+        let layout = Layout::from_u32(read_u32()?)?;
+        let dimensions = read_u32()?;
+        let cell_bits = (0..dimensions)
+            .map(|_| read_u32().map(|bits| bits as usize))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Header { layout, cell_bits })
+    }
+
+    // Same as `read`, from the start of `bytes`. Returns the header, and
+    // its length.
+    pub fn parse(bytes: &[u8]) -> Result<(Self, usize), SfcError> {
+        let mut reader = bytes;
+        let header = Self::read(&mut reader)?;
+
+        Ok((header, bytes.len() - reader.len()))
+    }
+
+    // Check that the index follows the header with `layout`.
+    pub fn expect(&self, layout: Layout) -> Result<(), SfcError> {
+        if self.layout != layout {
+            return Err(SfcError::InvalidFormat(format!(
+                "Unexpected layout {:?}, expected {:?}",
+                self.layout, layout
+            )));
+        }
+
+        Ok(())
+    }
+
+    // Check that `cell_bits` describes the index which followed the
+    // header.
+    pub fn check(&self, cell_bits: &[usize]) -> Result<(), SfcError> {
+        if self.cell_bits != cell_bits {
+            return Err(SfcError::InvalidFormat(format!(
+                "The index does not match its header, cell bits {:?} instead of {:?}",
+                cell_bits, self.cell_bits
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+impl<F, K, V> SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    /// Writes the whole index, including its write buffer, behind a
+    /// versioned header.
+    ///
+    /// Read it back with [`load`](#method.load).
+    pub fn save<W>(&self, writer: W) -> Result<(), SfcError>
+    where
+        F: Serialize,
+        K: Serialize,
+        V: Serialize,
+        W: Write,
+    {
+        self.save_compressed(writer, Compression::None)
+    }
+
+    /// Same as [`save`](#method.save), compressing the index after its
+    /// header.
+    ///
+    /// The compression is recorded in the header, so that
+    /// [`load`](#method.load) reads the index back as long as the
+    /// matching feature is enabled.
+    pub fn save_compressed<W>(
+        &self,
+        mut writer: W,
+        compression: Compression,
+    ) -> Result<(), SfcError>
+    where
+        F: Serialize,
+        K: Serialize,
+        V: Serialize,
+        W: Write,
+    {
+        Header::new(compression.layout(), self.cell_bits()).write(&mut writer)?;
+
+        match compression {
+            Compression::None => write_checked(&mut writer, self)?,
+            #[cfg(feature = "zstd")]
+            Compression::Zstd(level) => {
+                let mut encoder = zstd::Encoder::new(&mut writer, level).map_err(io)?;
+                write_checked(&mut encoder, self)?;
+                encoder.finish().map_err(io)?;
+            }
+            #[cfg(feature = "lz4")]
+            Compression::Lz4 => {
+                let mut encoder = lz4_flex::frame::FrameEncoder::new(&mut writer);
+                write_checked(&mut encoder, self)?;
+                encoder.finish().map_err(io)?;
+            }
+        }
+
+        writer.flush().map_err(io)
+    }
+
+    /// Reads an index written by [`save`](#method.save),
+    /// [`save_compressed`](#method.save_compressed) or
+    /// [`write_chunked`](#method.write_chunked).
+    ///
+    /// Indexes written with another version of the format are refused,
+    /// see [`FORMAT_VERSION`](constant.FORMAT_VERSION.html).
+    pub fn load<R>(mut reader: R) -> Result<Self, SfcError>
+    where
+        F: DeserializeOwned,
+        K: DeserializeOwned,
+        V: DeserializeOwned,
+        R: Read,
+    {
+        let header = Header::read(&mut reader)?;
+        let index: Self = match header.layout {
+            Layout::Serialized => read_checked(reader, "index")?,
+            Layout::Chunked => Self::read_chunked(reader)?,
+            #[cfg(feature = "zstd")]
+            Layout::SerializedZstd => {
+                let decoder = zstd::Decoder::new(reader).map_err(io)?;
+                read_checked(decoder, "index")?
+            }
+            #[cfg(feature = "lz4")]
+            Layout::SerializedLz4 => {
+                read_checked(lz4_flex::frame::FrameDecoder::new(reader), "index")?
+            }
+            layout => {
+                return Err(SfcError::InvalidFormat(format!(
+                    "Unsupported layout {:?}, the index may be compressed with a disabled feature",
+                    layout
+                )))
+            }
+        };
+        header.check(index.cell_bits())?;
+
+        Ok(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::convert::TryInto;
+
+    use ironsea_index::IndexedDestructured;
+
+    use crate::fixtures::points;
+    use crate::fixtures::Point;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    fn index() -> Index {
+        Index::new(points(300).into_iter(), 2, 4)
+    }
+
+    #[test]
+    fn save() {
+        let mut index = index();
+        index.insert_buffered(Point {
+            position: vec![100, 100],
+            id: 1000,
+        });
+
+        let mut bytes = vec![];
+        index.save(&mut bytes).unwrap();
+        assert_eq!(&bytes[..8], &MAGIC);
+
+        let loaded = Index::load(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.stats(), index.stats());
+        assert_eq!(loaded.cell_bits(), index.cell_bits());
+        assert_eq!(loaded.find(&vec![100, 100]), vec![&1000]);
+        assert_eq!(
+            loaded.find_range(&vec![0, 0], &vec![20, 20]).len(),
+            index.find_range(&vec![0, 0], &vec![20, 20]).len()
+        );
+    }
+
+    #[test]
+    fn mismatch() {
+        let index = index();
+        let mut bytes = vec![];
+        index.save(&mut bytes).unwrap();
+
+        let mut other = bytes.clone();
+        other[8..12].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Index::load(other.as_slice()).err(),
+            Some(SfcError::VersionMismatch {
+                expected: FORMAT_VERSION,
+                found: FORMAT_VERSION + 1
+            })
+        );
+
+        let mut other = bytes.clone();
+        other[0] = b'X';
+        assert!(matches!(
+            Index::load(other.as_slice()),
+            Err(SfcError::InvalidFormat(_))
+        ));
+
+        // A mapped index is not a serialized one.
+        let mut other = bytes.clone();
+        other[12..16].copy_from_slice(&(Layout::Mapped as u32).to_le_bytes());
+        assert!(matches!(
+            Index::load(other.as_slice()),
+            Err(SfcError::InvalidFormat(_))
+        ));
+
+        // Neither are cell bits of another index.
+        let (header, len) = Header::parse(&bytes).unwrap();
+        assert_eq!(header.layout, Layout::Serialized);
+        assert_eq!(header.cell_bits, index.cell_bits());
+        let mut other = bytes.clone();
+        let bits = u32::from_le_bytes(other[20..24].try_into().unwrap()) + 1;
+        other[20..24].copy_from_slice(&bits.to_le_bytes());
+        assert!(matches!(
+            Index::load(other.as_slice()),
+            Err(SfcError::InvalidFormat(_))
+        ));
+
+        assert!(Index::load(&bytes[..len - 1]).is_err());
+
+        // Truncated or damaged indexes are detected by their checksum.
+        assert_eq!(
+            Index::load(&bytes[..bytes.len() - 2]).err(),
+            Some(corrupted("index"))
+        );
+        let mut other = bytes.clone();
+        let last = other.len() - 10;
+        other[last] ^= 1;
+        assert_eq!(
+            Index::load(other.as_slice()).err(),
+            Some(corrupted("index"))
+        );
+
+        // Nor an unknown layout.
+        let mut other = bytes;
+        other[12..16].copy_from_slice(&42u32.to_le_bytes());
+        assert!(matches!(
+            Index::load(other.as_slice()),
+            Err(SfcError::InvalidFormat(_))
+        ));
+    }
+
+    #[cfg(any(feature = "zstd", feature = "lz4"))]
+    fn roundtrip(compression: Compression) {
+        let index = index();
+        let mut plain = vec![];
+        index.save(&mut plain).unwrap();
+
+        let mut bytes = vec![];
+        index.save_compressed(&mut bytes, compression).unwrap();
+        assert!(bytes.len() < plain.len());
+
+        let loaded = Index::load(bytes.as_slice()).unwrap();
+        assert_eq!(loaded.stats(), index.stats());
+        assert_eq!(
+            loaded.find_range(&vec![0, 0], &vec![20, 20]).len(),
+            index.find_range(&vec![0, 0], &vec![20, 20]).len()
+        );
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd() {
+        roundtrip(Compression::Zstd(3));
+    }
+
+    #[cfg(feature = "lz4")]
+    #[test]
+    fn lz4() {
+        roundtrip(Compression::Lz4);
+    }
+}
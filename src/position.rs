@@ -0,0 +1,207 @@
+use std::convert::TryInto;
+use std::fmt::Debug;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Per-dimension access to the coordinates of a key.
+///
+/// Vectors, boxed slices and arrays of coordinates implement this trait,
+/// all the dimensions sharing the same value type `V`, as well as points
+/// of `nalgebra` with the `nalgebra` feature. Other key types implement
+/// it directly, usually by forwarding to one of those.
+///
+/// Tuple keys, such as `(A, B, C)`, are supported as well, where every
+/// dimension has its own type. In that case `V` is the matching
+/// `CoordinateN` enum, for example `Coordinate3<A, B, C>`, which stores
+/// one variant per dimension.
+pub trait Position<V>: Debug + Sized {
+    /// Returns the coordinate of the key along dimension `k`.
+    fn coordinate(&self, k: usize) -> V;
+
+    /// Rebuilds a key from its coordinates, in dimension order.
+    fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = V>;
+
+    /// Returns the number of coordinates of the key, or `None` when it
+    /// is unknown, in which case the key is assumed to have one per
+    /// dimension of the index.
+    fn dimensions(&self) -> Option<usize> {
+        None
+    }
+}
+
+/// Record whose key can be borrowed as a slice of coordinates, one per
+/// dimension.
+///
+/// Unlike `Record::key`, reading the key does not build a new value, so
+/// that indexing in-memory data, for example columns of coordinates,
+/// does not allocate a key per record. See
+/// [`new_from_slices`](struct.IndexOwned.html#method.new_from_slices).
+pub trait RecordSlice<V> {
+    /// Returns the coordinates of the record, in dimension order.
+    fn key_slice(&self) -> &[V];
+}
+
+impl<V> Position<V> for Vec<V>
+where
+    V: Clone + Debug,
+{
+    fn coordinate(&self, k: usize) -> V {
+        self[k].clone()
+    }
+
+    fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = V>,
+    {
+        Ok(coordinates.into_iter().collect())
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<V> Position<V> for Box<[V]>
+where
+    V: Clone + Debug,
+{
+    fn coordinate(&self, k: usize) -> V {
+        self[k].clone()
+    }
+
+    fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = V>,
+    {
+        Ok(coordinates.into_iter().collect())
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+impl<V, const N: usize> Position<V> for [V; N]
+where
+    V: Clone + Debug,
+{
+    fn coordinate(&self, k: usize) -> V {
+        self[k].clone()
+    }
+
+    fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = V>,
+    {
+        let coordinates = coordinates.into_iter().collect::<Vec<_>>();
+        let found = coordinates.len();
+
+        coordinates
+            .try_into()
+            .map_err(|_| format!("Expected {} coordinates, got {}", N, found))
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        Some(N)
+    }
+}
+
+#[cfg(feature = "nalgebra")]
+impl<V, const N: usize> Position<V> for nalgebra::Point<V, N>
+where
+    V: nalgebra::Scalar,
+{
+    fn coordinate(&self, k: usize) -> V {
+        self[k].clone()
+    }
+
+    fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+    where
+        I: IntoIterator<Item = V>,
+    {
+        let coordinates = coordinates.into_iter().collect::<Vec<_>>();
+        if coordinates.len() != N {
+            return Err(format!(
+                "Expected {} coordinates, got {}",
+                N,
+                coordinates.len()
+            ));
+        }
+
+        Ok(Self::from_slice(&coordinates))
+    }
+
+    fn dimensions(&self) -> Option<usize> {
+        Some(N)
+    }
+}
+
+macro_rules! tuple_position {
+    ($(#[$meta:meta])* $name:ident { $($idx:tt => $variant:ident: $t:ident),+ }) => {
+        $(#[$meta])*
+        #[derive(Clone, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+        #[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+        pub enum $name<$($t),+> {
+            $(
+                #[allow(missing_docs)]
+                $variant($t),
+            )+
+        }
+
+        impl<$($t),+> Position<$name<$($t),+>> for ($($t,)+)
+        where
+            $($t: Clone + Debug,)+
+        {
+            fn coordinate(&self, k: usize) -> $name<$($t),+> {
+                match k {
+                    $($idx => $name::$variant(self.$idx.clone()),)+
+                    _ => panic!("Incorrect dimension {} for {:?}", k, self),
+                }
+            }
+
+            fn from_coordinates<I>(coordinates: I) -> Result<Self, String>
+            where
+                I: IntoIterator<Item = $name<$($t),+>>,
+            {
+                let mut coordinates = coordinates.into_iter();
+
+                Ok(($(
+                    match coordinates.next() {
+                        Some($name::$variant(v)) => v,
+                        Some(c) => {
+                            return Err(format!(
+                                "Incorrect coordinate for dimension {}: {:?}",
+                                $idx, c
+                            ))
+                        }
+                        None => return Err(format!("Missing coordinate for dimension {}", $idx)),
+                    },
+                )+))
+            }
+
+            fn dimensions(&self) -> Option<usize> {
+                Some([$($idx),+].len())
+            }
+        }
+    };
+}
+
+tuple_position!(
+    /// Coordinate of a two-dimensional tuple key, `(A, B)`.
+    Coordinate2 { 0 => D0: A, 1 => D1: B }
+);
+
+tuple_position!(
+    /// Coordinate of a three-dimensional tuple key, `(A, B, C)`.
+    Coordinate3 { 0 => D0: A, 1 => D1: B, 2 => D2: C }
+);
+
+tuple_position!(
+    /// Coordinate of a four-dimensional tuple key, `(A, B, C, D)`.
+    Coordinate4 { 0 => D0: A, 1 => D1: B, 2 => D2: C, 3 => D3: D }
+);
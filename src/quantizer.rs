@@ -0,0 +1,401 @@
+//! Computed mappings of the values of a dimension to cells, replacing its
+//! dictionary.
+
+use std::convert::TryFrom;
+use std::fmt::Debug;
+use std::panic::RefUnwindSafe;
+
+/// Mapping of the values of a dimension to cells, and to offsets within
+/// them, used instead of the dictionary of the dimension.
+///
+/// A dictionary stores every distinct value of its dimension, sorted and
+/// split into cells, which takes memory and requires all the values
+/// beforehand. A quantizer computes the cell and offset of a value
+/// instead. Positions are rebuilt from their cells and offsets, so the
+/// mapping has to be lossless: `value` must return the value given to
+/// `key`. Values must be ordered as their (cell, offset) pairs, and every
+/// cell must hold at least one value.
+///
+/// See [`Uniform`](struct.Uniform.html) and
+/// [`Breakpoints`](struct.Breakpoints.html) for integer coordinates, and
+/// [`new_quantized`](struct.IndexOwned.html#method.new_quantized) to use
+/// them.
+pub trait Quantizer<V>: Debug + RefUnwindSafe + Send + Sync {
+    /// Returns the number of cells, at most 1024.
+    fn cells(&self) -> usize;
+
+    /// Returns the number of values of `cell`.
+    fn offsets(&self, cell: usize) -> usize;
+
+    /// Returns the cell and offset of `value`, or `None` when the
+    /// quantizer cannot represent it.
+    fn key(&self, value: &V) -> Option<(usize, usize)>;
+
+    /// Returns the value at `offset` in `cell`, or `None` past the last
+    /// value.
+    fn value(&self, cell: usize, offset: usize) -> Option<V>;
+}
+
+// First of `0..n` for which `predicate` is false, `predicate` being true
+// for all the numbers before it and false for all the ones after.
+fn partition<P: Fn(usize) -> bool>(n: usize, predicate: P) -> usize {
+    let (mut low, mut high) = (0, n);
+    while low < high {
+        let middle = low + (high - low) / 2;
+        if predicate(middle) {
+            low = middle + 1;
+        } else {
+            high = middle;
+        }
+    }
+
+    low
+}
+
+// Cell and offset of the first value for which `predicate` is false, see
+// `slice::partition_point`. Returns `None` if it is true for all of them.
+pub(crate) fn search<V, Q, P>(quantizer: &Q, predicate: P) -> Option<(usize, usize)>
+where
+    Q: Quantizer<V> + ?Sized,
+    P: Fn(&V) -> bool,
+{
+    let holds = |cell, offset| matches!(quantizer.value(cell, offset), Some(v) if predicate(&v));
+
+    let cell = partition(quantizer.cells(), |cell| {
+        holds(cell, quantizer.offsets(cell) - 1)
+    });
+    if cell == quantizer.cells() {
+        return None;
+    }
+
+    Some((
+        cell,
+        partition(quantizer.offsets(cell), |offset| holds(cell, offset)),
+    ))
+}
+
+pub(crate) fn last<V, Q: Quantizer<V> + ?Sized>(quantizer: &Q) -> (usize, usize) {
+    let cell = quantizer.cells() - 1;
+
+    (cell, quantizer.offsets(cell) - 1)
+}
+
+// Cell and offset of `value`, or else of the largest value below it, or
+// of the first value if there is none.
+pub(crate) fn key_down<V, Q>(quantizer: &Q, value: &V) -> (usize, usize)
+where
+    V: Ord,
+    Q: Quantizer<V> + ?Sized,
+{
+    match search(quantizer, |v| v <= value) {
+        None => last(quantizer),
+        Some((0, 0)) => (0, 0),
+        Some((cell, 0)) => (cell - 1, quantizer.offsets(cell - 1) - 1),
+        Some((cell, offset)) => (cell, offset - 1),
+    }
+}
+
+// Cell and offset of `value`, or else of the smallest value above it, or
+// of the last value if there is none.
+pub(crate) fn key_up<V, Q>(quantizer: &Q, value: &V) -> (usize, usize)
+where
+    V: Ord,
+    Q: Quantizer<V> + ?Sized,
+{
+    search(quantizer, |v| v < value).unwrap_or_else(|| last(quantizer))
+}
+
+// Integer coordinates, computed on as `i128`, which holds any `i64` or
+// `u64` value as well as the difference of two of them.
+fn wide<V: Copy + Into<i128>>(value: &V) -> i128 {
+    (*value).into()
+}
+
+fn narrow<V: TryFrom<i128>>(value: i128) -> Option<V> {
+    V::try_from(value).ok()
+}
+
+/// Quantizer splitting the integers of `[min, max]` into cells of equal
+/// width.
+///
+/// This suits values spread evenly over a known interval, for example
+/// timestamps. Offsets are stored as `u32`, so values more than 2^32
+/// apart may not fit in a cell, in which case their records are
+/// rejected.
+#[derive(Clone, Debug)]
+pub struct Uniform<V> {
+    min: V,
+    max: V,
+    width: i128,
+    cells: usize,
+}
+
+impl<V> Uniform<V>
+where
+    V: Copy + Into<i128>,
+{
+    /// Creates a quantizer of at most `cells` cells for the values of
+    /// `[min, max]`.
+    ///
+    /// Returns `None` if `max` is smaller than `min`, or `cells` is 0.
+    pub fn new(min: V, max: V, cells: usize) -> Option<Self> {
+        let span = wide(&max) - wide(&min) + 1;
+        if span <= 0 || cells == 0 {
+            return None;
+        }
+
+        let cells = (cells as i128).min(span);
+        let width = (span + cells - 1) / cells;
+
+        Some(Uniform {
+            min,
+            max,
+            width,
+            cells: ((span + width - 1) / width) as usize,
+        })
+    }
+}
+
+impl<V> Quantizer<V> for Uniform<V>
+where
+    V: Copy + Debug + Into<i128> + TryFrom<i128> + RefUnwindSafe + Send + Sync,
+{
+    fn cells(&self) -> usize {
+        self.cells
+    }
+
+    fn offsets(&self, cell: usize) -> usize {
+        let start = wide(&self.min) + cell as i128 * self.width;
+
+        (wide(&self.max) - start + 1).clamp(0, self.width) as usize
+    }
+
+    fn key(&self, value: &V) -> Option<(usize, usize)> {
+        let distance = wide(value) - wide(&self.min);
+        if distance < 0 || wide(value) > wide(&self.max) {
+            return None;
+        }
+
+        Some((
+            usize::try_from(distance / self.width).ok()?,
+            usize::try_from(distance % self.width).ok()?,
+        ))
+    }
+
+    fn value(&self, cell: usize, offset: usize) -> Option<V> {
+        if cell >= self.cells || offset >= self.offsets(cell) {
+            return None;
+        }
+
+        narrow(wide(&self.min) + cell as i128 * self.width + offset as i128)
+    }
+}
+
+/// Quantizer splitting integers at given breakpoints, each cell holding
+/// the values from its breakpoint up to the next one.
+///
+/// This suits values whose distribution is known in advance.
+/// [`quantiles`](#method.quantiles) builds cells holding about as many
+/// values of a sample, and [`log`](#method.log) cells whose widths grow
+/// exponentially, for values concentrated near their minimum. Offsets
+/// are stored as `u32`, see [`Uniform`](struct.Uniform.html).
+#[derive(Clone, Debug)]
+pub struct Breakpoints<V> {
+    starts: Vec<V>,
+    max: V,
+}
+
+impl<V> Breakpoints<V>
+where
+    V: Copy + Into<i128> + TryFrom<i128>,
+{
+    /// Creates a quantizer with one cell per value of `starts`, the
+    /// first value of the cell, and `max` as the largest value of the
+    /// last cell. `starts` does not have to be sorted, duplicates are
+    /// ignored.
+    ///
+    /// Returns `None` if `starts` is empty, or holds values larger than
+    /// `max`.
+    pub fn new(mut starts: Vec<V>, max: V) -> Option<Self> {
+        starts.sort_unstable_by_key(wide);
+        starts.dedup_by_key(|start| wide(start));
+
+        match starts.last() {
+            Some(last) if wide(last) <= wide(&max) => Some(Breakpoints { starts, max }),
+            _ => None,
+        }
+    }
+
+    /// Creates a quantizer of at most `cells` cells for the values of
+    /// `[min, max]`, the width of each cell being a constant factor
+    /// larger than the previous one.
+    ///
+    /// Returns `None` if `max` is smaller than `min`, or `cells` is 0.
+    pub fn log(min: V, max: V, cells: usize) -> Option<Self> {
+        let span = wide(&max) - wide(&min) + 1;
+        if span <= 0 || cells == 0 {
+            return None;
+        }
+
+        // Cell `i` starts `span^(i / cells) - 1` after `min`.
+        let starts = (0..cells)
+            .map(|i| {
+                let distance = (span as f64).powf(i as f64 / cells as f64).round() as i128 - 1;
+                narrow(wide(&min) + distance.clamp(0, span - 1))
+            })
+            .collect::<Option<Vec<_>>>()?;
+
+        Self::new(starts, max)
+    }
+
+    /// Creates a quantizer of at most `cells` cells, starting at the
+    /// quantiles of `sample`, so that each cell holds about as many of
+    /// its values. The first and last cells extend to `min` and `max`.
+    ///
+    /// Values repeated more than `sample.len() / cells` times get a cell
+    /// of their own, so skewed samples may give fewer cells. Dictionaries
+    /// can be split the same way with
+    /// [`CellSizing::Records`](enum.CellSizing.html).
+    ///
+    /// Returns `None` if `max` is smaller than `min`, `cells` is 0, or
+    /// `sample` holds values outside of `[min, max]`.
+    pub fn quantiles(mut sample: Vec<V>, min: V, max: V, cells: usize) -> Option<Self> {
+        if wide(&max) < wide(&min) || cells == 0 {
+            return None;
+        }
+        sample.sort_unstable_by_key(wide);
+        match (sample.first(), sample.last()) {
+            (Some(first), Some(last)) if wide(first) < wide(&min) || wide(last) > wide(&max) => {
+                return None
+            }
+            _ => (),
+        }
+
+        let mut starts = vec![min];
+        starts.extend((1..cells).filter_map(|i| sample.get(i * sample.len() / cells).copied()));
+
+        Self::new(starts, max)
+    }
+}
+
+impl<V> Quantizer<V> for Breakpoints<V>
+where
+    V: Copy + Debug + Into<i128> + TryFrom<i128> + RefUnwindSafe + Send + Sync,
+{
+    fn cells(&self) -> usize {
+        self.starts.len()
+    }
+
+    fn offsets(&self, cell: usize) -> usize {
+        let end = match self.starts.get(cell + 1) {
+            Some(next) => wide(next),
+            None => wide(&self.max) + 1,
+        };
+
+        match self.starts.get(cell) {
+            Some(start) => (end - wide(start)) as usize,
+            None => 0,
+        }
+    }
+
+    fn key(&self, value: &V) -> Option<(usize, usize)> {
+        let value = wide(value);
+        if value > wide(&self.max) {
+            return None;
+        }
+
+        let cell = self
+            .starts
+            .partition_point(|start| wide(start) <= value)
+            .checked_sub(1)?;
+
+        Some((
+            cell,
+            usize::try_from(value - wide(&self.starts[cell])).ok()?,
+        ))
+    }
+
+    fn value(&self, cell: usize, offset: usize) -> Option<V> {
+        if offset >= self.offsets(cell) {
+            return None;
+        }
+
+        narrow(wide(&self.starts[cell]) + offset as i128)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check<Q: Quantizer<i64>>(quantizer: &Q, values: std::ops::RangeInclusive<i64>) {
+        let mut previous = None;
+        for value in values.clone() {
+            let key = quantizer.key(&value).unwrap();
+            assert_eq!(quantizer.value(key.0, key.1), Some(value));
+            assert!(previous < Some(key));
+            previous = Some(key);
+        }
+        assert_eq!(previous, Some(last(quantizer)));
+        assert_eq!(quantizer.key(&(values.start() - 1)), None);
+        assert_eq!(quantizer.key(&(values.end() + 1)), None);
+
+        let count = (0..quantizer.cells())
+            .map(|cell| quantizer.offsets(cell))
+            .sum::<usize>();
+        assert_eq!(count as i64, values.end() - values.start() + 1);
+    }
+
+    #[test]
+    fn uniform() {
+        let quantizer = Uniform::new(-50i64, 49, 8).unwrap();
+        assert_eq!(quantizer.cells(), 8);
+        assert_eq!(quantizer.offsets(0), 13);
+        assert_eq!(quantizer.offsets(7), 9);
+        check(&quantizer, -50..=49);
+
+        assert_eq!(Uniform::new(0i64, 2, 10).unwrap().cells(), 3);
+        assert!(Uniform::new(1i64, 0, 10).is_none());
+        assert!(Uniform::new(0i64, 1, 0).is_none());
+
+        let large = Uniform::new(u64::MAX - 99, u64::MAX, 10).unwrap();
+        assert_eq!(large.key(&u64::MAX), Some((9, 9)));
+        assert_eq!(large.value(9, 9), Some(u64::MAX));
+    }
+
+    #[test]
+    fn breakpoints() {
+        let quantizer = Breakpoints::new(vec![10i64, -5, 0, 10], 20).unwrap();
+        assert_eq!(quantizer.cells(), 3);
+        assert_eq!(quantizer.key(&3), Some((1, 3)));
+        check(&quantizer, -5..=20);
+        assert!(Breakpoints::new(vec![0i64, 30], 20).is_none());
+        assert!(Breakpoints::<i64>::new(vec![], 20).is_none());
+
+        let log = Breakpoints::log(0i64, 9999, 4).unwrap();
+        assert_eq!(log.starts, vec![0, 9, 99, 999]);
+        check(&log, 0..=9999);
+
+        // Squares: dense near 0, sparse near 10000.
+        let sample = (0..100i64).map(|i| i * i).collect::<Vec<_>>();
+        let quantiles = Breakpoints::quantiles(sample, -10, 10000, 4).unwrap();
+        assert_eq!(quantiles.starts, vec![-10, 625, 2500, 5625]);
+        check(&quantiles, -10..=10000);
+
+        let skewed = Breakpoints::quantiles(vec![0i64; 99], 0, 99, 4).unwrap();
+        assert_eq!(skewed.cells(), 1);
+        assert!(Breakpoints::quantiles(vec![100i64], 0, 99, 4).is_none());
+    }
+
+    #[test]
+    fn rounding() {
+        let quantizer = Breakpoints::new(vec![0i64, 10, 20], 29).unwrap();
+
+        assert_eq!(key_down(&quantizer, &-3), (0, 0));
+        assert_eq!(key_down(&quantizer, &10), (1, 0));
+        assert_eq!(key_up(&quantizer, &19), (1, 9));
+        assert_eq!(key_up(&quantizer, &100), (2, 9));
+        assert_eq!(search(&quantizer, |v| *v < 15), Some((1, 5)));
+        assert_eq!(search(&quantizer, |_| true), None);
+    }
+}
@@ -0,0 +1,99 @@
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use super::morton::MortonCode;
+
+/// Work done by a range query, as reported by
+/// [`explain_range`](struct.IndexOwned.html#method.explain_range).
+///
+/// Cells whose bounds are within the range are contained: all their
+/// records are returned. The records of the other cells scanned are
+/// compared with the range one by one.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct RangeExplain {
+    /// Code of the first cell of the range, after rounding the start of
+    /// the range down to a value of the dictionaries.
+    pub start_code: MortonCode,
+    /// Code of the last cell of the range, after rounding the end of the
+    /// range up to a value of the dictionaries.
+    pub end_code: MortonCode,
+    /// Number of cells between the two codes, all scanned.
+    pub cells_scanned: usize,
+    /// Number of cells scanned contained in the range.
+    pub cells_contained: usize,
+    /// Number of cells scanned whose records are compared with the range.
+    pub cells_filtered: usize,
+    /// Number of records of the cells scanned and of the write buffer.
+    pub records_examined: usize,
+    /// Number of records in the range.
+    pub records_returned: usize,
+}
+
+/// Position from which to resume a range query, see
+/// [`find_range_page`](struct.IndexOwned.html#method.find_range_page).
+///
+/// It can be serialized, to be handed over to the clients of a service
+/// between pages.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct Cursor {
+    pub(crate) resume: Resume,
+}
+
+// Next record to examine: a record of the cell with the given code, or of
+// the write buffer, by position.
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub(crate) enum Resume {
+    Cell { code: MortonCode, record: usize },
+    Buffer(usize),
+}
+
+/// Order of the results of a query.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub enum Order {
+    /// Order of the records along the curve, the cheapest to produce.
+    #[default]
+    Curve,
+    /// Positions in ascending lexicographic order of their coordinates.
+    Ascending,
+    /// Positions in descending lexicographic order of their coordinates.
+    Descending,
+}
+
+/// Description of a query, which can be deserialized, for example from
+/// JSON, and executed with `IndexOwned::run_query`.
+///
+/// All the fields are optional when deserializing: a missing field does
+/// not restrict the results.
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(Deserialize, Serialize),
+    serde(bound(deserialize = "F: Deserialize<'de>, V: Deserialize<'de>"))
+)]
+pub struct QuerySpec<F, V> {
+    /// Lower bound of the range, one per dimension, `None` leaving the
+    /// dimension unbounded. An empty vector leaves all of them unbounded.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub start: Vec<Option<V>>,
+    /// Upper bound of the range, as for `start`.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub end: Vec<Option<V>>,
+    /// Only keep the records whose values are equal to one of these. An
+    /// empty vector keeps all the records.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub values: Vec<F>,
+    /// Order of the results.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub order: Order,
+    /// Number of results to skip, once ordered.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub skip: usize,
+    /// Maximum number of results to return.
+    #[cfg_attr(feature = "serde", serde(default))]
+    pub limit: Option<usize>,
+}
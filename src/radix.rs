@@ -0,0 +1,104 @@
+// LSD radix sort on Morton codes, used instead of comparison sorts to
+// build large indices.
+
+use super::morton::MortonCode;
+use super::sfc::flat_order;
+use super::sfc::FlatEntry;
+
+// Bits sorted per pass.
+const RADIX_BITS: usize = 8;
+const RADIX: usize = 1 << RADIX_BITS;
+
+// Stable sort of `items` by the code returned by `code`.
+pub(crate) fn sort_by_code<T, C>(items: &mut Vec<T>, code: C)
+where
+    C: Fn(&T) -> MortonCode,
+{
+    let mut keys = items
+        .iter()
+        .enumerate()
+        .map(|(i, item)| (code(item), i))
+        .collect::<Vec<_>>();
+
+    // Only sort the bytes used by the largest code.
+    let max = keys.iter().map(|(code, _)| *code).max().unwrap_or(0);
+    let bits = std::mem::size_of::<MortonCode>() * 8 - max.leading_zeros() as usize;
+
+    let mut sorted = keys.clone();
+    for shift in (0..bits).step_by(RADIX_BITS) {
+        let digit = |code: MortonCode| (code >> shift) as usize & (RADIX - 1);
+
+        let mut counts = [0usize; RADIX];
+        for (code, _) in &keys {
+            counts[digit(*code)] += 1;
+        }
+        let mut next = 0;
+        for count in counts.iter_mut() {
+            let c = *count;
+            *count = next;
+            next += c;
+        }
+
+        for key in &keys {
+            let d = digit(key.0);
+            sorted[counts[d]] = *key;
+            counts[d] += 1;
+        }
+        std::mem::swap(&mut keys, &mut sorted);
+    }
+
+    let mut slots = items.drain(..).map(Some).collect::<Vec<_>>();
+    items.extend(keys.into_iter().filter_map(|(_, i)| slots[i].take()));
+}
+
+// Sort the flat table in `flat_order`: by code with a radix sort, then
+// within each code with a comparison sort.
+pub(crate) fn sort_flat_table<F>(table: &mut Vec<FlatEntry<F>>) {
+    sort_by_code(table, |entry| entry.0);
+
+    let mut start = 0;
+    while start < table.len() {
+        let code = table[start].0;
+        let end = start + table[start..].iter().take_while(|e| e.0 == code).count();
+        table[start..end].sort_unstable_by(flat_order);
+        start = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sort_by_code() {
+        let mut seed = 0x2545_f491u64;
+        let mut items = (0..2000)
+            .map(|i| {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                (seed >> (i % 64), i)
+            })
+            .collect::<Vec<_>>();
+        let mut expected = items.clone();
+        expected.sort_by_key(|(code, _)| *code);
+
+        super::sort_by_code(&mut items, |(code, _)| *code);
+        assert_eq!(items, expected);
+
+        let mut empty: Vec<(MortonCode, usize)> = vec![];
+        super::sort_by_code(&mut empty, |(code, _)| *code);
+        assert!(empty.is_empty());
+    }
+
+    #[test]
+    fn sort_flat_table() {
+        let mut table = (0..500usize)
+            .map(|i| ((i % 17) as MortonCode, i, (vec![(i % 3) as u32], i)))
+            .rev()
+            .collect::<Vec<_>>();
+        let mut expected = table.clone();
+        expected.sort_unstable_by(flat_order);
+
+        super::sort_flat_table(&mut table);
+        assert_eq!(table, expected);
+    }
+}
@@ -0,0 +1,15 @@
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+/// Identifier of a record of an index, see
+/// [`get`](struct.IndexOwned.html#method.get).
+///
+/// Records of a new index are numbered by their position in the source
+/// iterator, and inserted records take the following numbers. A record
+/// keeps its identifier while other records are inserted or removed, and
+/// identifiers of removed records are not reused.
+#[derive(Clone, Copy, Debug, Eq, Hash, Ord, PartialEq, PartialOrd)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct RecordId(pub u64);
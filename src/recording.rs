@@ -0,0 +1,239 @@
+//! Recording of the queries executed on an index, to replay them later
+//! against another build of the index.
+//!
+//! Comparing `cell_bits` or encoder configurations on the queries of a
+//! real workload is more telling than on synthetic ones. Queries run
+//! with [`run_query_recorded`](../struct.IndexOwned.html#method.run_query_recorded)
+//! are appended to a writer, from which
+//! [`replay`](../struct.IndexOwned.html#method.replay) reads them back.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io::BufRead;
+use std::io::BufReader;
+use std::io::Read;
+use std::io::Write;
+use std::time::Duration;
+use std::time::Instant;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde::Serialize;
+
+use crate::error::SfcError;
+use crate::position::Position;
+use crate::query::QuerySpec;
+use crate::sfc::SpaceFillingCurve;
+
+/// A query, as executed on an index.
+#[derive(Clone, Debug, Deserialize, PartialEq, Serialize)]
+pub struct QueryRecord<F, V> {
+    /// Query, with the bounds expanded to one per dimension.
+    pub spec: QuerySpec<F, V>,
+    /// Time taken by the query.
+    pub duration: Duration,
+    /// Number of records returned.
+    pub results: usize,
+}
+
+/// Outcome of a recorded query, replayed on an index.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Replay {
+    /// Time taken by the query when recorded.
+    pub recorded: Duration,
+    /// Time taken by the query when replayed.
+    pub replayed: Duration,
+    /// Number of records returned when recorded.
+    pub recorded_results: usize,
+    /// Number of records returned when replayed.
+    pub replayed_results: usize,
+}
+
+/// Writes the executed queries to a writer.
+#[derive(Debug)]
+pub struct Recorder<W> {
+    writer: W,
+    count: usize,
+}
+
+impl<W> Recorder<W>
+where
+    W: Write,
+{
+    /// Creates a recorder appending the queries to `writer`.
+    pub fn new(writer: W) -> Self {
+        Recorder { writer, count: 0 }
+    }
+
+    /// Appends `record` to the writer.
+    pub fn record<F, V>(&mut self, record: &QueryRecord<F, V>) -> Result<(), SfcError>
+    where
+        F: Serialize,
+        V: Serialize,
+    {
+        bincode::serialize_into(&mut self.writer, record)
+            .map_err(|e| SfcError::Io(e.to_string()))?;
+        self.count += 1;
+
+        Ok(())
+    }
+
+    /// Returns the number of queries recorded.
+    pub fn count(&self) -> usize {
+        self.count
+    }
+
+    /// Returns the writer, after flushing it.
+    pub fn into_inner(mut self) -> Result<W, SfcError> {
+        self.writer
+            .flush()
+            .map_err(|e| SfcError::Io(e.to_string()))?;
+
+        Ok(self.writer)
+    }
+}
+
+/// Reads back all the queries written by a [`Recorder`](struct.Recorder.html).
+pub fn read_records<R, F, V>(reader: R) -> Result<Vec<QueryRecord<F, V>>, SfcError>
+where
+    R: Read,
+    F: DeserializeOwned,
+    V: DeserializeOwned,
+{
+    let mut reader = BufReader::new(reader);
+    let mut records = vec![];
+    while !reader
+        .fill_buf()
+        .map_err(|e| SfcError::Io(e.to_string()))?
+        .is_empty()
+    {
+        records
+            .push(bincode::deserialize_from(&mut reader).map_err(|e| SfcError::Io(e.to_string()))?);
+    }
+
+    Ok(records)
+}
+
+impl<F, K, V> SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    /// Executes `spec` as [`run_query`](#method.run_query) does, and
+    /// records it with its duration and number of results.
+    pub fn run_query_recorded<W>(
+        &self,
+        spec: &QuerySpec<F, V>,
+        recorder: &mut Recorder<W>,
+    ) -> Result<Vec<(K, &F)>, SfcError>
+    where
+        F: Clone + Serialize,
+        V: Serialize,
+        W: Write,
+    {
+        let start = Instant::now();
+        let results = self.run_query(spec)?;
+        let duration = start.elapsed();
+
+        let dimensions = self.cell_bits().len();
+        let expand = |bounds: &[Option<V>]| {
+            if bounds.is_empty() {
+                vec![None; dimensions]
+            } else {
+                bounds.to_vec()
+            }
+        };
+        let spec = QuerySpec {
+            start: expand(&spec.start),
+            end: expand(&spec.end),
+            ..spec.clone()
+        };
+        recorder.record(&QueryRecord {
+            spec,
+            duration,
+            results: results.len(),
+        })?;
+
+        Ok(results)
+    }
+
+    /// Executes the queries read from `reader`, returning for each of
+    /// them the recorded and the new durations and number of results.
+    pub fn replay<R>(&self, reader: R) -> Result<Vec<Replay>, SfcError>
+    where
+        F: DeserializeOwned,
+        V: DeserializeOwned,
+        R: Read,
+    {
+        read_records::<R, F, V>(reader)?
+            .into_iter()
+            .map(|record| {
+                let start = Instant::now();
+                let results = self.run_query(&record.spec)?.len();
+
+                Ok(Replay {
+                    recorded: record.duration,
+                    replayed: start.elapsed(),
+                    recorded_results: record.results,
+                    replayed_results: results,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::points;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    #[test]
+    fn replay() {
+        let points = points(500);
+        let index = Index::new(points.iter().cloned(), 2, 3);
+
+        let mut recorder = Recorder::new(vec![]);
+        let specs = vec![
+            QuerySpec {
+                start: vec![Some(5), None],
+                end: vec![Some(20), Some(30)],
+                values: vec![],
+                order: Default::default(),
+                skip: 0,
+                limit: None,
+            },
+            QuerySpec {
+                start: vec![],
+                end: vec![],
+                values: vec![3, 4, 5],
+                order: Default::default(),
+                skip: 0,
+                limit: Some(2),
+            },
+        ];
+        let mut counts = vec![];
+        for spec in &specs {
+            counts.push(index.run_query_recorded(spec, &mut recorder).unwrap().len());
+        }
+        assert_eq!(recorder.count(), 2);
+        let bytes = recorder.into_inner().unwrap();
+
+        let records = read_records::<_, u32, u16>(&bytes[..]).unwrap();
+        assert_eq!(records[0].spec, specs[0]);
+        assert_eq!(records[1].spec.start, vec![None, None]);
+
+        let other = Index::new(points.iter().cloned(), 2, 5);
+        let replays = other.replay(&bytes[..]).unwrap();
+        assert_eq!(replays.len(), 2);
+        for (replay, count) in replays.iter().zip(counts) {
+            assert_eq!(replay.recorded_results, count);
+            assert_eq!(replay.replayed_results, count);
+        }
+    }
+}
@@ -0,0 +1,17 @@
+/// Arbitrary region of the space, for
+/// [`find_in_region`](struct.IndexOwned.html#method.find_in_region).
+///
+/// Points and boxes are given as one coordinate per dimension.
+pub trait Region<V> {
+    /// Returns whether `point` is within the region.
+    fn contains(&self, point: &[&V]) -> bool;
+
+    /// Returns whether the region intersects the box `[min, max]`, bounds
+    /// included.
+    ///
+    /// This is used to skip the cells of the index which cannot hold any
+    /// point of the region, so it may return `true` for boxes which do
+    /// not intersect the region, at the cost of scanning them for
+    /// nothing, but must never return `false` for boxes which do.
+    fn intersects_box(&self, min: &[&V], max: &[&V]) -> bool;
+}
@@ -1,388 +1,7726 @@
 #![allow(clippy::type_repetition_in_bounds)]
 
+use std::borrow::Borrow;
+use std::borrow::Cow;
+use std::cmp::Ordering;
 use std::cmp::PartialEq;
+use std::cmp::Reverse;
+use std::collections::BTreeSet;
+use std::collections::BinaryHeap;
+use std::collections::HashMap;
+use std::collections::HashSet;
+use std::convert::TryFrom;
 use std::fmt::Debug;
 use std::hash::Hash;
+use std::ops::Bound;
+use std::ops::Range;
+use std::ops::RangeBounds;
+#[cfg(feature = "serde")]
+use std::path::Path;
+use std::sync::Arc;
+use std::sync::OnceLock;
 //use std::io;
-use std::iter::FromIterator;
-use std::ops::Index;
 
 pub use ironsea_index::IndexedDestructured;
 pub use ironsea_index::Record;
 pub use ironsea_index::RecordFields;
 //use ironsea_store::Load;
 //use ironsea_store::Store;
-//use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
+use serde::de::DeserializeOwned;
+#[cfg(feature = "serde")]
 use serde::Deserialize;
+#[cfg(feature = "serde")]
 use serde::Serialize;
 
+use super::advice::Advice;
+use super::build::BuildPolicy;
+use super::build::BuildReport;
+use super::build::CellSizing;
+use super::build::CompactionReport;
+use super::build::Normalized;
+use super::build::Rejection;
+use super::builder::SpaceFillingCurveBuilder;
+use super::cell_space::CellLayout;
 use super::cell_space::CellSpace;
+use super::cell_space::Inserted;
+use super::dimension::Dimension;
+use super::error::SfcError;
+use super::frozen::FrozenIndex;
+use super::metric::Euclidean;
+use super::metric::Metric;
 use super::morton::MortonCode;
 use super::morton::MortonEncoder;
 use super::morton::MortonValue;
+use super::morton::MORTON_VALUE_BITS;
+use super::overlay::OverlayView;
+use super::position::Position;
+use super::position::RecordSlice;
+use super::quantizer::Quantizer;
+use super::query::Cursor;
+use super::query::Order;
+use super::query::QuerySpec;
+use super::query::RangeExplain;
+use super::query::Resume;
+#[cfg(feature = "radix")]
+use super::radix;
+use super::record_id::RecordId;
+use super::region::Region;
+#[cfg(feature = "serde")]
+use super::spill::Merge;
+#[cfg(feature = "serde")]
+use super::spill::Run;
+use super::stats::CellSummary;
+use super::stats::IndexStats;
+use super::stats::MemoryBreakdown;
+use super::violation::Violation;
+use super::visit::CellVisit;
 
-type SFCCode = MortonCode;
-type SFCOffset = u32;
+pub(crate) type SFCCode = MortonCode;
+pub(crate) type SFCOffset = u32;
+type SFCValue = u32;
 
-//FIXME: Remove the need for a constant, how can we make it type-checked instead?
-//       type-num crate?
-const MAX_K: usize = 3;
+// Record of the flat table built by `new_normalized`: code, position in the
+// source iterator, offsets and values.
+pub(crate) type FlatEntry<F> = (SFCCode, usize, (Vec<SFCOffset>, F));
+
+// Code, records and values of a cell, see `cells`.
+#[cfg(feature = "serde")]
+pub(crate) type CellParts<'a, F> = (SFCCode, Vec<(&'a [SFCOffset], usize)>, &'a [F]);
+
+// Cell holding the records at a key, and the offsets of the key.
+type Lookup<'a, F> = Option<(&'a SFCCell<F>, Vec<SFCOffset>)>;
+
+// Records of two indexes close to each other, and their distance, see
+// `spatial_join`.
+type JoinedPair<'a, 'b, F, G, K> = (f64, (K, &'a F), (K, &'b G));
+
+// Record within a distance of a center, with that distance, see `within`.
+type Neighbour<'a, F, K> = (f64, RecordId, K, &'a F);
+
+// Thresholds used by `advise`.
+const ADVICE_SAMPLES: usize = 32;
+const ADVICE_MAX_OCCUPANCY: f64 = 64.0;
+const ADVICE_MIN_OCCUPANCY: f64 = 1.5;
+const ADVICE_MAX_SCAN_RATIO: f64 = 8.0;
+const ADVICE_MIN_SHARED: f64 = 0.5;
+const ADVICE_MAX_BUFFERED: usize = 1024;
+
+// Number of records per cell targeted by `with_defaults`.
+const DEFAULT_OCCUPANCY: usize = 16;
+
+// Number of duplicates listed in a `BuildReport`.
+const DUPLICATE_SAMPLES: usize = 16;
 
 #[derive(Debug)]
 struct Limit<V> {
     idx: usize,
+    code: SFCCode,
     position: Vec<V>,
 }
 
 #[derive(Debug)]
-struct Limits<'a, V> {
-    start: Limit<&'a V>,
-    end: Limit<&'a V>,
+struct Limits<'a, V: Clone> {
+    start: Limit<Cow<'a, V>>,
+    end: Limit<Cow<'a, V>>,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
-struct SFCRecord<F> {
-    //FIXME: Find a way around hardcoding MAX_K
-    offsets: [SFCOffset; MAX_K],
-    fields: F,
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+struct SFCRecord {
+    // Index of the values of the record in the table of its cell.
+    value: SFCValue,
+    id: RecordId,
 }
 
-#[derive(Clone, Debug, Deserialize, Serialize)]
+// Only cells holding at least one record are stored, so sparse regions
+// do not lengthen the list of cells. Records are located from the code of
+// their cell, which is why adjacent cells are never coalesced.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
 struct SFCCell<F> {
     code: MortonCode,
-    records: Vec<SFCRecord<F>>,
+    records: Vec<SFCRecord>,
+    // Offsets of the records, one per dimension, stored one record after
+    // the other so that records do not each need an allocation.
+    offsets: Vec<SFCOffset>,
+    // Values of the records. When compressed, records with equal values
+    // share a single entry.
+    values: Vec<F>,
 }
 
-/// Space Filling Curve-based index.
-///
-/// This structure retains the state of the index.
-#[derive(Clone, Debug, Deserialize, Serialize)]
-pub struct SpaceFillingCurve<F, K, V>
+impl<F> SFCCell<F>
 where
     F: PartialEq,
-    K: Debug + FromIterator<V> + Index<usize, Output = V>,
-    V: Clone + Debug + From<usize> + Ord,
 {
-    dimensions: usize,
-    morton: MortonEncoder,
-    space: CellSpace<K, V>,
-    index: Vec<SFCCell<F>>,
-}
+    fn new(code: SFCCode) -> Self {
+        SFCCell {
+            code,
+            records: vec![],
+            offsets: vec![],
+            values: vec![],
+        }
+    }
 
-impl<F, K, V> SpaceFillingCurve<F, K, V>
-where
-    F: PartialEq,
-    K: Debug + FromIterator<V> + Index<usize, Output = V>,
-    V: Clone + Debug + From<usize> + Hash + Ord,
-{
-    /// Creates a new Index from the provided iterator.
-    ///
-    /// * `dimensions`: The number of dimensions of the space, a.k.a the
-    ///                 length of the vector representing a single
-    ///                 position.
-    /// * `cell_bits`: The number of bits to reserve for the grid we
-    ///                build on top of the coordinate dictionaries.
-    ///                We generate 2^`cell_bits` Cells per dimension.
-    ///
-    //FIXME: Should accept indexing 0 elements, at least not crash!
-    pub fn new<I, R>(iter: I, dimensions: usize, cell_bits: usize) -> Self
-    where
-        I: Clone + Iterator<Item = R>,
-        R: Debug + Record<K> + RecordFields<F>,
-    {
-        // 1. build the dictionnary space, called here CellSpace, as well as
-        // initialize the morton encoder used to project the multi-dimensional
-        // coordinates into a single dimension.
-        let mut index = SpaceFillingCurve {
-            dimensions,
-            morton: MortonEncoder::new(dimensions, cell_bits),
-            space: CellSpace::new(iter.clone(), dimensions, cell_bits),
-            index: vec![],
-        };
+    fn fields(&self, record: &SFCRecord) -> &F {
+        &self.values[record.value as usize]
+    }
 
-        // 2. Build a flat table of (code, offset, entries)
-        let mut flat_table = vec![];
-        let (nb_records, _) = iter.size_hint();
-        for record in iter.into_iter() {
-            let position = record.key();
-            match index.space.key(&position) {
-                Ok((cell_ids, offsets)) => match index.encode(&cell_ids) {
-                    Ok(code) => {
-                        let offsets = offsets.iter().map(|i| *i as SFCOffset).collect::<Vec<_>>();
-                        flat_table.push((
-                            code,
-                            SFCRecord {
-                                offsets: *array_ref!(offsets, 0, MAX_K),
-                                fields: record.fields(),
-                            },
-                        ))
-                    }
-                    Err(e) => error!("Unable to encode position {:#?}: {}", cell_ids, e),
-                },
-                Err(e) => error!("Invalid position {:#?}: {}", position, e),
-            }
-        }
+    // Bounds of the offsets of the record `record` in `offsets`, cut to
+    // the stored offsets should some be missing.
+    fn span(&self, record: usize, dimensions: usize) -> Range<usize> {
+        let len = self.offsets.len();
 
-        debug!("Processed {:#?} records into the index", nb_records);
+        (record * dimensions).min(len)..((record + 1) * dimensions).min(len)
+    }
 
-        // 5. Sort by SFCcode
-        flat_table.sort_unstable_by(|a, b| a.0.cmp(&b.0));
-        let nb_records = flat_table.len();
+    // Offsets of the record `record`.
+    fn offsets(&self, record: usize, dimensions: usize) -> &[SFCOffset] {
+        &self.offsets[self.span(record, dimensions)]
+    }
 
-        let mut current_cell_code = flat_table[0].0;
-        let mut count = 0;
-        index.index.push(SFCCell {
-            code: current_cell_code,
-            records: vec![],
-        });
-        for (code, record) in flat_table {
-            if code == current_cell_code {
-                index.index[count].records.push(record);
-            } else {
-                index.index.push(SFCCell {
-                    code,
-                    records: vec![record],
-                });
-                current_cell_code = code;
-                count += 1;
+    fn offsets_mut(&mut self, record: usize, dimensions: usize) -> &mut [SFCOffset] {
+        let span = self.span(record, dimensions);
+
+        &mut self.offsets[span]
+    }
+
+    // Records of the cell, with their offsets.
+    fn entries(
+        &self,
+        dimensions: usize,
+    ) -> impl DoubleEndedIterator<Item = (&SFCRecord, &[SFCOffset])> {
+        self.records
+            .iter()
+            .enumerate()
+            .map(move |(r, record)| (record, self.offsets(r, dimensions)))
+    }
+
+    // Add a record, reusing an equal value of the cell if `compressed`.
+    fn push(&mut self, id: RecordId, offsets: Vec<SFCOffset>, fields: F, compressed: bool) {
+        let value = if compressed {
+            self.values.iter().position(|value| value == &fields)
+        } else {
+            None
+        };
+
+        let value = match value {
+            Some(value) => value,
+            None => {
+                self.values.push(fields);
+                self.values.len() - 1
             }
-        }
-        debug!("Inserted {:#?} records into the index", nb_records);
+        };
 
-        index
+        self.offsets.extend_from_slice(&offsets);
+        self.records.push(SFCRecord {
+            value: value as SFCValue,
+            id,
+        });
     }
 
-    /// Returns a vector of keys which have stored values in the index
-    /// equal to `value`.
-    pub fn find_by_value(&self, value: &F) -> Vec<K> {
-        let mut results = vec![];
-        for cell in &self.index {
-            for record in &cell.records {
-                if &record.fields == value {
-                    if let Ok(key) = self.position(cell.code, &record.offsets) {
-                        results.push(key);
-                    }
+    // Store equal values of the cell only once.
+    fn compress(&mut self) {
+        let mut distinct: Vec<F> = vec![];
+        let mut remap = Vec::with_capacity(self.values.len());
+        for value in self.values.drain(..) {
+            match distinct.iter().position(|d| d == &value) {
+                Some(idx) => remap.push(idx),
+                None => {
+                    distinct.push(value);
+                    remap.push(distinct.len() - 1);
                 }
             }
         }
 
-        results
+        for record in &mut self.records {
+            record.value = remap[record.value as usize] as SFCValue;
+        }
+        self.values = distinct;
     }
 
-    // Map the cell_ids of a point to its SFCcode
-    fn encode(&self, cell_ids: &[usize]) -> Result<SFCCode, String> {
-        let mut t = vec![];
-        for v in cell_ids.iter() {
-            t.push(*v as MortonValue);
+    // Return mutable references to the values of the records selected by
+    // `predicate`, after giving them their own copy of any shared value.
+    fn fields_mut<P>(&mut self, dimensions: usize, predicate: P) -> Vec<&mut F>
+    where
+        F: Clone,
+        P: Fn(&[SFCOffset]) -> bool,
+    {
+        let mut references = vec![0; self.values.len()];
+        for record in &self.records {
+            references[record.value as usize] += 1;
         }
 
-        self.morton.encode(&t)
-    }
+        let matching = (0..self.records.len())
+            .map(|r| predicate(self.offsets(r, dimensions)))
+            .collect::<Vec<_>>();
+        let mut selected = vec![];
+        for (record, _) in self.records.iter_mut().zip(matching).filter(|(_, m)| *m) {
+            let value = record.value as usize;
+            if references[value] > 1 {
+                references[value] -= 1;
+                self.values.push(self.values[value].clone());
+                record.value = (self.values.len() - 1) as SFCValue;
+            }
+            selected.push(record.value as usize);
+        }
 
-    fn last(&self) -> (Vec<usize>, Vec<usize>) {
-        self.space.last()
-    }
+        let mut mask = vec![false; self.values.len()];
+        for value in selected {
+            mask[value] = true;
+        }
 
-    fn value(&self, code: SFCCode, offsets: &[SFCOffset]) -> Result<Vec<&V>, String> {
-        Ok(self.space.value(
-            self.morton
-                .decode(code)
-                .iter()
-                .map(|e| *e as usize)
-                .collect(),
-            offsets.iter().map(|e| *e as usize).collect(),
-        )?)
+        self.values
+            .iter_mut()
+            .zip(mask)
+            .filter(|(_, selected)| *selected)
+            .map(|(value, _)| value)
+            .collect()
     }
 
-    // Build coordinate values from encoded value
-    fn position(&self, code: SFCCode, offsets: &[SFCOffset]) -> Result<K, String> {
-        let position = self.value(code, offsets)?;
+    // Remove the records selected by `predicate`, returning their
+    // identifiers and values.
+    fn take<P>(&mut self, dimensions: usize, predicate: P) -> Vec<(RecordId, F)>
+    where
+        F: Clone,
+        P: Fn(&SFCRecord, &[SFCOffset], &F) -> bool,
+    {
+        let selected = self
+            .entries(dimensions)
+            .map(|(record, offsets)| predicate(record, offsets, self.fields(record)))
+            .collect::<Vec<_>>();
+        if !selected.contains(&true) {
+            return vec![];
+        }
 
-        Ok(position.iter().map(|i| (*i).clone()).collect())
-    }
+        let mut offsets = Vec::with_capacity(self.offsets.len());
+        for (r, _) in selected.iter().enumerate().filter(|(_, s)| !**s) {
+            offsets.extend_from_slice(self.offsets(r, dimensions));
+        }
+        self.offsets = offsets;
 
-    fn limits(&self, start: &K, end: &K) -> Result<Limits<V>, String> {
-        trace!("limits: {:?} - {:?}", start, end);
+        let mut selected = selected.into_iter();
+        let (removed, kept): (Vec<_>, Vec<_>) = std::mem::take(&mut self.records)
+            .into_iter()
+            .partition(|_| selected.next().unwrap());
 
-        // Round down if not found, for start of range:
-        let (cells, offsets) = self.space.key_down(start)?;
-        let code = self.encode(&cells)?;
-        let idx = match self.index.binary_search_by(|e| e.code.cmp(&code)) {
-            Err(e) => {
-                if e > 0 {
-                    e - 1
-                } else {
-                    0
+        // Move the values still used to a new table.
+        let mut values = std::mem::take(&mut self.values)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let mut remap = vec![None; values.len()];
+        for mut record in kept {
+            let old = record.value as usize;
+            let value = match remap[old] {
+                Some(value) => value,
+                None => {
+                    self.values.push(values[old].take().unwrap());
+                    remap[old] = Some(self.values.len() - 1);
+                    self.values.len() - 1
                 }
-            }
-            Ok(c) => c,
-        };
-        let position = self.space.value(cells, offsets)?;
-        let start = Limit { idx, position };
+            };
+            record.value = value as SFCValue;
+            self.records.push(record);
+        }
 
-        // Round up if not found, for end of range:
-        let (cells, offsets) = self.space.key_up(end)?;
-        let code = self.encode(&cells)?;
-        let idx = match self.index.binary_search_by(|e| e.code.cmp(&code)) {
-            Err(e) => {
-                if e >= self.index.len() {
-                    self.index.len()
+        let mut pending = vec![0; values.len()];
+        for record in &removed {
+            pending[record.value as usize] += 1;
+        }
+
+        removed
+            .into_iter()
+            .map(|record| {
+                let old = record.value as usize;
+                pending[old] -= 1;
+                let fields = match remap[old] {
+                    Some(value) => self.values[value].clone(),
+                    None if pending[old] == 0 => values[old].take().unwrap(),
+                    None => values[old].as_ref().unwrap().clone(),
+                };
+
+                (record.id, fields)
+            })
+            .collect()
+    }
+
+    // Return the offsets, values and identifiers of the records.
+    fn into_records(self, dimensions: usize) -> Vec<(Vec<SFCOffset>, F, RecordId)>
+    where
+        F: Clone,
+    {
+        let mut references = vec![0; self.values.len()];
+        for record in &self.records {
+            references[record.value as usize] += 1;
+        }
+
+        let offsets = (0..self.records.len())
+            .map(|r| self.offsets(r, dimensions).to_vec())
+            .collect::<Vec<_>>();
+        let mut values = self.values.into_iter().map(Some).collect::<Vec<_>>();
+        self.records
+            .into_iter()
+            .zip(offsets)
+            .map(|(record, offsets)| {
+                let value = record.value as usize;
+                references[value] -= 1;
+                let fields = if references[value] == 0 {
+                    values[value].take().unwrap()
                 } else {
-                    e
-                }
-            }
-            Ok(c) => c + 1,
-        };
+                    values[value].as_ref().unwrap().clone()
+                };
 
-        let position = self.space.value(cells, offsets)?;
-        let end = Limit { idx, position };
+                (offsets, fields, record.id)
+            })
+            .collect()
+    }
+}
 
-        trace!("limits: {:?} - {:?}", start, end);
+// Code of the cell of every record, computed on the first lookup by
+// identifier, then kept up to date as records are added and removed.
+#[derive(Clone, Debug, Default)]
+struct Locations(OnceLock<HashMap<RecordId, SFCCode>>);
 
-        Ok(Limits { start, end })
+impl Locations {
+    // Record the cell of a new record, once the codes are computed.
+    fn insert(&mut self, id: RecordId, code: SFCCode) {
+        if let Some(codes) = self.0.get_mut() {
+            codes.insert(id, code);
+        }
+    }
+
+    fn remove(&mut self, id: RecordId) {
+        if let Some(codes) = self.0.get_mut() {
+            codes.remove(&id);
+        }
+    }
+
+    // Code of the cell of the record `id`, computing the codes of all the
+    // records with `init` on the first call.
+    fn get<I>(&self, id: RecordId, init: I) -> Option<SFCCode>
+    where
+        I: FnOnce() -> HashMap<RecordId, SFCCode>,
+    {
+        self.0.get_or_init(init).get(&id).copied()
     }
 }
 
-impl<F, K, V> IndexedDestructured<F, K> for SpaceFillingCurve<F, K, V>
+// Append a record to a list of cells sorted by code, creating its cell
+// if needed.
+fn push_record<F: Clone + PartialEq>(
+    cells: &mut Vec<Arc<SFCCell<F>>>,
+    code: SFCCode,
+    (offsets, fields, id): (Vec<SFCOffset>, F, RecordId),
+    compressed: bool,
+) {
+    match cells.last_mut() {
+        Some(cell) if cell.code == code => {
+            Arc::make_mut(cell).push(id, offsets, fields, compressed);
+        }
+        _ => {
+            let mut cell = SFCCell::new(code);
+            cell.push(id, offsets, fields, compressed);
+            cells.push(Arc::new(cell));
+        }
+    }
+}
+
+// Record of a top-k query, ordered by its values according to `cmp`.
+struct Ranked<'a, 'c, K, F, C> {
+    key: K,
+    fields: &'a F,
+    cmp: &'c C,
+}
+
+impl<'a, 'c, K, F, C> Ord for Ranked<'a, 'c, K, F, C>
 where
-    F: PartialEq,
-    K: Debug + FromIterator<V> + Index<usize, Output = V>,
-    V: Clone + Debug + From<usize> + Hash + Ord,
+    C: Fn(&F, &F) -> Ordering,
 {
-    fn find(&self, key: &K) -> Vec<&F> {
-        let mut values = vec![];
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.cmp)(self.fields, other.fields)
+    }
+}
 
-        if let Ok((cell_ids, offsets)) = self.space.key(key) {
-            match self.encode(&cell_ids) {
-                Err(e) => error!("{}", e),
-                Ok(code) => {
-                    if let Ok(cell) = self.index.binary_search_by(|a| a.code.cmp(&code)) {
-                        for record in &self.index[cell].records {
-                            let mut select = true;
-                            for (k, o) in offsets.iter().enumerate().take(self.dimensions) {
-                                select &= record.offsets[k] == (*o as SFCOffset);
-                            }
-
-                            if select {
-                                values.push(&record.fields);
-                            }
-                        }
-                    }
-                }
-            }
-        }
+impl<'a, 'c, K, F, C> PartialOrd for Ranked<'a, 'c, K, F, C>
+where
+    C: Fn(&F, &F) -> Ordering,
+{
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
 
-        values
+impl<'a, 'c, K, F, C> PartialEq for Ranked<'a, 'c, K, F, C>
+where
+    C: Fn(&F, &F) -> Ordering,
+{
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
     }
+}
 
-    fn find_range(&self, start: &K, end: &K) -> Vec<(K, &F)> {
-        let mut values = vec![];
+impl<'a, 'c, K, F, C> Eq for Ranked<'a, 'c, K, F, C> where C: Fn(&F, &F) -> Ordering {}
 
-        match self.limits(start, end) {
-            Ok(limits) => {
-                for idx in limits.start.idx..limits.end.idx {
-                    let code = self.index[idx].code;
+// Order of the flat table: by code, then offsets, then position in the
+// source iterator.
+pub(crate) fn flat_order<F>(a: &FlatEntry<F>, b: &FlatEntry<F>) -> Ordering {
+    (a.0, &(a.2).0, a.1).cmp(&(b.0, &(b.2).0, b.1))
+}
 
-                    let first = match self.value(code, &self.index[idx].records[0].offsets) {
-                        Err(e) => {
-                            error!("Cannot retrieve first value of cell: {}", e);
-                            continue;
-                        }
-                        Ok(r) => r,
-                    };
+// Bits per dimension giving about `DEFAULT_OCCUPANCY` records per cell.
+fn default_cell_bits(records: usize, dimensions: usize) -> usize {
+    let cells = (records / DEFAULT_OCCUPANCY).max(1) as f64;
+    let bits = (cells.log2() / dimensions.max(1) as f64).ceil() as usize;
 
-                    let (cell_ids, last_offsets) = self.last();
-                    let last = match self.space.value(cell_ids, last_offsets) {
-                        Err(e) => {
-                            error!("Cannot retrieve last value of cell: {}", e);
-                            continue;
-                        }
-                        Ok(r) => r,
-                    };
+    bits.clamp(1, MORTON_VALUE_BITS)
+}
 
-                    let start_pos = vec![&start[0], &start[1], &start[2]];
-                    let end_pos = vec![&end[0], &end[1], &end[2]];
-                    // Check first & last point of the cell, if both are fully
-                    // in the bounding box, then all the points of the cell will
-                    // be.
-                    let first_after_start = start_pos.iter().zip(first.iter()).all(|(&a, &b)| a <= b);
-                    let last_after_start = start_pos.iter().zip(last.iter()).all(|(&a, &b)| a <= b);
-                    let first_before_end = end_pos.iter().zip(first.iter()).all(|(&a, &b)| a >= b);
-                    let last_before_end  = end_pos.iter().zip(last.iter()).all(|(&a, &b)| a >= b);
-                    if first_after_start && last_after_start && first_before_end && last_before_end
-                    {
-                        for record in &self.index[idx].records {
-                            if let Ok(key) = self.position(code, &record.offsets) {
-                                values.push((key, &record.fields));
-                            }
-                        }
-                    } else {
-                        // We have points which are outside of the bounding box,
-                        // so check every points one by one.
-                        for record in &self.index[idx].records {
-                            let pos = match self.value(code, &record.offsets) {
-                                Err(e) => {
-                                    error!("{}", e);
-                                    continue;
-                                }
-                                Ok(r) => r,
-                            };
-
-                            let pos_after_start = start_pos.iter().zip(pos.iter()).all(|(&a, &b)| a <= b);
-                            let pos_before_end = end_pos.iter().zip(pos.iter()).all(|(&a, &b)| a >= b);
-                            if pos_after_start && pos_before_end {
-                                if let Ok(key) = self.position(code, &record.offsets) {
-                                    values.push((key, &record.fields));
-                                }
-                            }
-                        }
-                    }
-                }
-            }
-            Err(e) => error!("find_range: limits failed: {}", e),
-        };
+// Filter accepting all the records.
+pub(crate) fn any<F>(_: &F) -> bool {
+    true
+}
 
-        values
-    }
+// Check that `position` is at or after `start`, on every bounded dimension.
+pub(crate) fn is_after<V: Ord, B: Borrow<V>>(start: &[Option<V>], position: &[B]) -> bool {
+    start.iter().zip(position.iter()).all(|(a, b)| match a {
+        Some(a) => a <= b.borrow(),
+        None => true,
+    })
 }
 
-/*
-impl<F, K, V> Store for SpaceFillingCurve<F, K, V>
+// Check that `position` is at or before `end`, on every bounded dimension.
+pub(crate) fn is_before<V: Ord, B: Borrow<V>>(end: &[Option<V>], position: &[B]) -> bool {
+    end.iter().zip(position.iter()).all(|(a, b)| match a {
+        Some(a) => a >= b.borrow(),
+        None => true,
+    })
+}
+
+// Coordinates decoded from the index, as expected by `Region`.
+fn borrowed<'a, V: Clone>(position: &'a [Cow<V>]) -> Vec<&'a V> {
+    position.iter().map(|v| &**v).collect()
+}
+
+// Check that `position` holds the coordinates of `key`.
+fn is_at<K: Position<V>, V: PartialEq>(position: &[V], key: &K) -> bool {
+    !matches!(key.dimensions(), Some(n) if n != position.len())
+        && position
+            .iter()
+            .enumerate()
+            .all(|(k, v)| *v == key.coordinate(k))
+}
+
+/// Space Filling Curve-based index.
+///
+/// This structure retains the state of the index.
+///
+/// Coordinates only need to be ordered: the curve encodes the positions
+/// of the values in the sorted dictionary of each dimension, not the
+/// values themselves. Signed coordinates, such as `i64` or
+/// [`Float<f64>`](struct.Float.html), are therefore indexed as they are,
+/// negative values included, without shifting the origin. The bounds of
+/// queries do not have to be values of the dictionaries either.
+///
+/// The index is `Send` and `Sync` whenever `F` and `V` are, whatever `K`,
+/// since keys are only built from the coordinates when returned. Queries
+/// take `&self`, so an index can be shared behind an `Arc` by threads
+/// querying it concurrently.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct SpaceFillingCurve<F, K, V>
 where
-    F: PartialEq + Serialize,
-    K: Debug + Serialize + FromIterator<V> + Index<usize, Output = V>,
-    V: Clone + Debug + From<usize> + Ord + Serialize,
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Ord,
 {
-    fn store<W>(&mut self, writer: W) -> io::Result<()>
-    where
-        W: std::io::Write,
-    {
-        match bincode::serialize_into(writer, &self) {
-            Ok(_) => Ok(()),
-            Err(e) => Err(io::Error::new(io::ErrorKind::WriteZero, e)),
-        }
-    }
+    dimensions: usize,
+    // The grid is shared with the indices built over it by `new_sharing`,
+    // and copied when modified.
+    morton: Arc<MortonEncoder>,
+    space: Arc<CellSpace<K, V>>,
+    // Cells are shared between clones of the index, and copied when
+    // modified.
+    index: Vec<Arc<SFCCell<F>>>,
+    // Codes of the cells, in the same order, stored apart so that
+    // searching for a cell only touches a dense array.
+    codes: Vec<SFCCode>,
+    // Records inserted since the last flush, with their coordinates.
+    buffer: Arc<Vec<(Vec<V>, F, RecordId)>>,
+    // Identifier of the next record inserted.
+    #[cfg_attr(feature = "serde", serde(default))]
+    next_id: u64,
+    #[cfg_attr(feature = "serde", serde(skip))]
+    locations: Locations,
+    // Whether equal values are stored once per cell.
+    #[cfg_attr(feature = "serde", serde(default))]
+    compressed: bool,
+    stats: IndexStats,
+    // Optional description of each dimension.
+    #[cfg_attr(feature = "serde", serde(default))]
+    metadata: Vec<Dimension>,
 }
 
-impl<F, K, V> Load for SpaceFillingCurve<F, K, V>
+impl<F, K, V> SpaceFillingCurve<F, K, V>
 where
-    F: PartialEq + DeserializeOwned,
-    K: Debug + DeserializeOwned + FromIterator<V> + Index<usize, Output = V>,
-    V: Clone + Debug + DeserializeOwned + From<usize> + Ord,
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
 {
-    fn load<Re: io::Read>(reader: Re) -> io::Result<Self> {
-        match bincode::deserialize_from(reader) {
-            Ok(data) => Ok(data),
-            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
-        }
+    /// Creates a new Index from the provided iterator.
+    ///
+    /// * `dimensions`: The number of dimensions of the space, a.k.a the
+    ///                 length of the vector representing a single
+    ///                 position.
+    /// * `cell_bits`: The number of bits to reserve for the grid we
+    ///                build on top of the coordinate dictionaries.
+    ///                We generate on average 2^`cell_bits` Cells per
+    ///                dimension, dimensions with more distinct values
+    ///                getting more bits than the others, see
+    ///                [`cell_bits`](#method.cell_bits).
+    ///
+    /// A Morton code holds 64 bits: when `dimensions * cell_bits` is
+    /// larger, the dimensions share 64 bits instead.
+    ///
+    /// An empty iterator gives a valid, empty index, to which records
+    /// can be inserted.
+    pub fn new<I, R>(iter: I, dimensions: usize, cell_bits: usize) -> Self
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        let (index, _) =
+            Self::new_normalized(iter, dimensions, cell_bits, |_| Normalized::Unchanged);
+
+        index
     }
 
-    // only required for store_mapped_file
-    fn load_slice(from: &[u8]) -> io::Result<Self> {
-        match bincode::deserialize(from) {
+    /// Creates a new Index from the provided iterator, picking the number
+    /// of bits of the grid from the number of records.
+    ///
+    /// The grid gets about one cell per 16 records, so that queries scan
+    /// few records they do not return, without most cells holding a
+    /// single record. See [`new`](#method.new) for the other parameters.
+    pub fn with_defaults<I, R>(iter: I, dimensions: usize) -> Self
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        let cell_bits = default_cell_bits(iter.clone().count(), dimensions);
+        info!("Using {} bits per dimension", cell_bits);
+
+        Self::new(iter, dimensions, cell_bits)
+    }
+
+    /// Creates a new Index from the provided iterator, after applying
+    /// `normalize` to the position of every record.
+    ///
+    /// The callback can keep a position, replace it, for example to
+    /// clamp outliers or convert units, or drop the record altogether.
+    /// It may be called several times for the same record, so it should
+    /// always return the same result for a given position.
+    ///
+    /// See [`new`](#method.new) for the other parameters. Returns the
+    /// index, as well as a report of the processed records.
+    pub fn new_normalized<I, R, N>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        normalize: N,
+    ) -> (Self, BuildReport)
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+        N: Fn(&K) -> Normalized<K>,
+    {
+        match Self::build_normalized(
+            iter,
+            dimensions,
+            cell_bits,
+            CellLayout::default(),
+            normalize,
+            BuildPolicy::CollectAndReport,
+        ) {
+            Ok(built) => built,
+            Err(e) => unreachable!("Rejections are collected, not raised: {}", e),
+        }
+    }
+
+    /// Creates a new Index from the provided iterator, handling the
+    /// records which cannot be indexed according to `policy`.
+    ///
+    /// With [`BuildPolicy::FailFast`](enum.BuildPolicy.html), the first
+    /// rejected record makes the construction fail. See
+    /// [`new`](#method.new) for the other parameters. Returns the index,
+    /// as well as a report of the processed records.
+    pub fn new_with_policy<I, R>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        Self::build_normalized(
+            iter,
+            dimensions,
+            cell_bits,
+            CellLayout::default(),
+            |_| Normalized::Unchanged,
+            policy,
+        )
+    }
+
+    /// Creates a new Index from the provided iterator, splitting the
+    /// values of every dimension into cells according to `sizing`.
+    ///
+    /// With [`CellSizing::Records`](enum.CellSizing.html), cells hold
+    /// about as many records, instead of as many distinct values, which
+    /// keeps clustered data from piling up in a few cells. The resulting
+    /// balance can be checked with the `occupancy` histogram of
+    /// [`stats`](#method.stats). See [`new`](#method.new) for the other
+    /// parameters.
+    pub fn new_with_sizing<I, R>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        sizing: CellSizing,
+    ) -> Self
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        let layout = CellLayout {
+            quantizers: vec![],
+            sizing,
+        };
+        match Self::build_normalized(
+            iter,
+            dimensions,
+            cell_bits,
+            layout,
+            |_| Normalized::Unchanged,
+            BuildPolicy::CollectAndReport,
+        ) {
+            Ok((index, _)) => index,
+            Err(e) => unreachable!("Rejections are collected, not raised: {}", e),
+        }
+    }
+
+    /// Creates a new Index from the provided iterator, mapping the values
+    /// of some dimensions to cells with a
+    /// [`Quantizer`](trait.Quantizer.html) instead of a dictionary.
+    ///
+    /// `quantizers` holds one entry per dimension, `None` keeping the
+    /// dictionary of the dimension. Quantized dimensions get the bits
+    /// their cells need, the other ones sharing what remains of
+    /// `dimensions * cell_bits`. Records with coordinates the quantizers
+    /// cannot represent are rejected, and so are insertions of such
+    /// records later on.
+    ///
+    /// Indices using quantizers cannot be serialized. See
+    /// [`new`](#method.new) for the other parameters. Returns the index,
+    /// as well as a report of the processed records, or an error if a
+    /// quantizer has no cells or more than 1024.
+    pub fn new_quantized<I, R>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        quantizers: Vec<Option<Box<dyn Quantizer<V>>>>,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        if quantizers.len() != dimensions {
+            return Err(SfcError::DimensionMismatch {
+                expected: dimensions,
+                found: quantizers.len(),
+            });
+        }
+        let layout = CellLayout {
+            quantizers: quantizers
+                .into_iter()
+                .map(|quantizer| quantizer.map(Arc::from))
+                .collect(),
+            sizing: CellSizing::Distinct,
+        };
+        layout.check()?;
+
+        Self::build_normalized(
+            iter,
+            dimensions,
+            cell_bits,
+            layout,
+            |_| Normalized::Unchanged,
+            BuildPolicy::CollectAndReport,
+        )
+    }
+
+    /// Returns a builder, to set the parameters of a new Index one at a
+    /// time.
+    pub fn builder() -> SpaceFillingCurveBuilder<F, K, V> {
+        SpaceFillingCurveBuilder::new()
+    }
+
+    // Shared by the constructors applying a policy, a layout or a callback.
+    pub(crate) fn build_normalized<I, R, N>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        layout: CellLayout<V>,
+        normalize: N,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+        N: Fn(&K) -> Normalized<K>,
+    {
+        let normalized = |position: K| match normalize(&position) {
+            Normalized::Unchanged => Some(position),
+            Normalized::Modified(position) => Some(position),
+            Normalized::Dropped => None,
+        };
+
+        // 1. build the dictionnary space, called here CellSpace, as well as
+        // initialize the morton encoder used to project the multi-dimensional
+        // coordinates into a single dimension.
+        let space = CellSpace::new(
+            iter.clone().filter_map(|record| normalized(record.key())),
+            dimensions,
+            cell_bits,
+            layout,
+        );
+        let mut index = Self::empty(space, dimensions);
+
+        // 2. Build a flat table of (code, offset, entries)
+        let mut report = BuildReport::default();
+        let mut flat_table = vec![];
+        for record in iter.into_iter() {
+            report.records += 1;
+
+            let position = record.key();
+            let position = match normalize(&position) {
+                Normalized::Unchanged => position,
+                Normalized::Modified(position) => {
+                    report.modified += 1;
+                    position
+                }
+                Normalized::Dropped => {
+                    report.dropped += 1;
+                    continue;
+                }
+            };
+
+            match index.entry(&position) {
+                Ok((code, offsets)) => {
+                    flat_table.push((code, report.records - 1, (offsets, record.fields())))
+                }
+                Err(e) => {
+                    let source = report.records - 1;
+                    policy.reject(&mut report, source, &position, e)?;
+                }
+            }
+        }
+
+        debug!("Processed {:#?} records into the index", report.records);
+        info!(
+            "Normalization: {} records modified, {} records dropped, {} records rejected",
+            report.modified,
+            report.dropped,
+            report.rejected.len()
+        );
+
+        index.build(flat_table, &mut report);
+
+        Ok((index, report))
+    }
+
+    /// Creates a new Index from the provided iterator, reading it only
+    /// once.
+    ///
+    /// Unlike [`new`](#method.new), the iterator does not have to be
+    /// cloneable, for example when reading records from a stream: the
+    /// keys and values are collected in a single pass, then indexed.
+    /// Records which cannot be indexed are handled according to `policy`,
+    /// see [`new_with_policy`](#method.new_with_policy). Returns the
+    /// index, as well as a report of the processed records.
+    pub fn new_single_pass<I, R>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: IntoIterator<Item = R>,
+        R: Record<K> + RecordFields<F>,
+    {
+        let records = iter
+            .into_iter()
+            .map(|record| (record.key(), record.fields()))
+            .collect::<Vec<_>>();
+
+        let space = CellSpace::new_with(
+            records.iter(),
+            dimensions,
+            cell_bits,
+            CellLayout::default(),
+            |(key, _), k| match key.dimensions() {
+                Some(found) if found != dimensions => None,
+                _ => Some(key.coordinate(k)),
+            },
+        );
+        let mut index = Self::empty(space, dimensions);
+
+        let mut report = BuildReport {
+            records: records.len(),
+            ..BuildReport::default()
+        };
+        let mut flat_table = Vec::with_capacity(records.len());
+        for (i, (position, fields)) in records.into_iter().enumerate() {
+            match index.entry(&position) {
+                Ok((code, offsets)) => flat_table.push((code, i, (offsets, fields))),
+                Err(e) => policy.reject(&mut report, i, &position, e)?,
+            }
+        }
+
+        index.build(flat_table, &mut report);
+
+        Ok((index, report))
+    }
+
+    /// Creates a new Index from the provided iterator, keeping at most
+    /// `chunk` records in memory besides the index itself.
+    ///
+    /// The records are encoded `chunk` at a time, and every chunk is
+    /// sorted and written to a temporary file. The files are then merged
+    /// into the cells of the index, and removed. Records which cannot be
+    /// indexed are handled according to `policy`, see
+    /// [`new_with_policy`](#method.new_with_policy), and
+    /// [`new`](#method.new) for the other parameters. Returns the index,
+    /// as well as a report of the processed records.
+    ///
+    /// Fails on `wasm32-unknown-unknown`, which has no file system, as
+    /// soon as a chunk has to be written.
+    #[cfg(feature = "serde")]
+    pub fn new_chunked<I, R>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        chunk: usize,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Record<K> + RecordFields<F>,
+        F: Serialize + DeserializeOwned,
+    {
+        Self::build_chunked(iter, dimensions, cell_bits, chunk, None, policy)
+    }
+
+    /// Same as [`new_chunked`](#method.new_chunked), writing the
+    /// temporary files to `directory` instead of the temporary directory
+    /// of the system.
+    #[cfg(feature = "serde")]
+    pub fn new_chunked_in<I, R>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        chunk: usize,
+        directory: &Path,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Record<K> + RecordFields<F>,
+        F: Serialize + DeserializeOwned,
+    {
+        Self::build_chunked(iter, dimensions, cell_bits, chunk, Some(directory), policy)
+    }
+
+    #[cfg(feature = "serde")]
+    fn build_chunked<I, R>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        chunk: usize,
+        directory: Option<&Path>,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: Clone + Iterator<Item = R>,
+        R: Record<K> + RecordFields<F>,
+        F: Serialize + DeserializeOwned,
+    {
+        let chunk = chunk.max(1);
+        let space = CellSpace::new(
+            iter.clone().map(|record| record.key()),
+            dimensions,
+            cell_bits,
+            CellLayout::default(),
+        );
+        let mut index = Self::empty(space, dimensions);
+
+        let mut report = BuildReport::default();
+        let mut runs = vec![];
+        let mut flat_table = Vec::with_capacity(chunk);
+        for record in iter {
+            report.records += 1;
+
+            let position = record.key();
+            match index.entry(&position) {
+                Ok((code, offsets)) => {
+                    flat_table.push((code, report.records - 1, (offsets, record.fields())))
+                }
+                Err(e) => {
+                    let source = report.records - 1;
+                    policy.reject(&mut report, source, &position, e)?;
+                }
+            }
+
+            if flat_table.len() >= chunk {
+                runs.push(Run::spill(&mut flat_table, directory)?);
+            }
+        }
+
+        if runs.is_empty() {
+            index.build(flat_table, &mut report);
+        } else {
+            if !flat_table.is_empty() {
+                runs.push(Run::spill(&mut flat_table, directory)?);
+            }
+            let mut merge = Merge::new(runs)?;
+            index.build_sorted(merge.by_ref(), &mut report);
+            if let Some(e) = merge.error {
+                return Err(e);
+            }
+        }
+
+        Ok((index, report))
+    }
+
+    /// Creates a new Index from the provided iterator, borrowing the keys
+    /// of the records instead of building them.
+    ///
+    /// Records which do not have `dimensions` coordinates are rejected,
+    /// and handled according to `policy`, see
+    /// [`new_with_policy`](#method.new_with_policy). See
+    /// [`new`](#method.new) for the other parameters. Returns the index,
+    /// as well as a report of the processed records.
+    pub fn new_from_slices<I, R>(
+        iter: I,
+        dimensions: usize,
+        cell_bits: usize,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: Clone + Iterator<Item = R>,
+        R: RecordSlice<V> + RecordFields<F>,
+    {
+        let space = CellSpace::new_with(
+            iter.clone(),
+            dimensions,
+            cell_bits,
+            CellLayout::default(),
+            |record, k| {
+                let position = record.key_slice();
+                if position.len() == dimensions {
+                    Some(position[k].clone())
+                } else {
+                    None
+                }
+            },
+        );
+        let mut index = Self::empty(space, dimensions);
+
+        let mut report = BuildReport::default();
+        let mut flat_table = vec![];
+        for record in iter {
+            report.records += 1;
+
+            let position = record.key_slice();
+            let entry = index
+                .space
+                .key_slice(position)
+                .map_err(Rejection::InvalidPosition)
+                .and_then(|key| index.encode_entry(key));
+            match entry {
+                Ok((code, offsets)) => {
+                    flat_table.push((code, report.records - 1, (offsets, record.fields())))
+                }
+                Err(e) => {
+                    let source = report.records - 1;
+                    policy.reject(&mut report, source, &position, e)?;
+                }
+            }
+        }
+
+        index.build(flat_table, &mut report);
+
+        Ok((index, report))
+    }
+
+    /// Creates a new Index from the provided iterator, over the grid of
+    /// `other`.
+    ///
+    /// The dictionaries and the encoder of `other` are shared, not
+    /// copied, so that indices of similar data, for example one per day,
+    /// do not each hold their own copy, and the codes of their cells can
+    /// be compared. Coordinates missing from the dictionaries are added
+    /// to a copy of them, which keeps the cells, and thus the codes,
+    /// unchanged. Dimensions using a [`Quantizer`](trait.Quantizer.html)
+    /// never need such a copy.
+    ///
+    /// Records which cannot be indexed are handled according to `policy`,
+    /// see [`new_with_policy`](#method.new_with_policy). Returns the
+    /// index, as well as a report of the processed records.
+    pub fn new_sharing<I, R>(
+        iter: I,
+        other: &Self,
+        policy: BuildPolicy,
+    ) -> Result<(Self, BuildReport), SfcError>
+    where
+        I: IntoIterator<Item = R>,
+        R: Record<K> + RecordFields<F>,
+    {
+        let records = iter
+            .into_iter()
+            .map(|record| (record.key(), record.fields()))
+            .collect::<Vec<_>>();
+
+        let mut index = SpaceFillingCurve {
+            dimensions: other.dimensions,
+            morton: other.morton.clone(),
+            space: other.space.clone(),
+            index: vec![],
+            codes: vec![],
+            buffer: Arc::new(vec![]),
+            next_id: 0,
+            locations: Locations::default(),
+            compressed: other.compressed,
+            stats: IndexStats::default(),
+            metadata: other.metadata.clone(),
+        };
+        index.stats = index.compute_stats();
+        for (position, _) in &records {
+            if index.space.check(position).is_ok() {
+                index.insert_position(position);
+            }
+        }
+
+        let mut report = BuildReport {
+            records: records.len(),
+            ..BuildReport::default()
+        };
+        let mut flat_table = Vec::with_capacity(records.len());
+        for (i, (position, fields)) in records.into_iter().enumerate() {
+            match index.entry(&position) {
+                Ok((code, offsets)) => flat_table.push((code, i, (offsets, fields))),
+                Err(e) => policy.reject(&mut report, i, &position, e)?,
+            }
+        }
+
+        index.build(flat_table, &mut report);
+
+        Ok((index, report))
+    }
+
+    // Index without records, over `space`.
+    pub(crate) fn empty(space: CellSpace<K, V>, dimensions: usize) -> Self {
+        let mut index = SpaceFillingCurve {
+            dimensions,
+            morton: Arc::new(MortonEncoder::with_cell_bits(space.cell_bits().to_vec())),
+            space: Arc::new(space),
+            index: vec![],
+            codes: vec![],
+            buffer: Arc::new(vec![]),
+            next_id: 0,
+            locations: Locations::default(),
+            compressed: false,
+            stats: IndexStats::default(),
+            metadata: vec![],
+        };
+        index.stats = index.compute_stats();
+
+        index
+    }
+
+    // Group the records of the flat table into cells.
+    fn build(&mut self, mut flat_table: Vec<FlatEntry<F>>, report: &mut BuildReport) {
+        // 5. Sort by SFCcode, keeping records at the same position together
+        #[cfg(feature = "radix")]
+        radix::sort_flat_table(&mut flat_table);
+        #[cfg(not(feature = "radix"))]
+        flat_table.sort_unstable_by(flat_order);
+        self.build_sorted(flat_table, report);
+    }
+
+    // Group the records of the flat table, sorted with `flat_order`, into
+    // cells, counting the records sharing their position with a previous
+    // record, with equal values or not.
+    pub(crate) fn build_sorted<I>(&mut self, flat_table: I, report: &mut BuildReport)
+    where
+        I: IntoIterator<Item = FlatEntry<F>>,
+    {
+        let mut cell: Option<SFCCell<F>> = None;
+        let mut first_source = 0;
+        for (code, source, (offsets, fields)) in flat_table {
+            report.indexed += 1;
+
+            let current = match cell.take() {
+                Some(current) if current.code == code => current,
+                Some(current) => {
+                    self.index.push(Arc::new(current));
+                    SFCCell::new(code)
+                }
+                None => SFCCell::new(code),
+            };
+            let current = cell.get_or_insert(current);
+
+            let (shared, duplicate) = {
+                let mut same = current
+                    .entries(self.dimensions)
+                    .rev()
+                    .take_while(|(_, stored)| *stored == offsets.as_slice())
+                    .map(|(record, _)| record)
+                    .peekable();
+                let shared = same.peek().is_some();
+
+                (shared, same.any(|record| current.fields(record) == &fields))
+            };
+            if !shared {
+                first_source = source;
+            } else {
+                if duplicate {
+                    report.duplicates += 1;
+                } else {
+                    report.near_duplicates += 1;
+                }
+                if report.duplicate_samples.len() < DUPLICATE_SAMPLES {
+                    report.duplicate_samples.push((first_source, source));
+                }
+            }
+
+            // Records are identified by their position in the source.
+            let id = RecordId(source as u64);
+            self.next_id = self.next_id.max(id.0 + 1);
+            current.push(id, offsets, fields, false);
+        }
+        if let Some(cell) = cell {
+            self.index.push(Arc::new(cell));
+        }
+        self.update_codes();
+        self.stats = self.compute_stats();
+
+        if report.duplicates + report.near_duplicates > 0 {
+            warn!(
+                "Duplicates: {} records duplicated, {} records at the same position",
+                report.duplicates, report.near_duplicates
+            );
+        }
+        debug!("Inserted {:#?} records into the index", report.indexed);
+    }
+
+    /// Inserts a single record into the index.
+    ///
+    /// Coordinates which are not yet known are added to the
+    /// dictionaries, without changing the grid of cells. This keeps the
+    /// index valid, but the records of the cells receiving new values are
+    /// updated, so bulk loads should rather rebuild the index.
+    ///
+    /// Returns the identifier of the new record.
+    pub fn insert<R>(&mut self, record: R) -> Result<RecordId, SfcError>
+    where
+        F: Clone,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        let position = record.key();
+        self.space.check(&position)?;
+        let (cell_ids, offsets, inserted) = self.insert_position(&position);
+        self.shift_offsets(&inserted);
+
+        let code = self.encode(&cell_ids)?;
+        let offsets = self.offsets(&offsets)?;
+
+        let idx = match self.codes.binary_search(&code) {
+            Ok(idx) => idx,
+            Err(idx) => {
+                self.index.insert(idx, Arc::new(SFCCell::new(code)));
+                self.codes.insert(idx, code);
+                idx
+            }
+        };
+        let id = self.next_id();
+        let cell = Arc::make_mut(&mut self.index[idx]);
+        cell.push(id, offsets, record.fields(), self.compressed);
+        self.stats
+            .resize(cell.records.len() - 1, cell.records.len());
+        self.locations.insert(id, code);
+
+        Ok(id)
+    }
+
+    /// Inserts a single record into the write buffer of the index.
+    ///
+    /// The record is immediately visible to queries, but is only added
+    /// to the cells by [`flush`](#method.flush). This avoids updating the
+    /// dictionaries and the sorted cells on every insertion, at the cost
+    /// of a linear scan of the buffer by each query, so the buffer should
+    /// be flushed regularly.
+    ///
+    /// Returns the identifier of the new record, which it keeps once
+    /// flushed, or `None` if the record is rejected.
+    pub fn insert_buffered<R>(&mut self, record: R) -> Option<RecordId>
+    where
+        F: Clone,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        let position = match self.try_coordinates(&record.key()) {
+            Ok(position) => position,
+            Err(e) => {
+                error!("Rejected record {:?}: {}", record, e);
+                return None;
+            }
+        };
+        let id = self.next_id();
+        Arc::make_mut(&mut self.buffer).push((position, record.fields(), id));
+        self.stats.buffered += 1;
+
+        Some(id)
+    }
+
+    /// Lists the cells holding records, in the order of their codes, with
+    /// their bounds and number of records.
+    ///
+    /// The bounds are the smallest and largest values of the
+    /// dictionaries in the cell, which may be far apart from the records
+    /// themselves. The summaries can be serialized, for example to plot
+    /// the coverage of the grid. Records of the write buffer are not
+    /// included.
+    pub fn export_cells(&self) -> impl Iterator<Item = CellSummary<V>> + '_ {
+        let occupied = self.index.iter().filter(|cell| !cell.records.is_empty());
+
+        occupied.map(move |cell| {
+            let cells = self
+                .morton
+                .decode(cell.code)
+                .iter()
+                .map(|id| *id as usize)
+                .collect::<Vec<_>>();
+            let (min, max) = self.space.cell_bounds(&cells);
+
+            CellSummary {
+                code: cell.code,
+                min: min.into_iter().map(Cow::into_owned).collect(),
+                max: max.into_iter().map(Cow::into_owned).collect(),
+                cells,
+                records: cell.records.len(),
+            }
+        })
+    }
+
+    /// Returns an estimate of the memory used by the index, in bytes,
+    /// split between its parts.
+    ///
+    /// This walks every cell, so it is linear in the number of records.
+    pub fn mem_usage(&self) -> MemoryBreakdown {
+        let mut usage = MemoryBreakdown {
+            cells: std::mem::size_of::<Self>()
+                + self.index.capacity() * std::mem::size_of::<Arc<SFCCell<F>>>()
+                + self.codes.capacity() * std::mem::size_of::<SFCCode>()
+                + self.index.len() * std::mem::size_of::<SFCCell<F>>(),
+            buffer: self.buffer.capacity() * std::mem::size_of::<(Vec<V>, F, RecordId)>(),
+            dictionaries: self.space.size(),
+            ..MemoryBreakdown::default()
+        };
+        for cell in &self.index {
+            usage.records += cell.records.capacity() * std::mem::size_of::<SFCRecord>();
+            usage.values += cell.values.capacity() * std::mem::size_of::<F>();
+            usage.offsets += cell.offsets.capacity() * std::mem::size_of::<SFCOffset>();
+        }
+
+        usage
+    }
+
+    /// Returns the statistics of the index: its numbers of records and
+    /// cells, how the records are spread over the cells, and the sizes of
+    /// the dictionaries.
+    ///
+    /// They are maintained as the index is modified, so this is cheap.
+    pub fn stats(&self) -> &IndexStats {
+        &self.stats
+    }
+
+    /// Returns the number of records waiting in the write buffer.
+    pub fn buffered(&self) -> usize {
+        self.buffer.len()
+    }
+
+    /// Returns the number of records of the index, including the ones
+    /// waiting in the write buffer.
+    pub fn len(&self) -> usize {
+        self.stats.records + self.buffer.len()
+    }
+
+    /// Returns `true` if the index holds no records, not even in the
+    /// write buffer.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the number of cells holding records.
+    pub fn cell_count(&self) -> usize {
+        self.stats.cells
+    }
+
+    /// Moves the records of the write buffer into the cells, as a batch,
+    /// see [`extend`](#method.extend).
+    pub fn flush(&mut self) -> Result<(), SfcError>
+    where
+        F: Clone,
+    {
+        if self.buffer.is_empty() {
+            return Ok(());
+        }
+
+        let entries = self.take_buffer()?;
+        self.extend_entries(entries.into_iter())
+    }
+
+    /// Inserts a batch of records into the index.
+    ///
+    /// The batch is sorted on its own, then merged with the existing
+    /// cells in a single pass, which is much cheaper than inserting the
+    /// records one at a time. As for [`insert`](#method.insert), the
+    /// grid of cells is left unchanged. The records get consecutive
+    /// identifiers, in the order of `iter`.
+    pub fn extend<I, R>(&mut self, iter: I) -> Result<(), SfcError>
+    where
+        F: Clone,
+        I: Iterator<Item = R>,
+        R: Debug + Record<K> + RecordFields<F>,
+    {
+        let first = self.next_id;
+        self.extend_entries(iter.enumerate().map(|(i, record)| {
+            let id = RecordId(first + i as u64);
+            (record.key(), record.fields(), id)
+        }))
+    }
+
+    /// Moves all the records of `other` into this index.
+    ///
+    /// The coordinates of `other` are added to the dictionaries of this
+    /// index, and its records are merged into the existing cells, as for
+    /// [`extend`](#method.extend). The grid of this index is kept, so the
+    /// larger of the two indices should usually be the one receiving the
+    /// records of the other. The records of `other` get new identifiers.
+    pub fn merge_from(&mut self, mut other: Self) -> Result<(), SfcError>
+    where
+        F: Clone,
+    {
+        if other.dimensions != self.dimensions {
+            return Err(SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: other.dimensions,
+            });
+        }
+
+        if self.metadata.is_empty() {
+            self.metadata = std::mem::take(&mut other.metadata);
+        }
+
+        let cells = std::mem::take(&mut other.index);
+        let mut entries = other
+            .take_buffer()?
+            .into_iter()
+            .map(|(key, fields, _)| (key, fields))
+            .collect::<Vec<_>>();
+        for cell in cells {
+            let cell = Arc::try_unwrap(cell).unwrap_or_else(|cell| (*cell).clone());
+            let code = cell.code;
+            for (offsets, fields, _) in cell.into_records(other.dimensions) {
+                entries.push((other.position(code, &offsets)?, fields));
+            }
+        }
+
+        let first = self.next_id;
+        self.extend_entries(
+            entries
+                .into_iter()
+                .enumerate()
+                .map(|(i, (key, fields))| (key, fields, RecordId(first + i as u64))),
+        )
+    }
+
+    /// Merges two indices into one, see [`merge_from`](#method.merge_from).
+    pub fn merge(mut self, other: Self) -> Result<Self, SfcError>
+    where
+        F: Clone,
+    {
+        self.merge_from(other)?;
+
+        Ok(self)
+    }
+
+    /// Rebuilds the index with `cell_bits` bits for its grid, see
+    /// [`new`](#method.new).
+    ///
+    /// The positions of the records are decoded from the dictionaries, so
+    /// the records the index was built from are not needed. The values of
+    /// the dictionaries no longer used are dropped, quantized dimensions
+    /// keep their quantizer, and the others are split by their distinct
+    /// values. Records of the write buffer are indexed as well, and all
+    /// the records keep their identifiers.
+    ///
+    /// Fails if a position cannot be decoded, or encoded with the new
+    /// number of bits.
+    pub fn reindex(mut self, cell_bits: usize) -> Result<Self, SfcError>
+    where
+        F: Clone,
+    {
+        let buffer = std::mem::take(&mut self.buffer);
+        let mut entries = Arc::try_unwrap(buffer).unwrap_or_else(|buffer| (*buffer).clone());
+        for cell in std::mem::take(&mut self.index) {
+            let cell = Arc::try_unwrap(cell).unwrap_or_else(|cell| (*cell).clone());
+            let code = cell.code;
+            for (offsets, fields, id) in cell.into_records(self.dimensions) {
+                let position = self.value(code, &offsets)?;
+                let position = position.into_iter().map(Cow::into_owned).collect();
+                entries.push((position, fields, id));
+            }
+        }
+
+        let layout = CellLayout {
+            quantizers: self.space.quantizers().clone(),
+            sizing: CellSizing::Distinct,
+        };
+        let space = CellSpace::new_with(
+            entries.iter(),
+            self.dimensions,
+            cell_bits,
+            layout,
+            |(position, _, _), k| Some(position[k].clone()),
+        );
+        let mut index = Self::empty(space, self.dimensions);
+        index.compressed = self.compressed;
+        index.metadata = self.metadata;
+
+        // Records keep their identifiers, which are used as their source.
+        let mut flat_table = Vec::with_capacity(entries.len());
+        for (position, fields, id) in entries {
+            let (code, offsets) = index
+                .space
+                .key_slice(&position)
+                .map_err(Rejection::InvalidPosition)
+                .and_then(|key| index.encode_entry(key))?;
+            flat_table.push((code, id.0 as usize, (offsets, fields)));
+        }
+        index.build(flat_table, &mut BuildReport::default());
+        index.next_id = self.next_id;
+
+        Ok(index)
+    }
+
+    /// Returns a copy of the index sharing its cells with this one.
+    ///
+    /// Cells are only copied once modified through either index, so
+    /// variants of a large index can be derived without duplicating it.
+    /// `clone` shares the cells in the same way, but requires the values
+    /// to be cloneable.
+    pub fn cow_clone(&self) -> Self
+    where
+        K: Clone,
+    {
+        SpaceFillingCurve {
+            dimensions: self.dimensions,
+            morton: self.morton.clone(),
+            space: self.space.clone(),
+            index: self.index.clone(),
+            codes: self.codes.clone(),
+            buffer: self.buffer.clone(),
+            next_id: self.next_id,
+            locations: self.locations.clone(),
+            compressed: self.compressed,
+            stats: self.stats.clone(),
+            metadata: self.metadata.clone(),
+        }
+    }
+
+    /// Removes all the records stored at `key`, returning their values.
+    ///
+    /// Cells left without records are dropped, the dictionaries are not
+    /// modified.
+    pub fn remove(&mut self, key: &K) -> Vec<F>
+    where
+        F: Clone,
+    {
+        let mut removed = self.remove_buffered(key);
+
+        let (code, offsets) = match self.entry(key) {
+            Err(e) => {
+                debug!("remove: {}", e);
+                return removed;
+            }
+            Ok(entry) => entry,
+        };
+
+        let idx = match self.find_cell(code, &offsets) {
+            None => return removed,
+            Some(idx) => idx,
+        };
+
+        let cell = Arc::make_mut(&mut self.index[idx]);
+        let count = cell.records.len();
+        for (id, fields) in cell.take(self.dimensions, |_, stored, _| stored == offsets) {
+            self.locations.remove(id);
+            removed.push(fields);
+        }
+        self.stats.resize(count, cell.records.len());
+        if cell.records.is_empty() {
+            self.index.remove(idx);
+            self.codes.remove(idx);
+        }
+
+        removed
+    }
+
+    /// Removes the record identified by `id`, returning its values, or
+    /// `None` if no such record is stored.
+    ///
+    /// As for [`remove`](#method.remove), a cell left without records is
+    /// dropped.
+    pub fn remove_id(&mut self, id: RecordId) -> Option<F>
+    where
+        F: Clone,
+    {
+        if let Some(position) = self.buffer.iter().position(|(_, _, i)| *i == id) {
+            let (_, fields, _) = Arc::make_mut(&mut self.buffer).remove(position);
+            self.stats.buffered -= 1;
+            return Some(fields);
+        }
+
+        let (idx, _) = self.locate(id)?;
+        let cell = Arc::make_mut(&mut self.index[idx]);
+        let count = cell.records.len();
+        let fields = cell
+            .take(self.dimensions, |record, _, _| record.id == id)
+            .pop();
+        self.stats.resize(count, cell.records.len());
+        if cell.records.is_empty() {
+            self.index.remove(idx);
+            self.codes.remove(idx);
+        }
+        self.locations.remove(id);
+
+        fields.map(|(_, fields)| fields)
+    }
+
+    /// Returns mutable references to the values stored at `key`.
+    ///
+    /// This allows changing the values attached to a position in place,
+    /// without removing and re-inserting the records.
+    pub fn get_fields_mut(&mut self, key: &K) -> Vec<&mut F>
+    where
+        F: Clone,
+    {
+        let cell = match self.entry(key) {
+            Err(e) => {
+                debug!("get_fields_mut: {}", e);
+                None
+            }
+            Ok((code, offsets)) => self.find_cell(code, &offsets).map(|idx| (idx, offsets)),
+        };
+
+        let mut values = vec![];
+        if self
+            .buffer
+            .iter()
+            .any(|(position, _, _)| is_at(position, key))
+        {
+            values.extend(
+                Arc::make_mut(&mut self.buffer)
+                    .iter_mut()
+                    .filter(|(position, _, _)| is_at(position, key))
+                    .map(|(_, fields, _)| fields),
+            );
+        }
+
+        if let Some((idx, offsets)) = cell {
+            values.append(
+                &mut Arc::make_mut(&mut self.index[idx])
+                    .fields_mut(self.dimensions, |stored| stored == offsets),
+            );
+        }
+
+        values
+    }
+
+    /// Replaces the values of all the records stored at `key` by
+    /// `fields`, returning the number of records updated.
+    pub fn update(&mut self, key: &K, fields: F) -> usize
+    where
+        F: Clone,
+    {
+        let values = self.get_fields_mut(key);
+        let count = values.len();
+        for value in values {
+            *value = fields.clone();
+        }
+
+        count
+    }
+
+    /// Removes every record whose stored values are equal to `value`,
+    /// returning the number of records removed.
+    ///
+    /// This scans the whole index. Cells left without records are
+    /// dropped, the dictionaries are not modified.
+    pub fn remove_by_value(&mut self, value: &F) -> usize
+    where
+        F: Clone,
+    {
+        let mut removed = 0;
+        if self.buffer.iter().any(|(_, fields, _)| fields == value) {
+            let buffer = Arc::make_mut(&mut self.buffer);
+            let count = buffer.len();
+            buffer.retain(|(_, fields, _)| fields != value);
+            removed += count - buffer.len();
+        }
+
+        for cell in &mut self.index {
+            if cell.values.iter().all(|fields| fields != value) {
+                continue;
+            }
+
+            for (id, _) in Arc::make_mut(cell).take(self.dimensions, |_, _, fields| fields == value)
+            {
+                self.locations.remove(id);
+                removed += 1;
+            }
+        }
+        self.index.retain(|cell| !cell.records.is_empty());
+        self.update_codes();
+        self.stats = self.compute_stats();
+
+        removed
+    }
+
+    /// Removes every record within the range `[start, end]`, returning the
+    /// number of records removed.
+    ///
+    /// Only the cells overlapping the range are scanned, and the ones
+    /// fully within it are dropped without decoding their records. Cells
+    /// left without records are dropped, the dictionaries are not
+    /// modified, see [`compact`](#method.compact).
+    pub fn remove_range(&mut self, start: &K, end: &K) -> usize
+    where
+        F: Clone,
+    {
+        let start = self
+            .coordinates(start)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .coordinates(end)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        let mut removed = 0;
+        if start.len() == self.dimensions && end.len() == self.dimensions {
+            let inside =
+                |position: &Vec<V>| is_after(&start, position) && is_before(&end, position);
+            if self.buffer.iter().any(|(position, _, _)| inside(position)) {
+                let buffer = Arc::make_mut(&mut self.buffer);
+                let count = buffer.len();
+                buffer.retain(|(position, _, _)| !inside(position));
+                removed += count - buffer.len();
+            }
+        }
+
+        let mut dropped = HashSet::new();
+        for idx in self.range_cells(&start, &end) {
+            if self.cell_contained(idx, &start, &end) {
+                for record in &self.index[idx].records {
+                    self.locations.remove(record.id);
+                }
+                removed += self.index[idx].records.len();
+                dropped.insert(idx);
+                continue;
+            }
+
+            // Offsets of the records within the range, decoded before the
+            // cell is modified.
+            let cell = &self.index[idx];
+            let mut inside = HashSet::new();
+            for (_, offsets) in cell.entries(self.dimensions) {
+                match self.value(cell.code, offsets) {
+                    Ok(position) => {
+                        if is_after(&start, &position) && is_before(&end, &position) {
+                            inside.insert(offsets.to_vec());
+                        }
+                    }
+                    Err(e) => error!("remove_range: cell {} failed: {}", idx, e),
+                }
+            }
+            if inside.is_empty() {
+                continue;
+            }
+
+            let cell = Arc::make_mut(&mut self.index[idx]);
+            for (id, _) in cell.take(self.dimensions, |_, offsets, _| inside.contains(offsets)) {
+                self.locations.remove(id);
+                removed += 1;
+            }
+            if cell.records.is_empty() {
+                dropped.insert(idx);
+            }
+        }
+
+        if removed > 0 {
+            let mut idx = 0;
+            self.index.retain(|_| {
+                idx += 1;
+                !dropped.contains(&(idx - 1))
+            });
+            self.update_codes();
+            self.stats = self.compute_stats();
+        }
+
+        removed
+    }
+
+    /// Rewrites the cells to reclaim the memory left unused by
+    /// modifications of the index.
+    ///
+    /// Empty cells are dropped, and unused capacity released. Cells still
+    /// shared with a copy of the index are left untouched. When `tighten`
+    /// is set, the values of the dictionaries which are not used by any
+    /// record anymore are removed as well, which requires updating every
+    /// record. The grid of cells is left unchanged.
+    ///
+    /// Returns the estimated size of the index before and after.
+    pub fn compact(&mut self, tighten: bool) -> CompactionReport
+    where
+        F: Clone,
+    {
+        let before = self.size();
+
+        self.index.retain(|cell| !cell.records.is_empty());
+        self.update_codes();
+        // Computed again on the next lookup by identifier, without the
+        // capacity left by removed records.
+        self.locations = Locations::default();
+
+        if tighten && !self.index.is_empty() {
+            let mut used = vec![HashSet::new(); self.dimensions];
+            for cell in &self.index {
+                let cell_ids = self.morton.decode(cell.code);
+                for (_, offsets) in cell.entries(self.dimensions) {
+                    for (k, used) in used.iter_mut().enumerate() {
+                        used.insert((cell_ids[k] as usize, offsets[k] as usize));
+                    }
+                }
+            }
+
+            let remaps = Arc::make_mut(&mut self.space).retain(&used);
+            let morton = &self.morton;
+            let dimensions = self.dimensions;
+            for cell in &mut self.index {
+                let cell_ids = morton.decode(cell.code);
+                let cell = Arc::make_mut(cell);
+                for r in 0..cell.records.len() {
+                    let offsets = cell.offsets_mut(r, dimensions);
+                    for (k, remap) in remaps.iter().enumerate() {
+                        let remap = match remap {
+                            Some(remap) => remap,
+                            None => continue,
+                        };
+                        let offset = remap[cell_ids[k] as usize][offsets[k] as usize];
+                        offsets[k] = offset as SFCOffset;
+                    }
+                }
+            }
+            self.stats = self.compute_stats();
+        }
+
+        for cell in &mut self.index {
+            if let Some(cell) = Arc::get_mut(cell) {
+                cell.records.shrink_to_fit();
+                cell.offsets.shrink_to_fit();
+                cell.values.shrink_to_fit();
+            }
+        }
+        self.index.shrink_to_fit();
+        self.codes.shrink_to_fit();
+
+        let report = CompactionReport {
+            before,
+            after: self.size(),
+        };
+        info!(
+            "Compaction: {} bytes, down from {}",
+            report.after, report.before
+        );
+
+        report
+    }
+
+    /// Stores the values of the records only once per cell, records
+    /// with equal values sharing them.
+    ///
+    /// This is worthwhile for values with few distinct instances, for
+    /// example labels: each record then only keeps a small index into the
+    /// values of its cell. Records added afterwards share the values of
+    /// their cell as well. Finding the values to share is linear in the
+    /// number of distinct values of the cell, which makes insertions
+    /// slower for values with many distinct instances.
+    pub fn compress_values(&mut self)
+    where
+        F: Clone,
+    {
+        for cell in &mut self.index {
+            Arc::make_mut(cell).compress();
+        }
+        self.compressed = true;
+    }
+
+    /// Returns the records within the range `[start, end]`, where each
+    /// side of each dimension can be left unbounded.
+    ///
+    /// `start` and `end` provide one bound per dimension, `None` meaning
+    /// the corresponding side of the dimension is unbounded, for example
+    /// `z >= 100` is expressed as `start = [None, None, Some(100)]` and
+    /// `end = [None, None, None]`.
+    pub fn find_range_partial(&self, start: &[Option<V>], end: &[Option<V>]) -> Vec<(K, &F)> {
+        self.range(start, end)
+    }
+
+    /// Returns the records within `bounds`, which provide one range per
+    /// dimension.
+    ///
+    /// Any range of the standard library can be used, for example
+    /// `10..20` or `..=5`, as long as all the dimensions use the same
+    /// kind. Otherwise, each range can be given as a pair of `Bound`s,
+    /// for example `(Bound::Excluded(10), Bound::Unbounded)`.
+    pub fn find_range_bounds<B>(&self, bounds: &[B]) -> Vec<(K, &F)>
+    where
+        B: RangeBounds<V>,
+    {
+        let inclusive = |bound: Bound<&V>| match bound {
+            Bound::Included(value) | Bound::Excluded(value) => Some(value.clone()),
+            Bound::Unbounded => None,
+        };
+        let start = bounds
+            .iter()
+            .map(|range| inclusive(range.start_bound()))
+            .collect::<Vec<_>>();
+        let end = bounds
+            .iter()
+            .map(|range| inclusive(range.end_bound()))
+            .collect::<Vec<_>>();
+
+        let mut values = self.range(&start, &end);
+
+        // Excluded bounds are scanned as included ones, the records on
+        // them are then dropped.
+        let excluded = |bound: Bound<&V>| matches!(bound, Bound::Excluded(_));
+        if bounds
+            .iter()
+            .any(|range| excluded(range.start_bound()) || excluded(range.end_bound()))
+        {
+            values.retain(|(position, _)| {
+                bounds
+                    .iter()
+                    .enumerate()
+                    .all(|(k, range)| range.contains(&position.coordinate(k)))
+            });
+        }
+
+        values
+    }
+
+    /// Returns the records within the range `[start, end]`, with only the
+    /// coordinates of `dimensions`, in that order.
+    ///
+    /// The other coordinates are only decoded when the cell of a record
+    /// is not fully within the range on their dimension, to check the
+    /// record is. Returns no record if a dimension is not one of the
+    /// index.
+    pub fn find_range_projected(
+        &self,
+        start: &K,
+        end: &K,
+        dimensions: &[usize],
+    ) -> Vec<(Vec<V>, &F)> {
+        if let Some(k) = dimensions.iter().find(|k| **k >= self.dimensions) {
+            error!("find_range_projected: no dimension {}", k);
+            return vec![];
+        }
+        let start = self
+            .coordinates(start)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .coordinates(end)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        let inside = |k: usize, value: &V| {
+            let value = std::slice::from_ref(value);
+            is_after(&start[k..=k], value) && is_before(&end[k..=k], value)
+        };
+
+        let mut values = vec![];
+        for idx in self.range_cells(&start, &end) {
+            let cell = &self.index[idx];
+            let cells = self
+                .morton
+                .decode(cell.code)
+                .iter()
+                .map(|id| *id as usize)
+                .collect::<Vec<_>>();
+            let (min, max) = self.space.cell_bounds(&cells);
+
+            // Dimensions on which the range cuts the cell.
+            let checked = (0..self.dimensions)
+                .filter(|k| !inside(*k, &min[*k]) || !inside(*k, &max[*k]))
+                .collect::<Vec<_>>();
+
+            let value = |k: usize, offsets: &[SFCOffset]| {
+                self.space
+                    .value_of(k, cells[k], offsets[k] as usize)
+                    .map_err(|e| error!("find_range_projected: cell {} failed: {}", idx, e))
+            };
+            for (record, offsets) in cell.entries(self.dimensions) {
+                let within = checked.iter().all(|k| match value(*k, offsets) {
+                    Ok(v) => inside(*k, &v),
+                    Err(()) => false,
+                });
+                if !within {
+                    continue;
+                }
+
+                let projected = dimensions
+                    .iter()
+                    .map(|k| value(*k, offsets).map(Cow::into_owned))
+                    .collect::<Result<Vec<_>, _>>();
+                if let Ok(projected) = projected {
+                    values.push((projected, cell.fields(record)));
+                }
+            }
+        }
+
+        if start.len() == self.dimensions && end.len() == self.dimensions {
+            for (position, fields, _) in self.buffer.iter() {
+                if is_after(&start, position) && is_before(&end, position) {
+                    let projected = dimensions.iter().map(|k| position[*k].clone()).collect();
+                    values.push((projected, fields));
+                }
+            }
+        }
+
+        values
+    }
+
+    /// Returns at most `page_size` records within the range
+    /// `[start, end]`, starting from `cursor`, or from the beginning of
+    /// the range without one.
+    ///
+    /// Also returns the cursor of the next page, `None` once the range is
+    /// exhausted; the next page may be empty. Each page only scans the
+    /// cells from its cursor on. Records are returned in the order of
+    /// `find_range`, provided the index is not modified between pages,
+    /// in which case records may be skipped or repeated.
+    pub fn find_range_page(
+        &self,
+        start: &K,
+        end: &K,
+        cursor: Option<Cursor>,
+        page_size: usize,
+    ) -> (Vec<(K, &F)>, Option<Cursor>) {
+        let page_size = page_size.max(1);
+        let start = self
+            .coordinates(start)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .coordinates(end)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        let cells = self.range_cells(&start, &end);
+        let (first, mut skip, buffered) = match cursor.map(|cursor| cursor.resume) {
+            None => (cells.start, 0, 0),
+            Some(Resume::Cell { code, record }) => {
+                let idx = self.codes.partition_point(|c| *c < code);
+                match self.codes.get(idx) {
+                    Some(c) if *c == code => (idx, record, 0),
+                    _ => (idx, 0, 0),
+                }
+            }
+            Some(Resume::Buffer(position)) => (cells.end, 0, position),
+        };
+
+        let mut values = Vec::with_capacity(page_size);
+        for idx in first.max(cells.start)..cells.end {
+            let contained = self.cell_contained(idx, &start, &end);
+            for record in skip..self.index[idx].records.len() {
+                if values.len() == page_size {
+                    let resume = Resume::Cell {
+                        code: self.index[idx].code,
+                        record,
+                    };
+                    return (values, Some(Cursor { resume }));
+                }
+                values.extend(self.cell_record(idx, record, &start, &end, contained));
+            }
+            skip = 0;
+        }
+
+        if start.len() != self.dimensions || end.len() != self.dimensions {
+            return (values, None);
+        }
+        for (position, (coordinates, fields, _)) in self.buffer.iter().enumerate().skip(buffered) {
+            if values.len() == page_size {
+                let resume = Resume::Buffer(position);
+                return (values, Some(Cursor { resume }));
+            }
+            if is_after(&start, coordinates) && is_before(&end, coordinates) {
+                if let Ok(key) = K::from_coordinates(coordinates.iter().cloned()) {
+                    values.push((key, fields));
+                }
+            }
+        }
+
+        (values, None)
+    }
+
+    /// Returns `n` records within the range `[start, end]`, drawn
+    /// uniformly without replacement, or all of them if there are fewer.
+    ///
+    /// `rng` returns uniformly distributed random numbers. The records
+    /// of each cell are counted first, which only decodes the cells
+    /// crossing the bounds of the range, then the sampled records are
+    /// decoded from the cells holding them. They are returned in the
+    /// order of `find_range`.
+    pub fn sample_range<R>(&self, start: &K, end: &K, n: usize, mut rng: R) -> Vec<(K, &F)>
+    where
+        R: FnMut() -> u64,
+    {
+        let start = self
+            .coordinates(start)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .coordinates(end)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        let counts = self
+            .range_cells(&start, &end)
+            .map(|idx| {
+                let contained = self.cell_contained(idx, &start, &end);
+                let count = if contained {
+                    self.cell_len(idx)
+                } else {
+                    (0..self.cell_len(idx))
+                        .filter(|record| {
+                            self.cell_record(idx, *record, &start, &end, false)
+                                .is_some()
+                        })
+                        .count()
+                };
+                (idx, contained, count)
+            })
+            .collect::<Vec<_>>();
+
+        let mut buffered = vec![];
+        if start.len() == self.dimensions && end.len() == self.dimensions {
+            for (position, fields, _) in self.buffer.iter() {
+                if is_after(&start, position) && is_before(&end, position) {
+                    if let Ok(key) = K::from_coordinates(position.iter().cloned()) {
+                        buffered.push((key, fields));
+                    }
+                }
+            }
+        }
+
+        // Ranks of the sampled records among the ones within the range,
+        // drawn with Floyd's algorithm.
+        let total = counts.iter().map(|(_, _, count)| count).sum::<usize>() + buffered.len();
+        let mut below = |bound: usize| ((u128::from(rng()) * bound as u128) >> 64) as usize;
+        let mut ranks = BTreeSet::new();
+        for j in total - n.min(total)..total {
+            let rank = below(j + 1);
+            if !ranks.insert(rank) {
+                ranks.insert(j);
+            }
+        }
+
+        let mut values = Vec::with_capacity(ranks.len());
+        let mut ranks = ranks.into_iter().peekable();
+        let mut first = 0;
+        for (idx, contained, count) in counts {
+            let last = first + count;
+            if ranks.peek().is_some_and(|rank| *rank < last) {
+                let mut found = (0..self.cell_len(idx))
+                    .filter_map(|record| self.cell_record(idx, record, &start, &end, contained));
+                let mut skipped = first;
+                while let Some(rank) = ranks.next_if(|rank| *rank < last) {
+                    if contained {
+                        values.extend(self.cell_record(idx, rank - first, &start, &end, true));
+                    } else {
+                        values.extend(found.nth(rank - skipped));
+                        skipped = rank + 1;
+                    }
+                }
+            }
+            first = last;
+        }
+        let mut buffered = buffered.into_iter();
+        for rank in ranks {
+            values.extend(buffered.nth(rank - first));
+            first = rank + 1;
+        }
+
+        values
+    }
+
+    /// Calls `visit` on every cell overlapping the range `[start, end]`,
+    /// in Morton order, until it returns `false`.
+    ///
+    /// Nothing is collected: the visitor decides which records of each
+    /// cell to decode, if any, which lets custom filters or aggregates
+    /// skip the work of `find_range`. The records of the write buffer are
+    /// not visited, see [`flush`](#method.flush).
+    pub fn visit_cells_in_range<'a, G>(&'a self, start: &K, end: &K, mut visit: G)
+    where
+        G: FnMut(&CellVisit<'a, '_, F, K, V>) -> bool,
+    {
+        let start = self
+            .coordinates(start)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .coordinates(end)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        for idx in self.range_cells(&start, &end) {
+            let contained = self.cell_contained(idx, &start, &end);
+            if !visit(&CellVisit::new(self, idx, &start, &end, contained)) {
+                break;
+            }
+        }
+    }
+
+    /// Returns the records within Euclidean distance `radius` of
+    /// `center`, bounds included.
+    ///
+    /// Only the cells intersecting the bounding box of the sphere are
+    /// scanned, and the distance of each record found in them is then
+    /// checked.
+    pub fn find_within_radius(&self, center: &K, radius: f64) -> Vec<(K, &F)>
+    where
+        V: Into<f64>,
+    {
+        self.within(center, radius, &Euclidean)
+            .into_iter()
+            .map(|(_, key, fields)| (key, fields))
+            .collect()
+    }
+
+    /// Returns the records within distance `radius` of `center` according
+    /// to `metric`, bounds included, with their distance, closest first.
+    ///
+    /// As for [`find_within_radius`](#method.find_within_radius), only
+    /// the cells intersecting the bounds given by
+    /// [`Metric::extent`](trait.Metric.html#method.extent) are scanned.
+    pub fn find_within_distance<M>(&self, center: &K, radius: f64, metric: &M) -> Vec<(f64, K, &F)>
+    where
+        V: Into<f64>,
+        M: Metric + ?Sized,
+    {
+        let mut values = self.within(center, radius, metric);
+        values.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        values
+    }
+
+    /// Returns the `k` records closest to `center` according to `metric`,
+    /// with their distance, closest first.
+    ///
+    /// The records of the cells around `center` along the curve give a
+    /// distance within which there are at least `k` records, which are
+    /// then searched as [`find_within_distance`](#method.find_within_distance)
+    /// does. Records at the same distance are returned in no particular
+    /// order, and fewer than `k` records only if the index holds fewer.
+    pub fn find_nearest<M>(&self, center: &K, k: usize, metric: &M) -> Vec<(f64, K, &F)>
+    where
+        V: Into<f64>,
+        M: Metric + ?Sized,
+    {
+        if k == 0 {
+            return vec![];
+        }
+        let point = self
+            .coordinates(center)
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<f64>>();
+        if point.len() != self.dimensions {
+            return vec![];
+        }
+
+        // Grow the span of cells around the center until it holds `k`
+        // records.
+        let around = self.around(center);
+        let mut span = 1;
+        let cells = loop {
+            let cells = around.saturating_sub(span)..(around + span).min(self.index.len());
+            let records = self.index[cells.clone()]
+                .iter()
+                .map(|cell| cell.records.len())
+                .sum::<usize>();
+            if records >= k || cells.len() == self.index.len() {
+                break cells;
+            }
+            span *= 2;
+        };
+
+        let unbounded = vec![None; self.dimensions];
+        let mut distances = cells
+            .flat_map(|idx| self.range_cell(idx, &unbounded, &unbounded, &any))
+            .map(|(key, _)| metric.distance(&point, &self.to_f64(&key)))
+            .collect::<Vec<_>>();
+        let radius = if distances.len() >= k {
+            distances.sort_by(f64::total_cmp);
+            distances[k - 1]
+        } else {
+            f64::INFINITY
+        };
+
+        let mut values = self.find_within_distance(center, radius, metric);
+        values.truncate(k);
+
+        values
+    }
+
+    /// Returns about the `k` records closest to `center` according to
+    /// `metric`, with their distance, closest first.
+    ///
+    /// Unlike [`find_nearest`](#method.find_nearest), only the cell of
+    /// `center` and its two neighbours along the curve are scanned, as
+    /// well as the write buffer, so that the query takes about the same
+    /// time whatever `k` and the size of the index.
+    ///
+    /// Also returns a distance within which the results are exact: the
+    /// records closer than it to `center` are all in its cell, so the
+    /// returned records closer than this distance are the closest ones of
+    /// the index. Records further than it may be missed, the actual
+    /// neighbours being in other cells. This only holds for metrics
+    /// using the default [`Metric::extent`](trait.Metric.html#method.extent).
+    pub fn find_nearest_approx<M>(
+        &self,
+        center: &K,
+        k: usize,
+        metric: &M,
+    ) -> (Vec<(f64, K, &F)>, f64)
+    where
+        V: Into<f64>,
+        M: Metric + ?Sized,
+    {
+        let point = self
+            .coordinates(center)
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<f64>>();
+        if k == 0 || point.len() != self.dimensions {
+            return (vec![], 0.0);
+        }
+
+        // Distance from the center to the sides of its cell.
+        let cells = self.cells_of(center);
+        let exact = cells
+            .iter()
+            .zip(&point)
+            .enumerate()
+            .map(
+                |(dimension, (cell, c))| match self.space.cell_range(dimension, *cell) {
+                    Some((min, max)) => {
+                        let (min, max) = (min.into(), max.into());
+                        if min <= *c && *c <= max {
+                            (c - min).min(max - c)
+                        } else {
+                            0.0
+                        }
+                    }
+                    // An empty dimension holds no record.
+                    None => f64::INFINITY,
+                },
+            )
+            .fold(f64::INFINITY, f64::min);
+
+        let around = self.around(center);
+        let unbounded = vec![None; self.dimensions];
+        let mut values = (around.saturating_sub(1)..(around + 2).min(self.index.len()))
+            .flat_map(|idx| self.range_cell(idx, &unbounded, &unbounded, &any))
+            .collect::<Vec<_>>();
+        self.range_buffered(&unbounded, &unbounded, &mut values);
+
+        let mut values = values
+            .into_iter()
+            .map(|(key, fields)| (metric.distance(&point, &self.to_f64(&key)), key, fields))
+            .collect::<Vec<_>>();
+        values.sort_by(|a, b| a.0.total_cmp(&b.0));
+        values.truncate(k);
+
+        (values, exact)
+    }
+
+    /// Returns the records whose nearest neighbour according to `metric`
+    /// is `key`: the records no other record is closer to than `key`.
+    ///
+    /// The cells holding several records, and smaller than their distance
+    /// to `key`, cannot hold any of them and are skipped. The records of
+    /// the other cells are checked one at a time, searching for a record
+    /// closer to them than `key`, up to their distance to `key`. As for
+    /// [`find_nearest_approx`](#method.find_nearest_approx), this only
+    /// holds for metrics using the default
+    /// [`Metric::extent`](trait.Metric.html#method.extent).
+    pub fn reverse_nearest<M>(&self, key: &K, metric: &M) -> Vec<(K, &F)>
+    where
+        V: Into<f64>,
+        M: Metric + ?Sized,
+    {
+        let point = self
+            .coordinates(key)
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<f64>>();
+        if point.len() != self.dimensions {
+            return vec![];
+        }
+
+        let unbounded = vec![None; self.dimensions];
+        let mut candidates = vec![];
+        for (idx, cell) in self.index.iter().enumerate() {
+            if cell.records.len() > 1 {
+                let (min, max) = self.cell_box(idx);
+                let closest = point
+                    .iter()
+                    .zip(min.iter().zip(&max))
+                    .map(|(c, (min, max))| c.clamp(*min, *max))
+                    .collect::<Vec<_>>();
+
+                // Every record of the cell has another one closer than `key`.
+                if metric.distance(&min, &max) < metric.distance(&point, &closest) {
+                    continue;
+                }
+            }
+            for (record, SFCRecord { id, .. }) in cell.records.iter().enumerate() {
+                if let Some((key, fields)) =
+                    self.cell_record(idx, record, &unbounded, &unbounded, true)
+                {
+                    candidates.push((*id, key, fields));
+                }
+            }
+        }
+        self.range_buffered_ids(&unbounded, &unbounded, &mut candidates);
+
+        // Records are told apart by their identifiers, as equal values may
+        // be stored once.
+        candidates
+            .into_iter()
+            .filter(|(id, position, _)| {
+                let distance = metric.distance(&point, &self.to_f64(position));
+                !self
+                    .within_ids(position, distance, metric)
+                    .into_iter()
+                    .any(|(d, other, _, _)| d < distance && other != *id)
+            })
+            .map(|(_, position, fields)| (position, fields))
+            .collect()
+    }
+
+    /// Returns the pairs of records of this index and of `other` within
+    /// `radius` of each other according to `metric`, with their distance.
+    ///
+    /// The cells of both indexes are walked in Morton order. For every
+    /// cell of this index, the cells of `other` close enough to hold a
+    /// match are selected from their bounds, and only their records are
+    /// compared. The records of a cell of `other` are decoded once, and
+    /// kept as long as the next cells of this index may use them. Pairs
+    /// come in the order of the records of this index in `find_range`.
+    pub fn spatial_join<'b, G, M>(
+        &self,
+        other: &'b SpaceFillingCurve<G, K, V>,
+        radius: f64,
+        metric: &M,
+    ) -> Vec<JoinedPair<'_, 'b, F, G, K>>
+    where
+        G: PartialEq,
+        V: Into<f64>,
+        M: Metric + ?Sized,
+    {
+        if self.dimensions != other.dimensions {
+            let e = SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: other.dimensions,
+            };
+            error!("spatial_join: {}", e);
+            return vec![];
+        }
+
+        let rebuild = |coordinates: &[V]| K::from_coordinates(coordinates.iter().cloned()).ok();
+        let extent = (0..self.dimensions)
+            .map(|k| metric.extent(k, radius))
+            .collect::<Vec<_>>();
+        let unbounded = vec![None; self.dimensions];
+        let decode = |records: Vec<(K, &'b G)>| {
+            records
+                .into_iter()
+                .map(|(key, fields)| (other.to_f64(&key), other.coordinates(&key), fields))
+                .collect::<Vec<_>>()
+        };
+
+        let mut buffered = vec![];
+        other.range_buffered(&unbounded, &unbounded, &mut buffered);
+        let buffered = decode(buffered);
+
+        let mut decoded = HashMap::new();
+        let mut pairs = vec![];
+        for idx in 0..self.index.len() {
+            let (min, max) = self.cell_box(idx);
+
+            // Cells of `other` between the bounds of the cell, widened by
+            // the radius.
+            let start = (0..self.dimensions)
+                .map(|k| {
+                    other
+                        .space
+                        .partition_point(k, |v| v.clone().into() < min[k] - extent[k])
+                        .map(Cow::into_owned)
+                })
+                .collect::<Vec<_>>();
+            let end = (0..self.dimensions)
+                .map(|k| {
+                    other
+                        .space
+                        .partition_point(k, |v| v.clone().into() <= max[k] + extent[k])
+                        .map(Cow::into_owned)
+                })
+                .collect::<Vec<_>>();
+            let cells = if start.iter().any(Option::is_none) {
+                0..0
+            } else {
+                other.range_cells(&start, &end)
+            };
+
+            decoded.retain(|other_idx, _| *other_idx >= cells.start);
+            let near = cells
+                .filter(|other_idx| {
+                    let (other_min, other_max) = other.cell_box(*other_idx);
+                    (0..self.dimensions).all(|k| {
+                        other_min[k] - max[k] <= extent[k] && min[k] - other_max[k] <= extent[k]
+                    })
+                })
+                .collect::<Vec<_>>();
+            if near.is_empty() && buffered.is_empty() {
+                continue;
+            }
+            for other_idx in &near {
+                decoded.entry(*other_idx).or_insert_with(|| {
+                    decode(other.range_cell(*other_idx, &unbounded, &unbounded, &any))
+                });
+            }
+
+            for (key, fields) in self.range_cell(idx, &unbounded, &unbounded, &any) {
+                let position = self.to_f64(&key);
+                let coordinates = self.coordinates(&key);
+                let candidates = near
+                    .iter()
+                    .flat_map(|other_idx| &decoded[other_idx])
+                    .chain(&buffered);
+                for (other_position, other_coordinates, other_fields) in candidates {
+                    let distance = metric.distance(&position, other_position);
+                    if distance > radius {
+                        continue;
+                    }
+                    if let (Some(key), Some(other_key)) =
+                        (rebuild(&coordinates), rebuild(other_coordinates))
+                    {
+                        pairs.push((distance, (key, fields), (other_key, *other_fields)));
+                    }
+                }
+            }
+        }
+
+        let mut own = vec![];
+        self.range_buffered(&unbounded, &unbounded, &mut own);
+        for (key, fields) in own {
+            let coordinates = self.coordinates(&key);
+            for (distance, other_key, other_fields) in other.within(&key, radius, metric) {
+                if let Some(key) = rebuild(&coordinates) {
+                    pairs.push((distance, (key, fields), (other_key, other_fields)));
+                }
+            }
+        }
+
+        pairs
+    }
+
+    // Smallest and largest coordinates of the cell `idx`, converted for
+    // the metrics.
+    fn cell_box(&self, idx: usize) -> (Vec<f64>, Vec<f64>)
+    where
+        V: Into<f64>,
+    {
+        let cells = self
+            .morton
+            .decode(self.index[idx].code)
+            .iter()
+            .map(|id| *id as usize)
+            .collect::<Vec<_>>();
+        let (min, max) = self.space.cell_bounds(&cells);
+        let convert = |values: Vec<Cow<V>>| {
+            values
+                .into_iter()
+                .map(|v| v.into_owned().into())
+                .collect::<Vec<f64>>()
+        };
+
+        (convert(min), convert(max))
+    }
+
+    // Records within `radius` of `center`, with their distance, in the
+    // order of `find_range`.
+    fn within<M>(&self, center: &K, radius: f64, metric: &M) -> Vec<(f64, K, &F)>
+    where
+        V: Into<f64>,
+        M: Metric + ?Sized,
+    {
+        self.within_ids(center, radius, metric)
+            .into_iter()
+            .map(|(distance, _, key, fields)| (distance, key, fields))
+            .collect()
+    }
+
+    // Same as `within`, returning the identifiers of the records as well.
+    fn within_ids<M>(&self, center: &K, radius: f64, metric: &M) -> Vec<Neighbour<'_, F, K>>
+    where
+        V: Into<f64>,
+        M: Metric + ?Sized,
+    {
+        let center = self
+            .coordinates(center)
+            .into_iter()
+            .map(Into::into)
+            .collect::<Vec<f64>>();
+
+        // Bounding box of the ball, rounded to values of the dictionaries.
+        let mut empty = false;
+        let mut start = Vec::with_capacity(self.dimensions);
+        let mut end = Vec::with_capacity(self.dimensions);
+        for (k, c) in center.iter().enumerate() {
+            let extent = metric.extent(k, radius);
+            let min = self
+                .space
+                .partition_point(k, |v| v.clone().into() < c - extent);
+            empty |= min.is_none();
+            start.push(min.map(Cow::into_owned));
+
+            let max = self
+                .space
+                .partition_point(k, |v| v.clone().into() <= c + extent);
+            end.push(max.map(Cow::into_owned));
+        }
+
+        let values = if empty {
+            // No value of the dictionaries within the ball, but the write
+            // buffer may hold some.
+            let mut values = vec![];
+            let unbounded = vec![None; self.dimensions];
+            self.range_buffered_ids(&unbounded, &unbounded, &mut values);
+            values
+        } else {
+            self.range_ids(&start, &end)
+        };
+
+        values
+            .into_iter()
+            .filter_map(|(id, key, fields)| {
+                let distance = metric.distance(&center, &self.to_f64(&key));
+                if distance <= radius {
+                    Some((distance, id, key, fields))
+                } else {
+                    None
+                }
+            })
+            .collect()
+    }
+
+    // Coordinates of `key`, converted for the metrics.
+    fn to_f64(&self, key: &K) -> Vec<f64>
+    where
+        V: Into<f64>,
+    {
+        (0..self.dimensions)
+            .map(|k| key.coordinate(k).into())
+            .collect()
+    }
+
+    // Cell of each dimension in which `center` is, or would be, stored.
+    fn cells_of(&self, center: &K) -> Vec<usize> {
+        (0..self.dimensions)
+            .map(|k| {
+                let last = self.space.cells(k).saturating_sub(1);
+                self.space
+                    .cell_of(k, &center.coordinate(k))
+                    .map_or(last, |cell| cell.min(last))
+            })
+            .collect()
+    }
+
+    // Position in the list of cells of the cell in which `center` is, or
+    // would be, stored.
+    fn around(&self, center: &K) -> usize {
+        match self.encode(&self.cells_of(center)) {
+            Ok(code) => self.codes.partition_point(|c| *c < code),
+            Err(_) => 0,
+        }
+    }
+
+    /// Returns the records within the range `[start, end]`, one at a
+    /// time.
+    ///
+    /// Unlike `find_range`, the records are not collected beforehand:
+    /// they are selected one cell at a time, as the iterator advances.
+    pub fn find_range_iter<'a>(
+        &'a self,
+        start: &K,
+        end: &K,
+    ) -> impl Iterator<Item = (K, &'a F)> + 'a {
+        let start = self.coordinates(start).into_iter().map(Some).collect();
+        let end = self.coordinates(end).into_iter().map(Some).collect();
+
+        self.range_iter(start, end, &any)
+    }
+
+    /// Returns at most `limit` records within the range `[start, end]`.
+    ///
+    /// The records are the first ones `find_range` returns, and the scan
+    /// stops at the cell in which the last of them is found, instead of
+    /// going through the whole range.
+    pub fn find_range_limit(&self, start: &K, end: &K, limit: usize) -> Vec<(K, &F)> {
+        self.find_range_iter(start, end).take(limit).collect()
+    }
+
+    /// Returns every record of the index, one at a time.
+    ///
+    /// The records of the cells come in Morton order, followed by the
+    /// records of the write buffer, in insertion order.
+    pub fn iter(&self) -> impl Iterator<Item = (K, &F)> + '_ {
+        let unbounded = vec![None; self.dimensions];
+
+        self.range_iter(unbounded.clone(), unbounded, &any)
+    }
+
+    /// Returns the values stored at `key`, one at a time.
+    ///
+    /// Unlike `find`, the values are not collected, so that point lookups
+    /// do not allocate.
+    pub fn find_iter(&self, key: &K) -> impl Iterator<Item = &F> {
+        let cell = self.lookup(key).unwrap_or_else(|e| {
+            error!("find: {}", e);
+            None
+        });
+
+        self.select(key, cell)
+    }
+
+    /// Returns the values stored at `key`.
+    ///
+    /// Unlike `find`, errors raised while looking the key up are returned
+    /// instead of being logged, so that a key without records can be told
+    /// apart from a corrupted index.
+    pub fn try_find(&self, key: &K) -> Result<Vec<&F>, SfcError> {
+        let cell = self.lookup(key)?;
+
+        Ok(self.select(key, cell).collect())
+    }
+
+    /// Returns the records within the range `[start, end]`.
+    ///
+    /// Unlike `find_range`, records which cannot be decoded make the
+    /// query fail, instead of being logged and skipped.
+    pub fn try_find_range(&self, start: &K, end: &K) -> Result<Vec<(K, &F)>, SfcError> {
+        let start = self
+            .try_coordinates(start)?
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .try_coordinates(end)?
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        let cells = match self.limits(&start, &end) {
+            Ok(limits) => limits.start.idx..limits.end.idx,
+            Err(SfcError::EmptyIndex) => 0..0,
+            Err(e) => return Err(e),
+        };
+
+        let mut values = vec![];
+        for idx in cells {
+            values.extend(self.try_range_cell(idx, &start, &end, &any)?);
+        }
+        for (position, fields, _) in self.buffer.iter() {
+            let pos = position.iter().collect::<Vec<_>>();
+            if is_after(&start, &pos) && is_before(&end, &pos) {
+                let key =
+                    K::from_coordinates(position.iter().cloned()).map_err(SfcError::InvalidKey)?;
+                values.push((key, fields));
+            }
+        }
+
+        Ok(values)
+    }
+
+    // Cell holding the records at `key`, `None` if no record of the cells
+    // is at `key`.
+    fn lookup(&self, key: &K) -> Result<Lookup<'_, F>, SfcError> {
+        let (code, offsets) = match self.entry(key) {
+            Ok(entry) => entry,
+            // Not in the dictionaries, so not in the cells either.
+            Err(Rejection::InvalidPosition(SfcError::OutOfBounds { .. })) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        Ok(self
+            .codes
+            .binary_search(&code)
+            .ok()
+            .map(|idx| (&*self.index[idx], offsets)))
+    }
+
+    // Values of `cell` at `offsets`, followed by the buffered ones at
+    // `key`.
+    fn select<'a>(&'a self, key: &K, cell: Lookup<'a, F>) -> impl Iterator<Item = &'a F> {
+        let dimensions = self.dimensions;
+        let stored = cell.into_iter().flat_map(move |(cell, offsets)| {
+            cell.entries(dimensions)
+                .filter(move |(_, stored)| *stored == offsets.as_slice())
+                .map(move |(record, _)| cell.fields(record))
+        });
+
+        // Avoid decoding the key when the buffer is empty.
+        let position = if self.buffer.is_empty() {
+            vec![]
+        } else {
+            self.coordinates(key)
+        };
+        let buffered = self
+            .buffer
+            .iter()
+            .filter(move |(p, _, _)| *p == position)
+            .map(|(_, fields, _)| fields);
+
+        stored.chain(buffered)
+    }
+
+    /// Returns whether at least one record is stored at `key`.
+    ///
+    /// Stops at the first matching record.
+    pub fn contains(&self, key: &K) -> bool {
+        self.find_iter(key).next().is_some()
+    }
+
+    /// Returns the record identified by `id`, or `None` if no such record
+    /// is stored.
+    ///
+    /// The cells of the records are indexed by identifier on the first
+    /// call, and again after records are added to the cells.
+    pub fn get(&self, id: RecordId) -> Option<(K, &F)> {
+        if let Some((position, fields, _)) = self.buffer.iter().find(|(_, _, i)| *i == id) {
+            return K::from_coordinates(position.iter().cloned())
+                .ok()
+                .map(|key| (key, fields));
+        }
+
+        let (idx, record) = self.locate(id)?;
+        let cell = &self.index[idx];
+        let offsets = cell.offsets(record, self.dimensions);
+        match self.position(cell.code, offsets) {
+            Ok(key) => Some((key, cell.fields(&cell.records[record]))),
+            Err(e) => {
+                error!("get: {}", e);
+                None
+            }
+        }
+    }
+
+    /// Returns the values stored at `key`, with the identifiers of their
+    /// records.
+    pub fn find_ids(&self, key: &K) -> Vec<(RecordId, &F)> {
+        let mut values = vec![];
+        match self.lookup(key) {
+            Ok(Some((cell, offsets))) => values.extend(
+                cell.entries(self.dimensions)
+                    .filter(|(_, stored)| *stored == offsets.as_slice())
+                    .map(|(record, _)| (record.id, cell.fields(record))),
+            ),
+            Ok(None) => (),
+            Err(e) => error!("find_ids: {}", e),
+        }
+
+        if !self.buffer.is_empty() {
+            let position = self.coordinates(key);
+            values.extend(
+                self.buffer
+                    .iter()
+                    .filter(|(p, _, _)| *p == position)
+                    .map(|(_, fields, id)| (*id, fields)),
+            );
+        }
+
+        values
+    }
+
+    /// Returns the records within the range `[start, end]`, with their
+    /// identifiers, in the order of `find_range`.
+    pub fn find_range_ids(&self, start: &K, end: &K) -> Vec<(RecordId, K, &F)> {
+        let start = self
+            .coordinates(start)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .coordinates(end)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        self.range_ids(&start, &end)
+    }
+
+    /// Returns the keys stored both in this index and in `other`, each
+    /// once, in the order of `find_range`.
+    ///
+    /// When `other` shares the dictionaries of this index, see
+    /// [`new_sharing`](#method.new_sharing), both lists of cells are
+    /// walked together in Morton order, and records are compared by their
+    /// offsets: only the returned keys are decoded. Otherwise, every key
+    /// of this index is looked up in `other`.
+    pub fn intersect_keys<G: PartialEq>(&self, other: &SpaceFillingCurve<G, K, V>) -> Vec<K> {
+        self.compare_keys(other, true)
+    }
+
+    /// Returns the keys stored in this index but not in `other`, each
+    /// once, in the order of `find_range`.
+    ///
+    /// See [`intersect_keys`](#method.intersect_keys) for how the indexes
+    /// are compared.
+    pub fn difference_keys<G: PartialEq>(&self, other: &SpaceFillingCurve<G, K, V>) -> Vec<K> {
+        self.compare_keys(other, false)
+    }
+
+    // Keys of this index which are stored in `other` if `present`, or
+    // which are not otherwise.
+    fn compare_keys<G: PartialEq>(
+        &self,
+        other: &SpaceFillingCurve<G, K, V>,
+        present: bool,
+    ) -> Vec<K> {
+        let shared = Arc::ptr_eq(&self.space, &other.space);
+        let mut keys = vec![];
+        let mut next = 0;
+        for cell in &self.index {
+            // Offsets of the records of the same cell of `other`.
+            let mut stored = HashSet::new();
+            if shared {
+                next += other.codes[next..].partition_point(|code| *code < cell.code);
+                if other.codes.get(next) == Some(&cell.code) {
+                    stored.extend(other.index[next].entries(other.dimensions).map(|(_, o)| o));
+                }
+            }
+
+            let mut seen = HashSet::new();
+            for (_, offsets) in cell.entries(self.dimensions) {
+                if !seen.insert(offsets) {
+                    continue;
+                }
+                let found = stored.contains(offsets);
+                if (found && !present) || (shared && !found && present && other.buffer.is_empty()) {
+                    continue;
+                }
+
+                let key = match self.position(cell.code, offsets) {
+                    Ok(key) => key,
+                    Err(e) => {
+                        error!("compare_keys: {}", e);
+                        continue;
+                    }
+                };
+                let found = if shared && other.buffer.is_empty() {
+                    found
+                } else {
+                    found || other.contains(&key)
+                };
+                if found == present {
+                    keys.push(key);
+                }
+            }
+        }
+
+        let mut seen = HashSet::new();
+        for (position, _, _) in self.buffer.iter() {
+            if !seen.insert(position) {
+                continue;
+            }
+            let key = match K::from_coordinates(position.iter().cloned()) {
+                Ok(key) => key,
+                Err(_) => continue,
+            };
+            // Keys also stored in the cells are already handled.
+            let stored = match self.lookup(&key) {
+                Ok(Some((cell, offsets))) => cell
+                    .entries(self.dimensions)
+                    .any(|(_, stored)| stored == offsets.as_slice()),
+                _ => false,
+            };
+            if !stored && other.contains(&key) == present {
+                keys.push(key);
+            }
+        }
+
+        keys
+    }
+
+    /// Returns the records within the range `[start, end]` whose values
+    /// match `predicate`.
+    ///
+    /// The predicate is checked before the position of a record is
+    /// decoded, so that filtering out most of the records is cheaper
+    /// than filtering the result of `find_range`.
+    pub fn find_range_filter<P>(&self, start: &K, end: &K, predicate: P) -> Vec<(K, &F)>
+    where
+        P: Fn(&F) -> bool,
+    {
+        let start = self.coordinates(start).into_iter().map(Some).collect();
+        let end = self.coordinates(end).into_iter().map(Some).collect();
+
+        self.range_iter(start, end, &predicate).collect()
+    }
+
+    /// Folds the records within the range `[start, end]` into a single
+    /// value, starting with `init`, without collecting them.
+    pub fn aggregate_range<A, G>(&self, start: &K, end: &K, init: A, mut fold: G) -> A
+    where
+        G: FnMut(A, &K, &F) -> A,
+    {
+        let start = self.coordinates(start).into_iter().map(Some).collect();
+        let end = self.coordinates(end).into_iter().map(Some).collect();
+
+        self.range_iter(start, end, &any)
+            .fold(init, |acc, (key, fields)| fold(acc, &key, fields))
+    }
+
+    /// Returns the `k` records within the range `[start, end]` whose
+    /// values are the greatest according to `cmp`, greatest first.
+    ///
+    /// Only the best `k` records seen so far are kept while scanning the
+    /// range, in a bounded heap.
+    pub fn top_k_in_range<C>(&self, start: &K, end: &K, k: usize, cmp: C) -> Vec<(K, &F)>
+    where
+        C: Fn(&F, &F) -> Ordering,
+    {
+        if k == 0 {
+            return vec![];
+        }
+
+        let start = self.coordinates(start).into_iter().map(Some).collect();
+        let end = self.coordinates(end).into_iter().map(Some).collect();
+
+        // Min-heap on the values, so that the worst record kept is on top.
+        let mut heap = BinaryHeap::with_capacity(k + 1);
+        for (key, fields) in self.range_iter(start, end, &any) {
+            let ranked = Reverse(Ranked {
+                key,
+                fields,
+                cmp: &cmp,
+            });
+            if heap.len() < k {
+                heap.push(ranked);
+            } else if let Some(worst) = heap.peek() {
+                if cmp(fields, worst.0.fields) == Ordering::Greater {
+                    heap.pop();
+                    heap.push(ranked);
+                }
+            }
+        }
+
+        heap.into_sorted_vec()
+            .into_iter()
+            .map(|Reverse(ranked)| (ranked.key, ranked.fields))
+            .collect()
+    }
+
+    /// Returns the records within each of the ranges `[start, end]`, in
+    /// the same order as `ranges`.
+    ///
+    /// Cells shared by several ranges are scanned once, and the position
+    /// of each record decoded once, which is cheaper than calling
+    /// `find_range` for each of many overlapping or adjacent ranges.
+    pub fn find_ranges(&self, ranges: &[(K, K)]) -> Vec<Vec<(K, &F)>> {
+        let bounds = ranges
+            .iter()
+            .map(|(start, end)| {
+                let start = self.coordinates(start).into_iter().map(Some).collect();
+                let end = self.coordinates(end).into_iter().map(Some).collect();
+                (start, end)
+            })
+            .collect::<Vec<(Vec<_>, Vec<_>)>>();
+        let cells = bounds
+            .iter()
+            .map(|(start, end)| self.range_cells(start, end))
+            .collect::<Vec<_>>();
+
+        let mut values = ranges.iter().map(|_| vec![]).collect::<Vec<_>>();
+        let first = cells.iter().map(|c| c.start).min().unwrap_or(0);
+        let last = cells.iter().map(|c| c.end).max().unwrap_or(0);
+        for idx in first..last {
+            let selected = (0..ranges.len())
+                .filter(|&q| cells[q].contains(&idx))
+                .collect::<Vec<_>>();
+            if selected.is_empty() {
+                continue;
+            }
+
+            let cell = &self.index[idx];
+            for (record, offsets) in cell.entries(self.dimensions) {
+                let pos = match self.value(cell.code, offsets) {
+                    Err(e) => {
+                        error!("{}", e);
+                        continue;
+                    }
+                    Ok(r) => r,
+                };
+
+                for &q in &selected {
+                    let (start, end) = &bounds[q];
+                    if is_after(start, &pos) && is_before(end, &pos) {
+                        if let Ok(key) = K::from_coordinates(pos.iter().map(|v| (**v).clone())) {
+                            values[q].push((key, cell.fields(record)));
+                        }
+                    }
+                }
+            }
+        }
+
+        for (position, fields, _) in self.buffer.iter() {
+            let pos = position.iter().collect::<Vec<_>>();
+            for (q, (start, end)) in bounds.iter().enumerate() {
+                if is_after(start, &pos) && is_before(end, &pos) {
+                    if let Ok(key) = K::from_coordinates(position.iter().cloned()) {
+                        values[q].push((key, fields));
+                    }
+                }
+            }
+        }
+
+        values
+    }
+
+    /// Returns the records within `region`.
+    ///
+    /// Cells whose bounds do not intersect the region are skipped, and
+    /// every record of the other cells is checked.
+    pub fn find_in_region<G>(&self, region: &G) -> Vec<(K, &F)>
+    where
+        G: Region<V>,
+    {
+        let mut values = vec![];
+        for cell in &self.index {
+            let cell_ids = self
+                .morton
+                .decode(cell.code)
+                .iter()
+                .map(|id| *id as usize)
+                .collect::<Vec<_>>();
+            let (min, max) = self.space.cell_bounds(&cell_ids);
+            if !region.intersects_box(&borrowed(&min), &borrowed(&max)) {
+                continue;
+            }
+
+            for (record, offsets) in cell.entries(self.dimensions) {
+                let position = match self.value(cell.code, offsets) {
+                    Err(e) => {
+                        error!("{}", e);
+                        continue;
+                    }
+                    Ok(position) => position,
+                };
+
+                if region.contains(&borrowed(&position)) {
+                    if let Ok(key) = K::from_coordinates(position.into_iter().map(Cow::into_owned))
+                    {
+                        values.push((key, cell.fields(record)));
+                    }
+                }
+            }
+        }
+
+        for (position, fields, _) in self.buffer.iter() {
+            if region.contains(&position.iter().collect::<Vec<_>>()) {
+                if let Ok(key) = K::from_coordinates(position.iter().cloned()) {
+                    values.push((key, fields));
+                }
+            }
+        }
+
+        values
+    }
+
+    /// Executes the query described by `spec`.
+    ///
+    /// The records within the range are filtered on their values, then
+    /// ordered, and finally `skip` and `limit` select the ones returned.
+    /// In curve order, the scan stops as soon as enough records are
+    /// found.
+    pub fn run_query(&self, spec: &QuerySpec<F, V>) -> Result<Vec<(K, &F)>, SfcError> {
+        let bounds = |bounds: &[Option<V>]| {
+            if bounds.is_empty() {
+                Ok(vec![None; self.dimensions])
+            } else if bounds.len() != self.dimensions {
+                Err(SfcError::DimensionMismatch {
+                    expected: self.dimensions,
+                    found: bounds.len(),
+                })
+            } else {
+                Ok(bounds.to_vec())
+            }
+        };
+        let start = bounds(&spec.start)?;
+        let end = bounds(&spec.end)?;
+
+        let values = |fields: &F| spec.values.is_empty() || spec.values.contains(fields);
+        let limit = spec.limit.unwrap_or(usize::MAX);
+        if spec.order == Order::Curve {
+            return Ok(self
+                .range_iter(start, end, &values)
+                .skip(spec.skip)
+                .take(limit)
+                .collect());
+        }
+        let mut results = self.range_iter(start, end, &values).collect::<Vec<_>>();
+
+        let dimensions = self.dimensions;
+        let cmp = |a: &K, b: &K| {
+            (0..dimensions)
+                .map(|k| a.coordinate(k).cmp(&b.coordinate(k)))
+                .find(|o| *o != std::cmp::Ordering::Equal)
+                .unwrap_or(std::cmp::Ordering::Equal)
+        };
+        match spec.order {
+            Order::Curve => (),
+            Order::Ascending => results.sort_by(|(a, _), (b, _)| cmp(a, b)),
+            Order::Descending => results.sort_by(|(a, _), (b, _)| cmp(b, a)),
+        }
+
+        Ok(results.into_iter().skip(spec.skip).take(limit).collect())
+    }
+
+    /// Reports the work done by [`find_range`](#method.find_range) for
+    /// the same bounds: the codes delimiting the cells scanned, how many
+    /// cells are scanned, and how many records are compared with the
+    /// range to find the ones within it.
+    ///
+    /// Fails if `start` or `end` does not have one coordinate per
+    /// dimension.
+    pub fn explain_range(&self, start: &K, end: &K) -> Result<RangeExplain, SfcError> {
+        let start = self.try_coordinates(start)?;
+        let start = start.into_iter().map(Some).collect::<Vec<_>>();
+        let end = self.try_coordinates(end)?;
+        let end = end.into_iter().map(Some).collect::<Vec<_>>();
+
+        let mut explain = RangeExplain::default();
+        match self.limits(&start, &end) {
+            Ok(limits) => {
+                explain.start_code = limits.start.code;
+                explain.end_code = limits.end.code;
+                for idx in limits.start.idx..limits.end.idx {
+                    explain.cells_scanned += 1;
+                    if self.cell_contained(idx, &start, &end) {
+                        explain.cells_contained += 1;
+                    } else {
+                        explain.cells_filtered += 1;
+                    }
+                    explain.records_examined += self.index[idx].records.len();
+                    explain.records_returned += self.range_cell(idx, &start, &end, &any).len();
+                }
+            }
+            Err(SfcError::EmptyIndex) => (),
+            Err(e) => return Err(e),
+        }
+
+        let mut buffered = vec![];
+        self.range_buffered(&start, &end, &mut buffered);
+        explain.records_examined += self.buffer.len();
+        explain.records_returned += buffered.len();
+
+        Ok(explain)
+    }
+
+    /// Checks the consistency of the index, for example after loading it
+    /// from an untrusted source, and returns the violations found.
+    ///
+    /// Cells must be sorted by code without duplicates, their codes must
+    /// round-trip through the encoder within the grid, and the records
+    /// must have one offset per dimension, within the dictionaries, and
+    /// values in their cell. The statistics must match the records. This
+    /// walks every record.
+    pub fn validate(&self) -> Vec<Violation> {
+        let mut violations = vec![];
+
+        let cells = self.index.iter().map(|cell| cell.code);
+        if !self.codes.iter().copied().eq(cells) {
+            violations.push(Violation::StaleCodes);
+        }
+
+        for (idx, cell) in self.index.iter().enumerate() {
+            if idx > 0 {
+                match self.index[idx - 1].code.cmp(&cell.code) {
+                    Ordering::Less => (),
+                    Ordering::Equal => violations.push(Violation::DuplicateCode { cell: idx }),
+                    Ordering::Greater => violations.push(Violation::Unsorted { cell: idx }),
+                }
+            }
+
+            let cell_ids = self.morton.decode(cell.code);
+            let in_grid = cell_ids
+                .iter()
+                .enumerate()
+                .all(|(k, id)| (*id as usize) < self.space.cells(k));
+            if !in_grid || self.morton.encode(&cell_ids) != Ok(cell.code) {
+                violations.push(Violation::InvalidCode {
+                    cell: idx,
+                    code: cell.code,
+                });
+                continue;
+            }
+
+            // Offsets are stored one record after the other, so extra ones
+            // are counted with the last record.
+            let extra = cell
+                .offsets
+                .len()
+                .saturating_sub(cell.records.len() * self.dimensions);
+            for (r, record) in cell.records.iter().enumerate() {
+                let offsets = cell.offsets(r, self.dimensions);
+                let found = match r + 1 == cell.records.len() {
+                    true => offsets.len() + extra,
+                    false => offsets.len(),
+                };
+                if found != self.dimensions {
+                    violations.push(Violation::DimensionMismatch {
+                        cell: idx,
+                        record: r,
+                        found,
+                    });
+                    continue;
+                }
+
+                let cell_ids = cell_ids.iter().map(|id| *id as usize).collect();
+                let offsets = offsets.iter().map(|o| *o as usize).collect();
+                if let Err(SfcError::OutOfBounds { dimension, .. }) =
+                    self.space.value(cell_ids, offsets)
+                {
+                    violations.push(Violation::OffsetOutOfBounds {
+                        cell: idx,
+                        record: r,
+                        dimension,
+                    });
+                }
+
+                if record.value as usize >= cell.values.len() {
+                    violations.push(Violation::MissingValues {
+                        cell: idx,
+                        record: r,
+                    });
+                }
+            }
+        }
+
+        if self.stats != self.compute_stats() {
+            violations.push(Violation::StaleStats);
+        }
+
+        violations
+    }
+
+    /// Measures how the index is used, and suggests changes to its
+    /// configuration.
+    ///
+    /// This looks at the number of records per cell, and runs a few
+    /// queries between sampled records to compare the number of records
+    /// scanned to the number returned. It is meant to be run once the
+    /// index is loaded with representative data.
+    pub fn advise(&self) -> Vec<Advice> {
+        let mut advice = vec![];
+
+        let bits = self.cell_bits().iter().sum::<usize>() / self.dimensions.max(1);
+        let records = self
+            .index
+            .iter()
+            .map(|cell| cell.records.len())
+            .sum::<usize>();
+        let occupancy = records as f64 / self.index.len().max(1) as f64;
+        debug!("advise: {} records per cell on average", occupancy);
+
+        // Range queries between sampled records.
+        let step = (self.index.len() / ADVICE_SAMPLES).max(1);
+        let samples = self
+            .index
+            .iter()
+            .step_by(step)
+            .filter_map(|cell| self.value(cell.code, cell.offsets(0, self.dimensions)).ok())
+            .collect::<Vec<_>>();
+        let mut scanned = 0;
+        let mut returned = 0;
+        for pair in samples.windows(2) {
+            let (start, end) = pair[0]
+                .iter()
+                .zip(pair[1].iter())
+                .map(|(a, b)| {
+                    let (min, max) = if a <= b { (a, b) } else { (b, a) };
+                    (Some((**min).clone()), Some((**max).clone()))
+                })
+                .unzip::<_, _, Vec<_>, Vec<_>>();
+
+            if let Ok(limits) = self.limits(&start, &end) {
+                scanned += self.index[limits.start.idx..limits.end.idx]
+                    .iter()
+                    .map(|cell| cell.records.len())
+                    .sum::<usize>();
+                returned += self.range(&start, &end).len();
+            }
+        }
+        let ratio = scanned as f64 / returned.max(1) as f64;
+        debug!("advise: {} records scanned per record returned", ratio);
+
+        if (occupancy > ADVICE_MAX_OCCUPANCY || ratio > ADVICE_MAX_SCAN_RATIO)
+            && bits < MORTON_VALUE_BITS
+        {
+            advice.push(Advice::IncreaseCellBits {
+                current: bits,
+                suggested: bits + 1,
+            });
+        } else if occupancy < ADVICE_MIN_OCCUPANCY && bits > 1 {
+            advice.push(Advice::DecreaseCellBits {
+                current: bits,
+                suggested: bits - 1,
+            });
+        }
+
+        if !self.compressed && records > 0 {
+            let distinct = self
+                .index
+                .iter()
+                .map(|cell| {
+                    let mut distinct: Vec<&F> = vec![];
+                    for value in &cell.values {
+                        if !distinct.contains(&value) {
+                            distinct.push(value);
+                        }
+                    }
+                    distinct.len()
+                })
+                .sum::<usize>();
+            let shared = 1.0 - distinct as f64 / records as f64;
+            if shared > ADVICE_MIN_SHARED {
+                advice.push(Advice::CompressValues { shared });
+            }
+        }
+
+        if self.buffer.len() > ADVICE_MAX_BUFFERED {
+            advice.push(Advice::Flush {
+                buffered: self.buffer.len(),
+            });
+        }
+
+        advice
+    }
+
+    /// Turns the index into a read-only one, optimized for lookups.
+    ///
+    /// The write buffer is flushed first, see
+    /// [`FrozenIndex`](struct.FrozenIndex.html).
+    pub fn freeze(mut self) -> Result<FrozenIndex<F, K, V>, SfcError>
+    where
+        F: Clone,
+    {
+        self.flush()?;
+
+        Ok(FrozenIndex::new(self))
+    }
+
+    /// Returns the encoder mapping the cells of the grid to their Morton
+    /// codes.
+    pub fn encoder(&self) -> &MortonEncoder {
+        &self.morton
+    }
+
+    /// Returns the grid of the index: the dictionaries of the dimensions,
+    /// and their cells.
+    ///
+    /// The cells of a position, as given by
+    /// [`CellSpace::cell_of`](struct.CellSpace.html#method.cell_of), are
+    /// turned into its Morton code by the [`encoder`](#method.encoder).
+    pub fn space(&self) -> &CellSpace<K, V> {
+        &self.space
+    }
+
+    /// Returns the number of bits of the Morton code allocated to each
+    /// dimension.
+    ///
+    /// The `dimensions * cell_bits` bits requested at construction are
+    /// shared among the dimensions according to the number of distinct
+    /// coordinates found in each of them, so that nearly constant
+    /// dimensions do not waste bits which would improve the pruning on
+    /// the others.
+    pub fn cell_bits(&self) -> &[usize] {
+        self.space.cell_bits()
+    }
+
+    /// Returns a view of the index, where the records of `patch` shadow
+    /// the records of the index stored at the same key, and supplement
+    /// the others.
+    pub fn overlay<'a>(&'a self, patch: &'a Self) -> OverlayView<'a, F, K, V> {
+        OverlayView::new(self, patch)
+    }
+
+    /// Returns the description of the dimensions, empty unless set with
+    /// [`set_dimensions`](#method.set_dimensions).
+    pub fn dimensions(&self) -> &[Dimension] {
+        &self.metadata
+    }
+
+    /// Returns the description of the dimension `dimension`, if any.
+    pub fn dimension(&self, dimension: usize) -> Option<&Dimension> {
+        self.metadata.get(dimension)
+    }
+
+    /// Describes the dimensions of the space, one entry per dimension.
+    ///
+    /// The descriptions are stored with the index, and not interpreted.
+    pub fn set_dimensions(&mut self, dimensions: Vec<Dimension>) -> Result<(), SfcError> {
+        if dimensions.len() != self.dimensions {
+            return Err(SfcError::DimensionMismatch {
+                expected: self.dimensions,
+                found: dimensions.len(),
+            });
+        }
+
+        self.metadata = dimensions;
+        Ok(())
+    }
+
+    /// Returns a vector of keys which have stored values in the index
+    /// equal to `value`.
+    pub fn find_by_value(&self, value: &F) -> Vec<K> {
+        self.find_by_fields(|fields| fields == value)
+    }
+
+    // Keys of the records whose stored values match `predicate`, in a
+    // single scan of the cells and of the write buffer.
+    pub(crate) fn find_by_fields<P>(&self, predicate: P) -> Vec<K>
+    where
+        P: Fn(&F) -> bool,
+    {
+        let mut results = vec![];
+        for cell in &self.index {
+            for (record, offsets) in cell.entries(self.dimensions) {
+                if predicate(cell.fields(record)) {
+                    if let Ok(key) = self.position(cell.code, offsets) {
+                        results.push(key);
+                    }
+                }
+            }
+        }
+
+        for (position, fields, _) in self.buffer.iter() {
+            if predicate(fields) {
+                if let Ok(key) = K::from_coordinates(position.iter().cloned()) {
+                    results.push(key);
+                }
+            }
+        }
+
+        results
+    }
+
+    // Codes of the cells, in increasing order.
+    pub(crate) fn codes(&self) -> impl Iterator<Item = SFCCode> + '_ {
+        self.codes.iter().copied()
+    }
+
+    // Offsets of all the records, with the code of their cell.
+    pub(crate) fn entries(&self) -> impl Iterator<Item = (SFCCode, &[SFCOffset])> + '_ {
+        let dimensions = self.dimensions;
+        self.index.iter().flat_map(move |cell| {
+            cell.entries(dimensions)
+                .map(move |(_, offsets)| (cell.code, offsets))
+        })
+    }
+
+    // Every cell, as its code, the offsets and value index of its records,
+    // and its values.
+    #[cfg(feature = "serde")]
+    pub(crate) fn cells(&self) -> impl Iterator<Item = CellParts<'_, F>> + '_ {
+        let dimensions = self.dimensions;
+        self.index.iter().map(move |cell| {
+            let records = cell
+                .entries(dimensions)
+                .map(|(record, offsets)| (offsets, record.value as usize))
+                .collect();
+            (cell.code, records, cell.values.as_slice())
+        })
+    }
+
+    // Whether equal values are stored once per cell.
+    #[cfg(feature = "serde")]
+    pub(crate) fn is_compressed(&self) -> bool {
+        self.compressed
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_compressed(&mut self, compressed: bool) {
+        self.compressed = compressed;
+    }
+
+    // Identifier of the next record inserted, kept by the layouts which
+    // do not serialize the index as a whole.
+    #[cfg(feature = "serde")]
+    pub(crate) fn next_record_id(&self) -> u64 {
+        self.next_id
+    }
+
+    #[cfg(feature = "serde")]
+    pub(crate) fn set_next_record_id(&mut self, next_id: u64) {
+        self.next_id = next_id;
+    }
+
+    // Serialize at most `count` cells, starting from the cell `first`.
+    #[cfg(feature = "serde")]
+    pub(crate) fn serialize_cells(&self, first: usize, count: usize) -> Result<Vec<u8>, SfcError>
+    where
+        F: Serialize,
+    {
+        let end = self.index.len().min(first.saturating_add(count));
+        let start = first.min(end);
+
+        bincode::serialize(&self.index[start..end]).map_err(|e| SfcError::Io(e.to_string()))
+    }
+
+    // Append the cells serialized by `serialize_cells`, which have to
+    // follow the last cell of the index on the curve. Returns the number
+    // of cells appended.
+    #[cfg(feature = "serde")]
+    pub(crate) fn deserialize_cells(&mut self, bytes: &[u8]) -> Result<usize, SfcError>
+    where
+        F: DeserializeOwned,
+    {
+        let cells: Vec<Arc<SFCCell<F>>> =
+            bincode::deserialize(bytes).map_err(|e| SfcError::Io(e.to_string()))?;
+
+        let count = cells.len();
+        for cell in cells {
+            if cell.records.is_empty()
+                || matches!(self.codes.last(), Some(last) if *last >= cell.code)
+            {
+                return Err(SfcError::InvalidFormat(format!(
+                    "Cell {} is empty or out of order",
+                    cell.code
+                )));
+            }
+            for record in &cell.records {
+                self.next_id = self.next_id.max(record.id.0 + 1);
+                self.locations.insert(record.id, cell.code);
+            }
+            self.codes.push(cell.code);
+            self.stats.resize(0, cell.records.len());
+            self.index.push(cell);
+        }
+
+        Ok(count)
+    }
+
+    // Values of the records of the cell `idx` stored at `offsets`.
+    pub(crate) fn cell_fields(&self, idx: usize, offsets: &[SFCOffset]) -> Vec<&F> {
+        let cell = &self.index[idx];
+        cell.entries(self.dimensions)
+            .filter(|(_, stored)| *stored == offsets)
+            .map(|(record, _)| cell.fields(record))
+            .collect()
+    }
+
+    // Add the coordinates of `position` to the dictionaries, see
+    // `CellSpace::insert`. Dictionaries shared with other indices are
+    // only copied if some coordinates are missing.
+    fn insert_position(&mut self, position: &K) -> (Vec<usize>, Vec<usize>, Vec<Inserted>) {
+        let (cell_ids, offsets, inserted) = match self.space.key(position) {
+            Ok((cell_ids, offsets)) => (cell_ids, offsets, vec![]),
+            Err(_) => Arc::make_mut(&mut self.space).insert(position),
+        };
+        for (k, _, _) in &inserted {
+            self.stats.dictionaries[*k] += 1;
+        }
+
+        (cell_ids, offsets, inserted)
+    }
+
+    // Compute the code and stored offsets of a position.
+    pub(crate) fn entry(&self, position: &K) -> Result<(SFCCode, Vec<SFCOffset>), Rejection> {
+        let key = self.space.key(position).map_err(|e| match e {
+            SfcError::DimensionMismatch { expected, found } => {
+                Rejection::DimensionMismatch { expected, found }
+            }
+            e => Rejection::InvalidPosition(e),
+        })?;
+
+        self.encode_entry(key)
+    }
+
+    // Compute the code and stored offsets of the cells and offsets of a
+    // position in the dictionaries.
+    fn encode_entry(
+        &self,
+        (cell_ids, offsets): (Vec<usize>, Vec<usize>),
+    ) -> Result<(SFCCode, Vec<SFCOffset>), Rejection> {
+        let code = self.encode(&cell_ids).map_err(Rejection::Encoding)?;
+
+        Ok((code, self.offsets(&offsets)?))
+    }
+
+    // Estimate the memory used by the index, see `mem_usage`.
+    fn size(&self) -> usize {
+        self.mem_usage().total()
+    }
+
+    // Rebuild the codes of the cells, after they were modified in bulk.
+    fn update_codes(&mut self) {
+        self.codes = self.index.iter().map(|cell| cell.code).collect();
+    }
+
+    // Compute the statistics of the index from scratch.
+    fn compute_stats(&self) -> IndexStats {
+        let mut stats = IndexStats::default();
+        for cell in &self.index {
+            stats.resize(0, cell.records.len());
+        }
+        stats.buffered = self.buffer.len();
+        stats.dictionaries = (0..self.dimensions)
+            .map(|k| self.space.dictionary_size(k))
+            .collect();
+
+        stats
+    }
+
+    // Extract the coordinates of a position, which must have one per
+    // dimension.
+    pub(crate) fn try_coordinates(&self, position: &K) -> Result<Vec<V>, SfcError> {
+        self.space.check(position)?;
+
+        Ok((0..self.dimensions)
+            .map(|k| position.coordinate(k))
+            .collect())
+    }
+
+    // Same as `try_coordinates`, logging the error and returning no
+    // coordinates, which match no record and bound no range.
+    pub(crate) fn coordinates(&self, position: &K) -> Vec<V> {
+        self.try_coordinates(position).unwrap_or_else(|e| {
+            error!("Invalid key {:?}: {}", position, e);
+            vec![]
+        })
+    }
+
+    // Identifier of a new record.
+    fn next_id(&mut self) -> RecordId {
+        let id = RecordId(self.next_id);
+        self.next_id += 1;
+
+        id
+    }
+
+    // Cell, and position in it, of the record identified by `id`.
+    fn locate(&self, id: RecordId) -> Option<(usize, usize)> {
+        let code = self.locations.get(id, || {
+            self.index
+                .iter()
+                .flat_map(|cell| {
+                    cell.records
+                        .iter()
+                        .map(move |record| (record.id, cell.code))
+                })
+                .collect()
+        })?;
+
+        let idx = self.codes.binary_search(&code).ok()?;
+        let record = self.index[idx]
+            .records
+            .iter()
+            .position(|record| record.id == id)?;
+
+        Some((idx, record))
+    }
+
+    // Take the records out of the write buffer.
+    fn take_buffer(&mut self) -> Result<Vec<(K, F, RecordId)>, SfcError>
+    where
+        F: Clone,
+    {
+        let buffer = std::mem::take(&mut self.buffer);
+        let buffer = Arc::try_unwrap(buffer).unwrap_or_else(|buffer| (*buffer).clone());
+        self.stats.buffered = 0;
+
+        buffer
+            .into_iter()
+            .map(|(position, fields, id)| {
+                Ok((
+                    K::from_coordinates(position).map_err(SfcError::InvalidKey)?,
+                    fields,
+                    id,
+                ))
+            })
+            .collect()
+    }
+
+    // Remove the records of the write buffer stored at `key`.
+    fn remove_buffered(&mut self, key: &K) -> Vec<F>
+    where
+        F: Clone,
+    {
+        if !self
+            .buffer
+            .iter()
+            .any(|(position, _, _)| is_at(position, key))
+        {
+            return vec![];
+        }
+
+        let buffer = std::mem::take(Arc::make_mut(&mut self.buffer));
+        let (removed, kept): (Vec<_>, Vec<_>) = buffer
+            .into_iter()
+            .partition(|(position, _, _)| is_at(position, key));
+        self.stats.buffered = kept.len();
+        self.buffer = Arc::new(kept);
+
+        removed.into_iter().map(|(_, fields, _)| fields).collect()
+    }
+
+    // Find the cell holding records stored at `code` and `offsets`.
+    fn find_cell(&self, code: SFCCode, offsets: &[SFCOffset]) -> Option<usize> {
+        let idx = self.codes.binary_search(&code).ok()?;
+
+        if self.index[idx]
+            .entries(self.dimensions)
+            .any(|(_, stored)| stored == offsets)
+        {
+            Some(idx)
+        } else {
+            None
+        }
+    }
+
+    // Convert offsets to their stored representation.
+    fn offsets(&self, offsets: &[usize]) -> Result<Vec<SFCOffset>, Rejection> {
+        if offsets.len() != self.dimensions {
+            return Err(Rejection::DimensionMismatch {
+                expected: self.dimensions,
+                found: offsets.len(),
+            });
+        }
+
+        offsets
+            .iter()
+            .enumerate()
+            .map(|(dimension, offset)| {
+                SFCOffset::try_from(*offset).map_err(|_| Rejection::OffsetOverflow {
+                    dimension,
+                    offset: *offset,
+                })
+            })
+            .collect()
+    }
+
+    // Map the cell_ids of a point to its SFCcode
+    pub(crate) fn encode(&self, cell_ids: &[usize]) -> Result<SFCCode, SfcError> {
+        let mut t = vec![];
+        for v in cell_ids.iter() {
+            t.push(*v as MortonValue);
+        }
+
+        self.morton.encode(&t)
+    }
+
+    // Insert a batch of (position, fields) entries, see `extend`.
+    fn extend_entries<I>(&mut self, iter: I) -> Result<(), SfcError>
+    where
+        F: Clone,
+        I: Iterator<Item = (K, F, RecordId)>,
+    {
+        // 1. Add the new coordinates to the dictionaries, then fix the
+        // offsets of the records already in the index.
+        // Validate the whole batch first, so that the dictionaries are not
+        // modified when a record is rejected.
+        let entries = iter.collect::<Vec<_>>();
+        for (position, _, _) in &entries {
+            self.space.check(position)?;
+        }
+
+        let mut batch = vec![];
+        let mut added = vec![];
+        for (position, fields, id) in entries {
+            self.next_id = self.next_id.max(id.0 + 1);
+            let (_, _, inserted) = self.insert_position(&position);
+            let inserted = inserted.iter().map(|(k, _, _)| *k).collect::<Vec<_>>();
+            batch.push((position, inserted, (fields, id)));
+        }
+
+        // Offsets move while values are added, so retrieve the final ones.
+        let mut keys = Vec::with_capacity(batch.len());
+        for (position, inserted, fields) in batch {
+            let (cell_ids, offsets) = self.space.key(&position)?;
+            for k in inserted {
+                added.push((k, cell_ids[k], offsets[k]));
+            }
+            keys.push((cell_ids, offsets, fields));
+        }
+        self.shift_offsets(&added);
+
+        let mut flat_table = Vec::with_capacity(keys.len());
+        for (cell_ids, offsets, (fields, id)) in keys {
+            flat_table.push((
+                self.encode(&cell_ids)?,
+                (self.offsets(&offsets)?, fields, id),
+            ));
+        }
+
+        // 2. Sort the batch, and merge it with the existing cells.
+        #[cfg(feature = "radix")]
+        radix::sort_by_code(&mut flat_table, |(code, _)| *code);
+        #[cfg(not(feature = "radix"))]
+        flat_table.sort_unstable_by_key(|(code, _)| *code);
+        let nb_records = flat_table.len();
+
+        let mut cells = Vec::with_capacity(self.index.len() + flat_table.len());
+        let mut flat_table = flat_table.into_iter().peekable();
+        for mut cell in self.index.drain(..) {
+            // New cells, before the current one.
+            while let Some((code, _)) = flat_table.peek() {
+                if *code >= cell.code {
+                    break;
+                }
+                let (code, record) = flat_table.next().unwrap();
+                self.locations.insert(record.2, code);
+                push_record(&mut cells, code, record, self.compressed);
+            }
+
+            // New records of the current cell.
+            while let Some((code, _)) = flat_table.peek() {
+                if *code != cell.code {
+                    break;
+                }
+                let (code, (offsets, fields, id)) = flat_table.next().unwrap();
+                self.locations.insert(id, code);
+                Arc::make_mut(&mut cell).push(id, offsets, fields, self.compressed);
+            }
+
+            cells.push(cell);
+        }
+        for (code, record) in flat_table {
+            self.locations.insert(record.2, code);
+            push_record(&mut cells, code, record, self.compressed);
+        }
+        self.index = cells;
+        self.update_codes();
+        self.stats = self.compute_stats();
+
+        debug!("Inserted {:#?} records into the index", nb_records);
+
+        Ok(())
+    }
+
+    // Values added to a dictionary move the following values of their
+    // cell, so update the offsets of the records using them.
+    //
+    // `inserted` lists the final (dimension, cell, offset) of every added
+    // value, once all of them have been inserted.
+    fn shift_offsets(&mut self, inserted: &[Inserted])
+    where
+        F: Clone,
+    {
+        if inserted.is_empty() {
+            return;
+        }
+
+        let mut added: HashMap<(usize, usize), Vec<usize>> = HashMap::new();
+        for (k, cell_id, offset) in inserted {
+            added.entry((*k, *cell_id)).or_default().push(*offset);
+        }
+        for offsets in added.values_mut() {
+            offsets.sort_unstable();
+        }
+
+        let morton = &self.morton;
+        let dimensions = self.dimensions;
+        for cell in &mut self.index {
+            let cell_ids = morton.decode(cell.code);
+            for (k, cell_id) in cell_ids.iter().enumerate() {
+                let added = match added.get(&(k, *cell_id as usize)) {
+                    None => continue,
+                    Some(added) => added,
+                };
+
+                // Leave the cell shared if none of its offsets move.
+                let first = added[0];
+                let moved = (0..cell.records.len())
+                    .any(|r| cell.offsets(r, dimensions)[k] as usize >= first);
+                if !moved {
+                    continue;
+                }
+
+                let cell = Arc::make_mut(cell);
+                for r in 0..cell.records.len() {
+                    // Count the added values which end up before this one.
+                    let offsets = cell.offsets_mut(r, dimensions);
+                    let old = offsets[k] as usize;
+                    let mut shift = 0;
+                    for offset in added {
+                        if *offset <= old + shift {
+                            shift += 1;
+                        } else {
+                            break;
+                        }
+                    }
+                    offsets[k] += shift as SFCOffset;
+                }
+            }
+        }
+    }
+
+    fn value(&self, code: SFCCode, offsets: &[SFCOffset]) -> Result<Vec<Cow<'_, V>>, SfcError> {
+        Ok(self.space.value(
+            self.morton
+                .decode(code)
+                .iter()
+                .map(|e| *e as usize)
+                .collect(),
+            offsets.iter().map(|e| *e as usize).collect(),
+        )?)
+    }
+
+    // Build coordinate values from encoded value
+    fn position(&self, code: SFCCode, offsets: &[SFCOffset]) -> Result<K, SfcError> {
+        let position = self.value(code, offsets)?;
+
+        K::from_coordinates(position.into_iter().map(Cow::into_owned)).map_err(SfcError::InvalidKey)
+    }
+
+    fn limits(&self, start: &[Option<V>], end: &[Option<V>]) -> Result<Limits<V>, SfcError> {
+        trace!("limits: {:?} - {:?}", start, end);
+
+        // The dictionaries of an empty index hold no value to round to.
+        if self.index.is_empty() {
+            return Err(SfcError::EmptyIndex);
+        }
+
+        // Round down if not found, for start of range:
+        let (cells, offsets) = self.space.key_down(start)?;
+        let code = self.encode(&cells)?;
+        let idx = match self.codes.binary_search(&code) {
+            Err(e) => {
+                if e > 0 {
+                    e - 1
+                } else {
+                    0
+                }
+            }
+            Ok(c) => c,
+        };
+        let position = self.space.value(cells, offsets)?;
+        let start = Limit {
+            idx,
+            code,
+            position,
+        };
+
+        // Round up if not found, for end of range:
+        let (cells, offsets) = self.space.key_up(end)?;
+        let code = self.encode(&cells)?;
+        let idx = match self.codes.binary_search(&code) {
+            Err(e) => {
+                if e >= self.index.len() {
+                    self.index.len()
+                } else {
+                    e
+                }
+            }
+            Ok(c) => c + 1,
+        };
+
+        let position = self.space.value(cells, offsets)?;
+        let end = Limit {
+            idx,
+            code,
+            position,
+        };
+
+        trace!("limits: {:?} - {:?}", start, end);
+
+        Ok(Limits { start, end })
+    }
+
+    // Scan the cells between the limits of the range, and select the records
+    // within the bounds. `None` leaves a side of a dimension unbounded.
+    fn range(&self, start: &[Option<V>], end: &[Option<V>]) -> Vec<(K, &F)> {
+        self.range_iter(start.to_vec(), end.to_vec(), &any)
+            .collect()
+    }
+
+    // Same as `range`, returning the identifiers of the records as well.
+    fn range_ids(&self, start: &[Option<V>], end: &[Option<V>]) -> Vec<(RecordId, K, &F)> {
+        let mut values = vec![];
+        for idx in self.range_cells(start, end) {
+            let contained = self.cell_contained(idx, start, end);
+            for (record, SFCRecord { id, .. }) in self.index[idx].records.iter().enumerate() {
+                if let Some((key, fields)) = self.cell_record(idx, record, start, end, contained) {
+                    values.push((*id, key, fields));
+                }
+            }
+        }
+        self.range_buffered_ids(start, end, &mut values);
+
+        values
+    }
+
+    // Lazy version of `range`, selecting the records one cell at a time.
+    // Records whose values do not match `filter` are skipped before their
+    // positions are decoded.
+    fn range_iter<'a, 'p, P>(
+        &'a self,
+        start: Vec<Option<V>>,
+        end: Vec<Option<V>>,
+        filter: &'p P,
+    ) -> impl Iterator<Item = (K, &'a F)> + 'p
+    where
+        'a: 'p,
+        P: Fn(&F) -> bool,
+    {
+        let cells = self.range_cells(&start, &end);
+
+        // Bounds missing dimensions select nothing, not even buffered records.
+        let valid = start.len() == self.dimensions && end.len() == self.dimensions;
+        let bounds = (start.clone(), end.clone());
+        let buffered = self.buffer.iter().filter_map(move |(position, fields, _)| {
+            if !valid || !filter(fields) {
+                return None;
+            }
+            let (start, end) = &bounds;
+            let pos = position.iter().collect::<Vec<_>>();
+            if is_after(start, &pos) && is_before(end, &pos) {
+                K::from_coordinates(position.iter().cloned())
+                    .ok()
+                    .map(|key| (key, fields))
+            } else {
+                None
+            }
+        });
+
+        cells
+            .flat_map(move |idx| self.range_cell(idx, &start, &end, filter))
+            .chain(buffered)
+    }
+
+    // Indices of the cells to scan for the range, empty if the limits of
+    // the range cannot be computed.
+    pub(crate) fn range_cells(&self, start: &[Option<V>], end: &[Option<V>]) -> Range<usize> {
+        match self.limits(start, end) {
+            Ok(limits) => limits.start.idx..limits.end.idx,
+            Err(SfcError::EmptyIndex) => 0..0,
+            Err(e) => {
+                error!("find_range: limits failed: {}", e);
+                0..0
+            }
+        }
+    }
+
+    // Select the records of the cell `idx` within the bounds.
+    pub(crate) fn range_cell<'a, P>(
+        &'a self,
+        idx: usize,
+        start: &[Option<V>],
+        end: &[Option<V>],
+        filter: &P,
+    ) -> Vec<(K, &'a F)>
+    where
+        P: Fn(&F) -> bool,
+    {
+        match self.try_range_cell(idx, start, end, filter) {
+            Ok(values) => values,
+            Err(e) => {
+                error!("find_range: cell {} failed: {}", idx, e);
+                vec![]
+            }
+        }
+    }
+
+    // Same as `range_cell`, failing on the first record which cannot be
+    // decoded.
+    fn try_range_cell<'a, P>(
+        &'a self,
+        idx: usize,
+        start: &[Option<V>],
+        end: &[Option<V>],
+        filter: &P,
+    ) -> Result<Vec<(K, &'a F)>, SfcError>
+    where
+        P: Fn(&F) -> bool,
+    {
+        let mut values = vec![];
+        let cell = &self.index[idx];
+        let code = cell.code;
+
+        if self.cell_contained(idx, start, end) {
+            for (record, offsets) in cell.entries(self.dimensions) {
+                let fields = cell.fields(record);
+                if !filter(fields) {
+                    continue;
+                }
+                values.push((self.position(code, offsets)?, fields));
+            }
+        } else {
+            // We have points which are outside of the bounding box,
+            // so check every points one by one.
+            for (record, offsets) in cell.entries(self.dimensions) {
+                let fields = cell.fields(record);
+                if !filter(fields) {
+                    continue;
+                }
+                let pos = self.value(code, offsets)?;
+
+                let pos_after_start = is_after(start, &pos);
+                let pos_before_end = is_before(end, &pos);
+                if pos_after_start && pos_before_end {
+                    values.push((self.position(code, offsets)?, fields));
+                }
+            }
+        }
+
+        Ok(values)
+    }
+
+    pub(crate) fn cell_code(&self, idx: usize) -> SFCCode {
+        self.index[idx].code
+    }
+
+    pub(crate) fn cell_len(&self, idx: usize) -> usize {
+        self.index[idx].records.len()
+    }
+
+    // Values of the records of the cell `idx`, in record order.
+    pub(crate) fn cell_values(&self, idx: usize) -> impl Iterator<Item = &F> {
+        let cell = &self.index[idx];
+
+        cell.records.iter().map(move |record| cell.fields(record))
+    }
+
+    // Record `record` of the cell `idx`, if within the bounds, which are
+    // not checked if the cell is `contained` in them.
+    pub(crate) fn cell_record(
+        &self,
+        idx: usize,
+        record: usize,
+        start: &[Option<V>],
+        end: &[Option<V>],
+        contained: bool,
+    ) -> Option<(K, &F)> {
+        let cell = &self.index[idx];
+        let offsets = cell.offsets(record, self.dimensions);
+        let record = &cell.records[record];
+
+        let decoded = if contained {
+            self.position(cell.code, offsets).map(Some)
+        } else {
+            self.value(cell.code, offsets).and_then(|pos| {
+                if is_after(start, &pos) && is_before(end, &pos) {
+                    self.position(cell.code, offsets).map(Some)
+                } else {
+                    Ok(None)
+                }
+            })
+        };
+
+        match decoded {
+            Ok(position) => position.map(|position| (position, cell.fields(record))),
+            Err(e) => {
+                error!("visit: cell {} failed: {}", idx, e);
+                None
+            }
+        }
+    }
+
+    // Check the bounds of the cell `idx`: if they are fully in the
+    // bounding box, then all the points of the cell will be.
+    fn cell_contained(&self, idx: usize, start: &[Option<V>], end: &[Option<V>]) -> bool {
+        let cell_ids = self
+            .morton
+            .decode(self.index[idx].code)
+            .iter()
+            .map(|id| *id as usize)
+            .collect::<Vec<_>>();
+        let (min, max) = self.space.cell_bounds(&cell_ids);
+
+        is_after(start, &min) && is_before(end, &max)
+    }
+
+    // Same as `range_buffered`, with the identifiers of the records.
+    fn range_buffered_ids<'a>(
+        &'a self,
+        start: &[Option<V>],
+        end: &[Option<V>],
+        values: &mut Vec<(RecordId, K, &'a F)>,
+    ) {
+        if start.len() != self.dimensions || end.len() != self.dimensions {
+            return;
+        }
+
+        for (position, fields, id) in self.buffer.iter() {
+            if is_after(start, position) && is_before(end, position) {
+                if let Ok(key) = K::from_coordinates(position.iter().cloned()) {
+                    values.push((*id, key, fields));
+                }
+            }
+        }
+    }
+
+    // Select the records of the write buffer within the bounds.
+    pub(crate) fn range_buffered<'a>(
+        &'a self,
+        start: &[Option<V>],
+        end: &[Option<V>],
+        values: &mut Vec<(K, &'a F)>,
+    ) {
+        if start.len() != self.dimensions || end.len() != self.dimensions {
+            return;
+        }
+
+        for (position, fields, _) in self.buffer.iter() {
+            let pos = position.iter().collect::<Vec<_>>();
+            if is_after(start, &pos) && is_before(end, &pos) {
+                if let Ok(key) = K::from_coordinates(position.iter().cloned()) {
+                    values.push((key, fields));
+                }
+            }
+        }
+    }
+}
+
+impl<F, K, V> IndexedDestructured<F, K> for SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    fn find(&self, key: &K) -> Vec<&F> {
+        self.find_iter(key).collect()
+    }
+
+    fn find_range(&self, start: &K, end: &K) -> Vec<(K, &F)> {
+        let start = self
+            .coordinates(start)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+        let end = self
+            .coordinates(end)
+            .into_iter()
+            .map(Some)
+            .collect::<Vec<_>>();
+
+        self.range(&start, &end)
+    }
+}
+
+impl<'a, F, K, V> IntoIterator for &'a SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V> + 'a,
+    V: Clone + Debug + Hash + Ord,
+{
+    type Item = (K, &'a F);
+    type IntoIter = Box<dyn Iterator<Item = (K, &'a F)> + 'a>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        Box::new(self.iter())
+    }
+}
+
+/*
+impl<F, K, V> Store for SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq + Serialize,
+    K: Position<V> + Serialize,
+    V: Clone + Debug + Ord + Serialize,
+{
+    fn store<W>(&mut self, writer: W) -> io::Result<()>
+    where
+        W: std::io::Write,
+    {
+        match bincode::serialize_into(writer, &self) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(io::Error::new(io::ErrorKind::WriteZero, e)),
+        }
+    }
+}
+
+impl<F, K, V> Load for SpaceFillingCurve<F, K, V>
+where
+    F: PartialEq + DeserializeOwned,
+    K: Position<V> + DeserializeOwned,
+    V: Clone + Debug + DeserializeOwned + Ord,
+{
+    fn load<Re: io::Read>(reader: Re) -> io::Result<Self> {
+        match bincode::deserialize_from(reader) {
+            Ok(data) => Ok(data),
+            Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        }
+    }
+
+    // only required for store_mapped_file
+    fn load_slice(from: &[u8]) -> io::Result<Self> {
+        match bincode::deserialize(from) {
             Ok(data) => Ok(data),
             Err(e) => Err(io::Error::new(io::ErrorKind::InvalidData, e)),
         }
     }
 }
-*/
+*/
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::fixtures::Point;
+    use crate::position::Coordinate3;
+
+    #[derive(Clone, Debug)]
+    struct Event {
+        position: (i64, u16, u64),
+        id: u32,
+    }
+
+    impl Record<(i64, u16, u64)> for Event {
+        fn key(&self) -> (i64, u16, u64) {
+            self.position
+        }
+    }
+
+    impl RecordFields<u32> for Event {
+        fn fields(&self) -> u32 {
+            self.id
+        }
+    }
+
+    // Deterministic pseudo-random points, with coordinates in [0, max).
+    fn points(count: u32, dimensions: usize, max: u16) -> Vec<Point> {
+        let mut seed = 0x2545_f491u64;
+        let mut points = vec![];
+        for id in 0..count {
+            let mut position = vec![];
+            for _ in 0..dimensions {
+                seed = seed.wrapping_mul(6_364_136_223_846_793_005).wrapping_add(1);
+                position.push(((seed >> 33) % max as u64) as u16);
+            }
+            points.push(Point { position, id });
+        }
+
+        points
+    }
+
+    fn events() -> Vec<Event> {
+        let mut events = vec![];
+        for i in 0..64u32 {
+            events.push(Event {
+                position: (i as i64 - 32, (i % 8) as u16, 1_000 * i as u64),
+                id: i,
+            });
+        }
+
+        events
+    }
+
+    mod tuple {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, (i64, u16, u64), Coordinate3<i64, u16, u64>>;
+
+        #[test]
+        fn find() {
+            let events = events();
+            let index = Index::new(events.iter().cloned(), 3, 2);
+
+            for event in &events {
+                assert_eq!(index.find(&event.position), vec![&event.id]);
+            }
+            assert!(index.find(&(-32, 1, 0)).is_empty());
+        }
+
+        #[test]
+        fn find_range() {
+            let index = Index::new(events().into_iter(), 3, 2);
+
+            let mut ids = index
+                .find_range(&(-30, 0, 0), &(-20, 3, 20_000))
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            assert_eq!(ids, vec![2, 3, 8, 9, 10, 11]);
+        }
+
+        #[test]
+        fn offsets() {
+            let index = Index::new(events().into_iter(), 3, 2);
+
+            assert_eq!(index.offsets(&[0, 1, 2]), Ok(vec![0, 1, 2]));
+            assert_eq!(
+                index.offsets(&[0, 1]),
+                Err(Rejection::DimensionMismatch {
+                    expected: 3,
+                    found: 2
+                })
+            );
+            // Offsets always fit on 32-bit targets.
+            #[cfg(target_pointer_width = "64")]
+            assert_eq!(
+                index.offsets(&[0, 1, SFCOffset::MAX as usize + 1]),
+                Err(Rejection::OffsetOverflow {
+                    dimension: 2,
+                    offset: SFCOffset::MAX as usize + 1
+                })
+            );
+        }
+
+        #[test]
+        fn cell_bits() {
+            // 64 distinct values on x and t, only 8 on y.
+            let index = Index::new(events().into_iter(), 3, 2);
+
+            assert_eq!(index.cell_bits(), &[3, 0, 3]);
+        }
+
+        #[test]
+        fn find_by_value() {
+            let index = Index::new(events().into_iter(), 3, 2);
+
+            assert_eq!(index.find_by_value(&42), vec![(10, 2, 42_000)]);
+        }
+    }
+
+    mod array {
+        use super::*;
+
+        #[derive(Clone, Debug)]
+        struct Fixed {
+            position: [u16; 2],
+            id: u32,
+        }
+
+        impl Record<[u16; 2]> for Fixed {
+            fn key(&self) -> [u16; 2] {
+                self.position
+            }
+        }
+
+        impl RecordFields<u32> for Fixed {
+            fn fields(&self) -> u32 {
+                self.id
+            }
+        }
+
+        type Index = SpaceFillingCurve<u32, [u16; 2], u16>;
+
+        fn fixed() -> Vec<Fixed> {
+            points(300, 2, 60)
+                .into_iter()
+                .map(|p| Fixed {
+                    position: [p.position[0], p.position[1]],
+                    id: p.id,
+                })
+                .collect()
+        }
+
+        #[test]
+        fn find_range() {
+            let points = fixed();
+            let index = Index::new(points.iter().cloned(), 2, 3);
+
+            let found = index.find_range(&[10, 10], &[30, 40]);
+            let expected = points
+                .iter()
+                .filter(|p| {
+                    (10..=30).contains(&p.position[0]) && (10..=40).contains(&p.position[1])
+                })
+                .count();
+            assert_eq!(found.len(), expected);
+            assert!(found
+                .iter()
+                .all(|(k, id)| points[**id as usize].position == *k));
+
+            assert_eq!(<[u16; 2]>::from_coordinates(vec![1, 2]), Ok([1, 2]));
+            assert!(<[u16; 2]>::from_coordinates(vec![1, 2, 3]).is_err());
+        }
+
+        #[cfg(feature = "nalgebra")]
+        #[test]
+        fn nalgebra() {
+            use nalgebra::Point2;
+
+            #[derive(Clone, Debug)]
+            struct Vertex(Point2<u16>, u32);
+
+            impl Record<Point2<u16>> for Vertex {
+                fn key(&self) -> Point2<u16> {
+                    self.0
+                }
+            }
+
+            impl RecordFields<u32> for Vertex {
+                fn fields(&self) -> u32 {
+                    self.1
+                }
+            }
+
+            let points = fixed()
+                .into_iter()
+                .map(|p| Vertex(Point2::new(p.position[0], p.position[1]), p.id))
+                .collect::<Vec<_>>();
+            let index =
+                SpaceFillingCurve::<u32, Point2<u16>, u16>::new(points.iter().cloned(), 2, 3);
+
+            for Vertex(position, id) in &points {
+                assert!(index.find(position).contains(&id));
+            }
+            assert_eq!(
+                index
+                    .find_range(&Point2::new(0, 0), &Point2::new(60, 60))
+                    .len(),
+                points.len()
+            );
+        }
+    }
+
+    mod normalize {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, (i64, u16, u64), Coordinate3<i64, u16, u64>>;
+
+        #[test]
+        fn report() {
+            let (index, report) = Index::new_normalized(events().into_iter(), 3, 2, |p| {
+                if p.1 == 7 {
+                    Normalized::Dropped
+                } else if p.0 < -20 {
+                    Normalized::Modified((-20, p.1, p.2))
+                } else {
+                    Normalized::Unchanged
+                }
+            });
+
+            assert_eq!(
+                report,
+                BuildReport {
+                    records: 64,
+                    indexed: 56,
+                    modified: 11,
+                    dropped: 8,
+                    rejected: vec![],
+                    duplicates: 0,
+                    near_duplicates: 0,
+                    duplicate_samples: vec![],
+                }
+            );
+            assert_eq!(index.find(&(-20, 2, 2_000)), vec![&2]);
+            assert!(index.find(&(-25, 7, 7_000)).is_empty());
+        }
+    }
+
+    mod duplicates {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, (i64, u16, u64), Coordinate3<i64, u16, u64>>;
+
+        #[test]
+        fn report() {
+            let mut events = events();
+            let mut copy = events[3].clone();
+            events.push(copy.clone());
+            copy.id = 100;
+            events.push(copy);
+            events.push(events[10].clone());
+
+            let (index, report) =
+                Index::new_normalized(events.into_iter(), 3, 2, |_| Normalized::Unchanged);
+
+            assert_eq!(report.indexed, 67);
+            assert_eq!(report.duplicates, 2);
+            assert_eq!(report.near_duplicates, 1);
+            assert_eq!(report.duplicate_samples.len(), 3);
+            assert!(report.duplicate_samples.contains(&(3, 64)));
+            assert!(report.duplicate_samples.contains(&(10, 66)));
+            assert_eq!(index.find(&(-29, 3, 3_000)).len(), 3);
+        }
+    }
+
+    mod policy {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn new_with_policy() {
+            let points = points(200, 3, 40);
+            let (expected, expected_report) =
+                Index::new_normalized(points.iter().cloned(), 3, 3, |_| Normalized::Unchanged);
+
+            for &policy in &[
+                BuildPolicy::SkipSilently,
+                BuildPolicy::CollectAndReport,
+                BuildPolicy::FailFast,
+            ] {
+                let (index, report) =
+                    Index::new_with_policy(points.iter().cloned(), 3, 3, policy).unwrap();
+
+                assert_eq!(report, expected_report);
+                assert_eq!(index.stats(), expected.stats());
+            }
+        }
+
+        #[test]
+        fn dimension_mismatch() {
+            let mut points = points(100, 3, 40);
+            points[10].position.pop();
+            let mismatch = SfcError::DimensionMismatch {
+                expected: 3,
+                found: 2,
+            };
+
+            let (mut index, report) =
+                Index::new_with_policy(points.iter().cloned(), 3, 3, BuildPolicy::CollectAndReport)
+                    .unwrap();
+            assert_eq!(report.indexed, 99);
+            assert_eq!(
+                report.rejected,
+                vec![(
+                    10,
+                    Rejection::DimensionMismatch {
+                        expected: 3,
+                        found: 2
+                    }
+                )]
+            );
+
+            let result =
+                Index::new_with_policy(points.iter().cloned(), 3, 3, BuildPolicy::FailFast);
+            assert_eq!(result.err(), Some(mismatch.clone()));
+
+            // Queries with keys of the wrong length fail, or find nothing.
+            let short = vec![0, 0];
+            assert!(index.find(&short).is_empty());
+            assert_eq!(index.try_find(&short), Err(mismatch.clone()));
+            assert!(index.find_range(&short, &vec![40, 40]).is_empty());
+            assert_eq!(
+                index.try_find_range(&short, &vec![40, 40]),
+                Err(mismatch.clone())
+            );
+            assert!(index
+                .find_range(&vec![0, 0, 0, 0], &vec![40, 40, 40, 40])
+                .is_empty());
+
+            assert_eq!(index.insert(points[10].clone()), Err(mismatch));
+            index.insert_buffered(points[10].clone());
+            assert_eq!(index.buffered(), 0);
+            assert!(index.remove(&short).is_empty());
+            assert_eq!(index.stats().records, 99);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod chunked {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn new_chunked() {
+            let mut points = points(500, 3, 40);
+            points.push(points[7].clone());
+            let (expected, expected_report) =
+                Index::new_normalized(points.iter().cloned(), 3, 3, |_| Normalized::Unchanged);
+
+            for &chunk in &[0, 64, 1000] {
+                let (index, report) = Index::new_chunked(
+                    points.iter().cloned(),
+                    3,
+                    3,
+                    chunk,
+                    BuildPolicy::CollectAndReport,
+                )
+                .unwrap();
+
+                assert_eq!(report, expected_report);
+                assert_eq!(index.stats(), expected.stats());
+                for point in &points {
+                    assert_eq!(index.find(&point.position), expected.find(&point.position));
+                }
+            }
+        }
+
+        #[test]
+        fn new_chunked_in() {
+            let points = points(500, 3, 40);
+            let expected = Index::new(points.iter().cloned(), 3, 3);
+
+            let directory = std::env::temp_dir().join(format!(
+                "ironsea_index_sfc_dbc-{}-chunked",
+                std::process::id()
+            ));
+            std::fs::create_dir_all(&directory).unwrap();
+            let (index, _) = Index::new_chunked_in(
+                points.iter().cloned(),
+                3,
+                3,
+                64,
+                &directory,
+                BuildPolicy::FailFast,
+            )
+            .unwrap();
+            assert_eq!(index.stats(), expected.stats());
+
+            // The runs are removed once merged.
+            assert_eq!(std::fs::read_dir(&directory).unwrap().count(), 0);
+            std::fs::remove_dir(&directory).unwrap();
+
+            assert!(matches!(
+                Index::new_chunked_in(
+                    points.iter().cloned(),
+                    3,
+                    3,
+                    64,
+                    &directory,
+                    BuildPolicy::FailFast,
+                ),
+                Err(SfcError::Io(_))
+            ));
+        }
+    }
+
+    mod single_pass {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn new_single_pass() {
+            let points = points(500, 3, 40);
+            // Draining iterators cannot be cloned.
+            let mut source = points.clone();
+            let (index, report) =
+                Index::new_single_pass(source.drain(..), 3, 3, BuildPolicy::FailFast).unwrap();
+            let expected = Index::new(points.iter().cloned(), 3, 3);
+
+            assert_eq!(report.records, 500);
+            assert_eq!(report.indexed, 500);
+            assert_eq!(index.stats(), expected.stats());
+            for point in &points {
+                assert_eq!(index.find(&point.position), expected.find(&point.position));
+            }
+        }
+    }
+
+    mod empty {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn new() {
+            let mut index = Index::new(Vec::<Point>::new().into_iter(), 3, 3);
+            let (single, report) =
+                Index::new_single_pass(Vec::<Point>::new(), 3, 3, BuildPolicy::FailFast).unwrap();
+            assert_eq!(report.records, 0);
+            assert_eq!(single.stats(), index.stats());
+            #[cfg(feature = "serde")]
+            {
+                let (chunked, _) = Index::new_chunked(
+                    Vec::<Point>::new().into_iter(),
+                    3,
+                    3,
+                    10,
+                    BuildPolicy::FailFast,
+                )
+                .unwrap();
+                assert_eq!(chunked.stats(), index.stats());
+            }
+
+            let frozen = single.freeze().unwrap();
+            assert!(frozen
+                .find_range(&vec![0, 0, 0], &vec![10, 10, 10])
+                .is_empty());
+
+            assert!(index.find(&vec![1, 2, 3]).is_empty());
+            assert!(index
+                .find_range(&vec![0, 0, 0], &vec![10, 10, 10])
+                .is_empty());
+            assert!(index
+                .find_range_partial(&[None, None, None], &[None, None, None])
+                .is_empty());
+            assert!(index.find_by_value(&1).is_empty());
+            assert!(index.advise().is_empty());
+            assert!(index.remove(&vec![1, 2, 3]).is_empty());
+
+            let point = points(1, 3, 10).pop().unwrap();
+            index.insert(point.clone()).unwrap();
+            index.flush().unwrap();
+            assert_eq!(index.find(&point.position), vec![&point.id]);
+        }
+    }
+
+    mod slices {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        impl RecordSlice<u16> for Point {
+            fn key_slice(&self) -> &[u16] {
+                &self.position
+            }
+        }
+
+        #[test]
+        fn new_from_slices() {
+            let points = points(500, 3, 40);
+            let (index, report) =
+                Index::new_from_slices(points.iter().cloned(), 3, 3, BuildPolicy::FailFast)
+                    .unwrap();
+            let expected = Index::new(points.iter().cloned(), 3, 3);
+
+            assert_eq!(report.records, 500);
+            assert_eq!(report.indexed, 500);
+            assert_eq!(index.cell_bits(), expected.cell_bits());
+            for point in &points {
+                assert_eq!(index.find(&point.position), expected.find(&point.position));
+            }
+        }
+
+        #[test]
+        fn rejected() {
+            let mut points = points(100, 3, 40);
+            points[10].position.pop();
+            let records = || points[1..].iter().cloned();
+            let (index, report) =
+                Index::new_from_slices(records(), 3, 3, BuildPolicy::CollectAndReport).unwrap();
+
+            assert_eq!(report.indexed, 98);
+            assert_eq!(report.rejected.len(), 1);
+            assert_eq!(report.rejected[0].0, 9);
+            assert!(index.contains(&points[20].position));
+
+            let (_, report) =
+                Index::new_from_slices(records(), 3, 3, BuildPolicy::SkipSilently).unwrap();
+            assert_eq!(report.indexed, 98);
+            assert!(report.rejected.is_empty());
+
+            let built = Index::new_from_slices(records(), 3, 3, BuildPolicy::FailFast);
+            assert!(matches!(
+                built.err(),
+                Some(SfcError::DimensionMismatch {
+                    expected: 3,
+                    found: 2
+                })
+            ));
+        }
+    }
+
+    mod high_dimensions {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        fn check(dimensions: usize, cell_bits: usize) {
+            let points = points(500, dimensions, 20);
+            let index = Index::new(points.iter().cloned(), dimensions, cell_bits);
+
+            for point in &points {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+
+            let start = vec![2; dimensions];
+            let end = vec![17; dimensions];
+            let mut ids = index
+                .find_range(&start, &end)
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            let expected = points
+                .iter()
+                .filter(|p| p.position.iter().all(|v| *v >= 2 && *v <= 17))
+                .map(|p| p.id)
+                .collect::<Vec<_>>();
+            assert!(!expected.is_empty());
+            assert_eq!(ids, expected);
+        }
+
+        #[test]
+        fn dim2() {
+            check(2, 4);
+        }
+
+        #[test]
+        fn dim6() {
+            check(6, 4);
+        }
+
+        #[test]
+        fn dim10() {
+            check(10, 3);
+        }
+
+        #[test]
+        fn dim16() {
+            check(16, 2);
+        }
+
+        #[test]
+        fn wide() {
+            // 7 dimensions of 10 bits do not fit in a Morton code.
+            let points = points(2000, 7, 2000);
+            let (index, report) =
+                Index::new_with_policy(points.iter().cloned(), 7, 10, BuildPolicy::FailFast)
+                    .unwrap();
+            assert_eq!(report.indexed, 2000);
+            assert_eq!(index.cell_bits().iter().sum::<usize>(), 64);
+
+            for point in &points {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+            let ids = index
+                .find_range(&vec![0; 7], &vec![1999; 7])
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<HashSet<_>>();
+            assert_eq!(ids.len(), 2000);
+
+            let index = Index::builder()
+                .dimensions(7)
+                .cell_bits(10)
+                .build(points.iter().cloned())
+                .unwrap()
+                .0;
+            assert_eq!(index.len(), 2000);
+        }
+    }
+
+    mod insert {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn find() {
+            let points = points(300, 3, 50);
+            let (first, rest) = points.split_at(100);
+            let mut index = Index::new(first.iter().cloned(), 3, 3);
+            for point in rest {
+                index.insert(point.clone()).unwrap();
+            }
+
+            for point in &points {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+
+            let mut ids = index
+                .find_range(&vec![10, 10, 10], &vec![40, 40, 40])
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            let expected = points
+                .iter()
+                .filter(|p| p.position.iter().all(|v| *v >= 10 && *v <= 40))
+                .map(|p| p.id)
+                .collect::<Vec<_>>();
+            assert_eq!(ids, expected);
+        }
+
+        #[test]
+        fn outside() {
+            let mut index = Index::new(points(100, 3, 50).into_iter(), 3, 3);
+            let point = Point {
+                position: vec![100, 0, 60],
+                id: 1000,
+            };
+            index.insert(point.clone()).unwrap();
+
+            assert_eq!(index.find(&point.position), vec![&1000]);
+            assert_eq!(index.find_by_value(&1000), vec![point.position]);
+        }
+    }
+
+    mod extend {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn find() {
+            let points = points(400, 3, 60);
+            let mut index = Index::new(points[..100].iter().cloned(), 3, 3);
+            index.extend(points[100..250].iter().cloned()).unwrap();
+            index.extend(points[250..].iter().cloned()).unwrap();
+
+            for point in &points {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+
+            let mut ids = index
+                .find_range(&vec![5, 10, 0], &vec![50, 45, 30])
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            let expected = points
+                .iter()
+                .filter(|p| {
+                    let (x, y, z) = (p.position[0], p.position[1], p.position[2]);
+                    (5..=50).contains(&x) && (10..=45).contains(&y) && z <= 30
+                })
+                .map(|p| p.id)
+                .collect::<Vec<_>>();
+            assert_eq!(ids, expected);
+        }
+    }
+
+    mod merge {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn find() {
+            let points = points(300, 3, 60);
+            let index = Index::new(points[..200].iter().cloned(), 3, 3);
+            let other = Index::new(points[200..].iter().cloned(), 3, 2);
+            let index = index.merge(other).unwrap();
+
+            for point in &points {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+
+            let mut ids = index
+                .find_range(&vec![0, 0, 0], &vec![59, 59, 59])
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+            assert_eq!(ids, (0..300).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn dimensions() {
+            let mut index = Index::new(points(10, 3, 60).into_iter(), 3, 3);
+            let other = Index::new(points(10, 2, 60).into_iter(), 2, 3);
+
+            assert!(index.merge_from(other).is_err());
+        }
+    }
+
+    mod ids {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn get() {
+            let points = points(300, 3, 60);
+            let mut index = Index::new(points[..200].iter().cloned(), 3, 3);
+
+            // Records are numbered by their position in the source.
+            for (i, point) in points[..200].iter().enumerate() {
+                let id = RecordId(i as u64);
+                assert_eq!(index.get(id), Some((point.position.clone(), &point.id)));
+                assert!(index.find_ids(&point.position).contains(&(id, &point.id)));
+            }
+
+            let inserted = index.insert(points[200].clone()).unwrap();
+            let buffered = index.insert_buffered(points[201].clone()).unwrap();
+            assert_eq!((inserted, buffered), (RecordId(200), RecordId(201)));
+            assert_eq!(index.get(buffered).unwrap().1, &201);
+            index.extend(points[202..].iter().cloned()).unwrap();
+            index.flush().unwrap();
+            assert_eq!(index.get(buffered).unwrap().1, &201);
+            for (i, point) in points.iter().enumerate() {
+                let found = index.get(RecordId(i as u64));
+                assert_eq!(found, Some((point.position.clone(), &point.id)));
+            }
+            assert_eq!(index.get(RecordId(300)), None);
+
+            let (start, end) = (vec![5, 10, 0], vec![50, 45, 30]);
+            let found = index.find_range_ids(&start, &end);
+            assert_eq!(
+                found
+                    .iter()
+                    .map(|(_, key, id)| (key.clone(), *id))
+                    .collect::<Vec<_>>(),
+                index.find_range(&start, &end)
+            );
+            for (id, _, value) in found {
+                assert_eq!(id, RecordId(u64::from(*value)));
+            }
+        }
+
+        #[test]
+        fn remove_id() {
+            let points = points(300, 3, 60);
+            let mut index = Index::new(points[..250].iter().cloned(), 3, 3);
+            for point in &points[250..] {
+                index.insert_buffered(point.clone());
+            }
+
+            assert_eq!(index.remove_id(RecordId(10)), Some(10));
+            assert_eq!(index.remove_id(RecordId(10)), None);
+            assert_eq!(index.get(RecordId(10)), None);
+            assert_eq!(index.remove_id(RecordId(260)), Some(260));
+            assert_eq!(index.get(RecordId(260)), None);
+            assert_eq!(index.len(), 298);
+
+            // Identifiers are kept by the other records, and not reused.
+            index.remove(&points[20].position);
+            let id = index
+                .insert(Point {
+                    position: vec![61, 62, 63],
+                    id: 1000,
+                })
+                .unwrap();
+            assert_eq!(id, RecordId(300));
+            let index = index.reindex(2).unwrap();
+            assert_eq!(index.get(id).unwrap().1, &1000);
+            for (i, point) in points.iter().enumerate() {
+                let found = index.get(RecordId(i as u64)).map(|(_, id)| *id);
+                if i == 10 || i == 260 || point.position == points[20].position {
+                    assert_eq!(found, None);
+                } else {
+                    assert_eq!(found, Some(point.id));
+                }
+            }
+        }
+
+        #[test]
+        fn locations() {
+            let points = points(400, 3, 60);
+            let mut index = Index::new(points[..200].iter().cloned(), 3, 3);
+            let located = |index: &Index| index.locations.0.get().map(HashMap::len);
+            assert_eq!(located(&index), None);
+            assert!(index.get(RecordId(0)).is_some());
+            assert_eq!(located(&index), Some(200));
+
+            // Kept up to date by insertions and removals, not recomputed.
+            for point in &points[200..300] {
+                let id = index.insert(point.clone()).unwrap();
+                assert_eq!(index.get(id).unwrap().1, &point.id);
+            }
+            for point in &points[300..] {
+                index.insert_buffered(point.clone());
+            }
+            index.flush().unwrap();
+            assert_eq!(located(&index), Some(400));
+
+            assert_eq!(index.remove_id(RecordId(5)), Some(5));
+            index.remove_by_value(&6);
+            index.remove(&points[7].position);
+            let removed = 3 + index.remove_range(&vec![0, 0, 0], &vec![20, 20, 20]);
+            let len = index.len();
+            assert_eq!(len, 400 - removed);
+            assert_eq!(located(&index), Some(len));
+            for (i, point) in points.iter().enumerate() {
+                if let Some((position, id)) = index.get(RecordId(i as u64)) {
+                    assert_eq!((position, *id), (point.position.clone(), point.id));
+                }
+            }
+
+            index.compact(false);
+            assert_eq!(located(&index), None);
+            assert!(index.get(RecordId(399)).is_some());
+            assert_eq!(located(&index), Some(len));
+        }
+    }
+
+    mod reindex {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn reindex() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..450].iter().cloned(), 3, 2);
+            for point in &points[450..] {
+                index.insert_buffered(point.clone());
+            }
+            index.remove_range(&vec![0, 0, 0], &vec![10, 60, 60]);
+            let expected = index.iter().map(|(_, id)| *id).collect::<HashSet<_>>();
+
+            let cells = index.cell_count();
+            let index = index.reindex(4).unwrap();
+            assert_eq!(index.cell_bits().iter().sum::<usize>(), 12);
+            assert!(index.cell_count() > cells);
+            assert_eq!(index.buffered(), 0);
+            assert_eq!(index.len(), expected.len());
+            assert!(index.validate().is_empty());
+            // Values only used by removed records are dropped.
+            assert!(index.space().dictionary_size(0) < 60);
+
+            for point in points.iter().filter(|p| expected.contains(&p.id)) {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+            let coarse = index.reindex(1).unwrap();
+            assert_eq!(coarse.len(), expected.len());
+            assert_eq!(
+                coarse.iter().map(|(_, id)| *id).collect::<HashSet<_>>(),
+                expected
+            );
+        }
+    }
+
+    mod cow_clone {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn shared() {
+            let points = points(200, 3, 60);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+            let mut variant = index.cow_clone();
+
+            let key = &points[0].position;
+            let removed = variant.remove(key);
+            assert!(!removed.is_empty());
+            assert!(variant.find(key).is_empty());
+            assert_eq!(index.find(key).len(), removed.len());
+
+            // Only the modified cell has been copied.
+            let shared = variant
+                .index
+                .iter()
+                .filter(|a| index.index.iter().any(|b| Arc::ptr_eq(a, b)))
+                .count();
+            assert!(shared + 1 >= variant.index.len());
+        }
+
+        #[test]
+        fn insert() {
+            let points = points(200, 3, 60);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+            let mut variant = index.cow_clone();
+
+            // The new values come after all the others, so no offset moves.
+            let point = Point {
+                position: vec![100, 100, 100],
+                id: 1000,
+            };
+            variant.insert(point.clone()).unwrap();
+            assert_eq!(variant.find(&point.position), vec![&1000]);
+            assert!(index.find(&point.position).is_empty());
+
+            // Only the cell receiving the record has been copied.
+            let shared = variant
+                .index
+                .iter()
+                .filter(|a| index.index.iter().any(|b| Arc::ptr_eq(a, b)))
+                .count();
+            assert!(shared + 1 >= variant.index.len());
+        }
+    }
+
+    mod buffered {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        fn check(points: &[Point], index: &Index) {
+            for point in points {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+
+            let mut ids = index
+                .find_range(&vec![0, 0, 0], &vec![59, 59, 59])
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+            assert_eq!(ids, points.iter().map(|p| p.id).collect::<Vec<_>>());
+        }
+
+        #[test]
+        fn flush() {
+            let points = points(300, 3, 60);
+            let mut index = Index::new(points[..200].iter().cloned(), 3, 3);
+            for point in &points[200..] {
+                index.insert_buffered(point.clone());
+            }
+            assert_eq!(index.buffered(), 100);
+            check(&points, &index);
+
+            index.flush().unwrap();
+            assert_eq!(index.buffered(), 0);
+            check(&points, &index);
+        }
+
+        #[test]
+        fn remove() {
+            let points = points(300, 3, 60);
+            let mut index = Index::new(points[..200].iter().cloned(), 3, 3);
+            for point in &points[200..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let point = &points[250];
+            assert_eq!(index.update(&point.position, 1000), 1);
+            assert_eq!(index.find_by_value(&1000), vec![point.position.clone()]);
+            assert_eq!(index.remove(&point.position), vec![1000]);
+            assert!(index.find(&point.position).is_empty());
+            assert_eq!(index.buffered(), 99);
+        }
+    }
+
+    mod compact {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn tighten() {
+            let points = points(400, 3, 60);
+            let mut index = Index::new(points.iter().cloned(), 3, 3);
+
+            let (removed, kept) = points.split_at(300);
+            for point in removed {
+                index.remove(&point.position);
+            }
+
+            let report = index.compact(true);
+            assert!(report.after < report.before);
+
+            for point in kept {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+            let mut ids = index
+                .find_range(&vec![0, 0, 0], &vec![59, 59, 59])
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+            assert_eq!(ids, kept.iter().map(|p| p.id).collect::<Vec<_>>());
+
+            // The dictionaries are still usable for new records.
+            index.insert(removed[0].clone()).unwrap();
+            assert!(index.find(&removed[0].position).contains(&&removed[0].id));
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    mod run_query {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn json() {
+            let points = points(300, 3, 60);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+
+            let spec: QuerySpec<u32, u16> = serde_json::from_str(
+                r#"{
+                    "start": [10, null, null],
+                    "end": [40, 30, null],
+                    "order": "Descending",
+                    "skip": 2,
+                    "limit": 5
+                }"#,
+            )
+            .unwrap();
+
+            let mut expected = points
+                .iter()
+                .filter(|p| (10..=40).contains(&p.position[0]) && p.position[1] <= 30)
+                .collect::<Vec<_>>();
+            expected.sort_by(|a, b| b.position.cmp(&a.position));
+
+            let results = index.run_query(&spec).unwrap();
+            assert_eq!(results.len(), 5);
+            for ((key, _), point) in results.iter().zip(expected.iter().skip(2)) {
+                assert_eq!(key, &point.position);
+            }
+        }
+
+        #[test]
+        fn values() {
+            let points = points(300, 3, 60);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+
+            let spec: QuerySpec<u32, u16> =
+                serde_json::from_str(r#"{ "values": [3, 7] }"#).unwrap();
+            let mut ids = index
+                .run_query(&spec)
+                .unwrap()
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+            assert_eq!(ids, vec![3, 7]);
+
+            let spec: QuerySpec<u32, u16> = serde_json::from_str(r#"{ "start": [1, 2] }"#).unwrap();
+            assert_eq!(
+                index.run_query(&spec).unwrap_err(),
+                SfcError::DimensionMismatch {
+                    expected: 3,
+                    found: 2
+                }
+            );
+        }
+    }
+
+    mod compress_values {
+        use super::*;
+
+        type Index = SpaceFillingCurve<String, Vec<u16>, u16>;
+
+        #[derive(Clone, Debug)]
+        struct Label {
+            position: Vec<u16>,
+            label: String,
+        }
+
+        impl Record<Vec<u16>> for Label {
+            fn key(&self) -> Vec<u16> {
+                self.position.clone()
+            }
+        }
+
+        impl RecordFields<String> for Label {
+            fn fields(&self) -> String {
+                self.label.clone()
+            }
+        }
+
+        fn labels() -> Vec<Label> {
+            points(400, 2, 8)
+                .into_iter()
+                .map(|p| Label {
+                    position: p.position,
+                    label: format!("label{}", p.id % 3),
+                })
+                .collect()
+        }
+
+        fn sorted(mut values: Vec<&String>) -> Vec<&String> {
+            values.sort_unstable();
+            values
+        }
+
+        #[test]
+        fn find() {
+            let labels = labels();
+            let mut index = Index::new(labels.iter().cloned(), 2, 2);
+            let usage = index.mem_usage();
+
+            index.compress_values();
+            assert!(index.mem_usage().values < usage.values);
+            assert_eq!(index.mem_usage().offsets, usage.offsets);
+            for cell in &index.index {
+                assert!(cell.values.len() <= 3);
+            }
+
+            for label in &labels {
+                let mut expected = labels
+                    .iter()
+                    .filter(|l| l.position == label.position)
+                    .map(|l| &l.label)
+                    .collect::<Vec<_>>();
+                expected.sort_unstable();
+                assert_eq!(sorted(index.find(&label.position)), expected);
+            }
+        }
+
+        #[test]
+        fn update() {
+            let labels = labels();
+            let mut index = Index::new(labels.iter().cloned(), 2, 2);
+            index.compress_values();
+
+            let key = &labels[0].position;
+            let found = index.find(key);
+            let count = found.len();
+            let matching = found.iter().filter(|l| **l == &labels[0].label).count();
+            let total = index.find_by_value(&labels[0].label).len();
+            assert_eq!(index.update(key, "other".to_string()), count);
+            assert_eq!(index.find(key), vec!["other"; count]);
+            assert_eq!(
+                index.find_by_value(&labels[0].label).len(),
+                total - matching
+            );
+
+            let removed = index.remove(key);
+            assert_eq!(removed, vec!["other".to_string(); count]);
+            assert!(index.find(key).is_empty());
+
+            index
+                .insert(Label {
+                    position: key.clone(),
+                    label: labels[1].label.clone(),
+                })
+                .unwrap();
+            assert_eq!(index.find(key), vec![&labels[1].label]);
+        }
+    }
+
+    mod radius {
+        use super::*;
+
+        use crate::metric::Chebyshev;
+        use crate::metric::Manhattan;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        fn check(points: &[Point], index: &Index, center: &[u16], radius: f64) {
+            let mut ids = index
+                .find_within_radius(&center.to_vec(), radius)
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            let expected = points
+                .iter()
+                .filter(|p| {
+                    let distance = p
+                        .position
+                        .iter()
+                        .zip(center.iter())
+                        .map(|(a, b)| (f64::from(*a) - f64::from(*b)).powi(2))
+                        .sum::<f64>();
+                    distance <= radius * radius
+                })
+                .map(|p| p.id)
+                .collect::<Vec<_>>();
+            assert_eq!(ids, expected);
+        }
+
+        #[test]
+        fn find() {
+            let points = points(500, 3, 100);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            check(&points, &index, &[50, 50, 50], 20.0);
+            check(&points, &index, &[0, 0, 0], 35.0);
+            check(&points, &index, &[99, 10, 60], 12.5);
+            check(&points, &index, &[50, 50, 50], 0.0);
+            check(&points, &index, &[500, 500, 500], 10.0);
+        }
+
+        #[test]
+        fn spatial_join() {
+            let points = points(800, 3, 100);
+            let mut left = Index::new(points[..300].iter().cloned(), 3, 3);
+            for point in &points[300..350] {
+                left.insert_buffered(point.clone());
+            }
+            let mut right = Index::new(points[350..750].iter().cloned(), 3, 2);
+            for point in &points[750..] {
+                right.insert_buffered(point.clone());
+            }
+
+            let to_f64 = |p: &Point| p.position.iter().map(|v| f64::from(*v)).collect::<Vec<_>>();
+            for (metric, radius) in [
+                (&Euclidean as &dyn Metric, 8.0),
+                (&Chebyshev, 5.0),
+                (&Euclidean, 0.0),
+            ] {
+                let mut found = left
+                    .spatial_join(&right, radius, metric)
+                    .into_iter()
+                    .map(|(distance, (key, a), (other_key, b))| {
+                        assert!(distance <= radius);
+                        assert_eq!(key, points[*a as usize].position);
+                        assert_eq!(other_key, points[*b as usize].position);
+                        (*a, *b)
+                    })
+                    .collect::<Vec<_>>();
+                found.sort_unstable();
+
+                let mut expected = vec![];
+                for a in &points[..350] {
+                    for b in &points[350..] {
+                        if metric.distance(&to_f64(a), &to_f64(b)) <= radius {
+                            expected.push((a.id, b.id));
+                        }
+                    }
+                }
+                assert_eq!(found, expected);
+                assert!(radius == 0.0 || !expected.is_empty());
+            }
+
+            let flat = SpaceFillingCurve::<u32, Vec<u16>, u16>::new(
+                points.iter().map(|p| Point {
+                    position: p.position[..2].to_vec(),
+                    id: p.id,
+                }),
+                2,
+                3,
+            );
+            assert!(left.spatial_join(&flat, 10.0, &Euclidean).is_empty());
+        }
+
+        // Ids of the points sorted by distance from `center`, with ties
+        // broken by id.
+        fn closest<M: Metric + ?Sized>(
+            points: &[Point],
+            center: &[u16],
+            metric: &M,
+        ) -> Vec<(f64, u32)> {
+            let center = center.iter().map(|c| f64::from(*c)).collect::<Vec<_>>();
+            let mut closest = points
+                .iter()
+                .map(|p| {
+                    let position = p.position.iter().map(|v| f64::from(*v)).collect::<Vec<_>>();
+                    (metric.distance(&center, &position), p.id)
+                })
+                .collect::<Vec<_>>();
+            closest.sort_by(|a, b| a.0.total_cmp(&b.0).then(a.1.cmp(&b.1)));
+
+            closest
+        }
+
+        #[test]
+        fn find_within_distance() {
+            let points = points(500, 3, 100);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+
+            let center = [40, 60, 20];
+            for metric in [&Euclidean as &dyn Metric, &Manhattan, &Chebyshev] {
+                let found = index.find_within_distance(&center.to_vec(), 25.0, metric);
+                assert!(found.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+                let mut ids = found.iter().map(|(_, _, id)| **id).collect::<Vec<_>>();
+                ids.sort_unstable();
+                let mut expected = closest(&points, &center, metric)
+                    .into_iter()
+                    .filter(|(distance, _)| *distance <= 25.0)
+                    .map(|(_, id)| id)
+                    .collect::<Vec<_>>();
+                expected.sort_unstable();
+                assert!(!expected.is_empty());
+                assert_eq!(ids, expected);
+            }
+        }
+
+        #[test]
+        fn find_nearest() {
+            let points = points(500, 3, 100);
+            let mut index = Index::new(points[..450].iter().cloned(), 3, 3);
+            for point in &points[450..] {
+                index.insert_buffered(point.clone());
+            }
+
+            for center in [[50, 50, 50], [0, 0, 0], [99, 10, 60], [500, 500, 500]] {
+                for metric in [&Euclidean as &dyn Metric, &Manhattan, &Chebyshev] {
+                    let expected = closest(&points, &center, metric);
+                    for k in [1, 7, 40] {
+                        let found = index.find_nearest(&center.to_vec(), k, metric);
+                        let distances = found.iter().map(|(d, _, _)| *d).collect::<Vec<_>>();
+                        let closest = expected[..k].iter().map(|(d, _)| *d).collect::<Vec<_>>();
+                        assert_eq!(distances, closest);
+                    }
+                }
+            }
+
+            assert!(index.find_nearest(&vec![1, 2, 3], 0, &Euclidean).is_empty());
+            assert_eq!(
+                index.find_nearest(&vec![1, 2, 3], 600, &Euclidean).len(),
+                500
+            );
+            let empty = Index::new(std::iter::empty::<Point>(), 3, 3);
+            assert!(empty.find_nearest(&vec![1, 2, 3], 3, &Euclidean).is_empty());
+        }
+
+        #[test]
+        fn reverse_nearest() {
+            let points = points(300, 2, 100);
+            let mut index = Index::new(points[..280].iter().cloned(), 2, 3);
+            for point in &points[280..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let position = |p: &Point| p.position.iter().map(|v| f64::from(*v)).collect::<Vec<_>>();
+            let mut found = 0;
+            for center in [[50, 50], [0, 0], [99, 10], [33, 71]] {
+                let query = center.iter().map(|c| f64::from(*c)).collect::<Vec<_>>();
+                for metric in [&Euclidean as &dyn Metric, &Manhattan, &Chebyshev] {
+                    let mut ids = index
+                        .reverse_nearest(&center.to_vec(), metric)
+                        .into_iter()
+                        .map(|(_, id)| *id)
+                        .collect::<Vec<_>>();
+                    ids.sort_unstable();
+
+                    let expected = points
+                        .iter()
+                        .filter(|p| {
+                            let distance = metric.distance(&position(p), &query);
+                            points.iter().all(|other| {
+                                other.id == p.id
+                                    || metric.distance(&position(p), &position(other)) >= distance
+                            })
+                        })
+                        .map(|p| p.id)
+                        .collect::<Vec<_>>();
+                    found += expected.len();
+                    assert_eq!(ids, expected);
+                }
+            }
+            assert!(found > 0);
+        }
+
+        #[test]
+        fn reverse_nearest_compressed() {
+            // Few distinct values, stored once per cell.
+            let points = points(300, 2, 60)
+                .into_iter()
+                .map(|p| Point { id: p.id % 2, ..p })
+                .collect::<Vec<_>>();
+            let mut index = Index::new(points.iter().cloned(), 2, 2);
+            index.compress_values();
+
+            let position = |p: &Point| p.position.iter().map(|v| f64::from(*v)).collect::<Vec<_>>();
+            let mut total = 0;
+            for center in [[30, 30], [0, 0], [59, 10], [17, 45]] {
+                let query = center.iter().map(|c| f64::from(*c)).collect::<Vec<_>>();
+                let mut found = index
+                    .reverse_nearest(&center.to_vec(), &Euclidean)
+                    .into_iter()
+                    .map(|(key, id)| (key, *id))
+                    .collect::<Vec<_>>();
+                found.sort_unstable();
+
+                let mut expected = points
+                    .iter()
+                    .enumerate()
+                    .filter(|(i, p)| {
+                        let distance = Euclidean.distance(&position(p), &query);
+                        points.iter().enumerate().all(|(j, other)| {
+                            j == *i
+                                || Euclidean.distance(&position(p), &position(other)) >= distance
+                        })
+                    })
+                    .map(|(_, p)| (p.position.clone(), p.id))
+                    .collect::<Vec<_>>();
+                expected.sort_unstable();
+                total += expected.len();
+                assert_eq!(found, expected);
+            }
+            assert!(total > 0);
+        }
+
+        #[test]
+        fn find_nearest_approx() {
+            let points = points(2000, 3, 100);
+            let index = Index::new(points.iter().cloned(), 3, 2);
+
+            let mut bounded = 0;
+            for center in [[50, 50, 50], [12, 37, 88], [99, 10, 60], [500, 500, 500]] {
+                for metric in [&Euclidean as &dyn Metric, &Manhattan, &Chebyshev] {
+                    let expected = closest(&points, &center, metric);
+                    let (found, exact) = index.find_nearest_approx(&center.to_vec(), 10, metric);
+                    assert_eq!(found.len(), 10);
+                    assert!(found.windows(2).all(|pair| pair[0].0 <= pair[1].0));
+
+                    // Exact up to the returned distance, and never closer than
+                    // the actual neighbours.
+                    for (i, (distance, _, _)) in found.iter().enumerate() {
+                        if *distance < exact {
+                            assert_eq!(*distance, expected[i].0);
+                        }
+                        assert!(*distance >= expected[i].0);
+                    }
+                    if exact > found[0].0 {
+                        bounded += 1;
+                    }
+                }
+            }
+            assert!(bounded > 0);
+
+            let (found, exact) = index.find_nearest_approx(&vec![1, 2, 3], 0, &Euclidean);
+            assert!(found.is_empty());
+            assert_eq!(exact, 0.0);
+        }
+    }
+
+    mod region {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        // Triangle with vertices (0, 0), (n, 0) and (0, n).
+        struct Triangle {
+            n: u16,
+        }
+
+        impl Region<u16> for Triangle {
+            fn contains(&self, point: &[&u16]) -> bool {
+                u32::from(*point[0]) + u32::from(*point[1]) <= u32::from(self.n)
+            }
+
+            fn intersects_box(&self, min: &[&u16], _max: &[&u16]) -> bool {
+                self.contains(min)
+            }
+        }
+
+        #[test]
+        fn triangle() {
+            let points = points(500, 2, 100);
+            let mut index = Index::new(points[..400].iter().cloned(), 2, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            for n in &[0, 30, 100, 250] {
+                let mut ids = index
+                    .find_in_region(&Triangle { n: *n })
+                    .into_iter()
+                    .map(|(_, id)| *id)
+                    .collect::<Vec<_>>();
+                ids.sort_unstable();
+
+                let expected = points
+                    .iter()
+                    .filter(|p| p.position[0] + p.position[1] <= *n)
+                    .map(|p| p.id)
+                    .collect::<Vec<_>>();
+                assert_eq!(ids, expected);
+            }
+        }
+    }
+
+    mod defaults {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn default_cell_bits() {
+            assert_eq!(super::default_cell_bits(0, 3), 1);
+            assert_eq!(super::default_cell_bits(100, 3), 1);
+            assert_eq!(super::default_cell_bits(4096, 2), 4);
+            assert_eq!(super::default_cell_bits(4096, 3), 3);
+            assert_eq!(super::default_cell_bits(usize::MAX, 1), MORTON_VALUE_BITS);
+        }
+
+        #[test]
+        fn with_defaults() {
+            let points = points(4096, 3, 200);
+            let index = Index::with_defaults(points.iter().cloned(), 3);
+            assert_eq!(index.cell_bits().iter().sum::<usize>(), 9);
+            let mean = index.stats().mean_records();
+            assert!(ADVICE_MIN_OCCUPANCY < mean && mean < ADVICE_MAX_OCCUPANCY);
+
+            let empty = Index::with_defaults(std::iter::empty::<Point>(), 3);
+            assert!(empty.is_empty());
+        }
+    }
+
+    mod advise {
+        use super::*;
+
+        #[derive(Clone, Debug)]
+        struct Label {
+            position: Vec<u16>,
+            value: bool,
+        }
+
+        impl Record<Vec<u16>> for Label {
+            fn key(&self) -> Vec<u16> {
+                self.position.clone()
+            }
+        }
+
+        impl RecordFields<bool> for Label {
+            fn fields(&self) -> bool {
+                self.value
+            }
+        }
+
+        #[test]
+        fn cell_bits() {
+            let points = points(2000, 2, 200);
+            let index = SpaceFillingCurve::<u32, Vec<u16>, u16>::new(points.iter().cloned(), 2, 1);
+            assert!(index
+                .advise()
+                .iter()
+                .any(|a| matches!(a, Advice::IncreaseCellBits { .. })));
+
+            let index = SpaceFillingCurve::<u32, Vec<u16>, u16>::new(points.iter().cloned(), 2, 8);
+            assert!(index
+                .advise()
+                .iter()
+                .any(|a| matches!(a, Advice::DecreaseCellBits { .. })));
+        }
+
+        #[test]
+        fn values() {
+            let points = points(2000, 2, 200);
+            let mut index = SpaceFillingCurve::<bool, Vec<u16>, u16>::new(
+                points.iter().map(|p| Label {
+                    position: p.position.clone(),
+                    value: p.id % 2 == 0,
+                }),
+                2,
+                4,
+            );
+            for point in points.iter().take(ADVICE_MAX_BUFFERED + 1) {
+                index.insert_buffered(Label {
+                    position: point.position.clone(),
+                    value: true,
+                });
+            }
+
+            let advice = index.advise();
+            assert!(advice
+                .iter()
+                .any(|a| matches!(a, Advice::CompressValues { .. })));
+            assert!(advice.contains(&Advice::Flush {
+                buffered: ADVICE_MAX_BUFFERED + 1
+            }));
+        }
+    }
+
+    mod stats {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn maintained() {
+            let points = points(600, 3, 60);
+            let mut index = Index::new(points[..200].iter().cloned(), 3, 3);
+            assert_eq!(index.stats(), &index.compute_stats());
+            assert_eq!(index.stats().records, 200);
+
+            for point in &points[200..300] {
+                index.insert(point.clone()).unwrap();
+            }
+            assert_eq!(index.stats(), &index.compute_stats());
+
+            for point in &points[300..400] {
+                index.insert_buffered(point.clone());
+            }
+            assert_eq!(index.stats(), &index.compute_stats());
+            assert_eq!(index.stats().buffered, 100);
+
+            for point in points.iter().step_by(7) {
+                index.remove(&point.position);
+            }
+            assert_eq!(index.stats(), &index.compute_stats());
+
+            index.flush().unwrap();
+            index.extend(points[400..].iter().cloned()).unwrap();
+            index.remove_by_value(&3);
+            assert_eq!(index.stats(), &index.compute_stats());
+            assert_eq!(index.stats().buffered, 0);
+
+            index.compact(true);
+            assert_eq!(index.stats(), &index.compute_stats());
+            let sizes = index.index.iter().map(|cell| cell.records.len());
+            assert_eq!(index.stats().min_records(), sizes.clone().min().unwrap());
+            assert_eq!(index.stats().max_records(), sizes.max().unwrap());
+            assert_eq!(index.stats().dictionaries.len(), 3);
+            assert!(index.stats().dictionaries.iter().all(|size| *size <= 61));
+        }
+
+        #[test]
+        fn len() {
+            let points = points(600, 3, 60);
+            let mut index = Index::new(points[..500].iter().cloned(), 3, 3);
+            assert_eq!(
+                index.len(),
+                index.find_range(&vec![0; 3], &vec![60; 3]).len()
+            );
+            assert!(!index.is_empty());
+            assert_eq!(index.cell_count(), index.export_cells().count());
+
+            for point in &points[500..] {
+                index.insert_buffered(point.clone());
+            }
+            assert_eq!(index.len(), 600);
+
+            for point in &points {
+                index.remove(&point.position);
+            }
+            assert_eq!(index.len(), 0);
+            assert!(index.is_empty());
+            assert_eq!(index.cell_count(), 0);
+
+            let empty = Index::new(std::iter::empty::<Point>(), 3, 3);
+            assert!(empty.is_empty());
+            assert_eq!(empty.cell_count(), 0);
+        }
+
+        #[test]
+        fn export_cells() {
+            let points = points(600, 3, 60);
+            let mut index = Index::new(points.iter().cloned(), 3, 2);
+            index.remove(&points[0].position);
+
+            let cells = index.export_cells().collect::<Vec<_>>();
+            assert_eq!(cells.len(), index.stats().cells);
+            assert_eq!(
+                cells.iter().map(|cell| cell.records).sum::<usize>(),
+                index.stats().records
+            );
+            for cell in &cells {
+                assert!(cell.records > 0);
+                assert_eq!(
+                    index.encoder().decode(cell.code),
+                    cell.cells
+                        .iter()
+                        .map(|id| *id as MortonValue)
+                        .collect::<Vec<_>>()
+                );
+                assert!(cell.min.iter().zip(&cell.max).all(|(min, max)| min <= max));
+
+                let records = index.find_range(&cell.min, &cell.max);
+                assert!(records.len() >= cell.records);
+            }
+
+            #[cfg(feature = "serde")]
+            {
+                let json = serde_json::to_string(&cells[0]).unwrap();
+                assert_eq!(
+                    serde_json::from_str::<CellSummary<u16>>(&json).unwrap(),
+                    cells[0]
+                );
+            }
+        }
+
+        #[test]
+        fn mem_usage() {
+            let points = points(600, 3, 60);
+            let mut index = Index::new(points.iter().cloned(), 3, 3);
+            let usage = index.mem_usage();
+            assert_eq!(usage.total(), index.size());
+            assert!(usage.offsets >= 600 * 3 * std::mem::size_of::<SFCOffset>());
+            assert!(usage.values >= 600 * std::mem::size_of::<u32>());
+            assert_eq!(usage.buffer, 0);
+            assert_eq!(usage.dictionaries, index.space.size());
+
+            for point in points.iter().step_by(2) {
+                index.remove(&point.position);
+            }
+            index.compact(true);
+            let compacted = index.mem_usage();
+            assert!(compacted.records < usage.records);
+            assert!(compacted.dictionaries <= usage.dictionaries);
+
+            // Offsets are stored without any overhead per record.
+            let offsets = index.len() * 3 * std::mem::size_of::<SFCOffset>();
+            assert_eq!(compacted.offsets, offsets);
+        }
+
+        #[test]
+        fn codes() {
+            let points = points(600, 3, 60);
+            let mut index = Index::new(points[..200].iter().cloned(), 3, 3);
+            let check = |index: &Index| {
+                let codes = index.index.iter().map(|cell| cell.code).collect::<Vec<_>>();
+                assert_eq!(index.codes, codes);
+            };
+            check(&index);
+
+            for point in &points[200..300] {
+                index.insert(point.clone()).unwrap();
+            }
+            check(&index);
+
+            for point in points.iter().step_by(3) {
+                index.remove(&point.position);
+            }
+            check(&index);
+
+            index.extend(points[300..].iter().cloned()).unwrap();
+            index.remove_by_value(&301);
+            check(&index);
+
+            let all = (vec![0; 3], vec![60; 3]);
+            let count = index.find_range(&all.0, &all.1).len();
+            index.compact(true);
+            check(&index);
+            assert_eq!(index.find_range(&all.0, &all.1).len(), count);
+        }
+    }
+
+    mod space {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn introspection() {
+            let points = (0..100u32)
+                .map(|id| Point {
+                    position: vec![(id % 10 * 3) as u16, 7],
+                    id,
+                })
+                .collect::<Vec<_>>();
+            let index = Index::new(points.into_iter(), 2, 1);
+            let space = index.space();
+
+            assert_eq!(space.dimensions(), 2);
+            assert_eq!(space.cell_bits(), index.cell_bits());
+            assert_eq!(space.dictionary_size(0), 10);
+            assert_eq!(space.dictionary_size(1), 1);
+            assert_eq!(space.cells(1), 1);
+
+            // 10 values over 4 cells of 3 values.
+            assert_eq!(space.cells(0), 4);
+            assert_eq!(space.cell_range(0, 1), Some((9, 15)));
+            assert_eq!(space.cell_range(0, 4), None);
+            assert_eq!(space.cell_of(0, &15), Some(1));
+            assert_eq!(space.cell_of(0, &16), Some(2));
+            assert_eq!(space.cell_of(0, &28), None);
+
+            let cells = [space.cell_of(0, &15), space.cell_of(1, &7)]
+                .iter()
+                .map(|cell| cell.unwrap() as MortonValue)
+                .collect::<Vec<_>>();
+            let code = index.encoder().encode(&cells).unwrap();
+            assert!(index.index.iter().any(|cell| cell.code == code));
+        }
+    }
+
+    mod threads {
+        use super::*;
+
+        use std::thread;
+
+        use crate::frozen::FrozenIndex;
+
+        fn is_send_sync<T: Send + Sync>() {}
+
+        // Holds for any key type, `Send` and `Sync` or not.
+        #[allow(dead_code)]
+        fn send_sync<F, K, V>()
+        where
+            F: PartialEq + Send + Sync,
+            K: Position<V>,
+            V: Clone + Debug + Ord + Send + Sync,
+        {
+            is_send_sync::<SpaceFillingCurve<F, K, V>>();
+            is_send_sync::<FrozenIndex<F, K, V>>();
+        }
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn concurrent_queries() {
+            let points = points(2000, 3, 100);
+            let mut index = Index::new(points[..1900].iter().cloned(), 3, 3);
+            for point in &points[1900..] {
+                index.insert_buffered(point.clone());
+            }
+            let index = Arc::new(index);
+            let points = Arc::new(points);
+
+            let handles = (0..8)
+                .map(|t| {
+                    let (index, points) = (index.clone(), points.clone());
+                    thread::spawn(move || {
+                        for point in points.iter().skip(t).step_by(8) {
+                            assert!(index.find(&point.position).contains(&&point.id));
+                        }
+
+                        let start = vec![t as u16 * 10, 0, 20];
+                        let end = vec![t as u16 * 10 + 30, 50, 90];
+                        let mut found = index
+                            .find_range(&start, &end)
+                            .into_iter()
+                            .map(|(_, id)| *id)
+                            .collect::<Vec<_>>();
+                        found.sort_unstable();
+                        found
+                    })
+                })
+                .collect::<Vec<_>>();
+
+            for (t, handle) in handles.into_iter().enumerate() {
+                let found = handle.join().unwrap();
+
+                let (start, end) = (t as u16 * 10, t as u16 * 10 + 30);
+                let mut expected = points
+                    .iter()
+                    .filter(|p| {
+                        let p = &p.position;
+                        (start..=end).contains(&p[0]) && p[1] <= 50 && (20..=90).contains(&p[2])
+                    })
+                    .map(|p| p.id)
+                    .collect::<Vec<_>>();
+                expected.sort_unstable();
+                assert_eq!(found, expected);
+            }
+        }
+    }
+
+    mod explain {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn explain_range() {
+            let points = points(1000, 2, 100);
+            let mut index = Index::new(points.iter().cloned(), 2, 3);
+
+            let (start, end) = (vec![10, 20], vec![60, 45]);
+            let explain = index.explain_range(&start, &end).unwrap();
+            assert_eq!(
+                explain.records_returned,
+                index.find_range(&start, &end).len()
+            );
+            assert!(explain.cells_contained > 0 && explain.cells_filtered > 0);
+            assert_eq!(
+                explain.cells_scanned,
+                explain.cells_contained + explain.cells_filtered
+            );
+            assert!(explain.records_examined > explain.records_returned);
+            assert!(explain.start_code <= explain.end_code);
+
+            index.insert_buffered(Point {
+                position: vec![30, 30],
+                id: 1000,
+            });
+            let buffered = index.explain_range(&start, &end).unwrap();
+            assert_eq!(buffered.records_examined, explain.records_examined + 1);
+            assert_eq!(buffered.records_returned, explain.records_returned + 1);
+
+            let all = index.explain_range(&vec![0, 0], &vec![100, 100]).unwrap();
+            assert_eq!(all.cells_filtered, 0);
+            assert_eq!(all.records_returned, 1001);
+
+            assert!(index.explain_range(&vec![0], &end).is_err());
+            let empty = Index::new(Vec::<Point>::new().into_iter(), 2, 3);
+            assert_eq!(
+                empty.explain_range(&start, &end),
+                Ok(RangeExplain::default())
+            );
+        }
+    }
+
+    mod validate {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        fn index() -> Index {
+            let points = points(300, 3, 30);
+            let mut index = Index::new(points[..200].iter().cloned(), 3, 1);
+            index.extend(points[200..].iter().cloned()).unwrap();
+            for point in points.iter().step_by(5) {
+                index.remove(&point.position);
+            }
+            index.compact(true);
+
+            index
+        }
+
+        #[test]
+        fn valid() {
+            assert_eq!(index().validate(), vec![]);
+            assert_eq!(
+                Index::new(Vec::<Point>::new().into_iter(), 3, 3).validate(),
+                vec![]
+            );
+        }
+
+        #[test]
+        fn cells() {
+            let mut index = index();
+            index.index.swap(1, 2);
+            assert_eq!(
+                index.validate(),
+                vec![Violation::StaleCodes, Violation::Unsorted { cell: 2 },]
+            );
+            index.update_codes();
+            assert_eq!(index.validate(), vec![Violation::Unsorted { cell: 2 }]);
+
+            let mut index = self::index();
+            let cell = index.index[0].clone();
+            index.index.insert(0, cell);
+            index.update_codes();
+            assert_eq!(
+                index.validate(),
+                vec![Violation::DuplicateCode { cell: 1 }, Violation::StaleStats]
+            );
+
+            let mut index = self::index();
+            Arc::make_mut(&mut index.index[0]).code = MortonCode::MAX;
+            assert!(index.validate().contains(&Violation::InvalidCode {
+                cell: 0,
+                code: MortonCode::MAX
+            }));
+        }
+
+        #[test]
+        fn records() {
+            let mut index = index();
+            let cell = Arc::make_mut(&mut index.index[1]);
+            let last = cell.records.len() - 1;
+            cell.offsets[2] = 1000;
+            cell.records[1].value = 1000;
+            cell.offsets.pop();
+            assert_eq!(
+                index.validate(),
+                vec![
+                    Violation::OffsetOutOfBounds {
+                        cell: 1,
+                        record: 0,
+                        dimension: 2
+                    },
+                    Violation::MissingValues { cell: 1, record: 1 },
+                    Violation::DimensionMismatch {
+                        cell: 1,
+                        record: last,
+                        found: 2
+                    },
+                ]
+            );
+            assert_eq!(
+                index.validate()[2].to_string(),
+                format!("Record {} of cell 1 has 2 offsets", last)
+            );
+        }
+    }
+
+    mod sharing {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn new_sharing() {
+            let points = points(600, 3, 60);
+            let base = Index::new(points.iter().cloned(), 3, 3);
+
+            let (mut day, report) =
+                Index::new_sharing(points[..200].iter().cloned(), &base, BuildPolicy::FailFast)
+                    .unwrap();
+            assert_eq!(report.indexed, 200);
+            assert!(Arc::ptr_eq(&day.space, &base.space));
+            assert!(Arc::ptr_eq(&day.morton, &base.morton));
+            for point in &points[..200] {
+                assert!(day.find(&point.position).contains(&&point.id));
+            }
+            let all = (vec![0; 3], vec![60; 3]);
+            assert_eq!(day.find_range(&all.0, &all.1).len(), 200);
+
+            // Known coordinates keep the dictionaries shared.
+            day.insert(points[300].clone()).unwrap();
+            assert!(Arc::ptr_eq(&day.space, &base.space));
+
+            // New ones are added to a copy, without changing the cells.
+            let outside = Point {
+                position: vec![61, 0, 0],
+                id: 1000,
+            };
+            let (other, _) =
+                Index::new_sharing(vec![outside.clone()], &base, BuildPolicy::FailFast).unwrap();
+            assert!(!Arc::ptr_eq(&other.space, &base.space));
+            assert!(Arc::ptr_eq(&other.morton, &base.morton));
+            assert_eq!(other.find(&outside.position), vec![&1000]);
+            assert_eq!(base.find(&outside.position), Vec::<&u32>::new());
+
+            let mut short = points[0].clone();
+            short.position.pop();
+            let (_, report) =
+                Index::new_sharing(vec![short.clone()], &base, BuildPolicy::CollectAndReport)
+                    .unwrap();
+            assert_eq!(report.rejected.len(), 1);
+            let built = Index::new_sharing(vec![short], &base, BuildPolicy::FailFast);
+            assert!(matches!(
+                built.err(),
+                Some(SfcError::DimensionMismatch { .. })
+            ));
+        }
+
+        #[test]
+        fn compare_keys() {
+            let points = points(600, 3, 20);
+            let base = Index::new(points.iter().cloned(), 3, 3);
+            let (mut old, _) =
+                Index::new_sharing(points[..300].iter().cloned(), &base, BuildPolicy::FailFast)
+                    .unwrap();
+            old.insert_buffered(points[320].clone());
+            old.insert_buffered(points[10].clone());
+            let (new, _) = Index::new_sharing(
+                points[200..500].iter().cloned(),
+                &base,
+                BuildPolicy::FailFast,
+            )
+            .unwrap();
+            let mut copy = Index::new(points[200..450].iter().cloned(), 3, 2);
+            for point in &points[450..500] {
+                copy.insert_buffered(point.clone());
+            }
+
+            let positions = |points: &[Point]| {
+                points
+                    .iter()
+                    .map(|p| p.position.clone())
+                    .collect::<HashSet<_>>()
+            };
+            let mut old_keys = positions(&points[..300]);
+            old_keys.insert(points[320].position.clone());
+            let new_keys = positions(&points[200..500]);
+            let sorted = |mut keys: Vec<Vec<u16>>| {
+                let len = keys.len();
+                keys.sort_unstable();
+                keys.dedup();
+                assert_eq!(keys.len(), len);
+                keys
+            };
+
+            let mut common = old_keys
+                .intersection(&new_keys)
+                .cloned()
+                .collect::<Vec<_>>();
+            common.sort_unstable();
+            let mut removed = old_keys.difference(&new_keys).cloned().collect::<Vec<_>>();
+            removed.sort_unstable();
+            assert!(!common.is_empty() && !removed.is_empty());
+
+            for other in [&new, &copy] {
+                assert_eq!(sorted(old.intersect_keys(other)), common);
+                assert_eq!(sorted(old.difference_keys(other)), removed);
+            }
+            assert!(new.difference_keys(&base).is_empty());
+        }
+    }
+
+    mod sizing {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn records() {
+            // Most points in a corner, a few spread over the space.
+            let points = (0..1000u32)
+                .map(|id| Point {
+                    position: if id % 10 == 0 {
+                        vec![(id * 7 % 1000) as u16, (id * 13 % 1000) as u16]
+                    } else {
+                        vec![(id % 10) as u16, (id / 10 % 10) as u16]
+                    },
+                    id,
+                })
+                .collect::<Vec<_>>();
+
+            let distinct =
+                Index::new_with_sizing(points.iter().cloned(), 2, 3, CellSizing::Distinct);
+            let records = Index::new_with_sizing(points.iter().cloned(), 2, 3, CellSizing::Records);
+            assert_eq!(
+                distinct.stats(),
+                Index::new(points.iter().cloned(), 2, 3).stats()
+            );
+            assert!(records.stats().occupancy.len() < distinct.stats().occupancy.len());
+
+            for point in &points {
+                assert!(records.find(&point.position).contains(&&point.id));
+            }
+            let (start, end) = (vec![2, 3], vec![500, 8]);
+            assert_eq!(
+                records.find_range(&start, &end).len(),
+                distinct.find_range(&start, &end).len()
+            );
+        }
+    }
+
+    mod dimensions {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn set_dimensions() {
+            let mut index = Index::new(points(100, 2, 50).into_iter(), 2, 3);
+            assert!(index.dimensions().is_empty());
+            assert!(index.dimension(0).is_none());
+
+            let dimensions = vec![Dimension::new("x", "µm"), Dimension::new("t", "")];
+            assert_eq!(
+                index.set_dimensions(dimensions[..1].to_vec()),
+                Err(SfcError::DimensionMismatch {
+                    expected: 2,
+                    found: 1
+                })
+            );
+            index.set_dimensions(dimensions.clone()).unwrap();
+            assert_eq!(index.dimensions(), &dimensions[..]);
+            assert_eq!(index.dimension(0).unwrap().to_string(), "x: µm");
+            assert_eq!(index.dimension(1).unwrap().to_string(), "t");
+        }
+
+        #[cfg(feature = "serde")]
+        #[test]
+        fn persisted() {
+            let mut index = Index::new(points(100, 2, 50).into_iter(), 2, 3);
+            let dimensions = vec![Dimension::new("x", "µm"), Dimension::new("y", "µm")];
+            index.set_dimensions(dimensions.clone()).unwrap();
+
+            let bytes = bincode::serialize(&index).unwrap();
+            let index: Index = bincode::deserialize(&bytes).unwrap();
+            assert_eq!(index.dimensions(), &dimensions[..]);
+        }
+    }
+
+    mod range_scan {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn whole_cells() {
+            let points = points(2000, 2, 64);
+            let index = Index::new(points.iter().cloned(), 2, 2);
+
+            for (start, end) in &[
+                (vec![0, 20], vec![63, 63]),
+                (vec![17, 0], vec![63, 63]),
+                (vec![0, 0], vec![63, 63]),
+                (vec![16, 16], vec![31, 47]),
+            ] {
+                let mut values = index.find_range(start, end);
+                let mut expected = points
+                    .iter()
+                    .filter(|p| {
+                        (0..2).all(|k| start[k] <= p.position[k] && p.position[k] <= end[k])
+                    })
+                    .map(|p| (p.position.clone(), &p.id))
+                    .collect::<Vec<_>>();
+                values.sort();
+                expected.sort();
+                assert_eq!(values, expected);
+            }
+        }
+    }
+
+    mod find_iter {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn find_iter() {
+            let points = points(500, 3, 30);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            for point in &points {
+                let values = index.find_iter(&point.position).collect::<Vec<_>>();
+                assert!(values.contains(&&point.id));
+                assert_eq!(values, index.find(&point.position));
+            }
+            assert_eq!(index.find_iter(&vec![100, 0, 0]).count(), 0);
+        }
+
+        #[test]
+        fn contains() {
+            let points = points(500, 3, 30);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            for point in &points {
+                assert!(index.contains(&point.position));
+            }
+            assert!(!index.contains(&vec![100, 0, 0]));
+
+            let point = &points[0];
+            index.remove(&point.position);
+            assert!(!index.contains(&point.position));
+        }
+
+        #[test]
+        fn try_find() {
+            let points = points(500, 3, 30);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            for point in &points {
+                assert_eq!(
+                    index.try_find(&point.position).unwrap(),
+                    index.find(&point.position)
+                );
+            }
+            assert!(index.try_find(&vec![100, 0, 0]).unwrap().is_empty());
+
+            let (start, end) = (vec![5, 5, 5], vec![20, 25, 20]);
+            let mut found = index.try_find_range(&start, &end).unwrap();
+            let mut expected = index.find_range(&start, &end);
+            found.sort_unstable();
+            expected.sort_unstable();
+            assert_eq!(found, expected);
+
+            // Records pointing past the dictionaries are reported, instead
+            // of being skipped.
+            let cell = Arc::make_mut(&mut index.index[0]);
+            cell.offsets[..3].copy_from_slice(&[SFCOffset::MAX; 3]);
+            let origin = vec![0, 0, 0];
+            assert!(matches!(
+                index.try_find_range(&origin, &vec![30, 30, 30]),
+                Err(SfcError::OutOfBounds { .. })
+            ));
+            assert!(index.find_range(&origin, &vec![30, 30, 30]).len() < 500);
+        }
+    }
+
+    mod range_filter {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn find_range_filter() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let start = vec![5, 10, 0];
+            let end = vec![50, 45, 30];
+            let filtered = index.find_range_filter(&start, &end, |f| f % 3 == 0);
+            let mut expected = index.find_range(&start, &end);
+            expected.retain(|(_, f)| **f % 3 == 0);
+            assert_eq!(filtered, expected);
+            assert!(!filtered.is_empty());
+
+            assert!(index.find_range_filter(&start, &end, |_| false).is_empty());
+        }
+    }
+
+    mod aggregate {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn aggregate_range() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let start = vec![5, 10, 0];
+            let end = vec![50, 45, 30];
+            let expected = index.find_range(&start, &end);
+
+            let count = index.aggregate_range(&start, &end, 0, |count, _, _| count + 1);
+            assert_eq!(count, expected.len());
+
+            let (sum, max) =
+                index.aggregate_range(&start, &end, (0, 0), |(sum, max), key, fields| {
+                    (sum + *fields as u64, max.max(key[0]))
+                });
+            assert_eq!(sum, expected.iter().map(|(_, f)| **f as u64).sum::<u64>());
+            assert_eq!(max, expected.iter().map(|(k, _)| k[0]).max().unwrap());
+        }
+    }
+
+    mod find_ranges {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn find_ranges() {
+            let points = points(500, 2, 60);
+            let mut index = Index::new(points[..400].iter().cloned(), 2, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let mut ranges = vec![];
+            for x in 0..4 {
+                for y in 0..4 {
+                    ranges.push((vec![x * 15, y * 15], vec![x * 15 + 20, y * 15 + 20]));
+                }
+            }
+            ranges.push((vec![70, 70], vec![80, 80]));
+
+            let values = index.find_ranges(&ranges);
+            assert_eq!(values.len(), ranges.len());
+            for ((start, end), values) in ranges.iter().zip(values) {
+                let mut expected = index.find_range(start, end);
+                let mut values = values;
+                expected.sort();
+                values.sort();
+                assert_eq!(values, expected);
+            }
+            assert!(index.find_ranges(&[]).is_empty());
+        }
+    }
+
+    mod top_k {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn top_k_in_range() {
+            let points = points(500, 3, 60);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+
+            let start = vec![5, 10, 0];
+            let end = vec![50, 45, 30];
+            let mut expected = index.find_range(&start, &end);
+            expected.sort_by(|a, b| b.1.cmp(a.1));
+
+            let top = index.top_k_in_range(&start, &end, 10, |a, b| a.cmp(b));
+            assert_eq!(top, expected[..10].to_vec());
+
+            let bottom = index.top_k_in_range(&start, &end, 3, |a, b| b.cmp(a));
+            assert_eq!(
+                bottom.iter().map(|(_, f)| **f).collect::<Vec<_>>(),
+                expected
+                    .iter()
+                    .rev()
+                    .take(3)
+                    .map(|(_, f)| **f)
+                    .collect::<Vec<_>>()
+            );
+
+            let all = index.top_k_in_range(&start, &end, 10_000, |a, b| a.cmp(b));
+            assert_eq!(all, expected);
+            assert!(index
+                .top_k_in_range(&start, &end, 0, |a, b| a.cmp(b))
+                .is_empty());
+        }
+    }
+
+    mod page {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        fn pages<'a>(
+            index: &'a Index,
+            start: &[u16],
+            end: &[u16],
+            size: usize,
+        ) -> Vec<Vec<(Vec<u16>, &'a u32)>> {
+            let mut pages = vec![];
+            let mut cursor = None;
+            loop {
+                let (page, next) =
+                    index.find_range_page(&start.to_vec(), &end.to_vec(), cursor, size);
+                assert!(page.len() <= size.max(1));
+                pages.push(page);
+
+                #[cfg(feature = "serde")]
+                let next = next.map(|cursor| {
+                    let json = serde_json::to_string(&cursor).unwrap();
+                    serde_json::from_str::<Cursor>(&json).unwrap()
+                });
+                match next {
+                    Some(next) => cursor = Some(next),
+                    None => return pages,
+                }
+            }
+        }
+
+        #[test]
+        fn find_range_page() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..450].iter().cloned(), 3, 2);
+            for point in &points[450..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let (start, end) = (vec![5, 10, 0], vec![50, 45, 30]);
+            let expected = index.find_range(&start, &end);
+            assert!(expected.len() > 50);
+            for size in [0, 1, 7, 50, 1000] {
+                let pages = pages(&index, &start, &end, size);
+                assert_eq!(pages.concat(), expected);
+            }
+            assert_eq!(pages(&index, &start, &end, 1000).len(), 1);
+
+            let all = pages(&index, &[0; 3], &[60; 3], 64);
+            assert_eq!(all.iter().map(Vec::len).sum::<usize>(), 500);
+            assert!(pages(&index, &[70; 3], &[80; 3], 10).concat().is_empty());
+        }
+    }
+
+    mod sample {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        fn rng() -> impl FnMut() -> u64 {
+            let mut state = 0x9e37_79b9_7f4a_7c15u64;
+            move || {
+                state = state
+                    .wrapping_mul(6_364_136_223_846_793_005)
+                    .wrapping_add(1);
+                state ^ (state >> 29)
+            }
+        }
+
+        #[test]
+        fn sample_range() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..450].iter().cloned(), 3, 2);
+            for point in &points[450..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let (start, end) = (vec![5, 10, 0], vec![50, 45, 30]);
+            let all = index.find_range(&start, &end);
+            assert!(all.len() > 50);
+
+            let mut rng = rng();
+            for n in [0, 1, 20, all.len()] {
+                let sample = index.sample_range(&start, &end, n, &mut rng);
+                assert_eq!(sample.len(), n);
+                // Distinct records, in the order of `find_range`.
+                let mut ranks = sample
+                    .iter()
+                    .map(|found| all.iter().position(|r| r == found).unwrap());
+                let mut previous = ranks.next();
+                for rank in ranks {
+                    assert!(previous < Some(rank));
+                    previous = Some(rank);
+                }
+            }
+            assert_eq!(index.sample_range(&start, &end, 10_000, &mut rng), all);
+            assert!(index
+                .sample_range(&vec![70; 3], &vec![80; 3], 5, &mut rng)
+                .is_empty());
+        }
+
+        #[test]
+        fn uniform() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..450].iter().cloned(), 3, 2);
+            for point in &points[450..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let (start, end) = (vec![0, 0, 0], vec![30, 40, 15]);
+            let all = index.find_range(&start, &end);
+            assert!(all.len() > 10 && all.len() < 60);
+
+            let mut rng = rng();
+            let mut drawn = HashMap::new();
+            let trials = 300 * all.len();
+            for _ in 0..trials {
+                for (_, id) in index.sample_range(&start, &end, 1, &mut rng) {
+                    *drawn.entry(*id).or_insert(0) += 1;
+                }
+            }
+            assert_eq!(drawn.len(), all.len());
+            for count in drawn.values() {
+                assert!((200..400).contains(count), "{}", count);
+            }
+        }
+    }
+
+    mod visit {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn visit_cells_in_range() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points.iter().cloned(), 3, 2);
+            index.insert_buffered(Point {
+                position: vec![20, 20, 20],
+                id: 500,
+            });
+
+            let start = vec![5, 10, 0];
+            let end = vec![50, 45, 30];
+            let mut codes = vec![];
+            let mut records = vec![];
+            let mut contained = 0;
+            index.visit_cells_in_range(&start, &end, |cell| {
+                codes.push(cell.code());
+                assert_eq!(cell.fields().count(), cell.len());
+                if cell.contained() {
+                    contained += 1;
+                    assert_eq!(cell.records().count(), cell.len());
+                }
+                records.extend(cell.records());
+                true
+            });
+            assert!(codes.windows(2).all(|pair| pair[0] < pair[1]));
+            assert!(contained > 0);
+
+            // Without the buffered record.
+            let mut expected = index.find_range(&start, &end);
+            assert_eq!(expected.pop().map(|(_, id)| *id), Some(500));
+            assert_eq!(records, expected);
+
+            let mut visited = 0;
+            index.visit_cells_in_range(&start, &end, |_| {
+                visited += 1;
+                visited < 3
+            });
+            assert_eq!(visited, 3);
+        }
+    }
+
+    mod range_iter {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn find_range_iter() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let start = vec![5, 10, 0];
+            let end = vec![50, 45, 30];
+            let iter = index.find_range_iter(&start, &end).collect::<Vec<_>>();
+            assert_eq!(iter, index.find_range(&start, &end));
+            assert!(!iter.is_empty());
+
+            let first = index.find_range_iter(&start, &end).next();
+            assert_eq!(first, iter.into_iter().next());
+        }
+
+        #[test]
+        fn find_range_limit() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let start = vec![5, 10, 0];
+            let end = vec![50, 45, 30];
+            let all = index.find_range(&start, &end);
+            assert!(all.len() > 10);
+            for limit in [0, 1, 10, all.len(), all.len() + 5] {
+                let found = index.find_range_limit(&start, &end, limit);
+                assert_eq!(found, all[..limit.min(all.len())]);
+            }
+
+            let spec = QuerySpec {
+                start: start.iter().cloned().map(Some).collect(),
+                end: end.iter().cloned().map(Some).collect(),
+                values: vec![],
+                order: Order::Curve,
+                skip: 3,
+                limit: Some(5),
+            };
+            assert_eq!(index.run_query(&spec).unwrap(), all[3..8]);
+        }
+
+        #[test]
+        fn iter() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..400].iter().cloned(), 3, 3);
+            for point in &points[400..] {
+                index.insert_buffered(point.clone());
+            }
+
+            let all = index.iter().collect::<Vec<_>>();
+            assert_eq!(all, index.find_range(&vec![0; 3], &vec![60; 3]));
+            assert_eq!(all.len(), 500);
+            assert_eq!(
+                all[400..].iter().map(|(_, id)| **id).collect::<Vec<_>>(),
+                (400..500).collect::<Vec<_>>()
+            );
+
+            let codes = all[..400]
+                .iter()
+                .map(|(position, _)| index.entry(position).unwrap().0)
+                .collect::<Vec<_>>();
+            assert!(codes.windows(2).all(|pair| pair[0] <= pair[1]));
+
+            let mut count = 0;
+            for (position, id) in &index {
+                assert_eq!(position, points[*id as usize].position);
+                count += 1;
+            }
+            assert_eq!(count, 500);
+        }
+    }
+
+    mod remove {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn find() {
+            let points = points(200, 3, 20);
+            let mut index = Index::new(points.iter().cloned(), 3, 3);
+            let cells = index.index.len();
+
+            let removed = &points[17];
+            let mut expected = points
+                .iter()
+                .filter(|p| p.position == removed.position)
+                .map(|p| p.id)
+                .collect::<Vec<_>>();
+            let mut values = index.remove(&removed.position);
+            values.sort_unstable();
+            expected.sort_unstable();
+
+            assert_eq!(values, expected);
+            assert!(index.find(&removed.position).is_empty());
+            assert!(index.remove(&removed.position).is_empty());
+            assert!(index.index.len() <= cells);
+
+            for point in points.iter().filter(|p| p.position != removed.position) {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+        }
+
+        #[test]
+        fn by_value() {
+            let points = points(200, 3, 20)
+                .into_iter()
+                .map(|mut p| {
+                    p.id %= 3;
+                    p
+                })
+                .collect::<Vec<_>>();
+            let mut index = Index::new(points.iter().cloned(), 3, 3);
+
+            let expected = points.iter().filter(|p| p.id == 1).count();
+            assert_eq!(index.remove_by_value(&1), expected);
+            assert_eq!(index.remove_by_value(&1), 0);
+            assert!(index.find_by_value(&1).is_empty());
+            assert_eq!(
+                index.find_by_value(&2).len(),
+                points.iter().filter(|p| p.id == 2).count()
+            );
+        }
+
+        #[test]
+        fn remove_range() {
+            let points = points(500, 3, 60);
+            let mut index = Index::new(points[..450].iter().cloned(), 3, 3);
+            for point in &points[450..] {
+                index.insert_buffered(point.clone());
+            }
+            let cells = index.index.len();
+
+            let (start, end) = (vec![5, 10, 0], vec![50, 45, 30]);
+            let inside =
+                |p: &Point| (0..3).all(|k| start[k] <= p.position[k] && p.position[k] <= end[k]);
+            let expected = points.iter().filter(|p| inside(p)).count();
+            assert!(expected > 0);
+
+            assert_eq!(index.remove_range(&start, &end), expected);
+            assert_eq!(index.remove_range(&start, &end), 0);
+            assert!(index.find_range(&start, &end).is_empty());
+            assert!(index.index.len() < cells);
+            assert!(index.index.iter().all(|cell| !cell.records.is_empty()));
+            assert_eq!(index.len(), 500 - expected);
+            assert!(index.validate().is_empty());
+
+            for point in points.iter().filter(|p| !inside(p)) {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+
+            assert_eq!(
+                index.remove_range(&vec![0; 3], &vec![60; 3]),
+                500 - expected
+            );
+            assert!(index.is_empty());
+        }
+
+        #[test]
+        fn empty_cell() {
+            let points = points(100, 3, 50);
+            let mut index = Index::new(points.iter().cloned(), 3, 3);
+
+            for point in &points {
+                index.remove(&point.position);
+            }
+
+            assert!(index.index.is_empty());
+        }
+    }
+
+    mod partial {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        fn check(points: &[Point], index: &Index, start: &[Option<u16>], end: &[Option<u16>]) {
+            let mut ids = index
+                .find_range_partial(start, end)
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            let expected = points
+                .iter()
+                .filter(|p| {
+                    p.position.iter().enumerate().all(|(k, v)| {
+                        start[k].iter().all(|s| v >= s) && end[k].iter().all(|e| v <= e)
+                    })
+                })
+                .map(|p| p.id)
+                .collect::<Vec<_>>();
+            assert!(!expected.is_empty());
+            assert_eq!(ids, expected);
+        }
+
+        #[test]
+        fn half_space() {
+            let points = points(300, 3, 40);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+
+            check(
+                &points,
+                &index,
+                &[None, None, Some(30)],
+                &[None, None, None],
+            );
+            check(&points, &index, &[None, None, None], &[Some(5), None, None]);
+        }
+
+        #[test]
+        fn slab() {
+            let points = points(300, 3, 40);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+
+            check(
+                &points,
+                &index,
+                &[None, Some(10), None],
+                &[None, Some(20), None],
+            );
+            check(
+                &points,
+                &index,
+                &[Some(3), None, None],
+                &[Some(25), None, Some(12)],
+            );
+        }
+
+        #[test]
+        fn unbounded() {
+            let points = points(300, 3, 40);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+
+            check(&points, &index, &[None; 3], &[None; 3]);
+            assert_eq!(index.find_range_partial(&[None; 3], &[None; 3]).len(), 300);
+        }
+
+        #[test]
+        fn dimensions() {
+            let index = Index::new(points(30, 3, 40).into_iter(), 3, 3);
+
+            assert!(index.find_range_partial(&[None; 2], &[None; 3]).is_empty());
+            assert!(index.find_range_bounds(&[0..40, 0..40]).is_empty());
+        }
+
+        #[test]
+        fn bounds() {
+            let points = points(300, 3, 40);
+            let index = Index::new(points.iter().cloned(), 3, 3);
+
+            let check = |bounds: &[(Bound<u16>, Bound<u16>)]| {
+                let mut ids = index
+                    .find_range_bounds(bounds)
+                    .into_iter()
+                    .map(|(_, id)| *id)
+                    .collect::<Vec<_>>();
+                ids.sort_unstable();
+
+                let expected = points
+                    .iter()
+                    .filter(|p| bounds.iter().zip(&p.position).all(|(b, v)| b.contains(v)))
+                    .map(|p| p.id)
+                    .collect::<Vec<_>>();
+                assert!(!expected.is_empty());
+                assert_eq!(ids, expected);
+            };
+
+            use Bound::*;
+            check(&[
+                (Excluded(5), Excluded(30)),
+                (Included(2), Excluded(35)),
+                (Unbounded, Included(20)),
+            ]);
+            check(&[
+                (Excluded(10), Unbounded),
+                (Unbounded, Unbounded),
+                (Excluded(0), Excluded(39)),
+            ]);
+
+            assert_eq!(
+                index.find_range_bounds(&[5..20, 0..40, 10..30]),
+                index.find_range_bounds(&[
+                    (Included(5), Excluded(20)),
+                    (Included(0), Excluded(40)),
+                    (Included(10), Excluded(30)),
+                ])
+            );
+            assert_eq!(index.find_range_bounds(&[.., .., ..]).len(), 300);
+            assert!(index.find_range_bounds(&[5..5, 0..40, 0..40]).is_empty());
+        }
+
+        #[test]
+        fn projected() {
+            let points = points(400, 4, 40);
+            let mut index = Index::new(points[..350].iter().cloned(), 4, 2);
+            for point in &points[350..] {
+                index.insert_buffered(point.clone());
+            }
+
+            for (start, end) in [
+                (vec![5, 0, 10, 0], vec![30, 39, 35, 20]),
+                (vec![0; 4], vec![39; 4]),
+                (vec![10, 10, 10, 10], vec![12, 30, 30, 30]),
+            ] {
+                let expected = index
+                    .find_range(&start, &end)
+                    .into_iter()
+                    .map(|(position, id)| (vec![position[3], position[0]], id))
+                    .collect::<Vec<_>>();
+                assert!(!expected.is_empty());
+                assert_eq!(index.find_range_projected(&start, &end, &[3, 0]), expected);
+            }
+
+            let all = index.find_range_projected(&vec![0; 4], &vec![39; 4], &[]);
+            assert_eq!(all.len(), 400);
+            assert!(all.iter().all(|(position, _)| position.is_empty()));
+            assert!(index
+                .find_range_projected(&vec![0; 4], &vec![39; 4], &[4])
+                .is_empty());
+        }
+    }
+
+    mod update {
+        use super::*;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        #[test]
+        fn get_fields_mut() {
+            let points = points(100, 3, 50);
+            let mut index = Index::new(points.iter().cloned(), 3, 3);
+
+            for value in index.get_fields_mut(&points[3].position) {
+                *value += 1000;
+            }
+
+            assert!(index.find(&points[3].position).contains(&&1003));
+            assert!(index.get_fields_mut(&vec![60, 60, 60]).is_empty());
+        }
+
+        #[test]
+        fn update() {
+            let points = points(100, 3, 50);
+            let mut index = Index::new(points.iter().cloned(), 3, 3);
+
+            let count = points
+                .iter()
+                .filter(|p| p.position == points[7].position)
+                .count();
+            assert_eq!(index.update(&points[7].position, 42), count);
+            assert_eq!(index.find(&points[7].position), vec![&42; count]);
+        }
+    }
+
+    mod references {
+        use super::*;
+
+        // Neither serializable nor owned.
+        #[derive(Debug, PartialEq)]
+        struct Payload {
+            name: String,
+        }
+
+        #[derive(Clone, Debug)]
+        struct Borrowed<'a> {
+            position: Vec<u16>,
+            payload: &'a Payload,
+        }
+
+        impl<'a> Record<Vec<u16>> for Borrowed<'a> {
+            fn key(&self) -> Vec<u16> {
+                self.position.clone()
+            }
+        }
+
+        impl<'a> RecordFields<&'a Payload> for Borrowed<'a> {
+            fn fields(&self) -> &'a Payload {
+                self.payload
+            }
+        }
+
+        #[test]
+        fn new() {
+            let payloads = (0..100)
+                .map(|id| Payload {
+                    name: format!("point {}", id),
+                })
+                .collect::<Vec<_>>();
+            let records = payloads.iter().enumerate().map(|(id, payload)| Borrowed {
+                position: vec![(id % 10) as u16, (id / 10) as u16],
+                payload,
+            });
+
+            let index = SpaceFillingCurve::<&Payload, Vec<u16>, u16>::new(records, 2, 2);
+            assert_eq!(index.find(&vec![3, 4])[0].name, "point 43");
+            assert_eq!(index.find_range(&vec![0, 0], &vec![1, 1]).len(), 4);
+        }
+    }
+
+    mod signed {
+        use super::*;
+
+        use crate::float::Float;
+
+        #[derive(Clone, Debug)]
+        struct Sample<T> {
+            position: Vec<T>,
+            id: u32,
+        }
+
+        impl<T: Clone + Debug> Record<Vec<T>> for Sample<T> {
+            fn key(&self) -> Vec<T> {
+                self.position.clone()
+            }
+        }
+
+        impl<T> RecordFields<u32> for Sample<T> {
+            fn fields(&self) -> u32 {
+                self.id
+            }
+        }
+
+        // Points centered on the origin, with even coordinates in
+        // [-100, 100).
+        fn samples(count: u32) -> Vec<Sample<i64>> {
+            points(count, 3, 100)
+                .into_iter()
+                .map(|p| Sample {
+                    position: p.position.iter().map(|v| *v as i64 * 2 - 100).collect(),
+                    id: p.id,
+                })
+                .collect()
+        }
+
+        fn check(samples: &[Sample<i64>], index: &SpaceFillingCurve<u32, Vec<i64>, i64>) {
+            let ranges = [
+                // Bounds between the values of the dictionaries.
+                ([-31, -31, -31], [31, 31, 31]),
+                ([-99, -57, -1], [-3, -5, 1]),
+                // Bounds past the smallest and largest values.
+                ([-1000, -1000, -1000], [-95, 1000, 1000]),
+                ([90, -1000, -1000], [1000, -90, 1000]),
+                ([-1000; 3], [1000; 3]),
+                // Empty ranges, out of the dictionaries, or reversed.
+                ([-1000; 3], [-500; 3]),
+                ([500; 3], [1000; 3]),
+                ([10; 3], [-10; 3]),
+            ];
+            for (start, end) in ranges.iter() {
+                let mut ids = index
+                    .find_range(&start.to_vec(), &end.to_vec())
+                    .into_iter()
+                    .map(|(_, id)| *id)
+                    .collect::<Vec<_>>();
+                ids.sort_unstable();
+
+                let expected = samples
+                    .iter()
+                    .filter(|s| {
+                        (0..3).all(|k| start[k] <= s.position[k] && s.position[k] <= end[k])
+                    })
+                    .map(|s| s.id)
+                    .collect::<Vec<_>>();
+                assert_eq!(ids, expected, "{:?} - {:?}", start, end);
+            }
+        }
+
+        #[test]
+        fn find_range() {
+            let mut samples = samples(500);
+            let mut index =
+                SpaceFillingCurve::<u32, Vec<i64>, i64>::new(samples.iter().cloned(), 3, 3);
+            check(&samples, &index);
+
+            for sample in &samples {
+                assert!(index.find(&sample.position).contains(&&sample.id));
+            }
+            assert!(index.find(&vec![-1, -1, -1]).is_empty());
+
+            // Values before the smallest, and past the largest ones.
+            for (id, position) in [[-200, -150, -101], [101, 0, -300], [-7, 250, 99]]
+                .iter()
+                .enumerate()
+            {
+                let sample = Sample {
+                    position: position.to_vec(),
+                    id: 1000 + id as u32,
+                };
+                index.insert(sample.clone()).unwrap();
+                samples.push(sample);
+            }
+            check(&samples, &index);
+        }
+
+        #[test]
+        fn find_within_radius() {
+            let samples = samples(500)
+                .into_iter()
+                .map(|s| Sample {
+                    position: s.position.iter().map(|v| *v as i32).collect(),
+                    id: s.id,
+                })
+                .collect::<Vec<_>>();
+            let index = SpaceFillingCurve::<u32, Vec<i32>, i32>::new(samples.iter().cloned(), 3, 3);
+
+            for (center, radius) in [
+                ([0, 0, 0], 40.0),
+                ([-90, -90, 10], 35.0),
+                ([-3, 5, -7], 0.0),
+            ]
+            .iter()
+            {
+                let found = index.find_within_radius(&center.to_vec(), *radius).len();
+
+                let expected = samples
+                    .iter()
+                    .filter(|s| {
+                        let distance = (0..3)
+                            .map(|k| f64::from(s.position[k] - center[k]).powi(2))
+                            .sum::<f64>();
+                        distance <= radius * radius
+                    })
+                    .count();
+                assert_eq!(found, expected);
+            }
+        }
+
+        #[test]
+        fn floats() {
+            let samples = samples(500)
+                .into_iter()
+                .map(|s| Sample {
+                    position: s.position.iter().map(|v| *v as f64 / 64.0).collect(),
+                    id: s.id,
+                })
+                .collect::<Vec<_>>();
+            let index =
+                SpaceFillingCurve::<u32, Vec<f64>, Float<f64>>::new(samples.iter().cloned(), 3, 3);
+
+            let (start, end) = ([-0.5, -1.2, -0.01], [0.01, 0.0, 1.0]);
+            let found = index.find_range(&start.to_vec(), &end.to_vec()).len();
+            let expected = samples
+                .iter()
+                .filter(|s| (0..3).all(|k| start[k] <= s.position[k] && s.position[k] <= end[k]))
+                .count();
+            assert!(expected > 0);
+            assert_eq!(found, expected);
+        }
+    }
+
+    mod quantized {
+        use super::*;
+
+        use crate::quantizer::Breakpoints;
+        use crate::quantizer::Uniform;
+
+        type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+        fn quantizers() -> Vec<Option<Box<dyn Quantizer<u16>>>> {
+            vec![
+                Some(Box::new(Uniform::new(0, 99, 8).unwrap())),
+                Some(Box::new(Breakpoints::log(0, 199, 6).unwrap())),
+                None,
+            ]
+        }
+
+        fn check(points: &[Point], index: &Index, start: &[Option<u16>], end: &[Option<u16>]) {
+            let mut ids = index
+                .find_range_partial(start, end)
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            ids.sort_unstable();
+
+            let mut expected = points
+                .iter()
+                .filter(|p| {
+                    p.position.iter().enumerate().all(|(k, v)| {
+                        start[k].iter().all(|s| v >= s) && end[k].iter().all(|e| v <= e)
+                    })
+                })
+                .map(|p| p.id)
+                .collect::<Vec<_>>();
+            expected.sort_unstable();
+            assert_eq!(ids, expected);
+        }
+
+        #[test]
+        fn new_quantized() {
+            let mut points = points(500, 3, 100);
+            let (mut index, report) =
+                Index::new_quantized(points.iter().cloned(), 3, 3, quantizers()).unwrap();
+            assert_eq!(report.indexed, 500);
+            assert_eq!(index.cell_bits()[..2], [3, 3]);
+
+            for point in &points {
+                assert!(index.find(&point.position).contains(&&point.id));
+            }
+            check(
+                &points,
+                &index,
+                &[Some(20), Some(5), None],
+                &[Some(60), Some(90), Some(30)],
+            );
+            check(&points, &index, &[None, Some(150), None], &[None; 3]);
+
+            // Values the quantizer of x cannot represent.
+            let outside = Point {
+                position: vec![100, 0, 0],
+                id: 1000,
+            };
+            assert!(matches!(
+                index.insert(outside),
+                Err(SfcError::OutOfBounds { dimension: 0, .. })
+            ));
+            let inside = Point {
+                position: vec![99, 199, 100],
+                id: 1001,
+            };
+            index.insert(inside.clone()).unwrap();
+            points.push(inside);
+            check(&points, &index, &[Some(50), None, Some(50)], &[None; 3]);
+
+            for point in points.drain(..250) {
+                index.remove(&point.position);
+            }
+            index.compact(true);
+            check(&points, &index, &[None; 3], &[None; 3]);
+            check(
+                &points,
+                &index,
+                &[Some(30), Some(30), Some(30)],
+                &[Some(70); 3],
+            );
+        }
+
+        #[test]
+        fn rejected() {
+            let points = points(100, 3, 150);
+            let (index, report) =
+                Index::new_quantized(points.iter().cloned(), 3, 3, quantizers()).unwrap();
+            let outside = points.iter().filter(|p| p.position[0] >= 100).count();
+            assert_eq!(report.rejected.len(), outside);
+            assert_eq!(
+                index.find_range_partial(&[None; 3], &[None; 3]).len(),
+                100 - outside
+            );
+
+            assert!(matches!(
+                Index::new_quantized(points.iter().cloned(), 3, 3, vec![]),
+                Err(SfcError::DimensionMismatch { .. })
+            ));
+            let mut quantizers = quantizers();
+            quantizers[2] = Some(Box::new(
+                Breakpoints::new((0..2000).collect(), 2000).unwrap(),
+            ));
+            assert!(matches!(
+                Index::new_quantized(points.into_iter(), 3, 3, quantizers),
+                Err(SfcError::EncodeOverflow { dimension: 2, .. })
+            ));
+
+            // Each quantizer fits, but not all of them in a Morton code.
+            let quantizers = (0..7)
+                .map(|_| Some(Box::new(Uniform::new(0u16, 1999, 1024).unwrap()) as Box<_>))
+                .collect();
+            assert!(matches!(
+                Index::new_quantized(super::points(100, 7, 2000).into_iter(), 7, 10, quantizers),
+                Err(SfcError::EncodeOverflow { dimension: 6, .. })
+            ));
+        }
+
+        #[cfg(feature = "serde")]
+        #[test]
+        fn serialize() {
+            let (index, _) =
+                Index::new_quantized(points(100, 3, 100).into_iter(), 3, 3, quantizers()).unwrap();
+
+            assert!(bincode::serialize(&index).is_err());
+        }
+    }
+}
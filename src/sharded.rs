@@ -0,0 +1,284 @@
+//! Index split into independent shards, built and queried on all the
+//! available cores.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use ironsea_index::IndexedDestructured;
+use ironsea_index::Record;
+use ironsea_index::RecordFields;
+use rayon::prelude::*;
+
+use crate::build::BuildReport;
+use crate::build::Normalized;
+use crate::position::Position;
+use crate::sfc::SpaceFillingCurve;
+
+// Record of a shard, borrowed from the records given to `new`.
+#[derive(Debug)]
+struct Member<'a, R>(&'a R);
+
+impl<'a, R> Clone for Member<'a, R> {
+    fn clone(&self) -> Self {
+        Member(self.0)
+    }
+}
+
+impl<'a, K, R: Record<K>> Record<K> for Member<'a, R> {
+    fn key(&self) -> K {
+        self.0.key()
+    }
+}
+
+impl<'a, F, R: RecordFields<F>> RecordFields<F> for Member<'a, R> {
+    fn fields(&self) -> F {
+        self.0.fields()
+    }
+}
+
+/// Index split into shards along the first dimension, each shard being
+/// an independent [`IndexOwned`](struct.IndexOwned.html).
+///
+/// The shards hold about as many records each, over consecutive ranges
+/// of values of the first dimension. They are built in parallel, and
+/// queries only run on the shards overlapping them, in parallel as well.
+/// Each shard has its own dictionaries, sized for its records.
+#[derive(Clone, Debug)]
+pub struct ShardedSfc<F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Ord,
+{
+    // Smallest value of the first dimension of every shard but the first.
+    splits: Vec<V>,
+    shards: Vec<SpaceFillingCurve<F, K, V>>,
+}
+
+impl<F, K, V> ShardedSfc<F, K, V>
+where
+    F: PartialEq + Send + Sync,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord + Send + Sync,
+{
+    /// Creates a new Index from the provided records, split into at most
+    /// `shards` shards.
+    ///
+    /// See [`new`](struct.IndexOwned.html#method.new) for the other
+    /// parameters. Returns the index, as well as a report of the
+    /// processed records, numbered by their position in `records`.
+    pub fn new<R>(
+        records: &[R],
+        dimensions: usize,
+        cell_bits: usize,
+        shards: usize,
+    ) -> (Self, BuildReport)
+    where
+        R: Debug + Record<K> + RecordFields<F> + Sync,
+    {
+        // Records without a first coordinate are rejected by the first
+        // shard.
+        let first = |record: &R| {
+            let key = record.key();
+            match key.dimensions() {
+                Some(0) => None,
+                Some(found) if found != dimensions => None,
+                _ => Some(key.coordinate(0)),
+            }
+        };
+
+        let mut values = records.par_iter().filter_map(first).collect::<Vec<_>>();
+        values.par_sort_unstable();
+        let shards = shards.max(1);
+        let mut splits = (1..shards)
+            .filter_map(|i| values.get(i * values.len() / shards).cloned())
+            .collect::<Vec<_>>();
+        splits.dedup();
+        drop(values);
+
+        let mut members = vec![vec![]; splits.len() + 1];
+        for (i, record) in records.iter().enumerate() {
+            let shard = match first(record) {
+                Some(value) => splits.partition_point(|split| split <= &value),
+                None => 0,
+            };
+            members[shard].push(i);
+        }
+
+        let built = members
+            .par_iter()
+            .map(|members| {
+                SpaceFillingCurve::new_normalized(
+                    members.iter().map(|i| Member(&records[*i])),
+                    dimensions,
+                    cell_bits,
+                    |_| Normalized::Unchanged,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        // Number the records by their position in `records`.
+        let mut report = BuildReport::default();
+        let mut shards = Vec::with_capacity(built.len());
+        for ((index, shard), members) in built.into_iter().zip(&members) {
+            report.records += shard.records;
+            report.indexed += shard.indexed;
+            report.duplicates += shard.duplicates;
+            report.near_duplicates += shard.near_duplicates;
+            report.rejected.extend(
+                shard
+                    .rejected
+                    .into_iter()
+                    .map(|(source, e)| (members[source], e)),
+            );
+            report.duplicate_samples.extend(
+                shard
+                    .duplicate_samples
+                    .into_iter()
+                    .map(|(first, duplicate)| (members[first], members[duplicate])),
+            );
+            shards.push(index);
+        }
+        report.rejected.sort_by_key(|(source, _)| *source);
+
+        info!(
+            "Built {} shards of {} records on average",
+            shards.len(),
+            report.indexed / shards.len()
+        );
+
+        (ShardedSfc { splits, shards }, report)
+    }
+
+    /// Returns the shards of the index, in increasing order of the values
+    /// of their first dimension.
+    pub fn shards(&self) -> &[SpaceFillingCurve<F, K, V>] {
+        &self.shards
+    }
+
+    // Shard holding the records whose first coordinate is `value`.
+    fn shard(&self, value: &V) -> usize {
+        self.splits.partition_point(|split| split <= value)
+    }
+
+    // Shards which may hold records between `start` and `end`, none if
+    // the range is inverted along the first dimension.
+    fn overlapping(&self, start: &K, end: &K) -> std::ops::Range<usize> {
+        let coordinate = |key: &K| match key.dimensions() {
+            Some(0) => None,
+            _ => Some(key.coordinate(0)),
+        };
+
+        match (coordinate(start), coordinate(end)) {
+            (Some(start), Some(end)) if start > end => 0..0,
+            (Some(start), Some(end)) => self.shard(&start)..self.shard(&end) + 1,
+            _ => 0..self.shards.len(),
+        }
+    }
+}
+
+impl<F, K, V> IndexedDestructured<F, K> for ShardedSfc<F, K, V>
+where
+    F: PartialEq + Send + Sync,
+    K: Position<V> + Send + Sync,
+    V: Clone + Debug + Hash + Ord + Send + Sync,
+{
+    fn find(&self, key: &K) -> Vec<&F> {
+        match key.dimensions() {
+            Some(0) => vec![],
+            _ => self.shards[self.shard(&key.coordinate(0))].find(key),
+        }
+    }
+
+    /// The shards overlapping the range are queried in parallel, and
+    /// their records returned one shard after the other.
+    fn find_range(&self, start: &K, end: &K) -> Vec<(K, &F)> {
+        self.shards[self.overlapping(start, end)]
+            .par_iter()
+            .flat_map_iter(|shard| shard.find_range(start, end))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::build::Rejection;
+    use crate::fixtures::Point;
+
+    type Index = ShardedSfc<u32, Vec<u16>, u16>;
+
+    fn points() -> Vec<Point> {
+        (0..5000)
+            .map(|id| Point {
+                position: vec![(id % 100) as u16, (id / 100) as u16, (id * 7 % 9) as u16],
+                id,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn new() {
+        let mut points = points();
+        points[10].position.pop();
+        let (index, report) = Index::new(&points, 3, 3, 4);
+
+        assert_eq!(index.shards().len(), 4);
+        assert_eq!(report.records, 5000);
+        assert_eq!(report.indexed, 4999);
+        assert!(matches!(
+            report.rejected[..],
+            [(10, Rejection::DimensionMismatch { .. })]
+        ));
+        for shard in index.shards() {
+            assert!(shard.stats().records > 1000);
+        }
+
+        for point in &points[11..] {
+            assert_eq!(index.find(&point.position), vec![&point.id]);
+        }
+    }
+
+    #[test]
+    fn find_range() {
+        let points = points();
+        let (index, _) = Index::new(&points, 3, 3, 4);
+        let (single, _) = Index::new(&points, 3, 3, 1);
+        assert_eq!(single.shards().len(), 1);
+
+        for (start, end) in [
+            (vec![10, 5, 0], vec![40, 30, 4]),
+            (vec![0, 0, 0], vec![99, 39, 8]),
+            (vec![60, 0, 0], vec![61, 1, 8]),
+        ] {
+            let mut found = index
+                .find_range(&start, &end)
+                .into_iter()
+                .map(|(_, id)| *id)
+                .collect::<Vec<_>>();
+            found.sort_unstable();
+
+            let expected = points
+                .iter()
+                .filter(|p| (0..3).all(|k| start[k] <= p.position[k] && p.position[k] <= end[k]))
+                .map(|p| p.id)
+                .collect::<Vec<_>>();
+            assert_eq!(found, expected);
+            assert_eq!(single.find_range(&start, &end).len(), expected.len());
+        }
+    }
+
+    #[test]
+    fn inverted_range() {
+        let points = points();
+        let (index, _) = Index::new(&points, 3, 3, 4);
+        assert_eq!(index.shards().len(), 4);
+
+        let (start, end) = (vec![90, 0, 0], vec![5, 39, 8]);
+        assert_eq!(index.find_range(&start, &end), vec![]);
+        assert_eq!(index.shards()[0].find_range(&start, &end), vec![]);
+    }
+}
@@ -0,0 +1,282 @@
+// Sorted runs of the flat table spilled to temporary files, and merged
+// back in `flat_order`, to bound the memory used by the construction.
+
+use std::cmp::Ordering;
+use std::cmp::Reverse;
+use std::collections::BinaryHeap;
+use std::fs;
+use std::fs::File;
+use std::fs::OpenOptions;
+use std::io::BufReader;
+use std::io::BufWriter;
+use std::io::ErrorKind;
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::Path;
+use std::path::PathBuf;
+use std::sync::atomic::AtomicUsize;
+use std::sync::atomic::Ordering as AtomicOrdering;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+use super::error::SfcError;
+use super::sfc::flat_order;
+use super::sfc::FlatEntry;
+use super::sfc::SFCCode;
+use super::sfc::SFCOffset;
+
+// Used to name the files of the runs uniquely within the process.
+static RUNS: AtomicUsize = AtomicUsize::new(0);
+
+// Sorted entries of the flat table, stored in a temporary file which is
+// removed when the run is dropped.
+pub struct Run {
+    path: PathBuf,
+    len: usize,
+}
+
+impl Run {
+    // Sort `table` and write it to a new temporary file in `directory`,
+    // or the temporary directory of the system, leaving `table` empty.
+    pub fn spill<F>(
+        table: &mut Vec<FlatEntry<F>>,
+        directory: Option<&Path>,
+    ) -> Result<Self, SfcError>
+    where
+        F: Serialize,
+    {
+        // There is no file system on `wasm32-unknown-unknown`, where
+        // `temp_dir` panics.
+        if cfg!(all(target_arch = "wasm32", target_os = "unknown")) {
+            return Err(SfcError::Io(
+                "No file system to spill runs to on this platform".to_string(),
+            ));
+        }
+
+        table.sort_unstable_by(flat_order);
+
+        let directory = match directory {
+            Some(directory) => directory.to_path_buf(),
+            None => std::env::temp_dir(),
+        };
+        let (file, path) = create(&directory)?;
+        let run = Run {
+            path,
+            len: table.len(),
+        };
+
+        let mut writer = BufWriter::new(file);
+        for entry in table.drain(..) {
+            bincode::serialize_into(&mut writer, &entry)
+                .map_err(|e| SfcError::Io(e.to_string()))?;
+        }
+        writer.flush().map_err(|e| SfcError::Io(e.to_string()))?;
+        debug!("Spilled {} records to {:?}", run.len, run.path);
+
+        Ok(run)
+    }
+}
+
+// Create a new file for a run in `directory`. The names are predictable,
+// so an existing file, or a link planted by another user in a shared
+// directory, is never opened: the next name is tried instead.
+fn create(directory: &Path) -> Result<(File, PathBuf), SfcError> {
+    loop {
+        let path = directory.join(format!(
+            "ironsea_index_sfc_dbc-{}-{}.run",
+            std::process::id(),
+            RUNS.fetch_add(1, AtomicOrdering::Relaxed)
+        ));
+
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+
+        match options.open(&path) {
+            Ok(file) => return Ok((file, path)),
+            Err(e) if e.kind() == ErrorKind::AlreadyExists => continue,
+            Err(e) => return Err(SfcError::Io(e.to_string())),
+        }
+    }
+}
+
+impl Drop for Run {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!("Cannot remove {:?}: {}", self.path, e);
+        }
+    }
+}
+
+// Next entry of a run, ordered as the flat table.
+struct Head<F> {
+    entry: FlatEntry<F>,
+    run: usize,
+}
+
+impl<F> Head<F> {
+    fn key(&self) -> (SFCCode, &[SFCOffset], usize) {
+        (self.entry.0, &(self.entry.2).0, self.entry.1)
+    }
+}
+
+impl<F> Ord for Head<F> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.key().cmp(&other.key())
+    }
+}
+
+impl<F> PartialOrd for Head<F> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<F> PartialEq for Head<F> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key() == other.key()
+    }
+}
+
+impl<F> Eq for Head<F> {}
+
+// Merge of sorted runs, yielding their entries in `flat_order`. Reading
+// stops at the first error, which is kept in `error`.
+pub struct Merge<F> {
+    readers: Vec<(BufReader<File>, usize)>,
+    heads: BinaryHeap<Reverse<Head<F>>>,
+    pub error: Option<SfcError>,
+    // Removes the files once merged.
+    _runs: Vec<Run>,
+}
+
+impl<F> Merge<F>
+where
+    F: DeserializeOwned,
+{
+    pub fn new(runs: Vec<Run>) -> Result<Self, SfcError> {
+        let mut merge = Merge {
+            readers: vec![],
+            heads: BinaryHeap::with_capacity(runs.len()),
+            error: None,
+            _runs: vec![],
+        };
+        for run in &runs {
+            let file = File::open(&run.path).map_err(|e| SfcError::Io(e.to_string()))?;
+            merge.readers.push((BufReader::new(file), run.len));
+        }
+        merge._runs = runs;
+
+        for run in 0..merge.readers.len() {
+            merge.advance(run)?;
+        }
+
+        Ok(merge)
+    }
+
+    // Read the next entry of `run`, if any.
+    fn advance(&mut self, run: usize) -> Result<(), SfcError> {
+        let (reader, remaining) = &mut self.readers[run];
+        if *remaining > 0 {
+            *remaining -= 1;
+            let entry =
+                bincode::deserialize_from(reader).map_err(|e| SfcError::Io(e.to_string()))?;
+            self.heads.push(Reverse(Head { entry, run }));
+        }
+
+        Ok(())
+    }
+}
+
+impl<F> Iterator for Merge<F>
+where
+    F: DeserializeOwned,
+{
+    type Item = FlatEntry<F>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error.is_some() {
+            return None;
+        }
+
+        let Reverse(head) = self.heads.pop()?;
+        if let Err(e) = self.advance(head.run) {
+            self.error = Some(e);
+            return None;
+        }
+
+        Some(head.entry)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn merge() {
+        let mut table = (0..1000usize)
+            .map(|i| (((i * 7) % 31) as SFCCode, i, (vec![(i % 3) as u32], i)))
+            .collect::<Vec<_>>();
+        let mut expected = table.clone();
+        expected.sort_unstable_by(flat_order);
+
+        let mut runs = vec![];
+        let mut chunk = vec![];
+        for entry in table.drain(..) {
+            chunk.push(entry);
+            if chunk.len() == 300 {
+                runs.push(Run::spill(&mut chunk, None).unwrap());
+            }
+        }
+        runs.push(Run::spill(&mut chunk, None).unwrap());
+        let paths = runs.iter().map(|run| run.path.clone()).collect::<Vec<_>>();
+
+        let mut merge = Merge::new(runs).unwrap();
+        let merged = merge.by_ref().collect::<Vec<_>>();
+        assert!(merge.error.is_none());
+        assert_eq!(merged, expected);
+
+        drop(merge);
+        assert!(paths.iter().all(|path| !path.exists()));
+    }
+    #[test]
+    fn existing_files() {
+        let directory = std::env::temp_dir().join(format!(
+            "ironsea_index_sfc_dbc-{}-existing",
+            std::process::id()
+        ));
+        fs::create_dir_all(&directory).unwrap();
+
+        // Files already holding the next names are left untouched.
+        let next = RUNS.load(AtomicOrdering::Relaxed);
+        let planted = (next..next + 4)
+            .map(|run| {
+                let path = directory.join(format!(
+                    "ironsea_index_sfc_dbc-{}-{}.run",
+                    std::process::id(),
+                    run
+                ));
+                fs::write(&path, b"planted").unwrap();
+                path
+            })
+            .collect::<Vec<_>>();
+
+        let mut table = (0..10usize)
+            .map(|i| (i as SFCCode, i, (vec![0u32], i)))
+            .collect::<Vec<_>>();
+        let run = Run::spill(&mut table, Some(&directory)).unwrap();
+        assert!(run.path.starts_with(&directory));
+        assert!(!planted.contains(&run.path));
+        for path in &planted {
+            assert_eq!(fs::read(path).unwrap(), b"planted");
+        }
+
+        let merged = Merge::<usize>::new(vec![run]).unwrap().count();
+        assert_eq!(merged, 10);
+        fs::remove_dir_all(&directory).unwrap();
+    }
+}
@@ -0,0 +1,163 @@
+use std::collections::BTreeMap;
+
+#[cfg(feature = "serde")]
+use serde::Deserialize;
+#[cfg(feature = "serde")]
+use serde::Serialize;
+
+use super::morton::MortonCode;
+
+/// Statistics of an index, kept up to date as the index is modified, so
+/// that reading them is cheap.
+#[derive(Clone, Debug, Default, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct IndexStats {
+    /// Number of records stored in the cells.
+    pub records: usize,
+    /// Number of records waiting in the write buffer.
+    pub buffered: usize,
+    /// Number of cells holding records.
+    pub cells: usize,
+    /// Histogram of the number of records per cell, in powers of two:
+    /// `occupancy[i]` is the number of cells holding between `2^i` and
+    /// `2^(i+1) - 1` records.
+    pub occupancy: Vec<usize>,
+    /// Number of cells holding each number of records, for the numbers of
+    /// records found in at least one cell.
+    pub sizes: BTreeMap<usize, usize>,
+    /// Number of distinct values in the dictionary of each dimension, 0
+    /// for the dimensions using a quantizer.
+    pub dictionaries: Vec<usize>,
+}
+
+/// Occupied cell of an index, see
+/// [`export_cells`](struct.IndexOwned.html#method.export_cells).
+#[derive(Clone, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(Deserialize, Serialize))]
+pub struct CellSummary<V> {
+    /// Morton code of the cell.
+    pub code: MortonCode,
+    /// Cell of each dimension, decoded from the code.
+    pub cells: Vec<usize>,
+    /// Smallest value of the cell in each dimension.
+    pub min: Vec<V>,
+    /// Largest value of the cell in each dimension.
+    pub max: Vec<V>,
+    /// Number of records of the cell.
+    pub records: usize,
+}
+
+/// Estimated memory used by an index, see
+/// [`mem_usage`](struct.IndexOwned.html#method.mem_usage).
+///
+/// Sizes are in bytes, and do not include memory owned by the values or
+/// the coordinates themselves, for example the content of a `String`.
+/// Cells and dictionaries shared with other indices are counted in full.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct MemoryBreakdown {
+    /// Table of the cells: their codes and headers, and the index itself.
+    pub cells: usize,
+    /// Records of the cells, without their offsets.
+    pub records: usize,
+    /// Offsets of the coordinates of the records in the dictionaries.
+    pub offsets: usize,
+    /// Values of the records.
+    pub values: usize,
+    /// Records waiting in the write buffer.
+    pub buffer: usize,
+    /// Dictionaries of the dimensions.
+    pub dictionaries: usize,
+}
+
+impl MemoryBreakdown {
+    /// Returns the estimated size of the whole index.
+    pub fn total(&self) -> usize {
+        self.cells + self.records + self.offsets + self.values + self.buffer + self.dictionaries
+    }
+}
+
+impl IndexStats {
+    /// Returns the smallest number of records of a cell, or 0 if no cell
+    /// holds records.
+    pub fn min_records(&self) -> usize {
+        self.sizes.keys().next().copied().unwrap_or(0)
+    }
+
+    /// Returns the average number of records of the cells holding
+    /// records, or 0 if there are none.
+    pub fn mean_records(&self) -> f64 {
+        if self.cells == 0 {
+            return 0.0;
+        }
+
+        self.records as f64 / self.cells as f64
+    }
+
+    /// Returns the largest number of records of a cell.
+    pub fn max_records(&self) -> usize {
+        self.sizes.keys().next_back().copied().unwrap_or(0)
+    }
+
+    // Account for a cell going from `before` to `after` records, 0 meaning
+    // the cell does not exist.
+    pub(crate) fn resize(&mut self, before: usize, after: usize) {
+        if before == after {
+            return;
+        }
+
+        if before > 0 {
+            self.occupancy[Self::bucket(before)] -= 1;
+            self.cells -= 1;
+            if let Some(cells) = self.sizes.get_mut(&before) {
+                *cells -= 1;
+                if *cells == 0 {
+                    self.sizes.remove(&before);
+                }
+            }
+        }
+        if after > 0 {
+            let bucket = Self::bucket(after);
+            if self.occupancy.len() <= bucket {
+                self.occupancy.resize(bucket + 1, 0);
+            }
+            self.occupancy[bucket] += 1;
+            self.cells += 1;
+            *self.sizes.entry(after).or_default() += 1;
+        }
+
+        self.records = self.records + after - before;
+    }
+
+    // Index of the histogram bucket of a cell holding `records` records.
+    fn bucket(records: usize) -> usize {
+        (std::mem::size_of::<usize>() * 8 - 1) - records.leading_zeros() as usize
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize() {
+        let mut stats = IndexStats::default();
+        stats.resize(0, 1);
+        stats.resize(0, 5);
+        assert_eq!(stats.records, 6);
+        assert_eq!(stats.cells, 2);
+        assert_eq!(stats.occupancy, vec![1, 0, 1]);
+        assert_eq!((stats.min_records(), stats.max_records()), (1, 5));
+        assert_eq!(stats.mean_records(), 3.0);
+
+        stats.resize(5, 3);
+        stats.resize(1, 0);
+        assert_eq!(stats.records, 3);
+        assert_eq!(stats.cells, 1);
+        assert_eq!(stats.occupancy, vec![0, 1, 0]);
+        assert_eq!(stats.sizes, vec![(3, 1)].into_iter().collect());
+
+        stats.resize(3, 0);
+        assert_eq!((stats.min_records(), stats.max_records()), (0, 0));
+        assert_eq!(stats.mean_records(), 0.0);
+    }
+}
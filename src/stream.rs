@@ -0,0 +1,303 @@
+//! Queries returning their results as asynchronous streams, for use from
+//! async services.
+
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::Mutex;
+use std::task::Context;
+use std::task::Poll;
+use std::task::Waker;
+
+use futures_core::Stream;
+
+use crate::position::Position;
+use crate::query::Cursor;
+use crate::sfc::SpaceFillingCurve;
+
+/// Number of records a stream scans at a time, and so the most it holds
+/// before they are taken.
+pub const STREAM_CAPACITY: usize = 256;
+
+// Scan of a page of records, from a cursor.
+type Fetch<T> = Box<dyn Fn(Option<Cursor>) -> (Vec<T>, Option<Cursor>) + Send + Sync>;
+
+// State shared between a stream and the scans filling it.
+struct Shared<T> {
+    items: VecDeque<T>,
+    cursor: Option<Cursor>,
+    running: bool,
+    done: bool,
+    cancelled: bool,
+    waker: Option<Waker>,
+}
+
+// The shared state, with the scan of the pages.
+struct Channel<T> {
+    shared: Mutex<Shared<T>>,
+    fetch: Fetch<T>,
+}
+
+// Scans the next page of `channel` on the worker pool of rayon. The task
+// returns once the page is queued, so that streams which are not polled
+// do not hold the threads of the pool.
+fn spawn_page<T>(channel: Arc<Channel<T>>)
+where
+    T: Send + 'static,
+{
+    rayon::spawn(move || {
+        let mut cursor = channel.shared.lock().unwrap().cursor;
+        loop {
+            let (items, next) = (channel.fetch)(cursor);
+            cursor = next;
+
+            let mut shared = channel.shared.lock().unwrap();
+            if shared.cancelled {
+                return;
+            }
+            // Pages may be empty, the stream waits for records.
+            if items.is_empty() && cursor.is_some() {
+                continue;
+            }
+
+            shared.items.extend(items);
+            shared.cursor = cursor;
+            shared.done = cursor.is_none();
+            shared.running = false;
+            if let Some(waker) = shared.waker.take() {
+                waker.wake();
+            }
+            return;
+        }
+    });
+}
+
+/// Records of a range query, as returned by
+/// [`find_range_async`](struct.IndexOwned.html#method.find_range_async).
+///
+/// The scan runs on the worker pool of rayon, one page of
+/// [`STREAM_CAPACITY`](constant.STREAM_CAPACITY.html) records at a time.
+/// The next page is scanned once the records of the previous one have
+/// been taken, so the scan never waits for the stream to be polled.
+/// Dropping the stream stops the scan.
+pub struct RangeStream<T> {
+    channel: Arc<Channel<T>>,
+}
+
+impl<T> Stream for RangeStream<T>
+where
+    T: Send + 'static,
+{
+    type Item = T;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context) -> Poll<Option<T>> {
+        let mut shared = self.channel.shared.lock().unwrap();
+
+        let item = shared.items.pop_front();
+        if shared.items.is_empty() && !shared.done && !shared.running {
+            shared.running = true;
+            spawn_page(Arc::clone(&self.channel));
+        }
+
+        match item {
+            Some(item) => Poll::Ready(Some(item)),
+            None if shared.done => Poll::Ready(None),
+            None => {
+                shared.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Drop for RangeStream<T> {
+    fn drop(&mut self) {
+        if let Ok(mut shared) = self.channel.shared.lock() {
+            shared.cancelled = true;
+            shared.items.clear();
+        }
+    }
+}
+
+impl<T> Debug for RangeStream<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("RangeStream").finish()
+    }
+}
+
+impl<F, K, V> SpaceFillingCurve<F, K, V>
+where
+    F: Clone + PartialEq + Send + Sync + 'static,
+    K: Position<V> + Send + Sync + 'static,
+    V: Clone + Debug + Hash + Ord + Send + Sync + 'static,
+{
+    /// Returns the records within the range `[start, end]`, as a stream.
+    ///
+    /// The scan is spawned on the worker pool of rayon instead of running
+    /// on the calling thread, so that long scans do not block the threads
+    /// of the async executor. The values are cloned, as the stream may
+    /// outlive any borrow of the index.
+    pub fn find_range_async(self: &Arc<Self>, start: K, end: K) -> RangeStream<(K, F)> {
+        let index = Arc::clone(self);
+        let fetch: Fetch<(K, F)> = Box::new(move |cursor| {
+            let (page, next) = index.find_range_page(&start, &end, cursor, STREAM_CAPACITY);
+            let page = page
+                .into_iter()
+                .map(|(key, fields)| (key, fields.clone()))
+                .collect();
+            (page, next)
+        });
+
+        let channel = Arc::new(Channel {
+            shared: Mutex::new(Shared {
+                items: VecDeque::new(),
+                cursor: None,
+                running: true,
+                done: false,
+                cancelled: false,
+                waker: None,
+            }),
+            fetch,
+        });
+        spawn_page(Arc::clone(&channel));
+
+        RangeStream { channel }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::sync::mpsc;
+    use std::task::Wake;
+    use std::thread;
+    use std::thread::Thread;
+    use std::time::Duration;
+
+    use ironsea_index::IndexedDestructured;
+
+    use crate::fixtures::Point;
+
+    type Index = SpaceFillingCurve<u32, Vec<u16>, u16>;
+
+    struct Unpark(Thread);
+
+    impl Wake for Unpark {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    // Polls the stream to completion from the current thread.
+    fn collect<T: Send + 'static>(mut stream: RangeStream<T>) -> Vec<T> {
+        let waker = Waker::from(Arc::new(Unpark(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut items = vec![];
+
+        loop {
+            match Pin::new(&mut stream).poll_next(&mut cx) {
+                Poll::Ready(Some(item)) => items.push(item),
+                Poll::Ready(None) => return items,
+                Poll::Pending => thread::park(),
+            }
+        }
+    }
+
+    fn index() -> Arc<Index> {
+        let points = (0..2000)
+            .map(|id| Point {
+                position: vec![(id % 50) as u16, (id / 50) as u16],
+                id,
+            })
+            .collect::<Vec<_>>();
+
+        Arc::new(Index::new(points.into_iter(), 2, 3))
+    }
+
+    #[test]
+    fn find_range_async() {
+        let index = index();
+        let (start, end) = (vec![5, 3], vec![30, 20]);
+
+        let expected = index
+            .find_range(&start, &end)
+            .into_iter()
+            .map(|(key, id)| (key, *id))
+            .collect::<Vec<_>>();
+        assert_eq!(expected.len(), 26 * 18);
+        assert_eq!(collect(index.find_range_async(start, end)), expected);
+
+        let empty = index.find_range_async(vec![60, 0], vec![70, 10]);
+        assert_eq!(collect(empty), vec![]);
+    }
+
+    #[test]
+    fn bounded() {
+        let index = index();
+        let (start, end) = (vec![0, 0], vec![49, 39]);
+        let expected = index
+            .find_range(&start, &end)
+            .into_iter()
+            .map(|(key, id)| (key, *id))
+            .collect::<Vec<_>>();
+        assert!(expected.len() > STREAM_CAPACITY);
+
+        // The scan stops after a page, until the stream takes it.
+        let stream = index.find_range_async(start, end);
+        while stream.channel.shared.lock().unwrap().running {
+            thread::yield_now();
+        }
+        {
+            let shared = stream.channel.shared.lock().unwrap();
+            assert!(shared.items.len() <= STREAM_CAPACITY);
+            assert!(!shared.done);
+        }
+
+        assert_eq!(collect(stream), expected);
+    }
+
+    #[test]
+    fn pending_streams() {
+        let index = index();
+        let (start, end) = (vec![0, 0], vec![49, 39]);
+        let expected = index
+            .find_range(&start, &end)
+            .into_iter()
+            .map(|(key, id)| (key, *id))
+            .collect::<Vec<_>>();
+
+        // More streams than threads in the pool, none of them polled.
+        let streams = (0..rayon::current_num_threads() + 2)
+            .map(|_| index.find_range_async(start.clone(), end.clone()))
+            .collect::<Vec<_>>();
+
+        let (sender, receiver) = mpsc::channel();
+        let query = Arc::clone(&index);
+        thread::spawn(move || {
+            let stream = query.find_range_async(vec![0, 0], vec![1, 1]);
+            sender.send(collect(stream).len()).unwrap();
+        });
+        assert_eq!(receiver.recv_timeout(Duration::from_secs(10)), Ok(4));
+
+        for stream in streams {
+            assert_eq!(collect(stream), expected);
+        }
+    }
+
+    #[test]
+    fn drop_stream() {
+        let index = index();
+        let stream = index.find_range_async(vec![0, 0], vec![49, 39]);
+        drop(stream);
+
+        // The scan stops, and releases the index.
+        while Arc::strong_count(&index) > 1 {
+            thread::yield_now();
+        }
+    }
+}
@@ -0,0 +1,97 @@
+use std::fmt;
+
+use super::morton::MortonCode;
+
+/// Inconsistency found in the structure of an index, see
+/// [`validate`](struct.IndexOwned.html#method.validate).
+///
+/// Cells are identified by their position in the list of cells, and
+/// records by their position in their cell.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Violation {
+    /// The code of a cell is smaller than the code of the previous one.
+    Unsorted {
+        /// Position of the cell.
+        cell: usize,
+    },
+    /// A cell has the same code as the previous one.
+    DuplicateCode {
+        /// Position of the cell.
+        cell: usize,
+    },
+    /// The list of codes kept apart from the cells does not match them.
+    StaleCodes,
+    /// The code of a cell does not round-trip through the encoder, or
+    /// points outside of the grid.
+    InvalidCode {
+        /// Position of the cell.
+        cell: usize,
+        /// Code of the cell.
+        code: MortonCode,
+    },
+    /// A record does not have one offset per dimension.
+    DimensionMismatch {
+        /// Position of the cell.
+        cell: usize,
+        /// Position of the record.
+        record: usize,
+        /// Number of offsets of the record.
+        found: usize,
+    },
+    /// An offset of a record is past the values of its cell in the
+    /// dictionary.
+    OffsetOutOfBounds {
+        /// Position of the cell.
+        cell: usize,
+        /// Position of the record.
+        record: usize,
+        /// Dimension of the offset.
+        dimension: usize,
+    },
+    /// A record refers to values missing from its cell.
+    MissingValues {
+        /// Position of the cell.
+        cell: usize,
+        /// Position of the record.
+        record: usize,
+    },
+    /// The statistics of the index do not match its content.
+    StaleStats,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::Unsorted { cell } => write!(f, "Cell {} is out of order", cell),
+            Violation::DuplicateCode { cell } => {
+                write!(f, "Cell {} has the same code as the previous one", cell)
+            }
+            Violation::StaleCodes => write!(f, "The codes do not match the cells"),
+            Violation::InvalidCode { cell, code } => {
+                write!(f, "Cell {} has an invalid code {:#x}", cell, code)
+            }
+            Violation::DimensionMismatch {
+                cell,
+                record,
+                found,
+            } => write!(
+                f,
+                "Record {} of cell {} has {} offsets",
+                record, cell, found
+            ),
+            Violation::OffsetOutOfBounds {
+                cell,
+                record,
+                dimension,
+            } => write!(
+                f,
+                "Record {} of cell {} has an offset out of bounds on dim[{}]",
+                record, cell, dimension
+            ),
+            Violation::MissingValues { cell, record } => {
+                write!(f, "Record {} of cell {} has no values", record, cell)
+            }
+            Violation::StaleStats => write!(f, "The statistics do not match the index"),
+        }
+    }
+}
@@ -0,0 +1,134 @@
+#![allow(clippy::type_repetition_in_bounds)]
+
+use std::fmt::Debug;
+use std::hash::Hash;
+
+use super::morton::MortonCode;
+use super::position::Position;
+use super::sfc::SpaceFillingCurve;
+
+/// Cell of an index overlapping a range query, see
+/// [`visit_cells_in_range`](struct.IndexOwned.html#method.visit_cells_in_range).
+///
+/// Nothing is decoded nor allocated until the records are read. The
+/// values borrow the index, `'a`, and can be kept after the visit.
+#[derive(Debug)]
+pub struct CellVisit<'a, 'b, F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Ord,
+{
+    index: &'a SpaceFillingCurve<F, K, V>,
+    idx: usize,
+    start: &'b [Option<V>],
+    end: &'b [Option<V>],
+    contained: bool,
+}
+
+impl<'a, 'b, F, K, V> CellVisit<'a, 'b, F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    pub(crate) fn new(
+        index: &'a SpaceFillingCurve<F, K, V>,
+        idx: usize,
+        start: &'b [Option<V>],
+        end: &'b [Option<V>],
+        contained: bool,
+    ) -> Self {
+        CellVisit {
+            index,
+            idx,
+            start,
+            end,
+            contained,
+        }
+    }
+
+    /// Returns the Morton code of the cell.
+    pub fn code(&self) -> MortonCode {
+        self.index.cell_code(self.idx)
+    }
+
+    /// Returns the number of records of the cell, within the range or
+    /// not.
+    pub fn len(&self) -> usize {
+        self.index.cell_len(self.idx)
+    }
+
+    /// Returns `true` if the cell holds no records.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns `true` if the cell is fully within the range, in which
+    /// case all its records are.
+    pub fn contained(&self) -> bool {
+        self.contained
+    }
+
+    /// Returns the values of all the records of the cell, within the
+    /// range or not, without decoding their positions.
+    pub fn fields(&self) -> impl Iterator<Item = &'a F> + 'a {
+        self.index.cell_values(self.idx)
+    }
+
+    /// Returns the records of the cell within the range, decoding their
+    /// positions one at a time.
+    pub fn records(&self) -> CellRecords<'a, 'b, F, K, V> {
+        CellRecords {
+            index: self.index,
+            idx: self.idx,
+            start: self.start,
+            end: self.end,
+            contained: self.contained,
+            next: 0,
+        }
+    }
+}
+
+/// Records of a visited cell within the range, see
+/// [`CellVisit::records`](struct.CellVisit.html#method.records).
+#[derive(Debug)]
+pub struct CellRecords<'a, 'b, F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Ord,
+{
+    index: &'a SpaceFillingCurve<F, K, V>,
+    idx: usize,
+    start: &'b [Option<V>],
+    end: &'b [Option<V>],
+    contained: bool,
+    // Next record of the cell to decode.
+    next: usize,
+}
+
+impl<'a, 'b, F, K, V> Iterator for CellRecords<'a, 'b, F, K, V>
+where
+    F: PartialEq,
+    K: Position<V>,
+    V: Clone + Debug + Hash + Ord,
+{
+    type Item = (K, &'a F);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.next < self.index.cell_len(self.idx) {
+            let record = self.next;
+            self.next += 1;
+
+            let found =
+                self.index
+                    .cell_record(self.idx, record, self.start, self.end, self.contained);
+            if found.is_some() {
+                return found;
+            }
+        }
+
+        None
+    }
+}
@@ -0,0 +1,76 @@
+//! Range queries in the browser, over an index received as bytes.
+//!
+//! Run with `wasm-pack test --headless --firefox`, or any other browser
+//! supported by `wasm-bindgen-test`.
+
+#![cfg(all(target_arch = "wasm32", feature = "serde"))]
+
+use ironsea_index::IndexedDestructured;
+use ironsea_index_sfc_dbc::BuildPolicy;
+use ironsea_index_sfc_dbc::IndexOwned;
+use ironsea_index_sfc_dbc::Record;
+use ironsea_index_sfc_dbc::RecordFields;
+use ironsea_index_sfc_dbc::SfcError;
+use wasm_bindgen_test::wasm_bindgen_test;
+use wasm_bindgen_test::wasm_bindgen_test_configure;
+
+wasm_bindgen_test_configure!(run_in_browser);
+
+#[derive(Clone, Debug)]
+struct Point {
+    position: Vec<u16>,
+    id: u32,
+}
+
+impl Record<Vec<u16>> for Point {
+    fn key(&self) -> Vec<u16> {
+        self.position.clone()
+    }
+}
+
+impl RecordFields<u32> for Point {
+    fn fields(&self) -> u32 {
+        self.id
+    }
+}
+
+type Index = IndexOwned<u32, Vec<u16>, u16>;
+
+fn points() -> Vec<Point> {
+    (0..400)
+        .map(|id| Point {
+            position: vec![(id % 20) as u16, (id / 20) as u16],
+            id,
+        })
+        .collect()
+}
+
+#[wasm_bindgen_test]
+fn find_range() {
+    // Stands for an index downloaded by the page.
+    let mut bytes = vec![];
+    Index::new(points().into_iter(), 2, 3)
+        .save(&mut bytes)
+        .unwrap();
+
+    let index = Index::load(bytes.as_slice()).unwrap();
+    assert_eq!(index.find(&vec![3, 4]), vec![&83]);
+
+    let mut found = index
+        .find_range(&vec![2, 2], &vec![4, 3])
+        .into_iter()
+        .map(|(_, id)| *id)
+        .collect::<Vec<_>>();
+    found.sort_unstable();
+    assert_eq!(found, vec![42, 43, 44, 62, 63, 64]);
+}
+
+#[wasm_bindgen_test]
+fn new_chunked() {
+    // Nothing to spill to, but small inputs are built in memory.
+    assert!(Index::new_chunked(points().into_iter(), 2, 3, 1000, BuildPolicy::FailFast).is_ok());
+    assert!(matches!(
+        Index::new_chunked(points().into_iter(), 2, 3, 100, BuildPolicy::FailFast),
+        Err(SfcError::Io(_))
+    ));
+}